@@ -0,0 +1,134 @@
+struct Camera {
+    view_pos: vec4<f32>,
+    view: mat4x4<f32>,
+    view_proj: mat4x4<f32>,
+    inv_proj: mat4x4<f32>,
+    inv_view: mat4x4<f32>,
+}
+@group(0) @binding(0)
+var<uniform> camera: Camera;
+
+struct DebugPrimitives {
+    color: vec4<f32>,
+    viewport_size: vec2<f32>,
+    // Dot diameter or line width in pixels
+    size: f32,
+    // 0 draws a dot per vertex, the rest a line per edge of a triangle list
+    // (1), triangle strip (2), line list (3) or line strip (4)
+    mode: u32,
+}
+@group(1) @binding(0)
+var<uniform> params: DebugPrimitives;
+
+// The mesh's ModelVertex buffer, which starts each vertex with its position
+@group(1) @binding(1)
+var<storage, read> vertices: array<f32>;
+
+@group(1) @binding(2)
+var<storage, read> indices: array<u32>;
+
+const VERTEX_STRIDE: u32 = 14u;
+// Pulls the primitives slightly towards the camera so they aren't hidden by
+// the surfaces they lie on
+const DEPTH_BIAS: f32 = 0.0001;
+
+struct InstanceInput {
+    @location(5) model_mat_0: vec4<f32>,
+    @location(6) model_mat_1: vec4<f32>,
+    @location(7) model_mat_2: vec4<f32>,
+    @location(8) model_mat_3: vec4<f32>,
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    // Pixels from the dot's center or the line's middle
+    @location(0) offset: vec2<f32>,
+}
+
+fn vertex_clip_position(model_mat: mat4x4<f32>, vertex: u32) -> vec4<f32> {
+    let base = vertex * VERTEX_STRIDE;
+    let position = vec3(vertices[base], vertices[base + 1u], vertices[base + 2u]);
+    return camera.view_proj * model_mat * vec4(position, 1.0);
+}
+
+// Moves `clip` by `pixels` on screen
+fn offset_clip_position(clip: vec4<f32>, pixels: vec2<f32>) -> vec4<f32> {
+    let ndc_offset = pixels * 2.0 / params.viewport_size;
+    return vec4(clip.xy + ndc_offset * clip.w, clip.z - DEPTH_BIAS * clip.w, clip.w);
+}
+
+@vertex
+fn vs_main(
+    @builtin(vertex_index) id: u32,
+    instance: InstanceInput,
+) -> VertexOutput {
+    let model_mat = mat4x4(
+        instance.model_mat_0,
+        instance.model_mat_1,
+        instance.model_mat_2,
+        instance.model_mat_3,
+    );
+    var corners = array<vec2<f32>, 6>(
+        vec2(-1.0, -1.0),
+        vec2(1.0, -1.0),
+        vec2(1.0, 1.0),
+        vec2(-1.0, -1.0),
+        vec2(1.0, 1.0),
+        vec2(-1.0, 1.0),
+    );
+    let corner = corners[id % 6u];
+    let primitive = id / 6u;
+    // One extra pixel for the anti-aliased edge
+    let half_size = params.size / 2.0 + 1.0;
+
+    var out: VertexOutput;
+    if params.mode == 0u {
+        let clip = vertex_clip_position(model_mat, primitive);
+        out.clip_position = offset_clip_position(clip, corner * half_size);
+        out.offset = corner * half_size;
+    } else {
+        // Positions in `indices` of the edge's ends
+        var ends: vec2<u32>;
+        switch params.mode {
+            case 1u: {
+                // Edge from this index to the next one around its triangle
+                let triangle = primitive / 3u;
+                ends = vec2(primitive, triangle * 3u + (primitive % 3u + 1u) % 3u);
+            }
+            case 2u: {
+                // Alternates between the strip's sides and its diagonals
+                let first = primitive / 2u;
+                ends = vec2(first, first + 1u + primitive % 2u);
+            }
+            case 3u: {
+                ends = vec2(primitive * 2u, primitive * 2u + 1u);
+            }
+            default: {
+                ends = vec2(primitive, primitive + 1u);
+            }
+        }
+        let start = vertex_clip_position(model_mat, indices[ends.x]);
+        let end = vertex_clip_position(model_mat, indices[ends.y]);
+        let screen_start = start.xy / start.w * params.viewport_size;
+        let screen_end = end.xy / end.w * params.viewport_size;
+        var direction = vec2(1.0, 0.0);
+        if distance(screen_start, screen_end) > 0.0001 {
+            direction = normalize(screen_end - screen_start);
+        }
+        let normal = vec2(-direction.y, direction.x);
+        let clip = select(start, end, corner.x > 0.0);
+        let pixels = (direction * corner.x + normal * corner.y) * half_size;
+        out.clip_position = offset_clip_position(clip, pixels);
+        out.offset = vec2(0.0, corner.y * half_size);
+    }
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let coverage = clamp(params.size / 2.0 + 0.5 - length(in.offset), 0.0, 1.0);
+    if coverage <= 0.0 {
+        discard;
+    }
+    return vec4(params.color.rgb, params.color.a * coverage);
+}
@@ -0,0 +1,44 @@
+struct DepthParams {
+    znear: f32,
+    zfar: f32,
+}
+
+@group(0)
+@binding(0)
+var depth_texture: texture_depth_2d;
+
+@group(0)
+@binding(1)
+var<uniform> params: DepthParams;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+};
+
+@vertex
+fn vs_main(
+    @builtin(vertex_index) vi: u32,
+) -> VertexOutput {
+    var out: VertexOutput;
+    // Generate a triangle that covers the whole screen
+    let uv = vec2<f32>(
+        f32((vi << 1u) & 2u),
+        f32(vi & 2u),
+    );
+    out.clip_position = vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let depth = textureLoad(depth_texture, vec2<i32>(in.clip_position.xy), 0);
+
+    // The projection matrix is OpenGL style, so invert that mapping to get
+    // the distance from the camera
+    let near = params.znear;
+    let far = params.zfar;
+    let linear_depth = 2.0 * near * far / (far + near - depth * (far - near));
+
+    let normalized = clamp((linear_depth - near) / (far - near), 0.0, 1.0);
+    return vec4(vec3(normalized), 1.0);
+}
@@ -0,0 +1,95 @@
+struct DofParams {
+    // Distance from the camera that is in focus
+    focus_distance: f32,
+    // Scales how quickly things blur away from the focus distance
+    aperture: f32,
+    // Largest blur radius in pixels
+    max_radius: f32,
+    // 1 to focus on whatever is under the center of the screen
+    auto_focus: u32,
+    znear: f32,
+    zfar: f32,
+};
+
+@group(0)
+@binding(0)
+var hdr_image: texture_2d<f32>;
+
+@group(0)
+@binding(1)
+var depth_texture: texture_depth_2d;
+
+@group(0)
+@binding(2)
+var<uniform> params: DofParams;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+};
+
+@vertex
+fn vs_main(
+    @builtin(vertex_index) vi: u32,
+) -> VertexOutput {
+    var out: VertexOutput;
+    // Generate a triangle that covers the whole screen
+    let uv = vec2<f32>(
+        f32((vi << 1u) & 2u),
+        f32(vi & 2u),
+    );
+    out.clip_position = vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+    return out;
+}
+
+// The projection matrix is OpenGL style, so invert that mapping to get the
+// distance from the camera
+fn linear_depth(pixel: vec2<i32>) -> f32 {
+    let depth = textureLoad(depth_texture, pixel, 0);
+    let near = params.znear;
+    let far = params.zfar;
+    return 2.0 * near * far / (far + near - depth * (far - near));
+}
+
+// Blur radius in pixels of a point `distance` from the camera
+fn circle_of_confusion(distance: f32, focus: f32) -> f32 {
+    let coc = params.aperture * abs(distance - focus) / max(distance, 0.0001);
+    return clamp(coc, 0.0, 1.0) * params.max_radius;
+}
+
+const SAMPLES: i32 = 32;
+const GOLDEN_ANGLE: f32 = 2.39996323;
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let size = vec2<i32>(textureDimensions(hdr_image));
+    let pixel = vec2<i32>(in.clip_position.xy);
+
+    var focus = params.focus_distance;
+    if params.auto_focus == 1u {
+        focus = linear_depth(size / 2);
+    }
+
+    let center = textureLoad(hdr_image, pixel, 0);
+    let radius = circle_of_confusion(linear_depth(pixel), focus);
+    if radius < 0.5 {
+        return center;
+    }
+
+    // Gather over a spiral disk. A sample only counts if its own blur
+    // reaches this pixel, which keeps sharp foreground edges from bleeding
+    // into blurred backgrounds.
+    var color = center.rgb;
+    var weight = 1.0;
+    for (var i = 1; i < SAMPLES; i++) {
+        let r = radius * sqrt(f32(i) / f32(SAMPLES));
+        let theta = f32(i) * GOLDEN_ANGLE;
+        let offset = vec2<i32>(round(vec2(cos(theta), sin(theta)) * r));
+        let sample_pixel = clamp(pixel + offset, vec2(0), size - 1);
+        let sample_radius = circle_of_confusion(linear_depth(sample_pixel), focus);
+        if sample_radius >= r {
+            color += textureLoad(hdr_image, sample_pixel, 0).rgb;
+            weight += 1.0;
+        }
+    }
+    return vec4(color / weight, center.a);
+}
@@ -0,0 +1,23 @@
+// Vertex stage used for shaders that only have a fragment entry point
+
+struct VertexOutput {
+    @location(0) uv: vec2<f32>,
+    @builtin(position) clip_position: vec4<f32>,
+};
+
+@vertex
+fn vs_main(
+    @builtin(vertex_index) vi: u32,
+) -> VertexOutput {
+    var out: VertexOutput;
+    // Generate a triangle that covers the whole screen
+    out.uv = vec2<f32>(
+        f32((vi << 1u) & 2u),
+        f32(vi & 2u),
+    );
+    // At the near plane so that it covers everything drawn before it
+    out.clip_position = vec4<f32>(out.uv * 2.0 - 1.0, 0.0, 1.0);
+    // Invert the uv y coord so that (0, 0) is the top left
+    out.uv.y = 1.0 - out.uv.y;
+    return out;
+}
@@ -0,0 +1,80 @@
+struct Camera {
+    view_pos: vec4<f32>,
+    view: mat4x4<f32>,
+    view_proj: mat4x4<f32>,
+    inv_proj: mat4x4<f32>,
+    inv_view: mat4x4<f32>,
+}
+@group(0) @binding(0)
+var<uniform> camera: Camera;
+
+struct Grid {
+    color: vec4<f32>,
+    spacing: f32,
+}
+@group(1) @binding(0)
+var<uniform> grid: Grid;
+
+struct VertexOutput {
+    @builtin(position) frag_position: vec4<f32>,
+    @location(0) clip_position: vec4<f32>,
+}
+
+struct FragmentOutput {
+    @location(0) color: vec4<f32>,
+    @builtin(frag_depth) depth: f32,
+}
+
+@vertex
+fn vs_main(
+    @builtin(vertex_index) id: u32,
+) -> VertexOutput {
+    let uv = vec2<f32>(vec2<u32>(
+        id & 1u,
+        (id >> 1u) & 1u,
+    ));
+    var out: VertexOutput;
+    out.clip_position = vec4(uv * 4.0 - 1.0, 1.0, 1.0);
+    out.frag_position = vec4(uv * 4.0 - 1.0, 1.0, 1.0);
+    return out;
+}
+
+// The grid lies on the y = 0 plane. Every pixel casts a ray from the camera
+// and draws the lines around where it hits the plane, so the grid is infinite
+// and its lines stay one pixel wide at any distance.
+@fragment
+fn fs_main(in: VertexOutput) -> FragmentOutput {
+    let view_pos_homogeneous = camera.inv_proj * in.clip_position;
+    let view_ray_direction = view_pos_homogeneous.xyz / view_pos_homogeneous.w;
+    let ray_direction = normalize((camera.inv_view * vec4(view_ray_direction, 0.0)).xyz);
+    let origin = camera.view_pos.xyz;
+
+    let t = -origin.y / ray_direction.y;
+    let hit = origin + ray_direction * t;
+
+    // Derivatives need uniform control flow, so nothing is discarded until
+    // they have been taken
+    let coord = hit.xz / grid.spacing;
+    let width = fwidth(coord);
+    let distance_to_line = abs(fract(coord - 0.5) - 0.5) / width;
+    let line = 1.0 - min(min(distance_to_line.x, distance_to_line.y), 1.0);
+
+    // Fade out before the lines get denser than the pixels
+    let distance = length(hit - origin);
+    let fade = 1.0 - smoothstep(grid.spacing * 25.0, grid.spacing * 100.0, distance);
+
+    // Pushed back a little along the ray so models lying on the plane win
+    // the depth test instead of fighting with the grid
+    let depth_position = camera.view_proj * vec4(origin + ray_direction * (t * 1.001), 1.0);
+    let depth = depth_position.z / depth_position.w;
+
+    let alpha = grid.color.a * line * fade;
+    if t <= 0.0 || alpha <= 0.0 || depth > 1.0 {
+        discard;
+    }
+
+    var out: FragmentOutput;
+    out.color = vec4(grid.color.rgb, alpha);
+    out.depth = depth;
+    return out;
+}
@@ -47,9 +47,22 @@ var hdr_image: texture_2d<f32>;
 @binding(1)
 var hdr_sampler: sampler;
 
+struct OutputTransform {
+    // 0: leave the encode to an sRGB target, 1: gamma 2.2
+    mode: u32,
+};
+
+@group(0)
+@binding(2)
+var<uniform> output_transform: OutputTransform;
+
 @fragment
 fn fs_main(vs: VertexOutput) -> @location(0) vec4<f32> {
     let hdr = textureSample(hdr_image, hdr_sampler, vs.uv);
-    let sdr = aces_tone_map(hdr.rgb);
+    var sdr = aces_tone_map(hdr.rgb);
+    // Targets that aren't sRGB store what they are given, so encode here
+    if output_transform.mode == 1u {
+        sdr = pow(sdr, vec3(1.0 / 2.2));
+    }
     return vec4(sdr, hdr.a);
 }
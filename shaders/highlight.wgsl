@@ -0,0 +1,47 @@
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+};
+
+struct InstanceInput {
+    @location(5) model_mat_0: vec4<f32>,
+    @location(6) model_mat_1: vec4<f32>,
+    @location(7) model_mat_2: vec4<f32>,
+    @location(8) model_mat_3: vec4<f32>,
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+};
+
+struct CameraUniform {
+    view_pos: vec4<f32>,
+    view: mat4x4<f32>,
+    view_proj: mat4x4<f32>,
+    inv_proj: mat4x4<f32>,
+    inv_view: mat4x4<f32>,
+};
+
+@group(0) @binding(0)
+var<uniform> camera: CameraUniform;
+
+@vertex
+fn vs_main(
+    input: VertexInput,
+    instance: InstanceInput
+) -> VertexOutput {
+    let model_mat = mat4x4(
+        instance.model_mat_0,
+        instance.model_mat_1,
+        instance.model_mat_2,
+        instance.model_mat_3,
+    );
+    var out: VertexOutput;
+    out.clip_position = camera.view_proj * model_mat * vec4<f32>(input.position, 1.0);
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    // Added on top of the already shaded surface
+    return vec4<f32>(0.4, 0.3, 0.0, 0.0);
+}
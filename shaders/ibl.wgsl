@@ -0,0 +1,263 @@
+const PI: f32 = 3.1415926535897932384626433832795;
+const PREFILTER_SAMPLE_COUNT: u32 = 1024u;
+const BRDF_SAMPLE_COUNT: u32 = 1024u;
+
+struct Face {
+    forward: vec3<f32>,
+    up: vec3<f32>,
+    right: vec3<f32>,
+}
+
+struct PrefilterParams {
+    roughness: f32,
+}
+
+@group(0)
+@binding(0)
+var env_map: texture_cube<f32>;
+
+@group(0)
+@binding(1)
+var env_sampler: sampler;
+
+@group(0)
+@binding(2)
+var dst: texture_storage_2d_array<rgba16float, write>;
+
+@group(0)
+@binding(3)
+var<uniform> params: PrefilterParams;
+
+@group(0)
+@binding(4)
+var brdf_dst: texture_storage_2d<rgba16float, write>;
+
+@group(0)
+@binding(5)
+var downsample_src: texture_2d_array<f32>;
+
+@group(0)
+@binding(6)
+var downsample_dst: texture_storage_2d_array<rgba32float, write>;
+
+// Uses the same face orientation as equirectangular.wgsl so the generated
+// cubes line up with the environment map
+fn cube_direction(face_index: u32, cube_uv: vec2<f32>) -> vec3<f32> {
+    var FACES: array<Face, 6> = array(
+        // FACES +X
+        Face(
+            vec3(1.0, 0.0, 0.0),  // forward
+            vec3(0.0, 1.0, 0.0),  // up
+            vec3(0.0, 0.0, -1.0), // right
+        ),
+        // FACES -X
+        Face (
+            vec3(-1.0, 0.0, 0.0),
+            vec3(0.0, 1.0, 0.0),
+            vec3(0.0, 0.0, 1.0),
+        ),
+        // FACES +Y
+        Face (
+            vec3(0.0, -1.0, 0.0),
+            vec3(0.0, 0.0, 1.0),
+            vec3(1.0, 0.0, 0.0),
+        ),
+        // FACES -Y
+        Face (
+            vec3(0.0, 1.0, 0.0),
+            vec3(0.0, 0.0, -1.0),
+            vec3(1.0, 0.0, 0.0),
+        ),
+        // FACES +Z
+        Face (
+            vec3(0.0, 0.0, 1.0),
+            vec3(0.0, 1.0, 0.0),
+            vec3(1.0, 0.0, 0.0),
+        ),
+        // FACES -Z
+        Face (
+            vec3(0.0, 0.0, -1.0),
+            vec3(0.0, 1.0, 0.0),
+            vec3(-1.0, 0.0, 0.0),
+        ),
+    );
+    let face = FACES[face_index];
+    return normalize(face.forward + face.right * cube_uv.x + face.up * cube_uv.y);
+}
+
+fn hammersley(i: u32, n: u32) -> vec2<f32> {
+    let radical_inverse = f32(reverseBits(i)) * 2.3283064365386963e-10;
+    return vec2(f32(i) / f32(n), radical_inverse);
+}
+
+fn importance_sample_ggx(xi: vec2<f32>, n: vec3<f32>, roughness: f32) -> vec3<f32> {
+    let a = roughness * roughness;
+    let phi = 2.0 * PI * xi.x;
+    let cos_theta = sqrt((1.0 - xi.y) / (1.0 + (a * a - 1.0) * xi.y));
+    let sin_theta = sqrt(1.0 - cos_theta * cos_theta);
+    let h = vec3(cos(phi) * sin_theta, sin(phi) * sin_theta, cos_theta);
+
+    // Tangent space to world space
+    let up = select(vec3(1.0, 0.0, 0.0), vec3(0.0, 0.0, 1.0), abs(n.z) < 0.999);
+    let tangent = normalize(cross(up, n));
+    let bitangent = cross(n, tangent);
+    return normalize(tangent * h.x + bitangent * h.y + n * h.z);
+}
+
+fn distribution_ggx(n_dot_h: f32, roughness: f32) -> f32 {
+    let a = roughness * roughness;
+    let a2 = a * a;
+    let d = n_dot_h * n_dot_h * (a2 - 1.0) + 1.0;
+    return a2 / (PI * d * d);
+}
+
+fn geometry_schlick_ggx(n_dot_v: f32, roughness: f32) -> f32 {
+    // IBL uses a different k than direct lighting
+    let k = roughness * roughness / 2.0;
+    return n_dot_v / (n_dot_v * (1.0 - k) + k);
+}
+
+@compute
+@workgroup_size(16, 16, 1)
+fn compute_irradiance(
+    @builtin(global_invocation_id)
+    gid: vec3<u32>,
+) {
+    let dst_dimensions = textureDimensions(dst);
+    if gid.x >= dst_dimensions.x || gid.y >= dst_dimensions.y {
+        return;
+    }
+
+    let cube_uv = vec2<f32>(gid.xy) / vec2<f32>(dst_dimensions) * 2.0 - 1.0;
+    let normal = cube_direction(gid.z, cube_uv);
+    let up_guess = select(vec3(1.0, 0.0, 0.0), vec3(0.0, 1.0, 0.0), abs(normal.y) < 0.999);
+    let right = normalize(cross(up_guess, normal));
+    let up = cross(normal, right);
+
+    // Convolve the hemisphere around the normal
+    var irradiance = vec3(0.0);
+    var sample_count = 0.0;
+    let delta = 0.025;
+    for (var phi = 0.0; phi < 2.0 * PI; phi += delta) {
+        for (var theta = 0.0; theta < 0.5 * PI; theta += delta) {
+            let tangent_sample = vec3(sin(theta) * cos(phi), sin(theta) * sin(phi), cos(theta));
+            let sample_direction = tangent_sample.x * right + tangent_sample.y * up + tangent_sample.z * normal;
+            let sample = textureSampleLevel(env_map, env_sampler, sample_direction, 0.0).rgb;
+            irradiance += sample * cos(theta) * sin(theta);
+            sample_count += 1.0;
+        }
+    }
+    irradiance = PI * irradiance / sample_count;
+
+    textureStore(dst, gid.xy, gid.z, vec4(irradiance, 1.0));
+}
+
+@compute
+@workgroup_size(16, 16, 1)
+fn compute_prefilter(
+    @builtin(global_invocation_id)
+    gid: vec3<u32>,
+) {
+    let dst_dimensions = textureDimensions(dst);
+    if gid.x >= dst_dimensions.x || gid.y >= dst_dimensions.y {
+        return;
+    }
+
+    let cube_uv = vec2<f32>(gid.xy) / vec2<f32>(dst_dimensions) * 2.0 - 1.0;
+    // Assume the view direction equals the reflection direction
+    let n = cube_direction(gid.z, cube_uv);
+    let v = n;
+
+    let source_size = f32(textureDimensions(env_map).x);
+    let texel_solid_angle = 4.0 * PI / (6.0 * source_size * source_size);
+    let max_level = f32(textureNumLevels(env_map) - 1u);
+
+    var color = vec3(0.0);
+    var total_weight = 0.0;
+    for (var i = 0u; i < PREFILTER_SAMPLE_COUNT; i++) {
+        let xi = hammersley(i, PREFILTER_SAMPLE_COUNT);
+        let h = importance_sample_ggx(xi, n, params.roughness);
+        let l = normalize(2.0 * dot(v, h) * h - v);
+        let n_dot_l = dot(n, l);
+        if n_dot_l > 0.0 {
+            // Read the level whose texels cover about the solid angle this
+            // sample stands for, with n == v the pdf is D / 4
+            let pdf = distribution_ggx(max(dot(n, h), 0.0), params.roughness) / 4.0;
+            let sample_solid_angle = 1.0 / (f32(PREFILTER_SAMPLE_COUNT) * pdf + 0.0001);
+            let level = select(
+                clamp(0.5 * log2(sample_solid_angle / texel_solid_angle) + 1.0, 0.0, max_level),
+                0.0,
+                params.roughness == 0.0,
+            );
+            color += textureSampleLevel(env_map, env_sampler, l, level).rgb * n_dot_l;
+            total_weight += n_dot_l;
+        }
+    }
+    color = color / max(total_weight, 0.0001);
+
+    textureStore(dst, gid.xy, gid.z, vec4(color, 1.0));
+}
+
+@compute
+@workgroup_size(16, 16, 1)
+fn compute_brdf_lut(
+    @builtin(global_invocation_id)
+    gid: vec3<u32>,
+) {
+    let dst_dimensions = textureDimensions(brdf_dst);
+    if gid.x >= dst_dimensions.x || gid.y >= dst_dimensions.y {
+        return;
+    }
+
+    // x is n dot v, y is roughness
+    let n_dot_v = max((f32(gid.x) + 0.5) / f32(dst_dimensions.x), 0.0001);
+    let roughness = (f32(gid.y) + 0.5) / f32(dst_dimensions.y);
+    let v = vec3(sqrt(1.0 - n_dot_v * n_dot_v), 0.0, n_dot_v);
+    let n = vec3(0.0, 0.0, 1.0);
+
+    var scale = 0.0;
+    var bias = 0.0;
+    for (var i = 0u; i < BRDF_SAMPLE_COUNT; i++) {
+        let xi = hammersley(i, BRDF_SAMPLE_COUNT);
+        let h = importance_sample_ggx(xi, n, roughness);
+        let l = normalize(2.0 * dot(v, h) * h - v);
+
+        let n_dot_l = max(l.z, 0.0);
+        let n_dot_h = max(h.z, 0.0);
+        let v_dot_h = max(dot(v, h), 0.0);
+        if n_dot_l > 0.0 {
+            let g = geometry_schlick_ggx(n_dot_v, roughness) * geometry_schlick_ggx(n_dot_l, roughness);
+            let g_vis = g * v_dot_h / (n_dot_h * n_dot_v);
+            let fc = pow(1.0 - v_dot_h, 5.0);
+            scale += (1.0 - fc) * g_vis;
+            bias += fc * g_vis;
+        }
+    }
+
+    let result = vec2(scale, bias) / f32(BRDF_SAMPLE_COUNT);
+    textureStore(brdf_dst, gid.xy, vec4(result, 0.0, 1.0));
+}
+
+@compute
+@workgroup_size(16, 16, 1)
+fn compute_downsample(
+    @builtin(global_invocation_id)
+    gid: vec3<u32>,
+) {
+    let dst_dimensions = textureDimensions(downsample_dst);
+    if gid.x >= dst_dimensions.x || gid.y >= dst_dimensions.y {
+        return;
+    }
+
+    // Odd sizes clamp to the last row and column
+    let src_max = textureDimensions(downsample_src) - 1u;
+    var color = vec4(0.0);
+    for (var y = 0u; y < 2u; y++) {
+        for (var x = 0u; x < 2u; x++) {
+            let texel = min(gid.xy * 2u + vec2(x, y), src_max);
+            color += textureLoad(downsample_src, texel, gid.z, 0);
+        }
+    }
+
+    textureStore(downsample_dst, gid.xy, gid.z, color * 0.25);
+}
@@ -4,6 +4,14 @@ struct VertexInput {
     @location(0) position: vec3<f32>,
 };
 
+// Places the marker at the light, see LightMarker::update
+struct InstanceInput {
+    @location(5) model_matrix_0: vec4<f32>,
+    @location(6) model_matrix_1: vec4<f32>,
+    @location(7) model_matrix_2: vec4<f32>,
+    @location(8) model_matrix_3: vec4<f32>,
+};
+
 struct VertexOutput {
     @builtin(position) clip_position: vec4<f32>,
     @location(0) color: vec3<f32>,
@@ -30,11 +38,17 @@ var<uniform> light: Light;
 
 @vertex
 fn vs_main(
-    input: VertexInput
+    input: VertexInput,
+    instance: InstanceInput,
 ) -> VertexOutput {
-    let scale = 0.25;
+    let model_matrix = mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );
     var out: VertexOutput;
-    out.clip_position = camera.view_proj * vec4<f32>(input.position * scale + light.position, 1.0);
+    out.clip_position = camera.view_proj * model_matrix * vec4<f32>(input.position, 1.0);
     out.color = light.color;
     return out;
 }
@@ -0,0 +1,241 @@
+// Vertex Shader
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) tex_coords: vec2<f32>,
+    @location(2) normal: vec3<f32>,
+    @location(3) tangent: vec3<f32>,
+    @location(4) bitangent: vec3<f32>,
+};
+
+struct InstanceInput {
+    @location(5) model_mat_0: vec4<f32>,
+    @location(6) model_mat_1: vec4<f32>,
+    @location(7) model_mat_2: vec4<f32>,
+    @location(8) model_mat_3: vec4<f32>,
+
+    @location(9)  normal_mat_0: vec3<f32>,
+    @location(10) normal_mat_1: vec3<f32>,
+    @location(11) normal_mat_2: vec3<f32>,
+
+    @location(12) tint: vec4<f32>,
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+    @location(1) world_position: vec3<f32>,
+    @location(2) world_view_position: vec3<f32>,
+    @location(3) world_light_position: vec3<f32>,
+    @location(4) world_normal: vec3<f32>,
+    @location(5) world_tangent: vec3<f32>,
+    @location(6) world_bitangent: vec3<f32>,
+    @location(7) tint: vec4<f32>,
+};
+
+struct CameraUniform {
+    view_pos: vec4<f32>,
+    view: mat4x4<f32>,
+    view_proj: mat4x4<f32>,
+    inv_proj: mat4x4<f32>,
+    inv_view: mat4x4<f32>,
+};
+
+@group(1) @binding(0)
+var<uniform> camera: CameraUniform;
+
+struct Light {
+    position: vec3<f32>,
+    color: vec3<f32>
+};
+
+@group(2) @binding(0)
+var<uniform> light: Light;
+
+struct Shading {
+    double_sided: u32,
+};
+
+@group(2) @binding(4)
+var<uniform> shading: Shading;
+
+@vertex
+fn vs_main(
+    input: VertexInput,
+    instance: InstanceInput
+) -> VertexOutput {
+    let model_mat = mat4x4(
+        instance.model_mat_0,
+        instance.model_mat_1,
+        instance.model_mat_2,
+        instance.model_mat_3,
+    );
+    let normal_mat = mat3x3(
+        instance.normal_mat_0,
+        instance.normal_mat_1,
+        instance.normal_mat_2,
+    );
+
+    let world_position = model_mat * vec4<f32>(input.position, 1.0);
+
+    var out: VertexOutput;
+    out.clip_position = camera.view_proj * world_position;
+    out.tex_coords = input.tex_coords;
+    out.world_position = world_position.xyz;
+    out.world_view_position = camera.view_pos.xyz;
+    out.world_light_position = light.position;
+    out.world_normal = normalize(normal_mat * input.normal);
+    // Tangents lie in the surface, so they are transformed by the model
+    // matrix rather than the normal matrix
+    let model_mat3 = mat3x3(
+        instance.model_mat_0.xyz,
+        instance.model_mat_1.xyz,
+        instance.model_mat_2.xyz,
+    );
+    out.world_tangent = normalize(model_mat3 * input.tangent);
+    out.world_bitangent = normalize(model_mat3 * input.bitangent);
+    out.tint = instance.tint;
+    return out;
+}
+
+@group(0) @binding(0)
+var t_diffuse: texture_2d<f32>;
+@group(0) @binding(1)
+var s_diffuse: sampler;
+
+@group(0) @binding(2)
+var t_normal: texture_2d<f32>;
+@group(0) @binding(3)
+var s_normal: sampler;
+
+// Roughness in green and metallic in blue, as in glTF
+@group(0) @binding(4)
+var t_metallic_roughness: texture_2d<f32>;
+@group(0) @binding(5)
+var s_metallic_roughness: sampler;
+
+@group(0) @binding(6)
+var t_ao: texture_2d<f32>;
+@group(0) @binding(7)
+var s_ao: sampler;
+
+struct MaterialFactors {
+    metallic: f32,
+    roughness: f32,
+    // Diffuse alpha below this is cut out, 0 for opaque materials
+    alpha_cutoff: f32,
+};
+
+@group(0) @binding(8)
+var<uniform> material: MaterialFactors;
+
+@group(3) @binding(0)
+var env_map: texture_cube<f32>;
+@group(3) @binding(1)
+var env_sampler: sampler;
+
+const PI: f32 = 3.1415926535897932384626433832795;
+
+@group(3) @binding(2)
+var irradiance_map: texture_cube<f32>;
+@group(3) @binding(3)
+var prefiltered_map: texture_cube<f32>;
+@group(3) @binding(4)
+var brdf_lut: texture_2d<f32>;
+@group(3) @binding(5)
+var ibl_sampler: sampler;
+
+struct EnvironmentTransform {
+    // Takes world space directions to the environment's own space
+    rotation: mat4x4<f32>,
+    intensity: f32,
+}
+@group(3) @binding(6)
+var<uniform> env_transform: EnvironmentTransform;
+
+fn distribution_ggx(n_dot_h: f32, roughness: f32) -> f32 {
+    let a = roughness * roughness;
+    let a2 = a * a;
+    let denom = n_dot_h * n_dot_h * (a2 - 1.0) + 1.0;
+    return a2 / (PI * denom * denom);
+}
+
+fn geometry_schlick_ggx(n_dot_v: f32, roughness: f32) -> f32 {
+    let r = roughness + 1.0;
+    let k = r * r / 8.0;
+    return n_dot_v / (n_dot_v * (1.0 - k) + k);
+}
+
+fn fresnel_schlick(cos_theta: f32, f0: vec3<f32>) -> vec3<f32> {
+    return f0 + (1.0 - f0) * pow(clamp(1.0 - cos_theta, 0.0, 1.0), 5.0);
+}
+
+fn fresnel_schlick_roughness(cos_theta: f32, f0: vec3<f32>, roughness: f32) -> vec3<f32> {
+    return f0 + (max(vec3(1.0 - roughness), f0) - f0) * pow(clamp(1.0 - cos_theta, 0.0, 1.0), 5.0);
+}
+
+@fragment
+fn fs_main(in: VertexOutput, @builtin(front_facing) front_facing: bool) -> @location(0) vec4<f32> {
+    let obj_color = textureSample(t_diffuse, s_diffuse, in.tex_coords) * in.tint;
+    let obj_normal: vec4<f32> = textureSample(t_normal, s_normal, in.tex_coords);
+
+    // Fix the tangent and bitangent using the gramm-schmidt process
+    let world_tangent = normalize(in.world_tangent - dot(in.world_tangent, in.world_normal) * in.world_normal);
+    let world_bitangent = cross(world_tangent, in.world_normal);
+
+    // Convert the normal space to world space
+    let TBN = mat3x3(
+        world_tangent,
+        world_bitangent,
+        in.world_normal
+    );
+    let tangent_normal = obj_normal.xyz * 2.0 - 1.0;
+    let surface_normal = normalize(TBN * tangent_normal);
+    // Back faces of double-sided models are lit from the viewer's side
+    let flip = shading.double_sided != 0u && !front_facing;
+    let n = select(surface_normal, -surface_normal, flip);
+
+    let mr = textureSample(t_metallic_roughness, s_metallic_roughness, in.tex_coords);
+    // Very low roughness makes the highlight vanish, so keep a floor
+    let roughness = clamp(mr.g * material.roughness, 0.04, 1.0);
+    let metallic = mr.b * material.metallic;
+    let ao = textureSample(t_ao, s_ao, in.tex_coords).r;
+
+    let albedo = obj_color.rgb;
+    let v = normalize(in.world_view_position - in.world_position);
+    let n_dot_v = max(dot(n, v), 0.0001);
+    let f0 = mix(vec3(0.04), albedo, metallic);
+
+    // Direct lighting from the point light
+    let l = normalize(in.world_light_position - in.world_position);
+    let h = normalize(v + l);
+    let n_dot_l = max(dot(n, l), 0.0);
+    let n_dot_h = max(dot(n, h), 0.0);
+    let f = fresnel_schlick(max(dot(h, v), 0.0), f0);
+    let d = distribution_ggx(n_dot_h, roughness);
+    let g = geometry_schlick_ggx(n_dot_v, roughness) * geometry_schlick_ggx(n_dot_l, roughness);
+    let specular = d * g * f / (4.0 * n_dot_v * n_dot_l + 0.0001);
+    let k_d = (vec3(1.0) - f) * (1.0 - metallic);
+    let direct = (k_d * albedo / PI + specular) * light.color * n_dot_l;
+
+    // Image based lighting using the split sum approximation
+    let f_ibl = fresnel_schlick_roughness(n_dot_v, f0, roughness);
+    let k_d_ibl = (vec3(1.0) - f_ibl) * (1.0 - metallic);
+    let env_n = (env_transform.rotation * vec4(n, 0.0)).xyz;
+    let irradiance = textureSample(irradiance_map, ibl_sampler, env_n).rgb;
+    let diffuse_ibl = irradiance * albedo;
+
+    let r = (env_transform.rotation * vec4(reflect(-v, n), 0.0)).xyz;
+    let max_lod = f32(textureNumLevels(prefiltered_map) - 1u);
+    let prefiltered = textureSampleLevel(prefiltered_map, ibl_sampler, r, roughness * max_lod).rgb;
+    let brdf = textureSample(brdf_lut, ibl_sampler, vec2(n_dot_v, roughness)).rg;
+    let specular_ibl = prefiltered * (f_ibl * brdf.x + brdf.y);
+
+    let ambient = (k_d_ibl * diffuse_ibl + specular_ibl) * ao * env_transform.intensity;
+
+    // Only after the last texture sample, which needs uniform control flow
+    if obj_color.a < material.alpha_cutoff {
+        discard;
+    }
+    return vec4<f32>(direct + ambient, obj_color.a);
+}
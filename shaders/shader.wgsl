@@ -17,6 +17,8 @@ struct InstanceInput {
     @location(9)  normal_mat_0: vec3<f32>,
     @location(10) normal_mat_1: vec3<f32>,
     @location(11) normal_mat_2: vec3<f32>,
+
+    @location(12) tint: vec4<f32>,
 }
 
 struct VertexOutput {
@@ -28,6 +30,7 @@ struct VertexOutput {
     @location(4) world_normal: vec3<f32>,
     @location(5) world_tangent: vec3<f32>,
     @location(6) world_bitangent: vec3<f32>,
+    @location(7) tint: vec4<f32>,
 };
 
 struct CameraUniform {
@@ -49,6 +52,45 @@ struct Light {
 @group(2) @binding(0)
 var<uniform> light: Light;
 
+@group(2) @binding(1)
+var shadow_map: texture_depth_2d;
+@group(2) @binding(2)
+var shadow_sampler: sampler_comparison;
+
+struct Shadow {
+    light_view_proj: mat4x4<f32>,
+    enabled: u32,
+};
+
+@group(2) @binding(3)
+var<uniform> shadow: Shadow;
+
+struct Shading {
+    double_sided: u32,
+};
+
+@group(2) @binding(4)
+var<uniform> shading: Shading;
+
+// 1.0 where the light reaches `world_position`, 0.0 where it is blocked
+fn shadow_factor(world_position: vec3<f32>) -> f32 {
+    if shadow.enabled == 0u {
+        return 1.0;
+    }
+    let light_clip = shadow.light_view_proj * vec4<f32>(world_position, 1.0);
+    let ndc = light_clip.xyz / light_clip.w;
+    // Texture coordinates have y pointing down
+    let uv = vec2<f32>(ndc.x * 0.5 + 0.5, 0.5 - ndc.y * 0.5);
+    // The level variant can be used outside of uniform control flow
+    let lit = textureSampleCompareLevel(shadow_map, shadow_sampler, uv, ndc.z);
+    // Anything outside of the light's frustum is not shadowed
+    let outside = light_clip.w <= 0.0
+        || any(uv < vec2<f32>(0.0))
+        || any(uv > vec2<f32>(1.0))
+        || ndc.z > 1.0;
+    return select(lit, 1.0, outside);
+}
+
 @vertex
 fn vs_main(
     input: VertexInput,
@@ -75,8 +117,16 @@ fn vs_main(
     out.world_view_position = camera.view_pos.xyz;
     out.world_light_position = light.position;
     out.world_normal = normalize(normal_mat * input.normal);
-    out.world_tangent = normalize(normal_mat * input.tangent);
-    out.world_bitangent = normalize(normal_mat * input.bitangent);
+    // Tangents lie in the surface, so they are transformed by the model
+    // matrix rather than the normal matrix
+    let model_mat3 = mat3x3(
+        instance.model_mat_0.xyz,
+        instance.model_mat_1.xyz,
+        instance.model_mat_2.xyz,
+    );
+    out.world_tangent = normalize(model_mat3 * input.tangent);
+    out.world_bitangent = normalize(model_mat3 * input.bitangent);
+    out.tint = instance.tint;
     return out;
 }
 
@@ -90,14 +140,31 @@ var t_normal: texture_2d<f32>;
 @group(0) @binding(3)
 var s_normal: sampler;
 
+struct MaterialFactors {
+    metallic: f32,
+    roughness: f32,
+    // Diffuse alpha below this is cut out, 0 for opaque materials
+    alpha_cutoff: f32,
+};
+@group(0) @binding(8)
+var<uniform> material: MaterialFactors;
+
 @group(3) @binding(0)
 var env_map: texture_cube<f32>;
 @group(3) @binding(1)
 var env_sampler: sampler;
 
+struct EnvironmentTransform {
+    // Takes world space directions to the environment's own space
+    rotation: mat4x4<f32>,
+    intensity: f32,
+}
+@group(3) @binding(6)
+var<uniform> env_transform: EnvironmentTransform;
+
 @fragment
-fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    let obj_color = textureSample(t_diffuse, s_diffuse, in.tex_coords);
+fn fs_main(in: VertexOutput, @builtin(front_facing) front_facing: bool) -> @location(0) vec4<f32> {
+    let obj_color = textureSample(t_diffuse, s_diffuse, in.tex_coords) * in.tint;
     let obj_normal: vec4<f32> = textureSample(t_normal, s_normal, in.tex_coords);
 
     // Fix the tangent and bitangent using the gramm-schmidt process
@@ -111,7 +178,10 @@ fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
         in.world_normal
     );
     let tangent_normal = obj_normal.xyz * 2.0 - 1.0;
-    let world_normal = TBN*tangent_normal;
+    let surface_normal = TBN*tangent_normal;
+    // Back faces of double-sided models are lit from the viewer's side
+    let flip = shading.double_sided != 0u && !front_facing;
+    let world_normal = select(surface_normal, -surface_normal, flip);
 
     let ambient_strength = 0.1;
     let ambient_color = light.color * ambient_strength;
@@ -128,9 +198,15 @@ fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
 
     // Calculate reflections
     let world_reflect = reflect(-view_dir, world_normal);
-    let reflection = textureSample(env_map, env_sampler, world_reflect).rgb;
+    let env_reflect = (env_transform.rotation * vec4(world_reflect, 0.0)).xyz;
+    let reflection = textureSample(env_map, env_sampler, env_reflect).rgb * env_transform.intensity;
     let shininess = 0.1;
 
-    let final_color = (ambient_color + diffuse_color + specular_color) * obj_color.xyz + reflection * shininess;
+    let lit = shadow_factor(in.world_position);
+    let final_color = (ambient_color + (diffuse_color + specular_color) * lit) * obj_color.xyz + reflection * shininess;
+    // Only after the last texture sample, which needs uniform control flow
+    if obj_color.a < material.alpha_cutoff {
+        discard;
+    }
     return vec4<f32>(final_color, obj_color.a);
 }
@@ -0,0 +1,64 @@
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) tex_coords: vec2<f32>,
+};
+
+struct InstanceInput {
+    @location(5) model_mat_0: vec4<f32>,
+    @location(6) model_mat_1: vec4<f32>,
+    @location(7) model_mat_2: vec4<f32>,
+    @location(8) model_mat_3: vec4<f32>,
+}
+
+struct ShadowUniform {
+    light_view_proj: mat4x4<f32>,
+    enabled: u32,
+};
+
+@group(0) @binding(0)
+var<uniform> shadow: ShadowUniform;
+
+// Only what the alpha test needs from the material bind group
+@group(1) @binding(0)
+var t_diffuse: texture_2d<f32>;
+@group(1) @binding(1)
+var s_diffuse: sampler;
+
+struct MaterialFactors {
+    metallic: f32,
+    roughness: f32,
+    // Diffuse alpha below this is cut out, 0 for opaque materials
+    alpha_cutoff: f32,
+};
+@group(1) @binding(8)
+var<uniform> material: MaterialFactors;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+};
+
+@vertex
+fn vs_main(
+    input: VertexInput,
+    instance: InstanceInput
+) -> VertexOutput {
+    let model_mat = mat4x4(
+        instance.model_mat_0,
+        instance.model_mat_1,
+        instance.model_mat_2,
+        instance.model_mat_3,
+    );
+    var out: VertexOutput;
+    out.clip_position = shadow.light_view_proj * model_mat * vec4<f32>(input.position, 1.0);
+    out.tex_coords = input.tex_coords;
+    return out;
+}
+
+// Only depth is written, except where the material cuts the surface out
+@fragment
+fn fs_main(in: VertexOutput) {
+    if textureSample(t_diffuse, s_diffuse, in.tex_coords).a < material.alpha_cutoff {
+        discard;
+    }
+}
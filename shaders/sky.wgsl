@@ -14,6 +14,27 @@ var env_map: texture_cube<f32>;
 @group(1) @binding(1)
 var env_sampler: sampler;
 
+struct EnvironmentTransform {
+    // Takes world space directions to the environment's own space
+    rotation: mat4x4<f32>,
+    intensity: f32,
+}
+@group(1) @binding(6)
+var<uniform> env_transform: EnvironmentTransform;
+
+// Environment B, faded to by `crossfade.blend`
+@group(2) @binding(0)
+var env_map_b: texture_cube<f32>;
+
+@group(2) @binding(1)
+var env_sampler_b: sampler;
+
+struct Crossfade {
+    blend: f32,
+}
+@group(2) @binding(2)
+var<uniform> crossfade: Crossfade;
+
 struct VertexOutput {
     @builtin(position) frag_position: vec4<f32>,
     @location(0) clip_position: vec4<f32>,
@@ -28,6 +49,7 @@ fn vs_main(
         (id >> 1u) & 1u,
     ));
     var out: VertexOutput;
+    // On the far plane, so only pixels nothing was drawn to pass the depth test
     out.clip_position = vec4(uv * 4.0 - 1.0, 1.0, 1.0);
     out.frag_position = vec4(uv * 4.0 - 1.0, 1.0, 1.0);
     return out;
@@ -38,7 +60,10 @@ fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
     let view_pos_homogeneous = camera.inv_proj * in.clip_position;
     let view_ray_direction = view_pos_homogeneous.xyz / view_pos_homogeneous.w;
     var ray_direction = normalize((camera.inv_view * vec4(view_ray_direction, 0.0)).xyz);
+    ray_direction = (env_transform.rotation * vec4(ray_direction, 0.0)).xyz;
 
-    let sample = textureSample(env_map, env_sampler, ray_direction);
-    return sample;
+    let sample_a = textureSample(env_map, env_sampler, ray_direction);
+    let sample_b = textureSample(env_map_b, env_sampler_b, ray_direction);
+    let sample = mix(sample_a, sample_b, crossfade.blend);
+    return vec4(sample.rgb * env_transform.intensity, sample.a);
 }
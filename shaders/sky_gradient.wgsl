@@ -0,0 +1,47 @@
+struct Camera {
+    view_pos: vec4<f32>,
+    view: mat4x4<f32>,
+    view_proj: mat4x4<f32>,
+    inv_proj: mat4x4<f32>,
+    inv_view: mat4x4<f32>,
+}
+@group(0) @binding(0)
+var<uniform> camera: Camera;
+
+struct SkyColors {
+    top: vec4<f32>,
+    bottom: vec4<f32>,
+}
+@group(1) @binding(0)
+var<uniform> sky: SkyColors;
+
+struct VertexOutput {
+    @builtin(position) frag_position: vec4<f32>,
+    @location(0) clip_position: vec4<f32>,
+}
+
+@vertex
+fn vs_main(
+    @builtin(vertex_index) id: u32,
+) -> VertexOutput {
+    let uv = vec2<f32>(vec2<u32>(
+        id & 1u,
+        (id >> 1u) & 1u,
+    ));
+    var out: VertexOutput;
+    // On the far plane, so only pixels nothing was drawn to pass the depth test
+    out.clip_position = vec4(uv * 4.0 - 1.0, 1.0, 1.0);
+    out.frag_position = vec4(uv * 4.0 - 1.0, 1.0, 1.0);
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let view_pos_homogeneous = camera.inv_proj * in.clip_position;
+    let view_ray_direction = view_pos_homogeneous.xyz / view_pos_homogeneous.w;
+    let ray_direction = normalize((camera.inv_view * vec4(view_ray_direction, 0.0)).xyz);
+
+    // Blend from the bottom color at the nadir to the top color at the zenith
+    let t = ray_direction.y * 0.5 + 0.5;
+    return mix(sky.bottom, sky.top, t);
+}
@@ -0,0 +1,84 @@
+struct Taa {
+    // Takes this frame's clip space back to world space, jitter included
+    inv_view_proj: mat4x4<f32>,
+    // Last frame's projection without jitter
+    prev_view_proj: mat4x4<f32>,
+    // How much of the history is kept, 0 when there is none yet
+    history_weight: f32,
+};
+
+@group(0)
+@binding(0)
+var current_image: texture_2d<f32>;
+
+@group(0)
+@binding(1)
+var history_image: texture_2d<f32>;
+
+@group(0)
+@binding(2)
+var history_sampler: sampler;
+
+@group(0)
+@binding(3)
+var depth_texture: texture_depth_2d;
+
+@group(0)
+@binding(4)
+var<uniform> taa: Taa;
+
+struct VertexOutput {
+    @location(0) uv: vec2<f32>,
+    @builtin(position) clip_position: vec4<f32>,
+};
+
+@vertex
+fn vs_main(
+    @builtin(vertex_index) vi: u32,
+) -> VertexOutput {
+    var out: VertexOutput;
+    // Generate a triangle that covers the whole screen
+    out.uv = vec2<f32>(
+        f32((vi << 1u) & 2u),
+        f32(vi & 2u),
+    );
+    out.clip_position = vec4<f32>(out.uv * 2.0 - 1.0, 0.0, 1.0);
+    out.uv.y = 1.0 - out.uv.y;
+    return out;
+}
+
+@fragment
+fn fs_main(vs: VertexOutput) -> @location(0) vec4<f32> {
+    let size = vec2<i32>(textureDimensions(current_image));
+    let pixel = vec2<i32>(vs.clip_position.xy);
+    let current = textureLoad(current_image, pixel, 0);
+
+    // The history is clamped to the colors around the pixel so that what
+    // was disoccluded or changed doesn't leave a trail
+    var low = current.rgb;
+    var high = current.rgb;
+    for (var y = -1; y <= 1; y++) {
+        for (var x = -1; x <= 1; x++) {
+            let neighbour = clamp(pixel + vec2(x, y), vec2(0), size - 1);
+            let color = textureLoad(current_image, neighbour, 0).rgb;
+            low = min(low, color);
+            high = max(high, color);
+        }
+    }
+
+    // Reproject through the depth to find where the pixel was last frame
+    let depth = textureLoad(depth_texture, pixel, 0);
+    let ndc = vec4(vs.uv.x * 2.0 - 1.0, 1.0 - vs.uv.y * 2.0, depth, 1.0);
+    let world = taa.inv_view_proj * ndc;
+    let prev_clip = taa.prev_view_proj * (world / world.w);
+    let prev_ndc = prev_clip.xy / prev_clip.w;
+    let prev_uv = vec2(prev_ndc.x * 0.5 + 0.5, 0.5 - prev_ndc.y * 0.5);
+
+    var history_weight = taa.history_weight;
+    if any(prev_uv < vec2(0.0)) || any(prev_uv > vec2(1.0)) {
+        history_weight = 0.0;
+    }
+    let history = textureSampleLevel(history_image, history_sampler, prev_uv, 0.0).rgb;
+    let color = mix(current.rgb, clamp(history, low, high), history_weight);
+    return vec4(color, current.a);
+}
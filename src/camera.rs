@@ -1,15 +1,46 @@
-use cgmath::{perspective, InnerSpace, Matrix4, Point3, Rad, SquareMatrix, Vector3};
-use std::f32::consts::FRAC_PI_2;
+use cgmath::{
+    perspective, Angle, InnerSpace, Matrix4, Point3, Rad, SquareMatrix, Vector2, Vector3, Zero,
+};
+use std::collections::HashMap;
+use std::f32::consts::{FRAC_PI_2, PI};
 use std::time::Duration;
 use wgpu::util::DeviceExt;
 use winit::dpi::PhysicalPosition;
 use winit::event::*;
 use winit::keyboard::KeyCode;
 
-const SAFE_FRAC_PI_2: f32 = FRAC_PI_2 - 0.0001;
+use crate::error::{RendererError, RendererResult};
+use crate::model::Aabb;
+use crate::shader::buffer_binding_size;
 
+const SAFE_FRAC_PI_2: f32 = FRAC_PI_2 - 0.0001;
+const MIN_FOVY: Rad<f32> = Rad(0.1);
+const MAX_FOVY: Rad<f32> = Rad(std::f32::consts::PI - 0.1);
+/// Distance moved per pixel of scrolling when dollying, scaled by the speed
+const DOLLY_SCALE: f32 = 0.0025;
+
+/// The camera as shaders see it, at binding 0 of group 1 of the model
+/// shaders. Matrices are column major, `view_pos` has a `w` of 1.
+///
+/// | Offset | Field           | WGSL type     |
+/// |--------|-----------------|---------------|
+/// | 0      | `view_pos`      | `vec4<f32>`   |
+/// | 16     | `view`          | `mat4x4<f32>` |
+/// | 80     | `view_proj`     | `mat4x4<f32>` |
+/// | 144    | `inv_proj`      | `mat4x4<f32>` |
+/// | 208    | `inv_view`      | `mat4x4<f32>` |
+///
+/// 272 bytes in all. `CameraUniform::WGSL` declares a matching struct, for
+/// example to turn a clip space position into a world space ray:
+///
+/// ```wgsl
+/// let view_ray = camera.inv_proj * vec4(clip.xy, 1.0, 1.0);
+/// let world_ray = normalize((camera.inv_view * vec4(view_ray.xyz / view_ray.w, 0.0)).xyz);
+/// let origin = camera.view_pos.xyz;
+/// ```
 #[repr(C)]
 #[derive(Clone, Copy, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+#[cfg_attr(feature = "camera-serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct CameraUniform {
     view_position: [f32; 4],
     view: [[f32; 4]; 4],
@@ -18,6 +49,9 @@ pub struct CameraUniform {
     inv_view: [[f32; 4]; 4],
 }
 
+// The offsets above and `CameraUniform::WGSL` depend on this
+const _: () = assert!(std::mem::size_of::<CameraUniform>() == 272);
+
 impl Default for CameraUniform {
     fn default() -> Self {
         Self {
@@ -31,9 +65,27 @@ impl Default for CameraUniform {
 }
 
 impl CameraUniform {
-    pub fn update_view_projection(&mut self, camera: &Camera, projection: &Projection) {
+    /// WGSL declaration of the struct and its binding in the model shaders
+    pub const WGSL: &'static str = "struct CameraUniform {
+    view_pos: vec4<f32>,
+    view: mat4x4<f32>,
+    view_proj: mat4x4<f32>,
+    inv_proj: mat4x4<f32>,
+    inv_view: mat4x4<f32>,
+};
+@group(1) @binding(0)
+var<uniform> camera: CameraUniform;
+";
+
+    /// `jitter` offsets the projection in normalized device coordinates
+    pub fn update_view_projection(
+        &mut self,
+        camera: &Camera,
+        projection: &Projection,
+        jitter: Vector2<f32>,
+    ) {
         self.view_position = camera.position.to_homogeneous().into();
-        let proj = projection.calc_matrix();
+        let proj = Matrix4::from_translation(jitter.extend(0.0)) * projection.calc_matrix();
         let view = camera.calc_matrix();
         let view_proj = proj * view;
         self.view = view.into();
@@ -44,8 +96,15 @@ impl CameraUniform {
             .into();
         self.inv_view = view.invert().expect("View matrix was singular!").into();
     }
+
+    /// The view and view projection matrices
+    pub fn matrices(&self) -> (Matrix4<f32>, Matrix4<f32>) {
+        (self.view.into(), self.view_proj.into())
+    }
 }
 
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "camera-serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Camera {
     position: Point3<f32>,
     yaw: Rad<f32>,
@@ -65,18 +124,107 @@ impl Camera {
         }
     }
 
-    pub fn calc_matrix(&self) -> Matrix4<f32> {
+    pub fn position(&self) -> Point3<f32> {
+        self.position
+    }
+
+    pub fn yaw(&self) -> Rad<f32> {
+        self.yaw
+    }
+
+    pub fn pitch(&self) -> Rad<f32> {
+        self.pitch
+    }
+
+    /// Unit vector the camera is looking along
+    pub fn direction(&self) -> Vector3<f32> {
         let (sin_pitch, cos_pitch) = self.pitch.0.sin_cos();
         let (sin_yaw, cos_yaw) = self.yaw.0.sin_cos();
+        Vector3::new(cos_pitch * cos_yaw, sin_pitch, cos_pitch * sin_yaw).normalize()
+    }
+
+    pub fn calc_matrix(&self) -> Matrix4<f32> {
+        Matrix4::look_to_rh(self.position, self.direction(), Vector3::unit_y())
+    }
 
-        Matrix4::look_to_rh(
-            self.position,
-            Vector3::new(cos_pitch * cos_yaw, sin_pitch, cos_pitch * sin_yaw).normalize(),
-            Vector3::unit_y(),
-        )
+    /// A camera `distance` away from `target`, looking at it along `view`'s
+    /// axis
+    pub fn along_axis(view: AxisView, target: Point3<f32>, distance: f32) -> Self {
+        let (yaw, pitch) = view.orientation();
+        let mut camera = Self::new(target, yaw, pitch);
+        camera.position = target - camera.direction() * distance;
+        camera
     }
 }
 
+/// Views looking along one of the world axes, like the numpad views of
+/// modeling tools
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AxisView {
+    /// Looking along -Z
+    Front,
+    Back,
+    /// Looking along +X
+    Left,
+    Right,
+    /// Looking down along -Y
+    Top,
+    Bottom,
+}
+
+impl AxisView {
+    pub fn all() -> impl ExactSizeIterator<Item = Self> {
+        [
+            Self::Front,
+            Self::Back,
+            Self::Left,
+            Self::Right,
+            Self::Top,
+            Self::Bottom,
+        ]
+        .iter()
+        .copied()
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Front => "Front",
+            Self::Back => "Back",
+            Self::Left => "Left",
+            Self::Right => "Right",
+            Self::Top => "Top",
+            Self::Bottom => "Bottom",
+        }
+    }
+
+    /// Numpad 1, 3 and 7 give the front, right and top views, and the
+    /// opposite views with control held
+    pub fn for_numpad_key(key: KeyCode, control: bool) -> Option<Self> {
+        let (view, opposite) = match key {
+            KeyCode::Numpad1 => (Self::Front, Self::Back),
+            KeyCode::Numpad3 => (Self::Right, Self::Left),
+            KeyCode::Numpad7 => (Self::Top, Self::Bottom),
+            _ => return None,
+        };
+        Some(if control { opposite } else { view })
+    }
+
+    /// Yaw and pitch looking along the view's axis. Top and bottom stop just
+    /// short of straight down and up, like mouse look.
+    fn orientation(&self) -> (Rad<f32>, Rad<f32>) {
+        match self {
+            Self::Front => (Rad(-FRAC_PI_2), Rad(0.0)),
+            Self::Back => (Rad(FRAC_PI_2), Rad(0.0)),
+            Self::Left => (Rad(0.0), Rad(0.0)),
+            Self::Right => (Rad(PI), Rad(0.0)),
+            Self::Top => (Rad(-FRAC_PI_2), Rad(-SAFE_FRAC_PI_2)),
+            Self::Bottom => (Rad(-FRAC_PI_2), Rad(SAFE_FRAC_PI_2)),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "camera-serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Projection {
     aspect: f32,
     fovy: Rad<f32>,
@@ -98,15 +246,52 @@ impl Projection {
         self.aspect = width as f32 / height as f32;
     }
 
+    pub fn fovy(&self) -> Rad<f32> {
+        self.fovy
+    }
+
+    /// Clamped to a range that keeps the projection invertible
+    pub fn set_fovy<F: Into<Rad<f32>>>(&mut self, fovy: F) {
+        self.fovy = Rad(fovy.into().0.clamp(MIN_FOVY.0, MAX_FOVY.0));
+    }
+
+    pub fn znear(&self) -> f32 {
+        self.znear
+    }
+
+    pub fn zfar(&self) -> f32 {
+        self.zfar
+    }
+
+    /// Fails without changing anything unless `0 < znear < zfar`
+    pub fn set_clip_planes(&mut self, znear: f32, zfar: f32) -> RendererResult<()> {
+        if !(znear > 0.0 && znear < zfar) {
+            return Err(RendererError::InvalidClipPlanes { znear, zfar });
+        }
+        self.znear = znear;
+        self.zfar = zfar;
+        Ok(())
+    }
+
     pub fn calc_matrix(&self) -> cgmath::Matrix4<f32> {
         perspective(self.fovy, self.aspect, self.znear, self.zfar)
     }
+
+    /// How far away a sphere of `radius` has to be to fit in the view both
+    /// ways
+    pub fn fit_distance(&self, radius: f32) -> f32 {
+        let half_fovy = self.fovy.0 / 2.0;
+        let half_fovx = (half_fovy.tan() * self.aspect).atan();
+        radius / half_fovy.min(half_fovx).sin()
+    }
 }
 
 pub struct PerspectiveCamera {
     camera: Camera,
     projection: Projection,
     uniform: CameraUniform,
+    /// Sub-pixel offset of the projection, used for temporal anti-aliasing
+    jitter: Vector2<f32>,
     buffer: wgpu::Buffer,
     layout: wgpu::BindGroupLayout,
     bind_group: wgpu::BindGroup,
@@ -115,7 +300,7 @@ pub struct PerspectiveCamera {
 impl PerspectiveCamera {
     pub fn new(device: &wgpu::Device, camera: Camera, projection: Projection) -> Self {
         let mut uniform = CameraUniform::default();
-        uniform.update_view_projection(&camera, &projection);
+        uniform.update_view_projection(&camera, &projection, Vector2::zero());
 
         let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("Camera Buffer"),
@@ -138,6 +323,7 @@ impl PerspectiveCamera {
             camera,
             projection,
             uniform,
+            jitter: Vector2::zero(),
             buffer,
             layout,
             bind_group,
@@ -148,13 +334,78 @@ impl PerspectiveCamera {
         self.projection.resize(width, height);
     }
 
-    pub fn update(&mut self, controller: &mut CameraController, dt: Duration, queue: &wgpu::Queue) {
-        controller.update_camera(&mut self.camera, &mut self.projection, dt);
+    /// Changes the near and far planes, updating the projection matrices
+    /// right away
+    pub fn set_clip_planes(
+        &mut self,
+        queue: &wgpu::Queue,
+        znear: f32,
+        zfar: f32,
+    ) -> RendererResult<()> {
+        self.projection.set_clip_planes(znear, zfar)?;
+        self.write_uniform(queue);
+        Ok(())
+    }
+
+    pub fn set_fovy<F: Into<Rad<f32>>>(&mut self, queue: &wgpu::Queue, fovy: F) {
+        self.projection.set_fovy(fovy);
+        self.write_uniform(queue);
+    }
+
+    /// Moves the camera straight to `position`, looking along `yaw` and
+    /// `pitch`. The pitch is clamped like it is for mouse look.
+    pub fn set_pose<Y: Into<Rad<f32>>, P: Into<Rad<f32>>>(
+        &mut self,
+        queue: &wgpu::Queue,
+        position: Point3<f32>,
+        yaw: Y,
+        pitch: P,
+    ) {
+        self.camera.position = position;
+        self.camera.yaw = yaw.into().normalize_signed();
+        self.camera.pitch = Rad(pitch.into().0.clamp(-SAFE_FRAC_PI_2, SAFE_FRAC_PI_2));
+        self.write_uniform(queue);
+    }
+
+    /// Looks at the center of `bounds` along `view`'s axis, far enough away
+    /// to see all of it
+    pub fn snap_to_axis(&mut self, queue: &wgpu::Queue, view: AxisView, bounds: &Aabb) {
+        let (center, radius) = bounds.bounding_sphere();
+        let radius = if radius > 0.0 { radius } else { 1.0 };
+        let camera = Camera::along_axis(view, center, self.projection.fit_distance(radius));
+        self.set_pose(queue, camera.position, camera.yaw, camera.pitch);
+    }
+
+    /// Uploads the matrices, which `resize` leaves to the next update
+    pub fn write_uniform(&mut self, queue: &wgpu::Queue) {
         self.uniform
-            .update_view_projection(&self.camera, &self.projection);
+            .update_view_projection(&self.camera, &self.projection, self.jitter);
         queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[self.uniform]));
     }
 
+    /// Offsets the projection by `jitter` in normalized device coordinates
+    pub fn set_jitter(&mut self, queue: &wgpu::Queue, jitter: Vector2<f32>) {
+        if self.jitter != jitter {
+            self.jitter = jitter;
+            self.write_uniform(queue);
+        }
+    }
+
+    /// The view projection matrix the shaders see, including any jitter
+    pub fn view_proj(&self) -> Matrix4<f32> {
+        self.uniform.view_proj.into()
+    }
+
+    /// The view projection matrix without jitter
+    pub fn unjittered_view_proj(&self) -> Matrix4<f32> {
+        self.projection.calc_matrix() * self.camera.calc_matrix()
+    }
+
+    pub fn update(&mut self, controller: &mut CameraController, dt: Duration, queue: &wgpu::Queue) {
+        controller.update_camera(&mut self.camera, &mut self.projection, dt);
+        self.write_uniform(queue);
+    }
+
     pub const fn layout_desc() -> wgpu::BindGroupLayoutDescriptor<'static> {
         const LAYOUT_ENTRIES: [wgpu::BindGroupLayoutEntry; 1] = [wgpu::BindGroupLayoutEntry {
             binding: 0,
@@ -162,7 +413,7 @@ impl PerspectiveCamera {
             ty: wgpu::BindingType::Buffer {
                 ty: wgpu::BufferBindingType::Uniform,
                 has_dynamic_offset: false,
-                min_binding_size: None,
+                min_binding_size: buffer_binding_size::<CameraUniform>(),
             },
             count: None,
         }];
@@ -172,6 +423,14 @@ impl PerspectiveCamera {
         }
     }
 
+    pub fn camera(&self) -> &Camera {
+        &self.camera
+    }
+
+    pub fn projection(&self) -> &Projection {
+        &self.projection
+    }
+
     pub fn bind_group(&self) -> &wgpu::BindGroup {
         &self.bind_group
     }
@@ -181,6 +440,104 @@ impl PerspectiveCamera {
     }
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum CameraAction {
+    Forward,
+    Backward,
+    Left,
+    Right,
+    Up,
+    Down,
+}
+
+impl CameraAction {
+    pub fn all() -> impl ExactSizeIterator<Item = Self> {
+        [
+            Self::Forward,
+            Self::Backward,
+            Self::Left,
+            Self::Right,
+            Self::Up,
+            Self::Down,
+        ]
+        .iter()
+        .copied()
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Forward => "Forward",
+            Self::Backward => "Backward",
+            Self::Left => "Left",
+            Self::Right => "Right",
+            Self::Up => "Up",
+            Self::Down => "Down",
+        }
+    }
+}
+
+/// Maps camera movement actions to the keys that trigger them.
+#[derive(Clone, Debug)]
+pub struct KeyBindings {
+    bindings: HashMap<CameraAction, Vec<KeyCode>>,
+}
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        Self {
+            bindings: HashMap::from([
+                (CameraAction::Forward, vec![KeyCode::KeyW, KeyCode::ArrowUp]),
+                (
+                    CameraAction::Backward,
+                    vec![KeyCode::KeyS, KeyCode::ArrowDown],
+                ),
+                (CameraAction::Left, vec![KeyCode::KeyA, KeyCode::ArrowLeft]),
+                (
+                    CameraAction::Right,
+                    vec![KeyCode::KeyD, KeyCode::ArrowRight],
+                ),
+                (CameraAction::Up, vec![KeyCode::Space]),
+                (CameraAction::Down, vec![KeyCode::ShiftLeft]),
+            ]),
+        }
+    }
+}
+
+impl KeyBindings {
+    pub fn action_for(&self, key: KeyCode) -> Option<CameraAction> {
+        self.bindings
+            .iter()
+            .find(|(_, keys)| keys.contains(&key))
+            .map(|(action, _)| *action)
+    }
+
+    pub fn keys_for(&self, action: CameraAction) -> &[KeyCode] {
+        self.bindings
+            .get(&action)
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    /// Replaces all keys bound to `action` with `key`, unbinding `key` from
+    /// any other action.
+    pub fn bind(&mut self, action: CameraAction, key: KeyCode) {
+        for keys in self.bindings.values_mut() {
+            keys.retain(|k| *k != key);
+        }
+        self.bindings.insert(action, vec![key]);
+    }
+}
+
+/// What scrolling the mouse wheel does
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ZoomMode {
+    /// Moves the camera along the direction it's looking
+    #[default]
+    Dolly,
+    /// Changes the field of view, which distorts the perspective
+    Fov,
+}
+
 pub struct CameraController {
     amount_left: f32,
     amount_right: f32,
@@ -191,12 +548,24 @@ pub struct CameraController {
     rotate_horizontal: f32,
     rotate_vertical: f32,
     scroll: f32,
+    zoom_mode: ZoomMode,
     speed: f32,
     sensitivity: f32,
+    invert_y: bool,
+    /// Pixels the mouse has to move after being pressed before it rotates
+    /// the camera
+    drag_threshold: f32,
+    /// Distance moved since `begin_drag`
+    drag_distance: f32,
+    key_bindings: KeyBindings,
 }
 
 impl CameraController {
     pub fn new(speed: f32, sensitivity: f32) -> Self {
+        Self::with_key_bindings(speed, sensitivity, KeyBindings::default())
+    }
+
+    pub fn with_key_bindings(speed: f32, sensitivity: f32, key_bindings: KeyBindings) -> Self {
         Self {
             amount_left: 0.0,
             amount_right: 0.0,
@@ -207,49 +576,90 @@ impl CameraController {
             rotate_horizontal: 0.0,
             rotate_vertical: 0.0,
             scroll: 0.0,
+            zoom_mode: ZoomMode::default(),
             speed,
             sensitivity,
+            invert_y: false,
+            drag_threshold: 0.0,
+            drag_distance: 0.0,
+            key_bindings,
         }
     }
 
+    pub fn key_bindings(&self) -> &KeyBindings {
+        &self.key_bindings
+    }
+
+    pub fn key_bindings_mut(&mut self) -> &mut KeyBindings {
+        &mut self.key_bindings
+    }
+
     pub fn process_keyboard(&mut self, key: KeyCode, state: ElementState) -> bool {
         let amount = if state == ElementState::Pressed {
             1.0
         } else {
             0.0
         };
-        match key {
-            KeyCode::KeyW | KeyCode::ArrowUp => {
-                self.amount_forward = amount;
-                true
-            }
-            KeyCode::KeyS | KeyCode::ArrowDown => {
-                self.amount_backward = amount;
-                true
-            }
-            KeyCode::KeyD | KeyCode::ArrowRight => {
-                self.amount_right = amount;
-                true
-            }
-            KeyCode::KeyA | KeyCode::ArrowLeft => {
-                self.amount_left = amount;
-                true
-            }
-            KeyCode::Space => {
-                self.amount_up = amount;
-                true
-            }
-            KeyCode::ShiftLeft => {
-                self.amount_down = amount;
-                true
-            }
-            _ => false,
+        let Some(action) = self.key_bindings.action_for(key) else {
+            return false;
+        };
+        match action {
+            CameraAction::Forward => self.amount_forward = amount,
+            CameraAction::Backward => self.amount_backward = amount,
+            CameraAction::Right => self.amount_right = amount,
+            CameraAction::Left => self.amount_left = amount,
+            CameraAction::Up => self.amount_up = amount,
+            CameraAction::Down => self.amount_down = amount,
         }
+        true
+    }
+
+    pub fn zoom_mode(&self) -> ZoomMode {
+        self.zoom_mode
+    }
+
+    pub fn set_zoom_mode(&mut self, zoom_mode: ZoomMode) {
+        self.zoom_mode = zoom_mode;
+    }
+
+    pub fn invert_y(&self) -> bool {
+        self.invert_y
+    }
+
+    pub fn set_invert_y(&mut self, invert_y: bool) {
+        self.invert_y = invert_y;
     }
 
-    pub fn process_mouse(&mut self, mouse_dx: f64, mouse_dy: f64) {
+    pub fn drag_threshold(&self) -> f32 {
+        self.drag_threshold
+    }
+
+    pub fn set_drag_threshold(&mut self, drag_threshold: f32) {
+        self.drag_threshold = drag_threshold.max(0.0);
+    }
+
+    /// Call when the mouse button is pressed, so the drag threshold is
+    /// measured from there
+    pub fn begin_drag(&mut self) {
+        self.drag_distance = 0.0;
+    }
+
+    /// Returns whether the motion rotated the camera, which it doesn't until
+    /// the drag has moved further than the drag threshold
+    pub fn process_mouse(&mut self, mouse_dx: f64, mouse_dy: f64) -> bool {
+        if self.drag_distance < self.drag_threshold {
+            self.drag_distance += (mouse_dx * mouse_dx + mouse_dy * mouse_dy).sqrt() as f32;
+            if self.drag_distance < self.drag_threshold {
+                return false;
+            }
+        }
         self.rotate_horizontal = mouse_dx as f32;
-        self.rotate_vertical = mouse_dy as f32;
+        self.rotate_vertical = if self.invert_y {
+            -mouse_dy as f32
+        } else {
+            mouse_dy as f32
+        };
+        true
     }
 
     pub fn process_scroll(&mut self, delta: &MouseScrollDelta) {
@@ -275,13 +685,11 @@ impl CameraController {
         camera.position += forward * (self.amount_forward - self.amount_backward) * self.speed * dt;
         camera.position += right * (self.amount_right - self.amount_left) * self.speed * dt;
 
-        const MIN_FOVY: Rad<f32> = Rad(0.1);
-        const MAX_FOVY: Rad<f32> = Rad(std::f32::consts::PI - 0.1);
-        projection.fovy += Rad(-self.scroll / 1_000.0);
-        if projection.fovy <= MIN_FOVY {
-            projection.fovy = MIN_FOVY;
-        } else if projection.fovy >= MAX_FOVY {
-            projection.fovy = MAX_FOVY;
+        match self.zoom_mode {
+            ZoomMode::Dolly => {
+                camera.position += camera.direction() * -self.scroll * self.speed * DOLLY_SCALE;
+            }
+            ZoomMode::Fov => projection.set_fovy(projection.fovy + Rad(-self.scroll / 1_000.0)),
         }
         self.scroll = 0.0;
 
@@ -291,6 +699,8 @@ impl CameraController {
         // Rotate
         camera.yaw += Rad(self.rotate_horizontal) * self.sensitivity * dt;
         camera.pitch += Rad(-self.rotate_vertical) * self.sensitivity * dt;
+        // Kept in [-PI, PI] so the angle doesn't grow and lose precision
+        camera.yaw = camera.yaw.normalize_signed();
 
         // Reset to prevent accidentaly rotation
         self.rotate_horizontal = 0.0;
@@ -303,3 +713,64 @@ impl CameraController {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shader::reflect_wgsl;
+    use cgmath::Deg;
+    use std::f32::consts::PI;
+    use std::mem::offset_of;
+
+    #[test]
+    fn camera_uniform_layout_matches_wgsl() {
+        assert_eq!(offset_of!(CameraUniform, view_position), 0);
+        assert_eq!(offset_of!(CameraUniform, view), 16);
+        assert_eq!(offset_of!(CameraUniform, view_proj), 80);
+        assert_eq!(offset_of!(CameraUniform, inv_proj), 144);
+        assert_eq!(offset_of!(CameraUniform, inv_view), 208);
+
+        // Reflection needs an entry point and bind groups numbered from 0
+        let source = format!(
+            "{}
+@group(0) @binding(0)
+var<uniform> tint: vec4<f32>;
+
+@fragment
+fn fs_main() -> @location(0) vec4<f32> {{
+    return camera.view_pos * tint;
+}}
+",
+            CameraUniform::WGSL
+        );
+        let reflection = reflect_wgsl(&source).expect("CameraUniform::WGSL should parse");
+        let uniform = reflection
+            .uniforms
+            .iter()
+            .find(|u| u.group == 1 && u.binding == 0)
+            .expect("CameraUniform::WGSL should declare the camera uniform");
+        assert_eq!(uniform.size, 272);
+    }
+
+    #[test]
+    fn update_camera_wraps_yaw() {
+        let mut camera = Camera::new((0.0, 0.0, 0.0), Deg(0.0), Deg(0.0));
+        let mut projection = Projection::new(800, 600, Deg(45.0), 0.1, 100.0);
+        let mut controller = CameraController::new(1.0, 1.0);
+        let dt = Duration::from_millis(100);
+        // Several full turns each way, with steps that don't divide a turn
+        let mut unwrapped = 0.0f64;
+        for step in 0..2_000 {
+            let dx = if step < 1_000 { 7.3 } else { -11.9 };
+            assert!(controller.process_mouse(dx, 0.0));
+            controller.update_camera(&mut camera, &mut projection, dt);
+            unwrapped += dx * dt.as_secs_f64();
+
+            let yaw = camera.yaw().0;
+            assert!((-PI..=PI).contains(&yaw), "yaw {} out of range", yaw);
+            let (sin, cos) = camera.yaw().sin_cos();
+            let error = (sin - unwrapped.sin() as f32).abs() + (cos - unwrapped.cos() as f32).abs();
+            assert!(error < 1e-3, "step {}: yaw is off by {}", step, error);
+        }
+    }
+}
@@ -0,0 +1,132 @@
+use cgmath::{EuclideanSpace, InnerSpace, Point3, Quaternion, Rad, Rotation, Rotation3, Vector3};
+
+use crate::camera::Camera;
+
+/// Where the camera is and where it looks at a point along a `CameraPath`
+#[derive(Clone, Debug)]
+pub struct CameraKeyframe {
+    /// Seconds from the start of the path
+    pub time: f32,
+    pub position: Point3<f32>,
+    pub yaw: Rad<f32>,
+    pub pitch: Rad<f32>,
+}
+
+impl CameraKeyframe {
+    pub fn from_camera(time: f32, camera: &Camera) -> Self {
+        Self {
+            time,
+            position: camera.position(),
+            yaw: camera.yaw(),
+            pitch: camera.pitch(),
+        }
+    }
+
+    /// Rotation taking the x axis to the direction the camera looks along
+    fn orientation(&self) -> Quaternion<f32> {
+        Quaternion::from_angle_y(-self.yaw) * Quaternion::from_angle_z(self.pitch)
+    }
+}
+
+/// Keyframes the camera can be flown through, in time order. Positions are
+/// joined by a Catmull-Rom spline and orientations are slerped.
+#[derive(Clone, Debug, Default)]
+pub struct CameraPath {
+    keyframes: Vec<CameraKeyframe>,
+}
+
+impl CameraPath {
+    pub fn keyframes(&self) -> &[CameraKeyframe] {
+        &self.keyframes
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keyframes.is_empty()
+    }
+
+    /// Time of the last keyframe, 0 without any
+    pub fn duration(&self) -> f32 {
+        self.keyframes.last().map_or(0.0, |keyframe| keyframe.time)
+    }
+
+    /// Inserts `keyframe`, keeping the keyframes in time order
+    pub fn add(&mut self, keyframe: CameraKeyframe) {
+        let index = self
+            .keyframes
+            .partition_point(|other| other.time <= keyframe.time);
+        self.keyframes.insert(index, keyframe);
+    }
+
+    pub fn remove(&mut self, index: usize) -> CameraKeyframe {
+        self.keyframes.remove(index)
+    }
+
+    /// Moves the keyframe at `index` to `time`, which may reorder it
+    pub fn set_time(&mut self, index: usize, time: f32) {
+        let mut keyframe = self.keyframes.remove(index);
+        keyframe.time = time;
+        self.add(keyframe);
+    }
+
+    pub fn clear(&mut self) {
+        self.keyframes.clear();
+    }
+
+    /// The camera at `time`, holding still before the first keyframe and
+    /// after the last. `None` if there are no keyframes.
+    pub fn evaluate(&self, time: f32) -> Option<Camera> {
+        let last = self.keyframes.len().checked_sub(1)?;
+        // Index of the keyframe that starts the segment `time` is in
+        let index = self
+            .keyframes
+            .partition_point(|keyframe| keyframe.time <= time)
+            .saturating_sub(1)
+            .min(last.saturating_sub(1));
+        let start = &self.keyframes[index];
+        let end = &self.keyframes[(index + 1).min(last)];
+        let span = end.time - start.time;
+        let t = if span > 0.0 {
+            ((time - start.time) / span).clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+
+        let before = &self.keyframes[index.saturating_sub(1)];
+        let after = &self.keyframes[(index + 2).min(last)];
+        let position = catmull_rom(
+            before.position,
+            start.position,
+            end.position,
+            after.position,
+            t,
+        );
+
+        let direction = start
+            .orientation()
+            .slerp(end.orientation(), t)
+            .rotate_vector(Vector3::unit_x())
+            .normalize();
+        let yaw = Rad(direction.z.atan2(direction.x));
+        let pitch = Rad(direction.y.clamp(-1.0, 1.0).asin());
+        Some(Camera::new(position, yaw, pitch))
+    }
+}
+
+/// Point `t` of the way from `p1` to `p2` on a uniform Catmull-Rom spline
+fn catmull_rom(
+    p0: Point3<f32>,
+    p1: Point3<f32>,
+    p2: Point3<f32>,
+    p3: Point3<f32>,
+    t: f32,
+) -> Point3<f32> {
+    let (p0, p1, p2, p3) = (p0.to_vec(), p1.to_vec(), p2.to_vec(), p3.to_vec());
+    let t2 = t * t;
+    let t3 = t2 * t;
+    let point = (p1 * 2.0
+        + (p2 - p0) * t
+        + (p0 * 2.0 - p1 * 5.0 + p2 * 4.0 - p3) * t2
+        + (p1 * 3.0 - p0 - p2 * 3.0 + p3) * t3)
+        * 0.5;
+    Point3::from_vec(point)
+}
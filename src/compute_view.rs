@@ -0,0 +1,376 @@
+use std::time::Duration;
+
+use wgpu::{util::DeviceExt, Operations};
+
+use crate::{
+    error::{RendererError, RendererResult},
+    pipeline::{ComputePipeline, PipelineCreateInfo, RenderPipeline},
+    shader::{ComputeShader, ReflectedBinding, Shader},
+    texture,
+};
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct ComputeParamsUniform {
+    size: [u32; 2],
+    frame: u32,
+    time: f32,
+}
+
+/// Checks that the compute shader writes a 2D storage texture at group 0,
+/// binding 0, and returns that texture's format along with whether the
+/// shader also reads the params uniform at binding 1
+fn validate_layout(bindings: &[ReflectedBinding]) -> RendererResult<(wgpu::TextureFormat, bool)> {
+    let mut format = None;
+    let mut has_params = false;
+    for reflected in bindings {
+        let name = reflected.name.as_deref().unwrap_or("<unnamed>");
+        if reflected.group != 0 {
+            return Err(RendererError::ShaderCompile(format!(
+                "{} is in bind group {}, compute shaders must only use bind group 0",
+                name, reflected.group
+            )));
+        }
+        match (reflected.binding, reflected.ty) {
+            (
+                0,
+                wgpu::BindingType::StorageTexture {
+                    access: wgpu::StorageTextureAccess::WriteOnly,
+                    format: f,
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                },
+            ) => format = Some(f),
+            (
+                1,
+                wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    min_binding_size,
+                    ..
+                },
+            ) => {
+                // The layout is created from the reflection, so a bigger
+                // struct would only fail when the params buffer is bound
+                let provided = std::mem::size_of::<ComputeParamsUniform>() as u64;
+                if let Some(required) = min_binding_size.filter(|size| size.get() > provided) {
+                    return Err(RendererError::ShaderCompile(format!(
+                        "Params uniform {} at binding 1 is {} bytes, but only {} bytes are provided (size: vec2<u32>, frame: u32, time: f32)",
+                        name, required, provided
+                    )));
+                }
+                has_params = true;
+            }
+            (binding, ty) => {
+                return Err(RendererError::ShaderCompile(format!(
+                    "Unexpected binding {} ({}) of type {:?} in compute shader",
+                    binding, name, ty
+                )))
+            }
+        }
+    }
+    let Some(format) = format else {
+        return Err(RendererError::ShaderCompile(
+            "Compute shader must write to a texture_storage_2d at binding 0".into(),
+        ));
+    };
+    match format.sample_type(None, None) {
+        Some(wgpu::TextureSampleType::Float { .. }) => Ok((format, has_params)),
+        _ => Err(RendererError::ShaderCompile(format!(
+            "Compute output format {:?} can't be displayed, use a float format",
+            format
+        ))),
+    }
+}
+
+/// Runs a user compute shader every frame and draws the texture it writes to
+/// the screen.
+///
+/// The shader must declare `texture_storage_2d<format, write>` at group 0,
+/// binding 0. It may also declare a uniform at binding 1, which is filled
+/// with the output size, the frame index and the elapsed time in seconds.
+pub struct ComputeView {
+    shader: ComputeShader,
+    pipeline: ComputePipeline,
+    compute_layout: wgpu::BindGroupLayout,
+    compute_bind_group: wgpu::BindGroup,
+    params_buffer: Option<wgpu::Buffer>,
+    output: texture::Texture,
+    format: wgpu::TextureFormat,
+    blit_pipeline: RenderPipeline,
+    blit_layout: wgpu::BindGroupLayout,
+    blit_bind_group: wgpu::BindGroup,
+    frame: u32,
+    time: Duration,
+    /// Workgroups to dispatch when `auto_workgroup_count` is off
+    pub workgroup_count: [u32; 3],
+    /// Dispatch enough workgroups to cover the whole output texture
+    pub auto_workgroup_count: bool,
+    pub show_output: bool,
+}
+
+impl ComputeView {
+    pub fn new(
+        device: &wgpu::Device,
+        name: &str,
+        source: &str,
+        width: u32,
+        height: u32,
+        color_format: wgpu::TextureFormat,
+    ) -> RendererResult<Self> {
+        let shader = ComputeShader::new_wgsl(device, name, source)?;
+        let (format, has_params) = validate_layout(&shader.reflected_groups())?;
+        let layout_descs = shader.get_layout();
+
+        let compute_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("ComputeView::compute_layout"),
+            entries: layout_descs[0].entries,
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: None,
+            bind_group_layouts: &[&compute_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = ComputePipeline::new(
+            device,
+            pipeline_layout,
+            &shader,
+            Some("Compute View Pipeline"),
+        )?;
+
+        let params_buffer = has_params.then(|| {
+            device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("ComputeView::params_buffer"),
+                contents: bytemuck::cast_slice(&[ComputeParamsUniform {
+                    size: [width, height],
+                    frame: 0,
+                    time: 0.0,
+                }]),
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            })
+        });
+
+        let output = Self::create_output(device, width, height, format);
+
+        let blit_desc = wgpu::BindGroupLayoutDescriptor {
+            label: Some("ComputeView::blit_layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                count: None,
+            }],
+        };
+        let blit_layout = device.create_bind_group_layout(&blit_desc);
+
+        let blit_shader = Self::create_blit_shader(device);
+        assert_eq!(blit_shader.layout_matches(&[&blit_desc]), Ok(()));
+        let blit_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: None,
+            bind_group_layouts: &[&blit_layout],
+            push_constant_ranges: &[],
+        });
+        let blit_pipeline = RenderPipeline::new(
+            device,
+            blit_pipeline_layout,
+            Self::blit_create_info(&blit_shader, color_format),
+        );
+
+        let compute_bind_group =
+            Self::create_compute_bind_group(device, &compute_layout, &output, &params_buffer);
+        let blit_bind_group = Self::create_blit_bind_group(device, &blit_layout, &output);
+
+        let mut compute_view = Self {
+            shader,
+            pipeline,
+            compute_layout,
+            compute_bind_group,
+            params_buffer,
+            output,
+            format,
+            blit_pipeline,
+            blit_layout,
+            blit_bind_group,
+            frame: 0,
+            time: Duration::ZERO,
+            workgroup_count: [1, 1, 1],
+            auto_workgroup_count: true,
+            show_output: true,
+        };
+        compute_view.workgroup_count = compute_view.covering_workgroup_count();
+        Ok(compute_view)
+    }
+
+    fn create_blit_shader(device: &wgpu::Device) -> Shader {
+        Shader::new_wgsl(
+            device,
+            "blit",
+            include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/shaders/blit.wgsl")),
+        )
+        .expect("Could not parse blit shader")
+    }
+
+    fn blit_create_info(
+        shader: &Shader,
+        color_format: wgpu::TextureFormat,
+    ) -> PipelineCreateInfo<'_> {
+        PipelineCreateInfo {
+            color_format,
+            depth_format: None,
+            depth_write: true,
+            vertex_layouts: &[],
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            cull_mode: Some(wgpu::Face::Back),
+            blend: Some(wgpu::BlendState::REPLACE),
+            shader,
+            label: Some("Compute View Blit Pipeline"),
+        }
+    }
+
+    /// Rebuilds the blit pipeline to draw into a target of a new format
+    pub fn set_color_format(&mut self, device: &wgpu::Device, color_format: wgpu::TextureFormat) {
+        let shader = Self::create_blit_shader(device);
+        self.blit_pipeline
+            .recreate(device, Self::blit_create_info(&shader, color_format))
+            .expect("Could not recreate compute view blit pipeline");
+    }
+
+    fn create_output(
+        device: &wgpu::Device,
+        width: u32,
+        height: u32,
+        format: wgpu::TextureFormat,
+    ) -> texture::Texture {
+        texture::Texture::create_2d_texture(
+            device,
+            width,
+            height,
+            format,
+            wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::TEXTURE_BINDING,
+            wgpu::FilterMode::Nearest,
+            Some("ComputeView::output"),
+        )
+    }
+
+    fn create_compute_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        output: &texture::Texture,
+        params_buffer: &Option<wgpu::Buffer>,
+    ) -> wgpu::BindGroup {
+        let mut entries = vec![wgpu::BindGroupEntry {
+            binding: 0,
+            resource: wgpu::BindingResource::TextureView(&output.view),
+        }];
+        if let Some(params_buffer) = params_buffer {
+            entries.push(wgpu::BindGroupEntry {
+                binding: 1,
+                resource: params_buffer.as_entire_binding(),
+            });
+        }
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("ComputeView::compute_bind_group"),
+            layout,
+            entries: &entries,
+        })
+    }
+
+    fn create_blit_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        output: &texture::Texture,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("ComputeView::blit_bind_group"),
+            layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(&output.view),
+            }],
+        })
+    }
+
+    /// The smallest workgroup count that covers every texel of the output
+    fn covering_workgroup_count(&self) -> [u32; 3] {
+        let [x, y, _] = self.shader.workgroup_size();
+        [
+            self.output.size.width.div_ceil(x.max(1)),
+            self.output.size.height.div_ceil(y.max(1)),
+            1,
+        ]
+    }
+
+    pub fn name(&self) -> &str {
+        self.shader.name()
+    }
+
+    pub fn workgroup_size(&self) -> [u32; 3] {
+        self.shader.workgroup_size()
+    }
+
+    /// Must be called whenever the render target is resized
+    pub fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
+        self.output = Self::create_output(device, width, height, self.format);
+        self.compute_bind_group = Self::create_compute_bind_group(
+            device,
+            &self.compute_layout,
+            &self.output,
+            &self.params_buffer,
+        );
+        self.blit_bind_group =
+            Self::create_blit_bind_group(device, &self.blit_layout, &self.output);
+    }
+
+    pub fn update(&mut self, queue: &wgpu::Queue, dt: Duration) {
+        self.time += dt;
+        if let Some(params_buffer) = &self.params_buffer {
+            let size = self.output.size;
+            queue.write_buffer(
+                params_buffer,
+                0,
+                bytemuck::cast_slice(&[ComputeParamsUniform {
+                    size: [size.width, size.height],
+                    frame: self.frame,
+                    time: self.time.as_secs_f32(),
+                }]),
+            );
+        }
+    }
+
+    pub fn dispatch(&mut self, encoder: &mut wgpu::CommandEncoder) {
+        let [x, y, z] = if self.auto_workgroup_count {
+            self.covering_workgroup_count()
+        } else {
+            self.workgroup_count
+        };
+        let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("ComputeView::dispatch"),
+            timestamp_writes: None,
+        });
+        pass.set_pipeline(self.pipeline.pipeline());
+        pass.set_bind_group(0, &self.compute_bind_group, &[]);
+        pass.dispatch_workgroups(x, y, z);
+        self.frame = self.frame.wrapping_add(1);
+    }
+
+    pub fn render(&self, encoder: &mut wgpu::CommandEncoder, output: &wgpu::TextureView) {
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("ComputeView::render"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: output,
+                resolve_target: None,
+                ops: Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            ..Default::default()
+        });
+        pass.set_pipeline(self.blit_pipeline.pipeline());
+        pass.set_bind_group(0, &self.blit_bind_group, &[]);
+        pass.draw(0..3, 0..1);
+    }
+}
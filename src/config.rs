@@ -0,0 +1,86 @@
+use std::path::{Path, PathBuf};
+
+use directories::ProjectDirs;
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+
+use crate::{error::RendererResult, hdr::HdrFormat};
+
+const CONFIG_FILE_NAME: &str = "config.toml";
+const MAX_RECENT_SHADERS: usize = 10;
+
+/// Settings that are persisted between runs
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Config {
+    /// Most recently opened first
+    #[serde(default)]
+    recent_shaders: Vec<PathBuf>,
+    /// Applied when the renderer is created
+    #[serde(default)]
+    hdr_format: HdrFormat,
+}
+
+impl Config {
+    fn path() -> Option<PathBuf> {
+        ProjectDirs::from("", "", "rust_shader_viewer")
+            .map(|dirs| dirs.config_dir().join(CONFIG_FILE_NAME))
+    }
+
+    fn try_load(path: &Path) -> RendererResult<Self> {
+        let contents = std::fs::read_to_string(path)?;
+        Ok(toml::from_str(&contents)?)
+    }
+
+    /// Loads the config file, falling back to the defaults if it is missing
+    /// or can't be read
+    pub fn load() -> Self {
+        let Some(path) = Self::path() else {
+            return Self::default();
+        };
+        if !path.exists() {
+            return Self::default();
+        }
+        match Self::try_load(&path) {
+            Ok(config) => {
+                info!("Loaded config from {}", path.display());
+                config
+            }
+            Err(e) => {
+                error!("Unable to load config {}: {}", path.display(), e);
+                Self::default()
+            }
+        }
+    }
+
+    pub fn save(&self) -> RendererResult<()> {
+        let Some(path) = Self::path() else {
+            return Ok(());
+        };
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(&path, toml::to_string(self)?)?;
+        Ok(())
+    }
+
+    pub fn recent_shaders(&self) -> &[PathBuf] {
+        &self.recent_shaders
+    }
+
+    pub fn hdr_format(&self) -> HdrFormat {
+        self.hdr_format
+    }
+
+    pub fn set_hdr_format(&mut self, format: HdrFormat) {
+        self.hdr_format = format;
+    }
+
+    /// Moves `path` to the front of the recent shaders, dropping the oldest
+    /// entry if the list is full
+    pub fn add_recent_shader(&mut self, path: &Path) {
+        let path = std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+        self.recent_shaders.retain(|p| *p != path);
+        self.recent_shaders.insert(0, path);
+        self.recent_shaders.truncate(MAX_RECENT_SHADERS);
+    }
+}
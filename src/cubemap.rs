@@ -1,139 +1,817 @@
-use crate::error::RendererResult;
+use std::path::Path;
+
+use cgmath::{Deg, Matrix4};
+use log::warn;
+use wgpu::util::DeviceExt;
+
+use crate::error::{RendererError, RendererResult};
+use crate::ibl::{IblGenerator, IblTextures};
 use crate::pipeline::{PipelineCreateInfo, RenderPipeline};
 use crate::resources;
-use crate::shader::Shader;
+use crate::shader::{self, buffer_binding_size, Shader};
 use crate::texture::{CubeTexture, Texture};
 
+/// Face size of environment maps converted from equirectangular images
+pub const DEFAULT_CUBEMAP_SIZE: u32 = 1080;
+/// Face sizes offered in the UI, those over `max_size` are hidden
+pub const CUBEMAP_SIZES: [u32; 7] = [256, 512, 1024, 1080, 2048, 4096, 8192];
+/// Memory an environment map's faces may take up. They are `Rgba32Float`,
+/// so 8192 wide faces would need 6 GiB.
+const MAX_CUBEMAP_BYTES: u64 = 2 << 30;
+const CUBEMAP_BYTES_PER_TEXEL: u64 = 16;
+
+/// Largest face size `device` can create that fits in `MAX_CUBEMAP_BYTES`
+pub fn max_size(device: &wgpu::Device) -> u32 {
+    let max_texels_per_face = MAX_CUBEMAP_BYTES / (6 * CUBEMAP_BYTES_PER_TEXEL);
+    let max_by_memory = (max_texels_per_face as f64).sqrt() as u32;
+    device.limits().max_texture_dimension_2d.min(max_by_memory)
+}
+
+/// Checks that cube faces `size` texels wide can be created on `device`
+/// without using too much memory
+fn validate_size(device: &wgpu::Device, size: u32) -> RendererResult<()> {
+    let max = max_size(device);
+    if size == 0 || size > max {
+        return Err(RendererError::InvalidCubemapSize { size, max });
+    }
+    Ok(())
+}
+
+const ENVIRONMENT_LAYOUT_ENTRIES: [wgpu::BindGroupLayoutEntry; 7] = [
+    // environment map
+    wgpu::BindGroupLayoutEntry {
+        binding: 0,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Texture {
+            sample_type: wgpu::TextureSampleType::Float { filterable: false },
+            view_dimension: wgpu::TextureViewDimension::Cube,
+            multisampled: false,
+        },
+        count: None,
+    },
+    wgpu::BindGroupLayoutEntry {
+        binding: 1,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::NonFiltering),
+        count: None,
+    },
+    // diffuse irradiance
+    wgpu::BindGroupLayoutEntry {
+        binding: 2,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Texture {
+            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+            view_dimension: wgpu::TextureViewDimension::Cube,
+            multisampled: false,
+        },
+        count: None,
+    },
+    // specular prefiltered environment, one mip per roughness step
+    wgpu::BindGroupLayoutEntry {
+        binding: 3,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Texture {
+            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+            view_dimension: wgpu::TextureViewDimension::Cube,
+            multisampled: false,
+        },
+        count: None,
+    },
+    // BRDF lookup table
+    wgpu::BindGroupLayoutEntry {
+        binding: 4,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Texture {
+            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+            view_dimension: wgpu::TextureViewDimension::D2,
+            multisampled: false,
+        },
+        count: None,
+    },
+    wgpu::BindGroupLayoutEntry {
+        binding: 5,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+        count: None,
+    },
+    // rotation and intensity
+    wgpu::BindGroupLayoutEntry {
+        binding: 6,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Uniform,
+            has_dynamic_offset: false,
+            min_binding_size: buffer_binding_size::<EnvironmentTransformUniform>(),
+        },
+        count: None,
+    },
+];
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct EnvironmentTransformUniform {
+    /// Takes world space directions to the environment's own space
+    rotation: [[f32; 4]; 4],
+    intensity: f32,
+    _padding: [f32; 3],
+}
+
+impl EnvironmentTransformUniform {
+    fn new(rotation: Deg<f32>, intensity: f32) -> Self {
+        Self {
+            rotation: Matrix4::from_angle_y(-rotation).into(),
+            intensity,
+            _padding: [0.0; 3],
+        }
+    }
+}
+
+/// How the background behind the scene is drawn.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum SkyMode {
+    /// Sample the environment cube texture
+    #[default]
+    Environment,
+    SolidColor([f32; 3]),
+    /// Vertical gradient from `bottom` (looking down) to `top` (looking up)
+    Gradient {
+        top: [f32; 3],
+        bottom: [f32; 3],
+    },
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct SkyColorsUniform {
+    top: [f32; 4],
+    bottom: [f32; 4],
+}
+
+impl SkyColorsUniform {
+    fn from_mode(mode: SkyMode) -> Self {
+        let (top, bottom) = match mode {
+            SkyMode::Environment => ([0.0; 3], [0.0; 3]),
+            SkyMode::SolidColor(color) => (color, color),
+            SkyMode::Gradient { top, bottom } => (top, bottom),
+        };
+        Self {
+            top: [top[0], top[1], top[2], 1.0],
+            bottom: [bottom[0], bottom[1], bottom[2], 1.0],
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct CrossfadeUniform {
+    /// 0 shows environment A, 1 environment B
+    blend: f32,
+    _padding: [f32; 3],
+}
+
+impl CrossfadeUniform {
+    fn new(blend: f32) -> Self {
+        Self {
+            blend,
+            _padding: [0.0; 3],
+        }
+    }
+}
+
+/// Environment B's cube texture and the blend factor, used by the sky
+/// alongside environment A's environment bind group
+const fn get_crossfade_layout_desc() -> wgpu::BindGroupLayoutDescriptor<'static> {
+    const LAYOUT_ENTRIES: [wgpu::BindGroupLayoutEntry; 3] = [
+        wgpu::BindGroupLayoutEntry {
+            binding: 0,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                view_dimension: wgpu::TextureViewDimension::Cube,
+                multisampled: false,
+            },
+            count: None,
+        },
+        wgpu::BindGroupLayoutEntry {
+            binding: 1,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::NonFiltering),
+            count: None,
+        },
+        wgpu::BindGroupLayoutEntry {
+            binding: 2,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        },
+    ];
+    wgpu::BindGroupLayoutDescriptor {
+        label: Some("Environment Crossfade Bind Group Layout"),
+        entries: &LAYOUT_ENTRIES,
+    }
+}
+
+const fn get_sky_colors_layout_desc() -> wgpu::BindGroupLayoutDescriptor<'static> {
+    const LAYOUT_ENTRIES: [wgpu::BindGroupLayoutEntry; 1] = [wgpu::BindGroupLayoutEntry {
+        binding: 0,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Uniform,
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        count: None,
+    }];
+    wgpu::BindGroupLayoutDescriptor {
+        label: Some("Sky Colors Bind Group Layout"),
+        entries: &LAYOUT_ENTRIES,
+    }
+}
+
+/// The environment map, its image based lighting and its transform, bound
+/// at group 3 of the shader tabs and the sky
+pub const fn get_environment_layout_desc() -> wgpu::BindGroupLayoutDescriptor<'static> {
+    wgpu::BindGroupLayoutDescriptor {
+        label: Some("Environment Bind Group Layout"),
+        entries: &ENVIRONMENT_LAYOUT_ENTRIES,
+    }
+}
+
+struct EnvironmentResources<'a> {
+    texture: &'a CubeTexture,
+    ibl: &'a IblTextures,
+    brdf_lut: &'a Texture,
+    ibl_sampler: &'a wgpu::Sampler,
+    transform_buffer: &'a wgpu::Buffer,
+}
+
+/// A loaded environment with its image based lighting
+struct EnvironmentSlot {
+    name: String,
+    texture: CubeTexture,
+    _ibl: IblTextures,
+    bind_group: wgpu::BindGroup,
+}
+
 pub struct CubeMapRenderer {
     label: Option<String>,
-    _texture: CubeTexture,
+    hdr_loader: resources::HdrLoader,
+    /// Face size environment maps are converted to
+    size: u32,
+    ibl_generator: IblGenerator,
+    brdf_lut: Texture,
+    ibl_sampler: wgpu::Sampler,
     layout: wgpu::BindGroupLayout,
-    bind_group: wgpu::BindGroup,
+    /// Preloaded environments, there is always at least one
+    slots: Vec<EnvironmentSlot>,
+    /// Slots the sky crossfades between
+    slot_a: usize,
+    slot_b: usize,
+    blend: f32,
+    crossfade_buffer: wgpu::Buffer,
+    crossfade_layout: wgpu::BindGroupLayout,
+    crossfade_bind_group: wgpu::BindGroup,
     pipeline: RenderPipeline,
+    rotation: Deg<f32>,
+    intensity: f32,
+    transform_buffer: wgpu::Buffer,
+    sky_mode: SkyMode,
+    sky_colors_buffer: wgpu::Buffer,
+    sky_colors_bind_group: wgpu::BindGroup,
+    gradient_pipeline: RenderPipeline,
+}
+
+/// What `CubeMapRenderer::new` loads and draws the sky with
+pub struct CubeMapCreateInfo<'a> {
+    pub camera_bind_group_layout_desc: &'a wgpu::BindGroupLayoutDescriptor<'a>,
+    pub camera_bind_group_layout: &'a wgpu::BindGroupLayout,
+    /// Format of the target the sky is drawn to
+    pub color_format: wgpu::TextureFormat,
+    /// Equirectangular HDR image of the first environment
+    pub filename: &'a str,
+    /// Face size environment maps are converted to
+    pub size: u32,
+    pub label: Option<&'a str>,
 }
 
 impl CubeMapRenderer {
     pub async fn new(
         device: &wgpu::Device,
         queue: &wgpu::Queue,
-        camera_bind_group_layout_desc: &wgpu::BindGroupLayoutDescriptor<'_>,
-        camera_bind_group_layout: &wgpu::BindGroupLayout,
-        surface_format: wgpu::TextureFormat,
-        filename: &str,
-        label: Option<&str>,
+        create_info: CubeMapCreateInfo<'_>,
     ) -> RendererResult<Self> {
-        // TODO error handling
-        let texture = {
-            let hdr_loader = resources::HdrLoader::new(device);
-            let sky_bytes = resources::load_binary(filename).await?;
-            hdr_loader.cube_from_equirectangular_bytes(device, queue, &sky_bytes, 1080, label)?
+        let CubeMapCreateInfo {
+            camera_bind_group_layout_desc,
+            camera_bind_group_layout,
+            color_format: surface_format,
+            filename,
+            size,
+            label,
+        } = create_info;
+        validate_size(device, size)?;
+        let hdr_loader = resources::HdrLoader::new(device);
+        let loaded = match resources::load_binary(filename).await {
+            Ok(sky_bytes) => {
+                hdr_loader.cube_from_equirectangular_bytes(device, queue, &sky_bytes, size, label)
+            }
+            Err(e) => Err(e),
+        };
+        let name = if loaded.is_ok() {
+            slot_name(Path::new(filename))
+        } else {
+            "Plain sky".to_owned()
         };
+        // A missing or broken environment shouldn't keep the viewer from
+        // starting
+        let texture = loaded.unwrap_or_else(|e| {
+            warn!(
+                "Unable to load environment map {}, using a plain sky instead: {}",
+                filename, e
+            );
+            hdr_loader.gradient_cube(device, queue, [0.3, 0.5, 0.9], [0.2, 0.2, 0.2], label)
+        });
+
+        let ibl_generator = IblGenerator::new(device);
+        let ibl = ibl_generator.generate(device, queue, &texture, label);
+        let brdf_lut = ibl_generator.create_brdf_lut(device, queue);
+        let ibl_sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Ibl Sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        let rotation = Deg(0.0);
+        let intensity = 1.0;
+        let transform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Environment Transform Buffer"),
+            contents: bytemuck::cast_slice(&[EnvironmentTransformUniform::new(
+                rotation, intensity,
+            )]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
 
         let desc = wgpu::BindGroupLayoutDescriptor {
             label,
-            entries: &[
-                wgpu::BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: wgpu::ShaderStages::FRAGMENT,
-                    ty: wgpu::BindingType::Texture {
-                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
-                        view_dimension: wgpu::TextureViewDimension::Cube,
-                        multisampled: false,
-                    },
-                    count: None,
-                },
-                wgpu::BindGroupLayoutEntry {
-                    binding: 1,
-                    visibility: wgpu::ShaderStages::FRAGMENT,
-                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::NonFiltering),
-                    count: None,
-                },
-            ],
+            entries: &ENVIRONMENT_LAYOUT_ENTRIES,
         };
         let layout = device.create_bind_group_layout(&desc);
 
-        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+        let bind_group = Self::create_bind_group(
+            device,
+            &layout,
+            EnvironmentResources {
+                texture: &texture,
+                ibl: &ibl,
+                brdf_lut: &brdf_lut,
+                ibl_sampler: &ibl_sampler,
+                transform_buffer: &transform_buffer,
+            },
             label,
-            layout: &layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::TextureView(texture.view()),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::Sampler(texture.sampler()),
-                },
-            ],
+        );
+
+        let blend = 0.0;
+        let crossfade_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Environment Crossfade Buffer"),
+            contents: bytemuck::cast_slice(&[CrossfadeUniform::new(blend)]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
         });
+        let crossfade_desc = get_crossfade_layout_desc();
+        let crossfade_layout = device.create_bind_group_layout(&crossfade_desc);
+        let crossfade_bind_group = Self::create_crossfade_bind_group(
+            device,
+            &crossfade_layout,
+            &texture,
+            &crossfade_buffer,
+        );
 
         let pipeline = {
             let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
                 label,
-                bind_group_layouts: &[camera_bind_group_layout, &layout],
+                bind_group_layouts: &[camera_bind_group_layout, &layout, &crossfade_layout],
                 push_constant_ranges: &[],
             });
 
-            let shader = Shader::new_wgsl(
+            let shader = Self::create_sky_shader(device);
+            assert_eq!(
+                shader.layout_matches(&[camera_bind_group_layout_desc, &desc, &crossfade_desc]),
+                Ok(())
+            );
+            RenderPipeline::new(
                 device,
-                "cubemap",
-                include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/shaders/sky.wgsl")),
+                layout,
+                Self::sky_create_info(&shader, surface_format, "Cubemap Pipeline"),
             )
-            .expect("Could not parse cubemap shader");
+        };
+
+        let sky_mode = SkyMode::default();
+        let sky_colors_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Sky Colors Buffer"),
+            contents: bytemuck::cast_slice(&[SkyColorsUniform::from_mode(sky_mode)]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let sky_colors_desc = get_sky_colors_layout_desc();
+        let sky_colors_layout = device.create_bind_group_layout(&sky_colors_desc);
+        let sky_colors_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Sky Colors Bind Group"),
+            layout: &sky_colors_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: sky_colors_buffer.as_entire_binding(),
+            }],
+        });
+
+        let gradient_pipeline = {
+            let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Sky Gradient Pipeline Layout"),
+                bind_group_layouts: &[camera_bind_group_layout, &sky_colors_layout],
+                push_constant_ranges: &[],
+            });
+
+            let shader = Self::create_gradient_shader(device);
             assert_eq!(
-                shader.layout_matches(&[camera_bind_group_layout_desc, &desc]),
+                shader.layout_matches(&[camera_bind_group_layout_desc, &sky_colors_desc]),
                 Ok(())
             );
-            let create_info = PipelineCreateInfo {
-                color_format: surface_format,
-                depth_format: Some(Texture::DEPTH_FORMAT),
-                vertex_layouts: &[],
-                topology: wgpu::PrimitiveTopology::TriangleList,
-                shader: &shader,
-                label: Some("Cubemap Pipeline"),
-            };
-            RenderPipeline::new(device, layout, create_info)
+            RenderPipeline::new(
+                device,
+                layout,
+                Self::sky_create_info(&shader, surface_format, "Sky Gradient Pipeline"),
+            )
         };
 
         Ok(Self {
             label: label.map(str::to_owned),
-            _texture: texture,
+            hdr_loader,
+            size,
+            ibl_generator,
+            brdf_lut,
+            ibl_sampler,
             layout,
-            bind_group,
+            slots: vec![EnvironmentSlot {
+                name,
+                texture,
+                _ibl: ibl,
+                bind_group,
+            }],
+            slot_a: 0,
+            slot_b: 0,
+            blend,
+            crossfade_buffer,
+            crossfade_layout,
+            crossfade_bind_group,
             pipeline,
+            rotation,
+            intensity,
+            transform_buffer,
+            sky_mode,
+            sky_colors_buffer,
+            sky_colors_bind_group,
+            gradient_pipeline,
         })
     }
 
-    pub fn layout(&self) -> &wgpu::BindGroupLayout {
-        &self.layout
+    fn create_sky_shader(device: &wgpu::Device) -> Shader {
+        Shader::new_wgsl(
+            device,
+            "cubemap",
+            &shader::builtin_source(
+                "sky.wgsl",
+                include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/shaders/sky.wgsl")),
+            ),
+        )
+        .expect("Could not parse cubemap shader")
     }
 
-    pub fn layout_desc(&self) -> wgpu::BindGroupLayoutDescriptor {
-        wgpu::BindGroupLayoutDescriptor {
-            label: self.label.as_deref(),
+    fn create_gradient_shader(device: &wgpu::Device) -> Shader {
+        Shader::new_wgsl(
+            device,
+            "sky_gradient",
+            include_str!(concat!(
+                env!("CARGO_MANIFEST_DIR"),
+                "/shaders/sky_gradient.wgsl"
+            )),
+        )
+        .expect("Could not parse sky gradient shader")
+    }
+
+    fn sky_create_info<'a>(
+        shader: &'a Shader,
+        color_format: wgpu::TextureFormat,
+        label: &'a str,
+    ) -> PipelineCreateInfo<'a> {
+        PipelineCreateInfo {
+            color_format,
+            depth_format: Some(Texture::DEPTH_FORMAT),
+            // Drawn after opaque geometry, only where the depth is still at the
+            // far plane it's cleared to
+            depth_write: false,
+            vertex_layouts: &[],
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            cull_mode: Some(wgpu::Face::Back),
+            blend: Some(wgpu::BlendState::REPLACE),
+            shader,
+            label: Some(label),
+        }
+    }
+
+    /// Rebuilds the sky pipelines to draw into a target of a new format
+    pub fn set_color_format(&mut self, device: &wgpu::Device, color_format: wgpu::TextureFormat) {
+        let shader = Self::create_sky_shader(device);
+        self.pipeline
+            .recreate(
+                device,
+                Self::sky_create_info(&shader, color_format, "Cubemap Pipeline"),
+            )
+            .expect("Could not recreate cubemap pipeline");
+        let shader = Self::create_gradient_shader(device);
+        self.gradient_pipeline
+            .recreate(
+                device,
+                Self::sky_create_info(&shader, color_format, "Sky Gradient Pipeline"),
+            )
+            .expect("Could not recreate sky gradient pipeline");
+    }
+
+    pub fn sky_mode(&self) -> SkyMode {
+        self.sky_mode
+    }
+
+    pub fn set_sky_mode(&mut self, queue: &wgpu::Queue, sky_mode: SkyMode) {
+        if self.sky_mode == sky_mode {
+            return;
+        }
+        self.sky_mode = sky_mode;
+        queue.write_buffer(
+            &self.sky_colors_buffer,
+            0,
+            bytemuck::cast_slice(&[SkyColorsUniform::from_mode(sky_mode)]),
+        );
+    }
+
+    fn write_transform(&self, queue: &wgpu::Queue) {
+        queue.write_buffer(
+            &self.transform_buffer,
+            0,
+            bytemuck::cast_slice(&[EnvironmentTransformUniform::new(
+                self.rotation,
+                self.intensity,
+            )]),
+        );
+    }
+
+    /// Rotation of the environment around the world Y axis, applied to the
+    /// sky and to image based lighting
+    pub fn rotation(&self) -> Deg<f32> {
+        self.rotation
+    }
+
+    pub fn set_rotation(&mut self, queue: &wgpu::Queue, rotation: Deg<f32>) {
+        if self.rotation == rotation {
+            return;
+        }
+        self.rotation = rotation;
+        self.write_transform(queue);
+    }
+
+    /// Scales the environment's radiance
+    pub fn intensity(&self) -> f32 {
+        self.intensity
+    }
+
+    pub fn set_intensity(&mut self, queue: &wgpu::Queue, intensity: f32) {
+        if self.intensity == intensity || intensity < 0.0 {
+            return;
+        }
+        self.intensity = intensity;
+        self.write_transform(queue);
+    }
+
+    fn create_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        resources: EnvironmentResources,
+        label: Option<&str>,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label,
+            layout,
             entries: &[
-                wgpu::BindGroupLayoutEntry {
+                wgpu::BindGroupEntry {
                     binding: 0,
-                    visibility: wgpu::ShaderStages::FRAGMENT,
-                    ty: wgpu::BindingType::Texture {
-                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
-                        view_dimension: wgpu::TextureViewDimension::Cube,
-                        multisampled: false,
-                    },
-                    count: None,
+                    resource: wgpu::BindingResource::TextureView(resources.texture.view()),
                 },
-                wgpu::BindGroupLayoutEntry {
+                wgpu::BindGroupEntry {
                     binding: 1,
-                    visibility: wgpu::ShaderStages::FRAGMENT,
-                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::NonFiltering),
-                    count: None,
+                    resource: wgpu::BindingResource::Sampler(resources.texture.sampler()),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(resources.ibl.irradiance.view()),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(resources.ibl.prefiltered.view()),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: wgpu::BindingResource::TextureView(&resources.brdf_lut.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: wgpu::BindingResource::Sampler(resources.ibl_sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 6,
+                    resource: resources.transform_buffer.as_entire_binding(),
+                },
+            ],
+        })
+    }
+
+    fn create_crossfade_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        texture: &CubeTexture,
+        crossfade_buffer: &wgpu::Buffer,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Environment Crossfade Bind Group"),
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(texture.view()),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(texture.sampler()),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: crossfade_buffer.as_entire_binding(),
                 },
             ],
+        })
+    }
+
+    /// Converts an equirectangular HDR image into a slot
+    fn load_slot(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        name: String,
+        bytes: &[u8],
+        label: Option<&str>,
+    ) -> RendererResult<EnvironmentSlot> {
+        let texture = self
+            .hdr_loader
+            .cube_from_equirectangular_bytes(device, queue, bytes, self.size, label)?;
+        let ibl = self.ibl_generator.generate(device, queue, &texture, label);
+        let bind_group = Self::create_bind_group(
+            device,
+            &self.layout,
+            EnvironmentResources {
+                texture: &texture,
+                ibl: &ibl,
+                brdf_lut: &self.brdf_lut,
+                ibl_sampler: &self.ibl_sampler,
+                transform_buffer: &self.transform_buffer,
+            },
+            label,
+        );
+        Ok(EnvironmentSlot {
+            name,
+            texture,
+            _ibl: ibl,
+            bind_group,
+        })
+    }
+
+    /// Rebuilds environment A's cube texture and its image based lighting
+    /// textures from an equirectangular HDR image, keeping the existing
+    /// layout and pipeline.
+    pub fn reload(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        name: &str,
+        bytes: &[u8],
+        label: Option<&str>,
+    ) -> RendererResult<()> {
+        let slot = self.load_slot(device, queue, name.to_owned(), bytes, label)?;
+        self.slots[self.slot_a] = slot;
+        self.update_crossfade_bind_group(device);
+        Ok(())
+    }
+
+    /// Preloads another environment to crossfade to, returning its slot
+    pub fn add_slot(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        name: &str,
+        bytes: &[u8],
+        label: Option<&str>,
+    ) -> RendererResult<usize> {
+        let slot = self.load_slot(device, queue, name.to_owned(), bytes, label)?;
+        self.slots.push(slot);
+        Ok(self.slots.len() - 1)
+    }
+
+    /// Names of the loaded environments, by slot
+    pub fn slot_names(&self) -> impl Iterator<Item = &str> {
+        self.slots.iter().map(|slot| slot.name.as_str())
+    }
+
+    /// The slots the sky fades from and to
+    pub fn slots(&self) -> (usize, usize) {
+        (self.slot_a, self.slot_b)
+    }
+
+    /// Picks the environments to crossfade between, out of range slots are
+    /// ignored
+    pub fn set_slots(&mut self, device: &wgpu::Device, slot_a: usize, slot_b: usize) {
+        if slot_a >= self.slots.len() || slot_b >= self.slots.len() {
+            return;
         }
+        if (slot_a, slot_b) == (self.slot_a, self.slot_b) {
+            return;
+        }
+        let b_changed = slot_b != self.slot_b;
+        self.slot_a = slot_a;
+        self.slot_b = slot_b;
+        if b_changed {
+            self.update_crossfade_bind_group(device);
+        }
+    }
+
+    fn update_crossfade_bind_group(&mut self, device: &wgpu::Device) {
+        self.crossfade_bind_group = Self::create_crossfade_bind_group(
+            device,
+            &self.crossfade_layout,
+            &self.slots[self.slot_b].texture,
+            &self.crossfade_buffer,
+        );
+    }
+
+    /// How far the sky has faded from environment A to B, from 0 to 1
+    pub fn blend(&self) -> f32 {
+        self.blend
     }
 
+    pub fn set_blend(&mut self, queue: &wgpu::Queue, blend: f32) {
+        let blend = blend.clamp(0.0, 1.0);
+        if self.blend == blend {
+            return;
+        }
+        self.blend = blend;
+        queue.write_buffer(
+            &self.crossfade_buffer,
+            0,
+            bytemuck::cast_slice(&[CrossfadeUniform::new(blend)]),
+        );
+    }
+
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+
+    /// Changes the face size used by the next `reload`
+    pub fn set_size(&mut self, device: &wgpu::Device, size: u32) -> RendererResult<()> {
+        validate_size(device, size)?;
+        self.size = size;
+        Ok(())
+    }
+
+    pub fn layout(&self) -> &wgpu::BindGroupLayout {
+        &self.layout
+    }
+
+    pub fn layout_desc(&self) -> wgpu::BindGroupLayoutDescriptor {
+        wgpu::BindGroupLayoutDescriptor {
+            label: self.label.as_deref(),
+            ..get_environment_layout_desc()
+        }
+    }
+
+    /// Environment the scene is lit by, whichever of A and B the blend is
+    /// closer to since image based lighting isn't crossfaded
     pub fn bind_group(&self) -> &wgpu::BindGroup {
-        &self.bind_group
+        let slot = if self.blend < 0.5 {
+            self.slot_a
+        } else {
+            self.slot_b
+        };
+        &self.slots[slot].bind_group
     }
 
     pub fn render<'a>(
@@ -141,9 +819,63 @@ impl CubeMapRenderer {
         render_pass: &mut wgpu::RenderPass<'a>,
         camera_bind_group: &'a wgpu::BindGroup,
     ) {
-        render_pass.set_pipeline(self.pipeline.pipeline());
+        match self.sky_mode {
+            SkyMode::Environment => {
+                render_pass.set_pipeline(self.pipeline.pipeline());
+                render_pass.set_bind_group(1, &self.slots[self.slot_a].bind_group, &[]);
+                render_pass.set_bind_group(2, &self.crossfade_bind_group, &[]);
+            }
+            SkyMode::SolidColor(_) | SkyMode::Gradient { .. } => {
+                render_pass.set_pipeline(self.gradient_pipeline.pipeline());
+                render_pass.set_bind_group(1, &self.sky_colors_bind_group, &[]);
+            }
+        }
         render_pass.set_bind_group(0, camera_bind_group, &[]);
-        render_pass.set_bind_group(1, &self.bind_group, &[]);
         render_pass.draw(0..3, 0..1);
     }
 }
+
+/// Name shown for an environment loaded from `path`
+pub fn slot_name(path: &Path) -> String {
+    path.file_stem()
+        .unwrap_or(path.as_os_str())
+        .to_string_lossy()
+        .into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::camera::PerspectiveCamera;
+    use crate::shader::reflect_wgsl;
+
+    #[test]
+    fn sky_shaders_match_their_pipeline_layouts() {
+        let sky = reflect_wgsl(&shader::builtin_source(
+            "sky.wgsl",
+            include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/shaders/sky.wgsl")),
+        ))
+        .expect("sky.wgsl should parse");
+        assert_eq!(
+            sky.layout_matches(&[
+                &PerspectiveCamera::layout_desc(),
+                &get_environment_layout_desc(),
+                &get_crossfade_layout_desc(),
+            ]),
+            Ok(())
+        );
+
+        let gradient = reflect_wgsl(include_str!(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/shaders/sky_gradient.wgsl"
+        )))
+        .expect("sky_gradient.wgsl should parse");
+        assert_eq!(
+            gradient.layout_matches(&[
+                &PerspectiveCamera::layout_desc(),
+                &get_sky_colors_layout_desc(),
+            ]),
+            Ok(())
+        );
+    }
+}
@@ -0,0 +1,365 @@
+use wgpu::util::DeviceExt;
+
+use crate::model::{InstanceRaw, Model, ModelVertex};
+use crate::pipeline::{PipelineCreateInfo, RenderPipeline};
+use crate::shader::Shader;
+use crate::texture::Texture;
+
+/// What `DebugPrimitiveRenderer` draws for each mesh
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DebugPrimitiveMode {
+    /// A dot on every vertex
+    #[default]
+    Points,
+    /// A line along every edge of the mesh's primitives, nothing for point
+    /// lists
+    Lines,
+}
+
+impl DebugPrimitiveMode {
+    /// What the shader draws for meshes of `topology`
+    fn index(&self, topology: wgpu::PrimitiveTopology) -> u32 {
+        match (self, topology) {
+            (Self::Points, _) => 0,
+            (Self::Lines, wgpu::PrimitiveTopology::TriangleList) => 1,
+            (Self::Lines, wgpu::PrimitiveTopology::TriangleStrip) => 2,
+            (Self::Lines, wgpu::PrimitiveTopology::LineList) => 3,
+            (Self::Lines, wgpu::PrimitiveTopology::LineStrip) => 4,
+            // Not drawn, see `primitive_count`
+            (Self::Lines, wgpu::PrimitiveTopology::PointList) => 0,
+        }
+    }
+
+    /// How many dots or lines to draw for a mesh of `vertices` vertices and
+    /// `indices` indices drawn as `topology`
+    fn primitive_count(
+        &self,
+        topology: wgpu::PrimitiveTopology,
+        vertices: u32,
+        indices: u32,
+    ) -> u32 {
+        match (self, topology) {
+            (Self::Points, _) => vertices,
+            (Self::Lines, wgpu::PrimitiveTopology::TriangleList) => indices,
+            // The sides of the strip and a diagonal between each pair of
+            // triangles
+            (Self::Lines, wgpu::PrimitiveTopology::TriangleStrip) => {
+                (2 * indices).saturating_sub(3)
+            }
+            (Self::Lines, wgpu::PrimitiveTopology::LineList) => indices / 2,
+            (Self::Lines, wgpu::PrimitiveTopology::LineStrip) => indices.saturating_sub(1),
+            (Self::Lines, wgpu::PrimitiveTopology::PointList) => 0,
+        }
+    }
+}
+
+/// Whether `adapter` can read storage buffers in vertex shaders, which
+/// `DebugPrimitiveRenderer` needs
+pub fn is_supported(adapter: &wgpu::Adapter) -> bool {
+    adapter
+        .get_downlevel_capabilities()
+        .flags
+        .contains(wgpu::DownlevelFlags::VERTEX_STORAGE)
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct DebugPrimitiveUniform {
+    color: [f32; 4],
+    viewport_size: [f32; 2],
+    size: f32,
+    mode: u32,
+}
+
+const fn get_debug_primitive_layout_desc() -> wgpu::BindGroupLayoutDescriptor<'static> {
+    const fn storage(binding: u32) -> wgpu::BindGroupLayoutEntry {
+        wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::VERTEX,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only: true },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        }
+    }
+    const LAYOUT_ENTRIES: [wgpu::BindGroupLayoutEntry; 3] = [
+        wgpu::BindGroupLayoutEntry {
+            binding: 0,
+            visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        },
+        // the mesh's vertices
+        storage(1),
+        // the mesh's indices
+        storage(2),
+    ];
+    wgpu::BindGroupLayoutDescriptor {
+        label: Some("Debug Primitive Bind Group Layout"),
+        entries: &LAYOUT_ENTRIES,
+    }
+}
+
+/// Draws the model's vertices or triangle edges as anti-aliased dots or
+/// lines a given number of pixels wide. wgpu only rasterizes one pixel wide
+/// points and lines, so each one is expanded into a screen space quad in the
+/// vertex shader, which reads the mesh's vertex and index buffers directly.
+/// Only create it if `is_supported`.
+pub struct DebugPrimitiveRenderer {
+    mode: DebugPrimitiveMode,
+    /// How the model's meshes are drawn, which decides their edges
+    topology: wgpu::PrimitiveTopology,
+    size: f32,
+    color: [f32; 4],
+    viewport_size: [f32; 2],
+    buffer: wgpu::Buffer,
+    /// One per mesh of the model, in the same order
+    mesh_bind_groups: Vec<wgpu::BindGroup>,
+    pipeline: RenderPipeline,
+}
+
+impl DebugPrimitiveRenderer {
+    pub fn new(
+        device: &wgpu::Device,
+        model: &Model,
+        camera_bind_group_layout_desc: &wgpu::BindGroupLayoutDescriptor<'_>,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        color_format: wgpu::TextureFormat,
+        viewport_size: (u32, u32),
+        topology: wgpu::PrimitiveTopology,
+    ) -> Self {
+        let mode = DebugPrimitiveMode::default();
+        let size = 4.0;
+        let color = [1.0, 0.6, 0.0, 1.0];
+        let viewport_size = [viewport_size.0 as f32, viewport_size.1 as f32];
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Debug Primitive Buffer"),
+            contents: bytemuck::cast_slice(&[DebugPrimitiveUniform {
+                color,
+                viewport_size,
+                size,
+                mode: mode.index(topology),
+            }]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let desc = get_debug_primitive_layout_desc();
+        let layout = device.create_bind_group_layout(&desc);
+        let mesh_bind_groups = model
+            .meshes
+            .iter()
+            .map(|mesh| {
+                device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    label: Some("Debug Primitive Bind Group"),
+                    layout: &layout,
+                    entries: &[
+                        wgpu::BindGroupEntry {
+                            binding: 0,
+                            resource: buffer.as_entire_binding(),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 1,
+                            resource: mesh.vertex_buffer.as_entire_binding(),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 2,
+                            resource: mesh.index_buffer.as_entire_binding(),
+                        },
+                    ],
+                })
+            })
+            .collect();
+
+        let pipeline = {
+            let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Debug Primitive Pipeline Layout"),
+                bind_group_layouts: &[camera_bind_group_layout, &layout],
+                push_constant_ranges: &[],
+            });
+
+            let shader = Self::create_shader(device);
+            assert_eq!(
+                shader.layout_matches(&[camera_bind_group_layout_desc, &desc]),
+                Ok(())
+            );
+            let vertex_layouts = [InstanceRaw::desc()];
+            RenderPipeline::new(
+                device,
+                pipeline_layout,
+                Self::pipeline_create_info(&shader, &vertex_layouts, color_format),
+            )
+        };
+
+        Self {
+            mode,
+            topology,
+            size,
+            color,
+            viewport_size,
+            buffer,
+            mesh_bind_groups,
+            pipeline,
+        }
+    }
+
+    fn create_shader(device: &wgpu::Device) -> Shader {
+        Shader::new_wgsl(
+            device,
+            "debug_primitives",
+            include_str!(concat!(
+                env!("CARGO_MANIFEST_DIR"),
+                "/shaders/debug_primitives.wgsl"
+            )),
+        )
+        .expect("Could not parse debug primitive shader")
+    }
+
+    fn pipeline_create_info<'a>(
+        shader: &'a Shader,
+        vertex_layouts: &'a [wgpu::VertexBufferLayout<'a>],
+        color_format: wgpu::TextureFormat,
+    ) -> PipelineCreateInfo<'a> {
+        PipelineCreateInfo {
+            color_format,
+            depth_format: Some(Texture::DEPTH_FORMAT),
+            depth_write: true,
+            vertex_layouts,
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            // Line quads can face either way depending on the edge
+            cull_mode: None,
+            blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+            shader,
+            label: Some("Debug Primitive Pipeline"),
+        }
+    }
+
+    /// Rebuilds the pipeline to draw into a target of a new format
+    pub fn set_color_format(&mut self, device: &wgpu::Device, color_format: wgpu::TextureFormat) {
+        let shader = Self::create_shader(device);
+        let vertex_layouts = [InstanceRaw::desc()];
+        self.pipeline
+            .recreate(
+                device,
+                Self::pipeline_create_info(&shader, &vertex_layouts, color_format),
+            )
+            .expect("Could not recreate debug primitive pipeline");
+    }
+
+    fn write_uniform(&self, queue: &wgpu::Queue) {
+        queue.write_buffer(
+            &self.buffer,
+            0,
+            bytemuck::cast_slice(&[DebugPrimitiveUniform {
+                color: self.color,
+                viewport_size: self.viewport_size,
+                size: self.size,
+                mode: self.mode.index(self.topology),
+            }]),
+        );
+    }
+
+    pub fn mode(&self) -> DebugPrimitiveMode {
+        self.mode
+    }
+
+    pub fn set_mode(&mut self, queue: &wgpu::Queue, mode: DebugPrimitiveMode) {
+        if self.mode == mode {
+            return;
+        }
+        self.mode = mode;
+        self.write_uniform(queue);
+    }
+
+    pub fn set_topology(&mut self, queue: &wgpu::Queue, topology: wgpu::PrimitiveTopology) {
+        if self.topology == topology {
+            return;
+        }
+        self.topology = topology;
+        self.write_uniform(queue);
+    }
+
+    /// Dot diameter or line width in pixels
+    pub fn size(&self) -> f32 {
+        self.size
+    }
+
+    pub fn set_size(&mut self, queue: &wgpu::Queue, size: f32) {
+        if self.size == size || size <= 0.0 {
+            return;
+        }
+        self.size = size;
+        self.write_uniform(queue);
+    }
+
+    pub fn color(&self) -> [f32; 4] {
+        self.color
+    }
+
+    pub fn set_color(&mut self, queue: &wgpu::Queue, color: [f32; 4]) {
+        if self.color == color {
+            return;
+        }
+        self.color = color;
+        self.write_uniform(queue);
+    }
+
+    /// Size in pixels of the viewports drawn to, which the sizes are
+    /// relative to
+    pub fn resize(&mut self, queue: &wgpu::Queue, width: u32, height: u32) {
+        self.viewport_size = [width as f32, height as f32];
+        self.write_uniform(queue);
+    }
+
+    /// Draws over the culled instances of `model`, which must be the model
+    /// the renderer was created with
+    pub fn render<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        model: &'a Model,
+        camera_bind_group: &'a wgpu::BindGroup,
+    ) {
+        render_pass.set_pipeline(self.pipeline.pipeline());
+        render_pass.set_bind_group(0, camera_bind_group, &[]);
+        render_pass.set_vertex_buffer(0, model.visible_instance_buffer.slice(..));
+        for (mesh, bind_group) in model.meshes.iter().zip(&self.mesh_bind_groups) {
+            let vertices =
+                (mesh.vertex_buffer.size() / std::mem::size_of::<ModelVertex>() as u64) as u32;
+            let primitives =
+                self.mode
+                    .primitive_count(self.topology, vertices, mesh.number_of_elements);
+            if primitives == 0 {
+                continue;
+            }
+            render_pass.set_bind_group(1, bind_group, &[]);
+            render_pass.draw(0..primitives * 6, model.visible_instances());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lines_follow_the_topology() {
+        use wgpu::PrimitiveTopology::*;
+        let lines =
+            |topology, indices| DebugPrimitiveMode::Lines.primitive_count(topology, 4, indices);
+        assert_eq!(lines(TriangleList, 6), 6);
+        // 4 sides and a diagonal
+        assert_eq!(lines(TriangleStrip, 4), 5);
+        assert_eq!(lines(TriangleStrip, 1), 0);
+        assert_eq!(lines(LineList, 6), 3);
+        assert_eq!(lines(LineStrip, 6), 5);
+        assert_eq!(lines(PointList, 6), 0);
+        assert_eq!(
+            DebugPrimitiveMode::Points.primitive_count(PointList, 4, 6),
+            4
+        );
+    }
+}
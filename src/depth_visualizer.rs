@@ -0,0 +1,188 @@
+use wgpu::{util::DeviceExt, Operations};
+
+use crate::{
+    camera::Projection,
+    pipeline::{PipelineCreateInfo, RenderPipeline},
+    shader::Shader,
+    texture,
+};
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct DepthParamsUniform {
+    znear: f32,
+    zfar: f32,
+    _padding: [f32; 2],
+}
+
+impl DepthParamsUniform {
+    fn new(projection: &Projection) -> Self {
+        Self {
+            znear: projection.znear(),
+            zfar: projection.zfar(),
+            _padding: [0.0; 2],
+        }
+    }
+}
+
+/// Draws the linearized contents of the depth texture to the screen
+pub struct DepthVisualizer {
+    pipeline: RenderPipeline,
+    layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+    params_buffer: wgpu::Buffer,
+}
+
+impl DepthVisualizer {
+    pub fn new(
+        device: &wgpu::Device,
+        depth_texture: &texture::Texture,
+        projection: &Projection,
+        color_format: wgpu::TextureFormat,
+    ) -> Self {
+        let desc = wgpu::BindGroupLayoutDescriptor {
+            label: Some("DepthVisualizer::layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Depth,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        };
+        let layout = device.create_bind_group_layout(&desc);
+
+        let params_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("DepthVisualizer::params_buffer"),
+            contents: bytemuck::cast_slice(&[DepthParamsUniform::new(projection)]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let bind_group = Self::create_bind_group(device, &layout, depth_texture, &params_buffer);
+
+        let shader = Self::create_shader(device);
+        assert_eq!(shader.layout_matches(&[&desc]), Ok(()));
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: None,
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+
+        let pipeline = RenderPipeline::new(
+            device,
+            pipeline_layout,
+            Self::pipeline_create_info(&shader, color_format),
+        );
+
+        Self {
+            pipeline,
+            layout,
+            bind_group,
+            params_buffer,
+        }
+    }
+
+    fn create_shader(device: &wgpu::Device) -> Shader {
+        Shader::new_wgsl(
+            device,
+            "depth",
+            include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/shaders/depth.wgsl")),
+        )
+        .expect("Could not parse depth shader")
+    }
+
+    fn pipeline_create_info(
+        shader: &Shader,
+        color_format: wgpu::TextureFormat,
+    ) -> PipelineCreateInfo<'_> {
+        PipelineCreateInfo {
+            color_format,
+            depth_format: None,
+            depth_write: true,
+            vertex_layouts: &[],
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            cull_mode: Some(wgpu::Face::Back),
+            blend: Some(wgpu::BlendState::REPLACE),
+            shader,
+            label: Some("Depth Visualizer Pipeline"),
+        }
+    }
+
+    /// Rebuilds the pipeline to draw into a target of a new format
+    pub fn set_color_format(&mut self, device: &wgpu::Device, color_format: wgpu::TextureFormat) {
+        let shader = Self::create_shader(device);
+        self.pipeline
+            .recreate(device, Self::pipeline_create_info(&shader, color_format))
+            .expect("Could not recreate depth visualizer pipeline");
+    }
+
+    fn create_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        depth_texture: &texture::Texture,
+        params_buffer: &wgpu::Buffer,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("DepthVisualizer::bind_group"),
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&depth_texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: params_buffer.as_entire_binding(),
+                },
+            ],
+        })
+    }
+
+    /// Must be called whenever the depth texture is recreated
+    pub fn resize(&mut self, device: &wgpu::Device, depth_texture: &texture::Texture) {
+        self.bind_group =
+            Self::create_bind_group(device, &self.layout, depth_texture, &self.params_buffer);
+    }
+
+    pub fn update(&self, queue: &wgpu::Queue, projection: &Projection) {
+        queue.write_buffer(
+            &self.params_buffer,
+            0,
+            bytemuck::cast_slice(&[DepthParamsUniform::new(projection)]),
+        );
+    }
+
+    pub fn render(&self, encoder: &mut wgpu::CommandEncoder, output: &wgpu::TextureView) {
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("DepthVisualizer::render"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: output,
+                resolve_target: None,
+                ops: Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            ..Default::default()
+        });
+        pass.set_pipeline(self.pipeline.pipeline());
+        pass.set_bind_group(0, &self.bind_group, &[]);
+        pass.draw(0..3, 0..1);
+    }
+}
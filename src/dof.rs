@@ -0,0 +1,248 @@
+use wgpu::Operations;
+
+use crate::{
+    camera::Projection,
+    pipeline::{PipelineCreateInfo, RenderPipeline},
+    shader::{self, Shader},
+    texture,
+};
+
+/// Depth of field controls
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DofSettings {
+    /// Distance from the camera that is in focus
+    pub focus_distance: f32,
+    /// How quickly things blur away from the focus distance
+    pub aperture: f32,
+    /// Largest blur radius in pixels
+    pub max_radius: f32,
+    /// Focus on whatever is under the center of the screen instead of
+    /// `focus_distance`
+    pub auto_focus: bool,
+}
+
+impl Default for DofSettings {
+    fn default() -> Self {
+        Self {
+            focus_distance: 10.0,
+            aperture: 0.5,
+            max_radius: 8.0,
+            auto_focus: false,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct DofParamsUniform {
+    focus_distance: f32,
+    aperture: f32,
+    max_radius: f32,
+    auto_focus: u32,
+    znear: f32,
+    zfar: f32,
+    _padding: [f32; 2],
+}
+
+impl DofParamsUniform {
+    fn new(settings: &DofSettings, projection: &Projection) -> Self {
+        Self {
+            focus_distance: settings.focus_distance,
+            aperture: settings.aperture,
+            max_radius: settings.max_radius,
+            auto_focus: settings.auto_focus as u32,
+            znear: projection.znear(),
+            zfar: projection.zfar(),
+            _padding: [0.0; 2],
+        }
+    }
+}
+
+/// Blurs the HDR image by each pixel's circle of confusion before it is
+/// tonemapped
+pub struct DepthOfField {
+    pipeline: RenderPipeline,
+    layout: wgpu::BindGroupLayout,
+    params_buffer: wgpu::Buffer,
+    output: texture::Texture,
+    output_bind_group: wgpu::BindGroup,
+    /// One for each texture `render` can blur, in the order given to
+    /// `set_inputs`
+    input_bind_groups: Vec<wgpu::BindGroup>,
+}
+
+impl DepthOfField {
+    /// Creates a `width` by `height` output of `format`, which is tonemapped
+    /// with `create_output_bind_group`
+    pub fn new(
+        device: &wgpu::Device,
+        width: u32,
+        height: u32,
+        format: wgpu::TextureFormat,
+        create_output_bind_group: impl Fn(&texture::Texture) -> wgpu::BindGroup,
+    ) -> Self {
+        let desc = wgpu::BindGroupLayoutDescriptor {
+            label: Some("DepthOfField::layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Depth,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        };
+        let layout = device.create_bind_group_layout(&desc);
+
+        let params_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("DepthOfField::params_buffer"),
+            size: std::mem::size_of::<DofParamsUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let output = texture::Texture::create_2d_texture(
+            device,
+            width,
+            height,
+            format,
+            wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::RENDER_ATTACHMENT,
+            wgpu::FilterMode::Nearest,
+            Some("DepthOfField::output"),
+        );
+        let output_bind_group = create_output_bind_group(&output);
+
+        let shader = Shader::new_wgsl(
+            device,
+            "dof",
+            &shader::builtin_source(
+                "dof.wgsl",
+                include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/shaders/dof.wgsl")),
+            ),
+        )
+        .expect("Could not parse dof shader");
+        assert_eq!(shader.layout_matches(&[&desc]), Ok(()));
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: None,
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let create_info = PipelineCreateInfo {
+            color_format: format,
+            depth_format: None,
+            depth_write: true,
+            vertex_layouts: &[],
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            cull_mode: Some(wgpu::Face::Back),
+            blend: Some(wgpu::BlendState::REPLACE),
+            shader: &shader,
+            label: Some("Depth Of Field Pipeline"),
+        };
+        let pipeline = RenderPipeline::new(device, pipeline_layout, create_info);
+
+        Self {
+            pipeline,
+            layout,
+            params_buffer,
+            output,
+            output_bind_group,
+            input_bind_groups: Vec::new(),
+        }
+    }
+
+    pub fn update(&self, queue: &wgpu::Queue, settings: &DofSettings, projection: &Projection) {
+        queue.write_buffer(
+            &self.params_buffer,
+            0,
+            bytemuck::cast_slice(&[DofParamsUniform::new(settings, projection)]),
+        );
+    }
+
+    /// Makes the bind groups for blurring each of `inputs`. Must be called
+    /// before `render`, and again whenever the inputs or the depth texture
+    /// are recreated.
+    pub fn set_inputs(
+        &mut self,
+        device: &wgpu::Device,
+        inputs: &[&texture::Texture],
+        depth_texture: &texture::Texture,
+    ) {
+        self.input_bind_groups = inputs
+            .iter()
+            .map(|input| {
+                device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    label: Some("DepthOfField::input_bind_group"),
+                    layout: &self.layout,
+                    entries: &[
+                        wgpu::BindGroupEntry {
+                            binding: 0,
+                            resource: wgpu::BindingResource::TextureView(&input.view),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 1,
+                            resource: wgpu::BindingResource::TextureView(&depth_texture.view),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 2,
+                            resource: self.params_buffer.as_entire_binding(),
+                        },
+                    ],
+                })
+            })
+            .collect();
+    }
+
+    /// Blurs the input at index `input` of the last `set_inputs` into the
+    /// output texture
+    pub fn render(&self, encoder: &mut wgpu::CommandEncoder, input: usize) {
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("DepthOfField::render"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: &self.output.view,
+                resolve_target: None,
+                ops: Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            ..Default::default()
+        });
+        pass.set_pipeline(self.pipeline.pipeline());
+        pass.set_bind_group(0, &self.input_bind_groups[input], &[]);
+        pass.draw(0..3, 0..1);
+    }
+
+    pub fn output_view(&self) -> &wgpu::TextureView {
+        &self.output.view
+    }
+
+    /// Bind group for tonemapping the blurred image
+    pub fn output_bind_group(&self) -> &wgpu::BindGroup {
+        &self.output_bind_group
+    }
+}
@@ -2,7 +2,9 @@ use std::error;
 
 use thiserror::Error;
 use wgpu::naga::front::glsl;
+use wgpu::naga::front::spv;
 use wgpu::naga::front::wgsl;
+use wgpu::naga::SourceLocation;
 
 use image::ImageError;
 use tobj::LoadError;
@@ -12,16 +14,57 @@ use winit::error::EventLoopError;
 pub enum RendererError {
     #[error("Error Decoding HDR image: {0}")]
     HdrDecoding(#[from] ImageError),
+    #[error("Error saving image: {0}")]
+    ImageSave(ImageError),
+    #[error("Error writing EXR image: {0}")]
+    ExrWrite(#[from] exr::error::Error),
     #[error("Error reading file: {0}")]
     Io(#[from] std::io::Error),
     #[error("Error parsing WGSL source: {0}")]
     WgslShaderParse(#[from] wgsl::ParseError),
+    #[error("Error parsing WGSL source: {message}")]
+    PreprocessedWgslParse {
+        message: String,
+        /// Location in the file that was preprocessed, `None` if the error
+        /// is in an included file
+        location: Option<SourceLocation>,
+    },
+    #[error("Error including shader source: {0}")]
+    ShaderInclude(String),
     #[error("Error parsing GLSL source: {0:?}")]
     GlslShaderParse(Vec<glsl::Error>),
+    #[error("Error parsing SPIR-V: {0}")]
+    SpirvShaderParse(#[from] spv::Error),
+    #[error("Not a SPIR-V binary: {0}")]
+    InvalidSpirv(String),
     #[error("Error compiling shader: {0}")]
     ShaderCompile(String),
+    #[error("Invalid clip planes: near {znear} must be positive and less than far {zfar}")]
+    InvalidClipPlanes { znear: f32, zfar: f32 },
     #[error("Error loading model: {0}")]
     ObjLoad(#[from] LoadError),
+    #[error("Model load cancelled")]
+    Cancelled,
+    #[error("Error reading config: {0}")]
+    ConfigDeserialize(#[from] toml::de::Error),
+    #[error("Error writing config: {0}")]
+    ConfigSerialize(#[from] toml::ser::Error),
+    #[error("Output size {width}x{height} must be at least 1x1")]
+    InvalidOutputSize { width: u32, height: u32 },
+    #[error("Cube map size {size} must be between 1 and {max}, the most the device and the memory budget allow")]
+    InvalidCubemapSize { size: u32, max: u32 },
+    #[error("Surface does not support format {0:?}")]
+    UnsupportedSurfaceFormat(wgpu::TextureFormat),
+    #[error("Unable to create a surface for the window: {0}")]
+    CreateSurface(#[from] wgpu::CreateSurfaceError),
+    #[error(
+        "No suitable graphics adapter found, a GPU with Vulkan, Metal, DX12 or OpenGL support is required"
+    )]
+    AdapterNotFound,
+    #[error("The graphics adapter could not provide a device: {0}")]
+    DeviceRequest(#[from] wgpu::RequestDeviceError),
+    #[error("Error mapping buffer: {0}")]
+    BufferMap(#[from] wgpu::BufferAsyncError),
     #[error("Error running event loop: {0}")]
     EventLoop(#[from] EventLoopError),
     #[error("WGPU Validation Error: {source}\n\tDescription: {description}")]
@@ -39,3 +82,63 @@ impl From<Vec<glsl::Error>> for RendererError {
 }
 
 pub type RendererResult<T> = Result<T, RendererError>;
+
+/// Position of a shader compile error in the source, in characters
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ShaderErrorLocation {
+    /// 1-based line number
+    pub line: usize,
+    /// 1-based column
+    pub column: usize,
+    /// Character offset of the start of the offending span
+    pub start: usize,
+    /// Character offset one past the end of the offending span
+    pub end: usize,
+}
+
+/// A shader compile error as shown in the editor
+#[derive(Debug, Clone)]
+pub struct ShaderCompileError {
+    pub message: String,
+    pub location: Option<ShaderErrorLocation>,
+}
+
+impl ShaderCompileError {
+    pub fn new(error: &RendererError, source: &str) -> Self {
+        let location = match error {
+            RendererError::WgslShaderParse(parse_error) => parse_error.location(source),
+            RendererError::PreprocessedWgslParse { location, .. } => *location,
+            _ => None,
+        }
+        .map(|location| {
+            // naga reports byte offsets, but the editor works in characters
+            let offset = location.offset as usize;
+            let before = &source[..offset];
+            let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+            let start = before.chars().count();
+            let end = start
+                + source[offset..offset + location.length as usize]
+                    .chars()
+                    .count();
+            ShaderErrorLocation {
+                line: location.line_number as usize,
+                column: before[line_start..].chars().count() + 1,
+                start,
+                end,
+            }
+        });
+        Self {
+            message: error.to_string(),
+            location,
+        }
+    }
+}
+
+impl From<String> for ShaderCompileError {
+    fn from(message: String) -> Self {
+        Self {
+            message,
+            location: None,
+        }
+    }
+}
@@ -0,0 +1,88 @@
+use cgmath::{EuclideanSpace, InnerSpace, Matrix, Matrix4, Point3, Vector4};
+
+/// The six planes bounding what a camera can see, for culling
+#[derive(Clone, Copy, Debug)]
+pub struct Frustum {
+    /// Normalized planes with normals pointing inwards, `xyz` is the normal
+    /// and `w` the distance
+    planes: [Vector4<f32>; 6],
+}
+
+impl Frustum {
+    /// Extracts the planes from a view projection matrix. The projection is
+    /// OpenGL style, so the near plane is where z = -w.
+    pub fn from_view_proj(view_proj: Matrix4<f32>) -> Self {
+        let row = |i| view_proj.row(i);
+        let planes = [
+            row(3) + row(0),
+            row(3) - row(0),
+            row(3) + row(1),
+            row(3) - row(1),
+            row(3) + row(2),
+            row(3) - row(2),
+        ]
+        .map(|plane| plane / plane.truncate().magnitude());
+        Self { planes }
+    }
+
+    /// Whether any part of the sphere may be inside the frustum
+    pub fn intersects_sphere(&self, center: Point3<f32>, radius: f32) -> bool {
+        self.planes
+            .iter()
+            .all(|plane| plane.truncate().dot(center.to_vec()) + plane.w >= -radius)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cgmath::{ortho, perspective, Deg, Vector3};
+
+    fn assert_plane_eq(actual: Vector4<f32>, expected: Vector4<f32>) {
+        assert!(
+            (actual - expected).magnitude() < 1e-5,
+            "expected plane {:?}, got {:?}",
+            expected,
+            actual
+        );
+    }
+
+    #[test]
+    fn planes_of_an_orthographic_box() {
+        // Looking down -z, so the near plane is at z = -1 and the far one
+        // at z = -10
+        let frustum = Frustum::from_view_proj(ortho(-2.0, 2.0, -1.0, 1.0, 1.0, 10.0));
+        let expected = [
+            Vector4::new(1.0, 0.0, 0.0, 2.0),
+            Vector4::new(-1.0, 0.0, 0.0, 2.0),
+            Vector4::new(0.0, 1.0, 0.0, 1.0),
+            Vector4::new(0.0, -1.0, 0.0, 1.0),
+            Vector4::new(0.0, 0.0, -1.0, -1.0),
+            Vector4::new(0.0, 0.0, 1.0, 10.0),
+        ];
+        for (actual, expected) in frustum.planes.into_iter().zip(expected) {
+            assert_plane_eq(actual, expected);
+        }
+    }
+
+    #[test]
+    fn spheres_against_a_perspective_frustum() {
+        let view = Matrix4::look_to_rh(
+            Point3::new(0.0, 0.0, 5.0),
+            Vector3::new(0.0, 0.0, -1.0),
+            Vector3::unit_y(),
+        );
+        let frustum = Frustum::from_view_proj(perspective(Deg(90.0), 1.0, 0.1, 100.0) * view);
+
+        assert!(frustum.intersects_sphere(Point3::origin(), 1.0));
+        // Behind the camera and past the far plane
+        assert!(!frustum.intersects_sphere(Point3::new(0.0, 0.0, 10.0), 1.0));
+        assert!(!frustum.intersects_sphere(Point3::new(0.0, 0.0, -200.0), 1.0));
+        // With a 90 degree field of view the side planes are at 45 degrees,
+        // so at 5 units away they are 5 units from the center line
+        assert!(!frustum.intersects_sphere(Point3::new(7.0, 0.0, 0.0), 1.0));
+        assert!(frustum.intersects_sphere(Point3::new(5.5, 0.0, 0.0), 1.0));
+        assert!(!frustum.intersects_sphere(Point3::new(0.0, -7.0, 0.0), 1.0));
+        assert!(frustum.intersects_sphere(Point3::new(0.0, -5.5, 0.0), 1.0));
+    }
+}
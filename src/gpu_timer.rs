@@ -0,0 +1,139 @@
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+const SCENE_BEGIN: u32 = 0;
+const SCENE_END: u32 = 1;
+const TONEMAP_BEGIN: u32 = 2;
+const TONEMAP_END: u32 = 3;
+const QUERY_COUNT: u32 = 4;
+const BUFFER_SIZE: wgpu::BufferAddress =
+    QUERY_COUNT as wgpu::BufferAddress * wgpu::QUERY_SIZE as wgpu::BufferAddress;
+
+/// GPU time spent in each pass of a frame, in milliseconds
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PassTimings {
+    pub scene: f32,
+    pub tonemap: f32,
+}
+
+/// Times the model and tonemapping passes with timestamp queries. Results
+/// are read back without waiting on the GPU, so they trail the current frame
+/// by a few frames.
+pub struct GpuTimer {
+    query_set: wgpu::QuerySet,
+    resolve_buffer: wgpu::Buffer,
+    readback_buffer: wgpu::Buffer,
+    /// Nanoseconds per timestamp tick
+    period: f32,
+    /// Set while `readback_buffer` is mapped or waiting to be
+    mapping: bool,
+    mapped: Arc<AtomicBool>,
+    timings: Option<PassTimings>,
+}
+
+impl GpuTimer {
+    /// Returns `None` if the device wasn't opened with
+    /// `Features::TIMESTAMP_QUERY`
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue) -> Option<Self> {
+        if !device.features().contains(wgpu::Features::TIMESTAMP_QUERY) {
+            return None;
+        }
+        let query_set = device.create_query_set(&wgpu::QuerySetDescriptor {
+            label: Some("GpuTimer::query_set"),
+            ty: wgpu::QueryType::Timestamp,
+            count: QUERY_COUNT,
+        });
+        let resolve_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("GpuTimer::resolve_buffer"),
+            size: BUFFER_SIZE,
+            usage: wgpu::BufferUsages::QUERY_RESOLVE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let readback_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("GpuTimer::readback_buffer"),
+            size: BUFFER_SIZE,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+        Some(Self {
+            query_set,
+            resolve_buffer,
+            readback_buffer,
+            period: queue.get_timestamp_period(),
+            mapping: false,
+            mapped: Arc::new(AtomicBool::new(false)),
+            timings: None,
+        })
+    }
+
+    pub fn scene_writes(&self) -> wgpu::RenderPassTimestampWrites<'_> {
+        wgpu::RenderPassTimestampWrites {
+            query_set: &self.query_set,
+            beginning_of_pass_write_index: Some(SCENE_BEGIN),
+            end_of_pass_write_index: Some(SCENE_END),
+        }
+    }
+
+    pub fn tonemap_writes(&self) -> wgpu::RenderPassTimestampWrites<'_> {
+        wgpu::RenderPassTimestampWrites {
+            query_set: &self.query_set,
+            beginning_of_pass_write_index: Some(TONEMAP_BEGIN),
+            end_of_pass_write_index: Some(TONEMAP_END),
+        }
+    }
+
+    /// Copies this frame's timestamps out for reading, unless the previous
+    /// copy is still being read
+    pub fn resolve(&self, encoder: &mut wgpu::CommandEncoder) {
+        if self.mapping {
+            return;
+        }
+        encoder.resolve_query_set(&self.query_set, 0..QUERY_COUNT, &self.resolve_buffer, 0);
+        encoder.copy_buffer_to_buffer(
+            &self.resolve_buffer,
+            0,
+            &self.readback_buffer,
+            0,
+            BUFFER_SIZE,
+        );
+    }
+
+    /// Called after the frame that `resolve` was recorded in is submitted.
+    /// Starts reading back the resolved timestamps, or picks up the result of
+    /// an earlier read if it has finished.
+    pub fn collect(&mut self, device: &wgpu::Device) {
+        if !self.mapping {
+            let mapped = self.mapped.clone();
+            self.readback_buffer
+                .slice(..)
+                .map_async(wgpu::MapMode::Read, move |result| {
+                    mapped.store(result.is_ok(), Ordering::Release);
+                });
+            self.mapping = true;
+        }
+        device.poll(wgpu::Maintain::Poll);
+        if !self.mapped.swap(false, Ordering::Acquire) {
+            return;
+        }
+        {
+            let view = self.readback_buffer.slice(..).get_mapped_range();
+            let timestamps: &[u64] = bytemuck::cast_slice(&view);
+            let millis = |begin: usize, end: usize| {
+                timestamps[end].wrapping_sub(timestamps[begin]) as f32 * self.period / 1_000_000.0
+            };
+            self.timings = Some(PassTimings {
+                scene: millis(SCENE_BEGIN as usize, SCENE_END as usize),
+                tonemap: millis(TONEMAP_BEGIN as usize, TONEMAP_END as usize),
+            });
+        }
+        self.readback_buffer.unmap();
+        self.mapping = false;
+    }
+
+    /// The most recently read timings, `None` until the first read finishes
+    pub fn timings(&self) -> Option<PassTimings> {
+        self.timings
+    }
+}
@@ -0,0 +1,184 @@
+use wgpu::util::DeviceExt;
+
+use crate::pipeline::{PipelineCreateInfo, RenderPipeline};
+use crate::shader::Shader;
+use crate::texture::Texture;
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct GridUniform {
+    color: [f32; 4],
+    spacing: f32,
+    _padding: [f32; 3],
+}
+
+impl GridUniform {
+    fn new(spacing: f32, color: [f32; 4]) -> Self {
+        Self {
+            color,
+            spacing,
+            _padding: [0.0; 3],
+        }
+    }
+}
+
+const fn get_grid_layout_desc() -> wgpu::BindGroupLayoutDescriptor<'static> {
+    const LAYOUT_ENTRIES: [wgpu::BindGroupLayoutEntry; 1] = [wgpu::BindGroupLayoutEntry {
+        binding: 0,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Uniform,
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        count: None,
+    }];
+    wgpu::BindGroupLayoutDescriptor {
+        label: Some("Grid Bind Group Layout"),
+        entries: &LAYOUT_ENTRIES,
+    }
+}
+
+/// Draws an infinite ground grid on the y = 0 plane. The lines are computed
+/// per pixel in the fragment shader instead of being drawn as geometry.
+pub struct GridRenderer {
+    spacing: f32,
+    color: [f32; 4],
+    buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    pipeline: RenderPipeline,
+}
+
+impl GridRenderer {
+    pub fn new(
+        device: &wgpu::Device,
+        camera_bind_group_layout_desc: &wgpu::BindGroupLayoutDescriptor<'_>,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        surface_format: wgpu::TextureFormat,
+    ) -> Self {
+        let spacing = 1.0;
+        let color = [0.5, 0.5, 0.5, 1.0];
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Grid Buffer"),
+            contents: bytemuck::cast_slice(&[GridUniform::new(spacing, color)]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let desc = get_grid_layout_desc();
+        let layout = device.create_bind_group_layout(&desc);
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Grid Bind Group"),
+            layout: &layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: buffer.as_entire_binding(),
+            }],
+        });
+
+        let pipeline = {
+            let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Grid Pipeline Layout"),
+                bind_group_layouts: &[camera_bind_group_layout, &layout],
+                push_constant_ranges: &[],
+            });
+
+            let shader = Self::create_shader(device);
+            assert_eq!(
+                shader.layout_matches(&[camera_bind_group_layout_desc, &desc]),
+                Ok(())
+            );
+            RenderPipeline::new(
+                device,
+                pipeline_layout,
+                Self::pipeline_create_info(&shader, surface_format),
+            )
+        };
+
+        Self {
+            spacing,
+            color,
+            buffer,
+            bind_group,
+            pipeline,
+        }
+    }
+
+    fn create_shader(device: &wgpu::Device) -> Shader {
+        Shader::new_wgsl(
+            device,
+            "grid",
+            include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/shaders/grid.wgsl")),
+        )
+        .expect("Could not parse grid shader")
+    }
+
+    fn pipeline_create_info(
+        shader: &Shader,
+        color_format: wgpu::TextureFormat,
+    ) -> PipelineCreateInfo<'_> {
+        PipelineCreateInfo {
+            color_format,
+            depth_format: Some(Texture::DEPTH_FORMAT),
+            depth_write: true,
+            vertex_layouts: &[],
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            cull_mode: Some(wgpu::Face::Back),
+            blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+            shader,
+            label: Some("Grid Pipeline"),
+        }
+    }
+
+    /// Rebuilds the pipeline to draw into a target of a new format
+    pub fn set_color_format(&mut self, device: &wgpu::Device, color_format: wgpu::TextureFormat) {
+        let shader = Self::create_shader(device);
+        self.pipeline
+            .recreate(device, Self::pipeline_create_info(&shader, color_format))
+            .expect("Could not recreate grid pipeline");
+    }
+
+    fn write_uniform(&self, queue: &wgpu::Queue) {
+        queue.write_buffer(
+            &self.buffer,
+            0,
+            bytemuck::cast_slice(&[GridUniform::new(self.spacing, self.color)]),
+        );
+    }
+
+    /// Distance between lines in world units
+    pub fn spacing(&self) -> f32 {
+        self.spacing
+    }
+
+    pub fn set_spacing(&mut self, queue: &wgpu::Queue, spacing: f32) {
+        if self.spacing == spacing || spacing <= 0.0 {
+            return;
+        }
+        self.spacing = spacing;
+        self.write_uniform(queue);
+    }
+
+    /// Line color, alpha scales how opaque the lines are
+    pub fn color(&self) -> [f32; 4] {
+        self.color
+    }
+
+    pub fn set_color(&mut self, queue: &wgpu::Queue, color: [f32; 4]) {
+        if self.color == color {
+            return;
+        }
+        self.color = color;
+        self.write_uniform(queue);
+    }
+
+    /// Must be drawn after the sky, which it blends over
+    pub fn render<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        camera_bind_group: &'a wgpu::BindGroup,
+    ) {
+        render_pass.set_pipeline(self.pipeline.pipeline());
+        render_pass.set_bind_group(0, camera_bind_group, &[]);
+        render_pass.set_bind_group(1, &self.bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
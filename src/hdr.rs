@@ -1,6 +1,164 @@
-use wgpu::Operations;
+use serde::{Deserialize, Serialize};
+use wgpu::{util::DeviceExt, Operations};
 
-use crate::{pipeline::PipelineCreateInfo, shader::Shader, texture, RenderPipeline};
+use crate::{
+    dof::DepthOfField,
+    pipeline::{PipelineCreateInfo, RenderPipeline},
+    shader::{self, Shader},
+    taa::Taa,
+    texture,
+};
+
+/// Format of the texture the scene is rendered to before tonemapping
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum HdrFormat {
+    /// Renderable and filterable everywhere without extra features
+    #[default]
+    Rgba16Float,
+    /// More precision for debugging banding, needs `FLOAT32_FILTERABLE`
+    Rgba32Float,
+    /// Cheaper on weak adapters, but values above 1 are clamped
+    Rgb10a2Unorm,
+}
+
+impl HdrFormat {
+    pub const ALL: [HdrFormat; 3] = [
+        HdrFormat::Rgba16Float,
+        HdrFormat::Rgba32Float,
+        HdrFormat::Rgb10a2Unorm,
+    ];
+
+    pub fn texture_format(self) -> wgpu::TextureFormat {
+        match self {
+            HdrFormat::Rgba16Float => wgpu::TextureFormat::Rgba16Float,
+            HdrFormat::Rgba32Float => wgpu::TextureFormat::Rgba32Float,
+            HdrFormat::Rgb10a2Unorm => wgpu::TextureFormat::Rgb10a2Unorm,
+        }
+    }
+
+    /// Features the device needs for the format to be sampled with filtering
+    pub fn required_features(self) -> wgpu::Features {
+        match self {
+            HdrFormat::Rgba32Float => wgpu::Features::FLOAT32_FILTERABLE,
+            HdrFormat::Rgba16Float | HdrFormat::Rgb10a2Unorm => wgpu::Features::empty(),
+        }
+    }
+
+    /// Whether `device`, created from `adapter`, can render to, blend into,
+    /// filter and copy from textures of this format
+    pub fn is_supported(self, adapter: &wgpu::Adapter, device: &wgpu::Device) -> bool {
+        let features = adapter.get_texture_format_features(self.texture_format());
+        features.allowed_usages.contains(
+            wgpu::TextureUsages::RENDER_ATTACHMENT
+                | wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::COPY_SRC,
+        ) && features.flags.contains(
+            wgpu::TextureFormatFeatureFlags::FILTERABLE
+                | wgpu::TextureFormatFeatureFlags::BLENDABLE,
+        ) && device.features().contains(self.required_features())
+    }
+
+    /// Converts a row of texels read back from the texture to RGBA floats
+    pub fn decode_row(self, row: &[u8]) -> Vec<f32> {
+        match self {
+            HdrFormat::Rgba16Float => row
+                .chunks_exact(2)
+                .map(|bytes| half::f16::from_le_bytes([bytes[0], bytes[1]]).to_f32())
+                .collect(),
+            HdrFormat::Rgba32Float => row
+                .chunks_exact(4)
+                .map(|bytes| f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+                .collect(),
+            HdrFormat::Rgb10a2Unorm => row
+                .chunks_exact(4)
+                .flat_map(|bytes| {
+                    let texel = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+                    [
+                        (texel & 0x3ff) as f32 / 1023.0,
+                        ((texel >> 10) & 0x3ff) as f32 / 1023.0,
+                        ((texel >> 20) & 0x3ff) as f32 / 1023.0,
+                        (texel >> 30) as f32 / 3.0,
+                    ]
+                })
+                .collect(),
+        }
+    }
+
+    pub fn bytes_per_pixel(self) -> u32 {
+        match self {
+            HdrFormat::Rgba16Float => 8,
+            HdrFormat::Rgba32Float => 16,
+            HdrFormat::Rgb10a2Unorm => 4,
+        }
+    }
+}
+
+/// How the tonemapped color is encoded for the output
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputTransform {
+    /// Written linear, the sRGB target does the encode in hardware
+    HardwareSrgb,
+    /// Encoded with a 2.2 gamma in the shader, for targets that aren't sRGB
+    Gamma22,
+}
+
+impl OutputTransform {
+    /// The transform that gives correct output on a `format` target
+    pub fn for_format(format: wgpu::TextureFormat) -> Self {
+        if format.is_srgb() {
+            OutputTransform::HardwareSrgb
+        } else {
+            OutputTransform::Gamma22
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct OutputTransformUniform {
+    mode: u32,
+    _padding: [u32; 3],
+}
+
+impl OutputTransformUniform {
+    fn new(transform: OutputTransform) -> Self {
+        let mode = match transform {
+            OutputTransform::HardwareSrgb => 0,
+            OutputTransform::Gamma22 => 1,
+        };
+        Self {
+            mode,
+            _padding: [0; 3],
+        }
+    }
+}
+
+/// Bind group for tonemapping `texture`
+fn create_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    texture: &texture::Texture,
+    output_transform_buffer: &wgpu::Buffer,
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("Hdr::bind_group"),
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(&texture.view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::Sampler(&texture.sampler),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: output_transform_buffer.as_entire_binding(),
+            },
+        ],
+    })
+}
 
 /// Owns the render texture and controls tonemapping
 pub struct HdrPipeline {
@@ -9,29 +167,36 @@ pub struct HdrPipeline {
     texture: texture::Texture,
     width: u32,
     height: u32,
+    hdr_format: HdrFormat,
     format: wgpu::TextureFormat,
     layout: wgpu::BindGroupLayout,
+    output_transform: OutputTransform,
+    output_transform_buffer: wgpu::Buffer,
+    /// Resolves the render texture before tonemapping when enabled
+    taa: Option<Taa>,
+    /// Blurs the resolved image before tonemapping when enabled
+    dof: Option<DepthOfField>,
 }
 
 impl HdrPipeline {
     pub fn new(
         device: &wgpu::Device,
         extent: &wgpu::Extent3d,
+        hdr_format: HdrFormat,
         surface_format: wgpu::TextureFormat,
     ) -> Self {
         let width = extent.width;
         let height = extent.height;
-
-        // We could use `Rgba32Float`, but that requires some extra
-        // features to be enabled for rendering.
-        let format = wgpu::TextureFormat::Rgba16Float;
+        let format = hdr_format.texture_format();
 
         let texture = texture::Texture::create_2d_texture(
             device,
             width,
             height,
             format,
-            wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::RENDER_ATTACHMENT,
+            wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::RENDER_ATTACHMENT
+                | wgpu::TextureUsages::COPY_SRC,
             wgpu::FilterMode::Nearest,
             Some("Hdr::texture"),
         );
@@ -55,31 +220,30 @@ impl HdrPipeline {
                     ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
                     count: None,
                 },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
             ],
         };
         let layout = device.create_bind_group_layout(&desc);
-        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("Hdr::bind_group"),
-            layout: &layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&texture.view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&texture.sampler),
-                },
-            ],
-        });
+        let output_transform = OutputTransform::for_format(surface_format);
+        let output_transform_buffer =
+            device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("Hdr::output_transform_buffer"),
+                contents: bytemuck::cast_slice(&[OutputTransformUniform::new(output_transform)]),
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            });
+        let bind_group = create_bind_group(device, &layout, &texture, &output_transform_buffer);
 
         // Shader
-        let shader = Shader::new_wgsl(
-            device,
-            "hdr",
-            include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/shaders/hdr.wgsl")),
-        )
-        .expect("Could not parse hdr shader");
+        let shader = Self::create_shader(device);
         assert_eq!(shader.layout_matches(&[&desc]), Ok(()));
         let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
             label: None,
@@ -90,8 +254,11 @@ impl HdrPipeline {
         let create_info = PipelineCreateInfo {
             color_format: surface_format,
             depth_format: None,
+            depth_write: true,
             vertex_layouts: &[],
             topology: wgpu::PrimitiveTopology::TriangleList,
+            cull_mode: Some(wgpu::Face::Back),
+            blend: Some(wgpu::BlendState::REPLACE),
             shader: &shader,
             label: Some("HDR Pipeline"),
         };
@@ -104,47 +271,237 @@ impl HdrPipeline {
             texture,
             width,
             height,
+            hdr_format,
             format,
+            output_transform,
+            output_transform_buffer,
+            taa: None,
+            dof: None,
         }
     }
 
-    pub fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
+    fn create_shader(device: &wgpu::Device) -> Shader {
+        Shader::new_wgsl(
+            device,
+            "hdr",
+            &shader::builtin_source(
+                "hdr.wgsl",
+                include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/shaders/hdr.wgsl")),
+            ),
+        )
+        .expect("Could not parse hdr shader")
+    }
+
+    /// Rebuilds the tonemapping pipeline to write to a target of
+    /// `output_format`, switching to the output transform that suits it
+    pub fn set_output_format(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        output_format: wgpu::TextureFormat,
+    ) {
+        self.set_output_transform(queue, OutputTransform::for_format(output_format));
+        let shader = Self::create_shader(device);
+        let create_info = PipelineCreateInfo {
+            color_format: output_format,
+            depth_format: None,
+            depth_write: true,
+            vertex_layouts: &[],
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            cull_mode: Some(wgpu::Face::Back),
+            blend: Some(wgpu::BlendState::REPLACE),
+            shader: &shader,
+            label: Some("HDR Pipeline"),
+        };
+        self.pipeline
+            .recreate(device, create_info)
+            .expect("Could not recreate hdr pipeline");
+    }
+
+    /// Must be called whenever the depth texture is recreated, which TAA
+    /// reads from
+    pub fn resize(
+        &mut self,
+        device: &wgpu::Device,
+        width: u32,
+        height: u32,
+        depth_texture: &texture::Texture,
+    ) {
         self.texture = texture::Texture::create_2d_texture(
             device,
             width,
             height,
             self.format,
-            wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::RENDER_ATTACHMENT,
+            wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::RENDER_ATTACHMENT
+                | wgpu::TextureUsages::COPY_SRC,
             wgpu::FilterMode::Nearest,
             Some("Hdr::texture"),
         );
-        self.bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("Hdr::bind_group"),
-            layout: &self.layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&self.texture.view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&self.texture.sampler),
-                },
-            ],
-        });
+        self.bind_group = create_bind_group(
+            device,
+            &self.layout,
+            &self.texture,
+            &self.output_transform_buffer,
+        );
         self.width = width;
         self.height = height;
+        if self.taa.is_some() {
+            self.taa = Some(self.create_taa(device, depth_texture));
+        }
+        if self.dof.is_some() {
+            self.dof = Some(self.create_dof(device));
+            self.set_dof_inputs(device, depth_texture);
+        }
+    }
+
+    /// Recreates the texture, and TAA and depth of field if they are on, in
+    /// a new format. Everything drawn into the texture has to be rebuilt for
+    /// the new format too.
+    pub fn set_hdr_format(
+        &mut self,
+        device: &wgpu::Device,
+        hdr_format: HdrFormat,
+        depth_texture: &texture::Texture,
+    ) {
+        self.hdr_format = hdr_format;
+        self.format = hdr_format.texture_format();
+        self.resize(device, self.width, self.height, depth_texture);
+    }
+
+    fn create_taa(&self, device: &wgpu::Device, depth_texture: &texture::Texture) -> Taa {
+        Taa::new(
+            device,
+            &self.texture,
+            depth_texture,
+            self.format,
+            |history| {
+                create_bind_group(device, &self.layout, history, &self.output_transform_buffer)
+            },
+        )
+    }
+
+    pub fn taa_enabled(&self) -> bool {
+        self.taa.is_some()
+    }
+
+    /// Turns temporal anti-aliasing on or off. The history starts over when
+    /// it is turned on.
+    pub fn set_taa_enabled(
+        &mut self,
+        device: &wgpu::Device,
+        depth_texture: &texture::Texture,
+        enabled: bool,
+    ) {
+        self.taa = enabled.then(|| self.create_taa(device, depth_texture));
+        self.set_dof_inputs(device, depth_texture);
+    }
+
+    pub fn taa_mut(&mut self) -> Option<&mut Taa> {
+        self.taa.as_mut()
+    }
+
+    fn create_dof(&self, device: &wgpu::Device) -> DepthOfField {
+        DepthOfField::new(device, self.width, self.height, self.format, |output| {
+            create_bind_group(device, &self.layout, output, &self.output_transform_buffer)
+        })
+    }
+
+    /// Points depth of field at the textures it can blur: the TAA history
+    /// when TAA is on, or the HDR texture
+    fn set_dof_inputs(&mut self, device: &wgpu::Device, depth_texture: &texture::Texture) {
+        let Some(dof) = &mut self.dof else {
+            return;
+        };
+        let inputs: Vec<&texture::Texture> = match &self.taa {
+            Some(taa) => taa.history().iter().collect(),
+            None => vec![&self.texture],
+        };
+        dof.set_inputs(device, &inputs, depth_texture);
+    }
+
+    pub fn dof(&self) -> Option<&DepthOfField> {
+        self.dof.as_ref()
+    }
+
+    pub fn set_dof_enabled(
+        &mut self,
+        device: &wgpu::Device,
+        depth_texture: &texture::Texture,
+        enabled: bool,
+    ) {
+        self.dof = enabled.then(|| self.create_dof(device));
+        self.set_dof_inputs(device, depth_texture);
+    }
+
+    /// Runs the enabled effects between drawing the scene and tonemapping:
+    /// the TAA resolve followed by depth of field. Anything drawn after this
+    /// has to go to `output_view`.
+    pub fn post_process(&self, encoder: &mut wgpu::CommandEncoder) {
+        let mut resolved = 0;
+        if let Some(taa) = &self.taa {
+            taa.resolve(encoder);
+            resolved = taa.output_index();
+        }
+        if let Some(dof) = &self.dof {
+            dof.render(encoder, resolved);
+        }
+    }
+
+    pub fn output_transform(&self) -> OutputTransform {
+        self.output_transform
+    }
+
+    /// Overrides the encode chosen for the output format
+    pub fn set_output_transform(&mut self, queue: &wgpu::Queue, transform: OutputTransform) {
+        self.output_transform = transform;
+        queue.write_buffer(
+            &self.output_transform_buffer,
+            0,
+            bytemuck::cast_slice(&[OutputTransformUniform::new(transform)]),
+        );
     }
 
     pub fn view(&self) -> &wgpu::TextureView {
         &self.texture.view
     }
 
+    /// The texture `process` tonemaps, the output of the last effect that
+    /// is enabled
+    pub fn output_view(&self) -> &wgpu::TextureView {
+        match (&self.dof, &self.taa) {
+            (Some(dof), _) => dof.output_view(),
+            (None, Some(taa)) => taa.output_view(),
+            (None, None) => &self.texture.view,
+        }
+    }
+
+    pub fn texture(&self) -> &wgpu::Texture {
+        &self.texture.texture
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn hdr_format(&self) -> HdrFormat {
+        self.hdr_format
+    }
+
     pub fn format(&self) -> wgpu::TextureFormat {
         self.format
     }
 
-    pub fn process(&self, encoder: &mut wgpu::CommandEncoder, output: &wgpu::TextureView) {
+    pub fn process(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        output: &wgpu::TextureView,
+        timestamp_writes: Option<wgpu::RenderPassTimestampWrites<'_>>,
+    ) {
         let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
             label: Some("Hdr::process"),
             color_attachments: &[Some(wgpu::RenderPassColorAttachment {
@@ -156,10 +513,16 @@ impl HdrPipeline {
                 },
             })],
             depth_stencil_attachment: None,
+            timestamp_writes,
             ..Default::default()
         });
         pass.set_pipeline(self.pipeline.pipeline());
-        pass.set_bind_group(0, &self.bind_group, &[]);
+        let bind_group = match (&self.dof, &self.taa) {
+            (Some(dof), _) => dof.output_bind_group(),
+            (None, Some(taa)) => taa.output_bind_group(),
+            (None, None) => &self.bind_group,
+        };
+        pass.set_bind_group(0, bind_group, &[]);
         pass.draw(0..3, 0..1);
     }
 }
@@ -0,0 +1,200 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
+use log::info;
+
+use crate::{
+    error::{RendererError, RendererResult},
+    hdr::HdrFormat,
+    render_target::TextureRenderTarget,
+    renderer::{self, Renderer, RendererCreateInfo},
+    SceneOptions,
+};
+
+const OUTPUT_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
+const BYTES_PER_PIXEL: u32 = 4;
+
+/// Options for rendering a single frame without opening a window
+pub struct HeadlessArgs {
+    pub scene: SceneOptions,
+    pub width: u32,
+    pub height: u32,
+    /// Where to write the rendered PNG
+    pub output: PathBuf,
+}
+
+/// Options for rendering an animation to a numbered sequence of PNGs
+pub struct SequenceArgs {
+    pub scene: SceneOptions,
+    pub width: u32,
+    pub height: u32,
+    pub fps: u32,
+    pub duration: Duration,
+    /// Directory the `frame_0000.png`... files are written to
+    pub output_dir: PathBuf,
+}
+
+/// A renderer drawing into a texture that can be read back to the CPU
+struct Offscreen {
+    renderer: Renderer,
+    target: TextureRenderTarget,
+    readback_buffer: wgpu::Buffer,
+    extent: wgpu::Extent3d,
+    padded_bytes_per_row: u32,
+}
+
+impl Offscreen {
+    async fn new(scene: SceneOptions, width: u32, height: u32) -> RendererResult<Self> {
+        if width == 0 || height == 0 {
+            return Err(RendererError::InvalidOutputSize { width, height });
+        }
+        let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
+            backends: wgpu::Backends::all(),
+            ..Default::default()
+        });
+        let (_adapter, device, queue) =
+            renderer::request_device(&instance, None, scene.backends).await?;
+
+        let starting_shader = match scene.shader {
+            Some(path) => {
+                let source = std::fs::read_to_string(&path)?;
+                Some((path, source))
+            }
+            None => None,
+        };
+        let extent = wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        };
+        let mut renderer = Renderer::new(
+            device,
+            queue,
+            RendererCreateInfo {
+                extent: &extent,
+                output_format: OUTPUT_FORMAT,
+                hdr_format: HdrFormat::default(),
+                starting_shader,
+                model_file: &scene.model,
+                config: &scene.renderer,
+            },
+        )
+        .await?;
+        if let Some(path) = &scene.environment {
+            renderer.load_environment(path)?;
+        }
+        // Places the instances, nothing animates with a zero timestep
+        renderer.update(Duration::ZERO);
+
+        let target = TextureRenderTarget::new(
+            &renderer.device,
+            width,
+            height,
+            OUTPUT_FORMAT,
+            Some("Offscreen::target"),
+        );
+        let padded_bytes_per_row = (width * BYTES_PER_PIXEL)
+            .div_ceil(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT)
+            * wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+        let readback_buffer = renderer.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Offscreen::readback_buffer"),
+            size: (padded_bytes_per_row * height) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        Ok(Self {
+            renderer,
+            target,
+            readback_buffer,
+            extent,
+            padded_bytes_per_row,
+        })
+    }
+
+    /// Renders the current state of the scene and reads it back
+    fn capture(&self) -> RendererResult<image::RgbaImage> {
+        let mut encoder =
+            self.renderer
+                .device
+                .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                    label: Some("Offscreen::capture"),
+                });
+        self.renderer.render_to(&mut encoder, &self.target, None);
+        encoder.copy_texture_to_buffer(
+            self.target.texture().texture.as_image_copy(),
+            wgpu::ImageCopyBuffer {
+                buffer: &self.readback_buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(self.padded_bytes_per_row),
+                    rows_per_image: None,
+                },
+            },
+            self.extent,
+        );
+        self.renderer
+            .queue
+            .submit(std::iter::once(encoder.finish()));
+
+        let slice = self.readback_buffer.slice(..);
+        let (sender, receiver) = std::sync::mpsc::channel();
+        slice.map_async(wgpu::MapMode::Read, move |result| {
+            let _ = sender.send(result);
+        });
+        self.renderer.device.poll(wgpu::Maintain::Wait);
+        receiver
+            .recv()
+            .expect("Map callback dropped without being called")?;
+
+        let unpadded_bytes_per_row = (self.extent.width * BYTES_PER_PIXEL) as usize;
+        let pixels = slice
+            .get_mapped_range()
+            .chunks(self.padded_bytes_per_row as usize)
+            .flat_map(|row| &row[..unpadded_bytes_per_row])
+            .copied()
+            .collect::<Vec<_>>();
+        self.readback_buffer.unmap();
+
+        Ok(
+            image::RgbaImage::from_raw(self.extent.width, self.extent.height, pixels)
+                .expect("Readback buffer is the size of the image"),
+        )
+    }
+}
+
+/// Renders one frame to an offscreen texture and saves it to `args.output`
+pub async fn run_headless(args: HeadlessArgs) -> RendererResult<()> {
+    let offscreen = Offscreen::new(args.scene, args.width, args.height).await?;
+    offscreen
+        .capture()?
+        .save(&args.output)
+        .map_err(RendererError::ImageSave)?;
+    info!("Saved frame to {}", args.output.display());
+    Ok(())
+}
+
+/// Renders `args.duration` worth of frames, advancing the scene by a fixed
+/// `1 / args.fps` between each so that the output doesn't depend on how long
+/// a frame takes to render
+pub async fn run_sequence(args: SequenceArgs) -> RendererResult<()> {
+    let mut offscreen = Offscreen::new(args.scene, args.width, args.height).await?;
+    std::fs::create_dir_all(&args.output_dir)?;
+
+    let dt = Duration::from_secs(1) / args.fps;
+    let frame_count = (args.duration.as_secs_f64() * args.fps as f64).round() as u32;
+    for frame in 0..frame_count {
+        let path = args.output_dir.join(format!("frame_{:04}.png", frame));
+        offscreen
+            .capture()?
+            .save(&path)
+            .map_err(RendererError::ImageSave)?;
+        offscreen.renderer.update(dt);
+    }
+    info!(
+        "Saved {} frames to {}",
+        frame_count,
+        args.output_dir.display()
+    );
+    Ok(())
+}
@@ -0,0 +1,389 @@
+use wgpu::util::DeviceExt;
+
+use crate::texture::{self, CubeTexture, CubeTextureCreate2dParams};
+
+const IRRADIANCE_SIZE: u32 = 32;
+const PREFILTERED_SIZE: u32 = 128;
+const PREFILTERED_MIP_LEVELS: u32 = 5;
+const BRDF_LUT_SIZE: u32 = 512;
+/// Format of the environment maps `HdrLoader` creates, which the prefilter's
+/// mipmapped copy of the environment shares
+const SOURCE_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba32Float;
+
+/// Textures derived from an environment map for image based lighting
+pub struct IblTextures {
+    pub irradiance: CubeTexture,
+    pub prefiltered: CubeTexture,
+}
+
+/// Owns the compute pipelines that precompute the image based lighting textures
+pub struct IblGenerator {
+    texture_format: wgpu::TextureFormat,
+    convolve_layout: wgpu::BindGroupLayout,
+    brdf_layout: wgpu::BindGroupLayout,
+    downsample_layout: wgpu::BindGroupLayout,
+    irradiance: wgpu::ComputePipeline,
+    prefilter: wgpu::ComputePipeline,
+    brdf_lut: wgpu::ComputePipeline,
+    downsample: wgpu::ComputePipeline,
+}
+
+impl IblGenerator {
+    pub fn new(device: &wgpu::Device) -> Self {
+        let module = device.create_shader_module(wgpu::include_wgsl!(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/shaders/ibl.wgsl"
+        )));
+        let texture_format = wgpu::TextureFormat::Rgba16Float;
+
+        let convolve_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("IblGenerator::convolve_layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        view_dimension: wgpu::TextureViewDimension::Cube,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::NonFiltering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::StorageTexture {
+                        access: wgpu::StorageTextureAccess::WriteOnly,
+                        format: texture_format,
+                        view_dimension: wgpu::TextureViewDimension::D2Array,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+
+        let brdf_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("IblGenerator::brdf_layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 4,
+                visibility: wgpu::ShaderStages::COMPUTE,
+                ty: wgpu::BindingType::StorageTexture {
+                    access: wgpu::StorageTextureAccess::WriteOnly,
+                    format: texture_format,
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                },
+                count: None,
+            }],
+        });
+
+        let downsample_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("IblGenerator::downsample_layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 5,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        view_dimension: wgpu::TextureViewDimension::D2Array,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 6,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::StorageTexture {
+                        access: wgpu::StorageTextureAccess::WriteOnly,
+                        format: SOURCE_FORMAT,
+                        view_dimension: wgpu::TextureViewDimension::D2Array,
+                    },
+                    count: None,
+                },
+            ],
+        });
+
+        let convolve_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: None,
+                bind_group_layouts: &[&convolve_layout],
+                push_constant_ranges: &[],
+            });
+        let brdf_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: None,
+            bind_group_layouts: &[&brdf_layout],
+            push_constant_ranges: &[],
+        });
+        let downsample_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: None,
+                bind_group_layouts: &[&downsample_layout],
+                push_constant_ranges: &[],
+            });
+
+        let irradiance = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("ibl_irradiance"),
+            layout: Some(&convolve_pipeline_layout),
+            module: &module,
+            entry_point: "compute_irradiance",
+        });
+        let prefilter = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("ibl_prefilter"),
+            layout: Some(&convolve_pipeline_layout),
+            module: &module,
+            entry_point: "compute_prefilter",
+        });
+        let brdf_lut = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("ibl_brdf_lut"),
+            layout: Some(&brdf_pipeline_layout),
+            module: &module,
+            entry_point: "compute_brdf_lut",
+        });
+        let downsample = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("ibl_downsample"),
+            layout: Some(&downsample_pipeline_layout),
+            module: &module,
+            entry_point: "compute_downsample",
+        });
+
+        Self {
+            texture_format,
+            convolve_layout,
+            brdf_layout,
+            downsample_layout,
+            irradiance,
+            prefilter,
+            brdf_lut,
+            downsample,
+        }
+    }
+
+    /// Generates the split-sum BRDF lookup table. It doesn't depend on the
+    /// environment, so it only needs to be created once.
+    pub fn create_brdf_lut(&self, device: &wgpu::Device, queue: &wgpu::Queue) -> texture::Texture {
+        let lut = texture::Texture::create_2d_texture(
+            device,
+            BRDF_LUT_SIZE,
+            BRDF_LUT_SIZE,
+            self.texture_format,
+            wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::TEXTURE_BINDING,
+            wgpu::FilterMode::Linear,
+            Some("Brdf Lut"),
+        );
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("IblGenerator::brdf_bind_group"),
+            layout: &self.brdf_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 4,
+                resource: wgpu::BindingResource::TextureView(&lut.view),
+            }],
+        });
+
+        let mut encoder = device.create_command_encoder(&Default::default());
+        {
+            let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("IblGenerator::brdf_lut"),
+                ..Default::default()
+            });
+
+            let num_workgroups = BRDF_LUT_SIZE.div_ceil(16);
+            pass.set_pipeline(&self.brdf_lut);
+            pass.set_bind_group(0, &bind_group, &[]);
+            pass.dispatch_workgroups(num_workgroups, num_workgroups, 1);
+        }
+        queue.submit([encoder.finish()]);
+
+        lut
+    }
+
+    /// Copies `environment` into a cube with a full mip chain, each level
+    /// averaging the one above it. The prefilter reads coarser levels for
+    /// rougher mips, so that its samples can't pick out single bright texels.
+    fn mipmapped_source(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        environment: &CubeTexture,
+        label: Option<&str>,
+    ) -> CubeTexture {
+        let size = environment.texture().width();
+        let source = CubeTexture::create_2d(CubeTextureCreate2dParams {
+            device,
+            width: size,
+            height: size,
+            format: SOURCE_FORMAT,
+            mip_level_count: size.ilog2() + 1,
+            usage: wgpu::TextureUsages::STORAGE_BINDING
+                | wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::COPY_DST,
+            mag_filter: wgpu::FilterMode::Nearest,
+            label,
+        });
+        encoder.copy_texture_to_texture(
+            environment.texture().as_image_copy(),
+            source.texture().as_image_copy(),
+            environment.texture().size(),
+        );
+
+        let level_view = |mip| {
+            source.texture().create_view(&wgpu::TextureViewDescriptor {
+                label,
+                dimension: Some(wgpu::TextureViewDimension::D2Array),
+                base_mip_level: mip,
+                mip_level_count: Some(1),
+                ..Default::default()
+            })
+        };
+        for mip in 1..source.texture().mip_level_count() {
+            let src_view = level_view(mip - 1);
+            let dst_view = level_view(mip);
+            let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label,
+                layout: &self.downsample_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 5,
+                        resource: wgpu::BindingResource::TextureView(&src_view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 6,
+                        resource: wgpu::BindingResource::TextureView(&dst_view),
+                    },
+                ],
+            });
+
+            let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label,
+                ..Default::default()
+            });
+            let num_workgroups = (size >> mip).max(1).div_ceil(16);
+            pass.set_pipeline(&self.downsample);
+            pass.set_bind_group(0, &bind_group, &[]);
+            pass.dispatch_workgroups(num_workgroups, num_workgroups, 6);
+        }
+
+        source
+    }
+
+    /// Convolves `environment` into a diffuse irradiance cube and a specular
+    /// cube whose mip levels are prefiltered for increasing roughness.
+    /// `environment` has to be an Rgba32Float cube created with `COPY_SRC`.
+    pub fn generate(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        environment: &CubeTexture,
+        label: Option<&str>,
+    ) -> IblTextures {
+        let irradiance = CubeTexture::create_2d(CubeTextureCreate2dParams {
+            device,
+            width: IRRADIANCE_SIZE,
+            height: IRRADIANCE_SIZE,
+            format: self.texture_format,
+            mip_level_count: 1,
+            usage: wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::TEXTURE_BINDING,
+            mag_filter: wgpu::FilterMode::Linear,
+            label,
+        });
+        let prefiltered = CubeTexture::create_2d(CubeTextureCreate2dParams {
+            device,
+            width: PREFILTERED_SIZE,
+            height: PREFILTERED_SIZE,
+            format: self.texture_format,
+            mip_level_count: PREFILTERED_MIP_LEVELS,
+            usage: wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::TEXTURE_BINDING,
+            mag_filter: wgpu::FilterMode::Linear,
+            label,
+        });
+
+        // Each dispatch gets its own params buffer, since queued writes would
+        // all land before any of the dispatches run
+        let mut passes = vec![(
+            &self.irradiance,
+            irradiance.texture(),
+            0,
+            IRRADIANCE_SIZE,
+            0.0,
+        )];
+        for mip in 0..PREFILTERED_MIP_LEVELS {
+            let roughness = mip as f32 / (PREFILTERED_MIP_LEVELS - 1) as f32;
+            passes.push((
+                &self.prefilter,
+                prefiltered.texture(),
+                mip,
+                PREFILTERED_SIZE >> mip,
+                roughness,
+            ));
+        }
+
+        let mut encoder = device.create_command_encoder(&Default::default());
+        let source = self.mipmapped_source(device, &mut encoder, environment, label);
+        for (pipeline, texture, mip, size, roughness) in passes {
+            let dst_view = texture.create_view(&wgpu::TextureViewDescriptor {
+                label,
+                dimension: Some(wgpu::TextureViewDimension::D2Array),
+                base_mip_level: mip,
+                mip_level_count: Some(1),
+                ..Default::default()
+            });
+            let params = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("IblGenerator::params"),
+                contents: bytemuck::cast_slice(&[roughness, 0.0, 0.0, 0.0]),
+                usage: wgpu::BufferUsages::UNIFORM,
+            });
+            let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label,
+                layout: &self.convolve_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(source.view()),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::Sampler(source.sampler()),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: wgpu::BindingResource::TextureView(&dst_view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 3,
+                        resource: params.as_entire_binding(),
+                    },
+                ],
+            });
+
+            let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label,
+                ..Default::default()
+            });
+            let num_workgroups = size.div_ceil(16);
+            pass.set_pipeline(pipeline);
+            pass.set_bind_group(0, &bind_group, &[]);
+            pass.dispatch_workgroups(num_workgroups, num_workgroups, 6);
+        }
+        queue.submit([encoder.finish()]);
+
+        IblTextures {
+            irradiance,
+            prefiltered,
+        }
+    }
+}
@@ -1,139 +1,219 @@
 use std::{
     path::{Path, PathBuf},
-    sync::{Arc, Mutex},
+    sync::Arc,
     time::{Duration, Instant},
 };
 
-use egui::{Color32, RichText};
+use egui::{
+    text::{CCursor, CCursorRange},
+    Color32, RichText,
+};
 use egui_wgpu::ScreenDescriptor;
-use error::RendererResult;
-use scene_tree::SceneTree;
+use scene_tree::{NodeHandle, TransformComponents};
 use winit::{
+    dpi::{PhysicalPosition, PhysicalSize},
     event::*,
-    event_loop::EventLoop,
-    keyboard::{KeyCode, PhysicalKey},
-    window::{Window, WindowBuilder},
+    event_loop::{ControlFlow, EventLoop},
+    keyboard::{KeyCode, ModifiersState, PhysicalKey},
+    window::{Fullscreen, Window, WindowBuilder},
 };
 
-use cgmath::prelude::*;
-use log::{error, info};
-use wgpu::util::DeviceExt;
+use log::{error, info, warn};
 
 mod camera;
+mod camera_path;
+mod compute_view;
+mod config;
 mod cubemap;
+mod debug_primitives;
+mod depth_visualizer;
+mod dof;
 mod error;
+mod frustum;
+mod gpu_timer;
+mod grid;
 mod hdr;
+mod headless;
+mod ibl;
 mod light;
 mod model;
+mod picking;
 mod pipeline;
+mod plugin;
+mod preprocessor;
 mod render_target;
+mod renderer;
 mod resources;
 mod scene_tree;
 mod shader;
+mod shader_tab;
+mod shadow;
 mod surface;
+mod taa;
 mod texture;
 mod ui;
+mod user_uniforms;
+mod viewport;
+
+pub use camera::{Camera, CameraUniform, Projection};
+pub use error::{RendererError, RendererResult};
+pub use headless::{run_headless, run_sequence, HeadlessArgs, SequenceArgs};
+pub use plugin::{RenderPassContext, RenderPassPlugin};
+pub use render_target::{RenderTarget, TextureRenderTarget};
+pub use renderer::{InstanceLayout, RendererConfig};
+pub use shader::{
+    reflect_wgsl, OwningBindGroupLayoutDescriptor, ReflectedBinding, ReflectionInfo, Shader,
+    UniformStruct, VertexInput,
+};
+pub use texture::{ColorSpace, CubeTexture, CubeTextureCreate2dParams, Texture};
 
-use camera::{Camera, CameraController, PerspectiveCamera, Projection};
-use cubemap::CubeMapRenderer;
-use light::LightUniform;
-use model::{LightRenderer, ModelRenderer, Vertex};
-use pipeline::{PipelineCreateInfo, RenderPipeline};
-use render_target::{RenderTarget, SurfaceTextureRenderTarget};
-use shader::Shader;
+use camera::{AxisView, CameraAction, CameraController, ZoomMode};
+use camera_path::{CameraKeyframe, CameraPath};
+use config::Config;
+use cubemap::{SkyMode, CUBEMAP_SIZES};
+use debug_primitives::DebugPrimitiveMode;
+use hdr::{HdrFormat, OutputTransform};
+use render_target::SurfaceTextureRenderTarget;
+use renderer::{default_shader, Renderer, RendererCreateInfo};
+use shadow::SHADOW_MAP_SIZES;
 use surface::Surface;
-use ui::{highlight, CodeTheme, EguiDrawParams, EguiRenderer};
-
-use crate::model::InstanceRaw;
-
-const fn get_texture_layout_desc() -> wgpu::BindGroupLayoutDescriptor<'static> {
-    const LAYOUT_ENTRIES: [wgpu::BindGroupLayoutEntry; 4] = [
-        // diffuse texture
-        wgpu::BindGroupLayoutEntry {
-            binding: 0,
-            visibility: wgpu::ShaderStages::FRAGMENT,
-            ty: wgpu::BindingType::Texture {
-                multisampled: false,
-                sample_type: wgpu::TextureSampleType::Float { filterable: true },
-                view_dimension: wgpu::TextureViewDimension::D2,
-            },
-            count: None,
-        },
-        wgpu::BindGroupLayoutEntry {
-            binding: 1,
-            visibility: wgpu::ShaderStages::FRAGMENT,
-            ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
-            count: None,
-        },
-        // normal map
-        wgpu::BindGroupLayoutEntry {
-            binding: 2,
-            visibility: wgpu::ShaderStages::FRAGMENT,
-            ty: wgpu::BindingType::Texture {
-                multisampled: false,
-                sample_type: wgpu::TextureSampleType::Float { filterable: true },
-                view_dimension: wgpu::TextureViewDimension::D2,
-            },
-            count: None,
-        },
-        wgpu::BindGroupLayoutEntry {
-            binding: 3,
-            visibility: wgpu::ShaderStages::FRAGMENT,
-            ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
-            count: None,
-        },
-    ];
-    wgpu::BindGroupLayoutDescriptor {
-        label: Some("Bind Group Layout"),
-        entries: &LAYOUT_ENTRIES,
-    }
-}
+use texture::ANISOTROPY_LEVELS;
+use ui::{highlight, highlight_line, line_number_gutter, CodeTheme, EguiDrawParams, EguiRenderer};
+use user_uniforms::UniformValue;
 
-const fn get_light_layout_desc() -> wgpu::BindGroupLayoutDescriptor<'static> {
-    const LAYOUT_ENTRIES: [wgpu::BindGroupLayoutEntry; 1] = [wgpu::BindGroupLayoutEntry {
-        binding: 0,
-        visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
-        ty: wgpu::BindingType::Buffer {
-            ty: wgpu::BufferBindingType::Uniform,
-            has_dynamic_offset: false,
-            min_binding_size: None,
-        },
-        count: None,
-    }];
-    wgpu::BindGroupLayoutDescriptor {
-        label: Some("Light Bind Group Layout"),
-        entries: &LAYOUT_ENTRIES,
-    }
-}
+/// Used in place of the wall-clock frame time when `State::fixed_timestep` is set
+const FIXED_TIMESTEP: Duration = Duration::from_nanos(1_000_000_000 / 60);
+
+/// How often the frame rate in the window title is recalculated
+const FPS_UPDATE_INTERVAL: Duration = Duration::from_millis(250);
+
+/// How long the window size has to stay the same before the surface and
+/// render targets are resized to match
+const RESIZE_SETTLE_TIME: Duration = Duration::from_millis(50);
 
-struct State {
+/// Time between frames while the window doesn't have focus, if
+/// `State::throttle_unfocused` is set
+const UNFOCUSED_FRAME_TIME: Duration = Duration::from_millis(100);
+
+/// How long "Compiled OK" is shown after a shader compiles
+const COMPILED_MESSAGE_TIME: Duration = Duration::from_secs(2);
+
+/// Seconds between a new camera keyframe and the one before it
+const KEYFRAME_SPACING: f32 = 2.0;
+
+/// Limits of the near and far planes in the camera window
+const MIN_ZNEAR: f32 = 0.001;
+const MAX_ZFAR: f32 = 100_000.0;
+/// Smallest distance kept between the near and far planes, which can't be
+/// equal
+const MIN_CLIP_DEPTH: f32 = 0.001;
+
+/// The interactive viewer: the renderer, its surface and the egui windows.
+///
+/// `run` drives one of these with its own event loop. To embed the viewer in
+/// another winit application instead, create the window there and hand it to
+/// `State::new`, then forward events to `window_event` and `device_event`.
+/// `window_event` redraws on `RedrawRequested`, so keep requesting redraws,
+/// for example from `AboutToWait`, at the times `next_redraw` gives.
+pub struct State {
     surface: Surface,
-    device: wgpu::Device,
-    queue: wgpu::Queue,
     size: winit::dpi::PhysicalSize<u32>,
-    camera: PerspectiveCamera,
+    /// Size from the latest resize event and when it arrived, applied by
+    /// `apply_pending_resize` once resizing stops
+    pending_resize: Option<(winit::dpi::PhysicalSize<u32>, Instant)>,
+    last_render_time: Instant,
+    /// Whether the window is completely hidden, nothing is drawn while it is
+    occluded: bool,
+    focused: bool,
+    /// Cap the frame rate while the window doesn't have focus
+    throttle_unfocused: bool,
+    renderer: Renderer,
+    /// What the state was created with, reused when the device is recreated
+    scene_options: SceneOptions,
+    adapter_info: wgpu::AdapterInfo,
+    /// Largest anisotropy clamp the adapter supports, 1 if it has none
+    max_anisotropy: u16,
+    /// Whether the adapter can draw debug primitives
+    debug_primitives_supported: bool,
+    /// HDR formats the device supports, for the format picker
+    hdr_formats: Vec<HdrFormat>,
     camera_controller: CameraController,
+    /// Keyframes for fly-throughs
+    camera_path: CameraPath,
+    /// Time along `camera_path` while the camera follows it instead of the
+    /// controller
+    camera_path_time: Option<f32>,
+    /// Advance `camera_path_time` every update
+    camera_path_playing: bool,
+    rebinding_action: Option<CameraAction>,
+    modifiers: ModifiersState,
     mouse_pressed: bool,
-    depth_texture: texture::Texture,
-    shader_source: String,
-    shader_compile_error: Option<String>,
-    render_pipeline: RenderPipeline,
-    light_render_pipeline: RenderPipeline,
-    scene_tree: SceneTree,
-    model: Arc<Mutex<model::Model>>,
-    light: LightUniform,
-    light_buffer: wgpu::Buffer,
-    light_bind_group: wgpu::BindGroup,
-    hdr: hdr::HdrPipeline,
-    cubemap: CubeMapRenderer,
+    mouse_dragged: bool,
+    cursor_position: PhysicalPosition<f64>,
+    /// Physical pixels per logical pixel of the window, mouse look is scaled
+    /// down by it so that it turns as fast on high-DPI displays
+    scale_factor: f64,
+    selected_node: Option<NodeHandle>,
+    /// Cached so that the euler angles don't jump around while being edited
+    selected_transform: Option<TransformComponents>,
+    /// Advance by `FIXED_TIMESTEP` every frame so that animation doesn't
+    /// depend on the frame rate
+    fixed_timestep: bool,
+    paused: bool,
+    /// Advance by one `FIXED_TIMESTEP` on the next update while paused
+    step_requested: bool,
+    /// Why the last compute shader failed to load, shown in the UI
+    compute_error: Option<String>,
+    /// Whether to ask before resetting a shader with unsaved edits
+    confirm_shader_reset: bool,
+    /// Whether the shader editor had keyboard focus last frame, movement
+    /// keys are left to the editor while it does
+    editor_focused: bool,
+    /// Toggled with F1, when `false` no windows are shown and egui gets no
+    /// input
+    ui_visible: bool,
+    /// Frames presented since `fps_timer` was last reset
+    frame_count: u32,
+    fps_timer: Instant,
+    fps: f32,
+    /// Last title set on the window, so it's only changed when needed
+    title: String,
+    config: Config,
     ui: EguiRenderer,
+    /// Run in order every frame, see `add_render_pass_plugin`
+    render_pass_plugins: Vec<Box<dyn RenderPassPlugin>>,
+}
+
+/// The HDR formats `device` supports and the one to use, the configured one
+/// if it is supported
+fn supported_hdr_formats(
+    config: &Config,
+    adapter: &wgpu::Adapter,
+    device: &wgpu::Device,
+) -> (Vec<HdrFormat>, HdrFormat) {
+    let hdr_formats: Vec<HdrFormat> = HdrFormat::ALL
+        .into_iter()
+        .filter(|format| format.is_supported(adapter, device))
+        .collect();
+    let hdr_format = if hdr_formats.contains(&config.hdr_format()) {
+        config.hdr_format()
+    } else {
+        warn!(
+            "HDR format {:?} is not supported by this adapter, using {:?}",
+            config.hdr_format(),
+            HdrFormat::default()
+        );
+        HdrFormat::default()
+    };
+    (hdr_formats, hdr_format)
 }
 
 impl State {
-    async fn new<P: AsRef<Path>>(
-        window: Arc<Window>,
-        starting_shader: Option<P>,
-    ) -> RendererResult<Self> {
+    /// Sets up wgpu for `window` and loads the scene described by `options`.
+    /// A shader or environment that fails to load is logged and the default
+    /// is used instead. The surface holds on to `window`, so it stays alive as
+    /// long as the state does. It's sized to the window's current inner size.
+    pub async fn new(window: Arc<Window>, options: SceneOptions) -> RendererResult<Self> {
         let size = window.inner_size();
 
         let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
@@ -141,28 +221,10 @@ impl State {
             ..Default::default()
         });
 
-        let surface = instance.create_surface(window.clone()).unwrap();
-
-        let adapter = instance
-            .request_adapter(&wgpu::RequestAdapterOptions {
-                power_preference: wgpu::PowerPreference::default(),
-                compatible_surface: Some(&surface),
-                force_fallback_adapter: false,
-            })
-            .await
-            .unwrap();
-
-        let (device, queue) = adapter
-            .request_device(
-                &wgpu::DeviceDescriptor {
-                    label: None,
-                    required_features: wgpu::Features::all_webgpu_mask(),
-                    required_limits: wgpu::Limits::default(),
-                },
-                None,
-            )
-            .await
-            .unwrap();
+        let surface = instance.create_surface(window.clone())?;
+
+        let (adapter, device, queue) =
+            renderer::request_device(&instance, Some(&surface), options.backends).await?;
 
         let surface = Surface::new((size.width, size.height), surface, &adapter, &device);
         info!(
@@ -170,227 +232,488 @@ impl State {
             surface.surface(),
             surface.capabilities()
         );
-        let surface_size = surface.extent();
-
-        let depth_texture =
-            texture::Texture::create_depth_texture(&device, &surface_size, "depth_texture");
-
-        let texture_bind_group_layout_desc = get_texture_layout_desc();
-        let texture_bind_group_layout =
-            device.create_bind_group_layout(&texture_bind_group_layout_desc);
-
-        let camera = Camera::new((0.0, 5.0, 10.0), cgmath::Deg(-90.0), cgmath::Deg(-20.0));
-        let projection = Projection::new(
-            surface_size.width,
-            surface_size.height,
-            cgmath::Deg(45.0),
-            0.1,
-            100.0,
-        );
 
-        let camera = PerspectiveCamera::new(&device, camera, projection);
-
-        let mut scene_tree = SceneTree::default();
-
-        // Create instances
-        const NUM_INSTANCE_PER_ROW: usize = 10;
-        let model = Arc::new(Mutex::new(
-            resources::load_model("cube.obj", &device, &queue, &texture_bind_group_layout)
-                .await
-                .expect("Failed to load model!"),
-        ));
-
-        const SPACE_BETWEEN: f32 = 3.0;
-        for z in 0..NUM_INSTANCE_PER_ROW {
-            for x in 0..NUM_INSTANCE_PER_ROW {
-                let x = SPACE_BETWEEN * (x as f32 - NUM_INSTANCE_PER_ROW as f32 / 2.0);
-                let z = SPACE_BETWEEN * (z as f32 - NUM_INSTANCE_PER_ROW as f32 / 2.0);
-                let position = cgmath::Vector3 { x, y: 0.0, z };
-                let rotation = if position.is_zero() {
-                    cgmath::Quaternion::from_axis_angle(cgmath::Vector3::unit_z(), cgmath::Deg(0.0))
-                } else {
-                    cgmath::Quaternion::from_axis_angle(position.normalize(), cgmath::Deg(45.0))
-                };
-
-                let node_handle = scene_tree.new_node();
-                let mut node = scene_tree
-                    .get_mut(&node_handle)
-                    .expect("We just created this node!");
-                node.update_local_transform(
-                    cgmath::Matrix4::from_translation(position) * cgmath::Matrix4::from(rotation),
+        // SPIR-V can't be read as text, so it's loaded once the state exists
+        let (spirv_shader, starting_shader) = match &options.shader {
+            Some(p) if p.extension().is_some_and(|ext| ext == "spv") => (Some(p.clone()), None),
+            p => (None, p.clone()),
+        };
+        let starting_shader = starting_shader.and_then(|p| match std::fs::read_to_string(&p) {
+            Ok(s) => Some((p, s)),
+            Err(e) => {
+                error!(
+                    "Unable to load supplied shader {} ({}), using default!",
+                    p.display(),
+                    e
                 );
-                node.set_model(model.clone());
+                None
             }
-        }
-
-        let light = LightUniform::new([2.0, 2.0, 2.0], [1.0, 1.0, 1.0]);
-
-        let light_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Light Buffer"),
-            contents: bytemuck::cast_slice(&[light]),
-            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
         });
 
-        let light_bind_group_layout_desc = get_light_layout_desc();
-        let light_bind_group_layout =
-            device.create_bind_group_layout(&light_bind_group_layout_desc);
-
-        let light_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("Light Bind Group"),
-            layout: &light_bind_group_layout,
-            entries: &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: light_buffer.as_entire_binding(),
-            }],
-        });
+        let config = Config::load();
+        let (hdr_formats, hdr_format) = supported_hdr_formats(&config, &adapter, &device);
 
-        let hdr = hdr::HdrPipeline::new(&device, &surface_size, surface.format());
-        let cubemap = CubeMapRenderer::new(
-            &device,
-            &queue,
-            &PerspectiveCamera::layout_desc(),
-            camera.layout(),
-            hdr.format(),
-            "pure-sky.hdr",
-            Some("Sky Cubemap"),
+        let mut renderer = Renderer::new(
+            device,
+            queue,
+            RendererCreateInfo {
+                extent: &surface.extent(),
+                output_format: surface.format(),
+                hdr_format,
+                starting_shader,
+                model_file: &options.model,
+                config: &options.renderer,
+            },
         )
         .await?;
+        if let Some(path) = &options.environment {
+            if let Err(e) = renderer.load_environment(path) {
+                error!("Unable to load environment map {}: {}", path.display(), e);
+            }
+        }
 
-        let render_pipeline_layout =
-            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                label: Some("Pipeline layout"),
-                bind_group_layouts: &[
-                    &texture_bind_group_layout,
-                    camera.layout(),
-                    &light_bind_group_layout,
-                    cubemap.layout(),
-                ],
-                push_constant_ranges: &[],
-            });
-
-        let shader_source = starting_shader
-            .and_then(|p| match std::fs::read_to_string(p.as_ref()) {
-                Ok(s) => Some(s),
-                Err(e) => {
-                    error!(
-                        "Unable to load supplied shader {} ({}), using default!",
-                        p.as_ref().display(),
-                        e
-                    );
-                    None
-                }
-            })
-            .unwrap_or(
-                include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/shaders/shader.wgsl"))
-                    .to_string(),
-            );
+        let ui = EguiRenderer::new(&renderer.device, renderer.hdr.format(), None, 1, &window);
+        let mut state = Self {
+            surface,
+            size,
+            pending_resize: None,
+            last_render_time: Instant::now(),
+            occluded: false,
+            focused: window.has_focus(),
+            throttle_unfocused: true,
+            renderer,
+            scene_options: options,
+            adapter_info: adapter.get_info(),
+            max_anisotropy: texture::max_anisotropy(&adapter),
+            debug_primitives_supported: debug_primitives::is_supported(&adapter),
+            camera_controller: CameraController::new(4.0, 1.0),
+            camera_path: CameraPath::default(),
+            camera_path_time: None,
+            camera_path_playing: false,
+            rebinding_action: None,
+            modifiers: ModifiersState::empty(),
+            mouse_pressed: false,
+            mouse_dragged: false,
+            cursor_position: PhysicalPosition::default(),
+            scale_factor: window.scale_factor(),
+            selected_node: None,
+            selected_transform: None,
+            fixed_timestep: false,
+            paused: false,
+            step_requested: false,
+            compute_error: None,
+            confirm_shader_reset: false,
+            editor_focused: false,
+            ui_visible: true,
+            frame_count: 0,
+            fps_timer: Instant::now(),
+            fps: 0.0,
+            title: String::new(),
+            config,
+            hdr_formats,
+            ui,
+            render_pass_plugins: Vec::new(),
+        };
+        if let Some(path) = spirv_shader {
+            if let Err(e) = state.load_shader(&path) {
+                error!(
+                    "Unable to load supplied shader {} ({}), using default!",
+                    path.display(),
+                    e
+                );
+            }
+        }
+        Ok(state)
+    }
 
-        let render_pipeline = {
-            let shader = Shader::new_wgsl(&device, "normal", &shader_source)
-                .expect("Could not parse normal shader");
-            assert_eq!(
-                shader.layout_matches(&[
-                    &texture_bind_group_layout_desc,
-                    &PerspectiveCamera::layout_desc(),
-                    &light_bind_group_layout_desc,
-                    &cubemap.layout_desc()
-                ]),
-                Ok(())
-            );
+    /// Records the new size, a drag resize sends many of these so the actual
+    /// resize waits until they stop
+    fn request_resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
+        self.pending_resize = Some((new_size, Instant::now()));
+    }
 
-            let create_info = PipelineCreateInfo {
-                color_format: hdr.format(),
-                depth_format: Some(texture::Texture::DEPTH_FORMAT),
-                vertex_layouts: &[model::ModelVertex::layout(), InstanceRaw::desc()],
-                topology: wgpu::PrimitiveTopology::TriangleList,
-                shader: &shader,
-                label: Some("Normal Pipeline"),
-            };
-            RenderPipeline::new(&device, render_pipeline_layout, create_info)
+    /// Resizes once the size has settled, or right away if `force` is set
+    fn apply_pending_resize(&mut self, force: bool) {
+        let Some((new_size, requested_at)) = self.pending_resize else {
+            return;
         };
+        if !force && requested_at.elapsed() < RESIZE_SETTLE_TIME {
+            return;
+        }
+        self.pending_resize = None;
+        if new_size != self.size {
+            self.resize(new_size);
+        }
+    }
 
-        let light_render_pipeline_layout =
-            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                label: Some("Pipeline layout"),
-                bind_group_layouts: &[camera.layout(), &light_bind_group_layout],
-                push_constant_ranges: &[],
-            });
+    pub fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
+        self.size = new_size;
+        if self.is_minimized() {
+            info!("Window minimized, pausing rendering");
+            return;
+        }
+        info!("Resizing to {}x{}", new_size.width, new_size.height);
+        self.surface
+            .resize(&self.renderer.device, new_size.width, new_size.height);
+        self.renderer.resize(new_size.width, new_size.height);
+    }
 
-        let light_render_pipeline = {
-            let shader = Shader::new_wgsl(
-                &device,
-                "Light",
-                include_str!(concat!(
-                    env!("CARGO_MANIFEST_DIR"),
-                    "/shaders/light_shader.wgsl"
-                )),
-            )
-            .expect("Could not parse light shader");
-            assert_eq!(
-                shader.layout_matches(&[
-                    &PerspectiveCamera::layout_desc(),
-                    &light_bind_group_layout_desc
-                ]),
-                Ok(())
-            );
+    /// A zero sized surface can't be configured, so nothing is drawn until
+    /// the window is restored
+    fn is_minimized(&self) -> bool {
+        self.size.width == 0 || self.size.height == 0
+    }
 
-            let create_info = PipelineCreateInfo {
-                color_format: hdr.format(),
-                depth_format: Some(texture::Texture::DEPTH_FORMAT),
-                vertex_layouts: &[model::ModelVertex::layout()],
-                topology: wgpu::PrimitiveTopology::TriangleList,
-                shader: &shader,
-                label: Some("Light Pipeline"),
-            };
-            RenderPipeline::new(&device, light_render_pipeline_layout, create_info)
-        };
-        let ui = EguiRenderer::new(&device, hdr.format(), None, 1, &window);
-        Ok(Self {
+    fn set_surface_format(&mut self, format: wgpu::TextureFormat) {
+        match self.surface.set_format(format, &self.renderer.device) {
+            Ok(()) => {
+                info!("Surface format changed to {:?}", format);
+                // The tonemapping pass writes straight to the surface
+                self.renderer.hdr.set_output_format(
+                    &self.renderer.device,
+                    &self.renderer.queue,
+                    format,
+                );
+            }
+            Err(e) => error!("Unable to change surface format: {}", e),
+        }
+    }
+
+    /// Handles the surface no longer matching the window. A new window size
+    /// waits to settle like a resize event, without restarting the wait on
+    /// every frame the surface stays outdated.
+    fn surface_outdated(&mut self, window: &Window) {
+        let size = window.inner_size();
+        if size == self.size {
+            self.reconfigure_surface();
+        } else if self
+            .pending_resize
+            .is_none_or(|(pending_size, _)| pending_size != size)
+        {
+            self.request_resize(size);
+        }
+    }
+
+    fn reconfigure_surface(&mut self) {
+        // Any size change has to be applied before drawing again
+        if self.pending_resize.is_some() {
+            self.apply_pending_resize(true);
+            return;
+        }
+        info!(
+            "Reconfiguring surface at {}x{}",
+            self.size.width, self.size.height
+        );
+        self.surface
+            .resize(&self.renderer.device, self.size.width, self.size.height);
+    }
+
+    /// Rebuilds the device and everything created from it. The scene,
+    /// cameras and shader tabs are carried over to the new renderer and the
+    /// rest of the viewer's state is kept.
+    async fn recreate_device(&mut self, window: Arc<Window>) -> RendererResult<()> {
+        let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
+            backends: wgpu::Backends::all(),
+            ..Default::default()
+        });
+        let surface = instance.create_surface(window.clone())?;
+        let (adapter, device, queue) =
+            renderer::request_device(&instance, Some(&surface), self.scene_options.backends)
+                .await?;
+        let mut surface = Surface::new(
+            (self.size.width, self.size.height),
             surface,
+            &adapter,
+            &device,
+        );
+        if let Err(e) = surface.set_format(self.surface.format(), &device) {
+            warn!("Unable to keep the surface format: {}", e);
+        }
+        let (hdr_formats, hdr_format) = supported_hdr_formats(&self.config, &adapter, &device);
+
+        let config = RendererConfig {
+            instance_layout: self.renderer.instance_layout(),
+            ..self.scene_options.renderer.clone()
+        };
+        let mut renderer = Renderer::new(
             device,
             queue,
-            size,
-            camera,
-            camera_controller: CameraController::new(4.0, 1.0),
-            mouse_pressed: false,
-            depth_texture,
-            render_pipeline,
-            shader_source,
-            shader_compile_error: None,
-            light_render_pipeline,
-            scene_tree,
-            model,
-            light,
-            light_buffer,
-            light_bind_group,
-            hdr,
-            cubemap,
-            ui,
-        })
-    }
-
-    fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
-        if new_size.width > 0 && new_size.height > 0 {
-            info!("Resizing to {}x{}", new_size.width, new_size.height);
-            self.size = new_size;
-            self.surface
-                .resize(&self.device, new_size.width, new_size.height);
-            self.camera.resize(new_size.width, new_size.height);
-            self.depth_texture = texture::Texture::create_depth_texture(
-                &self.device,
-                &self.surface.extent(),
-                "depth_texture",
+            RendererCreateInfo {
+                extent: &surface.extent(),
+                output_format: surface.format(),
+                hdr_format,
+                starting_shader: None,
+                model_file: &self.scene_options.model,
+                config: &config,
+            },
+        )
+        .await?;
+        if let Some(path) = &self.scene_options.environment {
+            if let Err(e) = renderer.load_environment(path) {
+                error!("Unable to load environment map {}: {}", path.display(), e);
+            }
+        }
+        let debug_primitives_supported = debug_primitives::is_supported(&adapter);
+        let show_debug_primitives = self.renderer.show_debug_primitives();
+        let old_renderer = std::mem::replace(&mut self.renderer, renderer);
+        self.renderer.restore_from(old_renderer);
+        self.renderer
+            .set_show_debug_primitives(show_debug_primitives && debug_primitives_supported);
+
+        if !self.render_pass_plugins.is_empty() {
+            warn!(
+                "Dropping {} render pass plugins made for the old device",
+                self.render_pass_plugins.len()
             );
-            self.hdr
-                .resize(&self.device, new_size.width, new_size.height);
+            self.render_pass_plugins.clear();
+        }
+        self.ui = EguiRenderer::new(
+            &self.renderer.device,
+            self.renderer.hdr.format(),
+            None,
+            1,
+            &window,
+        );
+        self.surface = surface;
+        self.adapter_info = adapter.get_info();
+        self.max_anisotropy = texture::max_anisotropy(&adapter);
+        self.debug_primitives_supported = debug_primitives_supported;
+        self.hdr_formats = hdr_formats;
+        info!("Device recreated");
+        Ok(())
+    }
+
+    /// Tries to get drawing again after the surface was lost, first by
+    /// reconfiguring it and then by recreating the device
+    fn recover_lost_surface(&mut self, window: &Arc<Window>) {
+        warn!("Surface lost");
+        self.reconfigure_surface();
+        match self.render(window) {
+            Err(wgpu::SurfaceError::Lost) => (),
+            _ => return,
+        }
+        warn!("Surface still lost after reconfiguring, recreating the device");
+        if let Err(e) = pollster::block_on(self.recreate_device(window.clone())) {
+            error!("Unable to recreate the device: {}", e);
+        }
+    }
+
+    /// Handles an event for the viewer's window. Returns `true` when the
+    /// viewer wants to close, from the window being closed, Escape or an
+    /// unrecoverable surface error.
+    pub fn window_event(&mut self, window: &Arc<Window>, event: &WindowEvent) -> bool {
+        match event {
+            WindowEvent::Occluded(occluded) => {
+                info!(
+                    "Window {}, {} rendering",
+                    if *occluded { "occluded" } else { "visible" },
+                    if *occluded { "pausing" } else { "resuming" }
+                );
+                self.occluded = *occluded;
+                if !occluded {
+                    // Don't make up for the time spent hidden in one frame
+                    self.last_render_time = Instant::now();
+                }
+            }
+            WindowEvent::Focused(focused) => self.focused = *focused,
+            _ => (),
+        }
+        if self.input(window, event) {
+            return false;
+        }
+        match event {
+            WindowEvent::CloseRequested
+            | WindowEvent::KeyboardInput {
+                event:
+                    KeyEvent {
+                        state: ElementState::Pressed,
+                        physical_key: PhysicalKey::Code(KeyCode::Escape),
+                        ..
+                    },
+                ..
+            } => return true,
+            WindowEvent::Resized(physical_size) => self.request_resize(*physical_size),
+            WindowEvent::ScaleFactorChanged { scale_factor, .. } => {
+                self.scale_factor = *scale_factor;
+            }
+            WindowEvent::RedrawRequested => {
+                self.apply_pending_resize(false);
+                let now = Instant::now();
+                let dt = now - self.last_render_time;
+                self.update(dt);
+                self.last_render_time = now;
+                if self.is_minimized() || self.occluded {
+                    return false;
+                }
+                match self.render(window) {
+                    Ok(()) => (),
+                    Err(wgpu::SurfaceError::Outdated) => {
+                        warn!("Surface outdated");
+                        self.surface_outdated(window);
+                    }
+                    Err(wgpu::SurfaceError::Lost) => self.recover_lost_surface(window),
+                    Err(wgpu::SurfaceError::Timeout) => {
+                        warn!("Timed out acquiring the next frame, skipping it");
+                    }
+                    Err(wgpu::SurfaceError::OutOfMemory) => {
+                        error!("WGPU Error: Out of Memory!");
+                        return true;
+                    }
+                }
+            }
+            _ => (),
+        }
+        false
+    }
+
+    /// When to request the next redraw. `None` while the window is occluded,
+    /// the `Occluded` event that shows it again is enough to wake up for. A
+    /// time in the past means right away.
+    pub fn next_redraw(&self) -> Option<Instant> {
+        if self.occluded {
+            None
+        } else if self.throttle_unfocused && !self.focused {
+            Some(self.last_render_time + UNFOCUSED_FRAME_TIME)
+        } else {
+            Some(self.last_render_time)
+        }
+    }
+
+    /// Adds `plugin` after the ones already added. Its passes draw into the
+    /// HDR target between the scene and tonemapping. Plugins are dropped if
+    /// the device is lost and recreated, their resources belong to the old one.
+    pub fn add_render_pass_plugin(&mut self, plugin: Box<dyn RenderPassPlugin>) {
+        self.render_pass_plugins.push(plugin);
+    }
+
+    /// Handles raw device input, used for mouse look
+    pub fn device_event(&mut self, event: &DeviceEvent) {
+        if let DeviceEvent::MouseMotion { delta } = event {
+            self.process_mouse_motion(delta.0, delta.1);
         }
     }
 
     fn input(&mut self, window: &Window, event: &WindowEvent) -> bool {
-        if self.ui.handle_input(window, event) {
+        if let Some(action) = self.rebinding_action {
+            if let WindowEvent::KeyboardInput {
+                event:
+                    KeyEvent {
+                        physical_key: PhysicalKey::Code(key),
+                        state: ElementState::Pressed,
+                        ..
+                    },
+                ..
+            } = event
+            {
+                if *key != KeyCode::Escape {
+                    self.camera_controller.key_bindings_mut().bind(action, *key);
+                }
+                self.rebinding_action = None;
+                return true;
+            }
+        }
+        if let WindowEvent::ModifiersChanged(modifiers) = event {
+            self.modifiers = modifiers.state();
+        }
+        // Handled before egui so that saving works while typing in the editor
+        if let WindowEvent::KeyboardInput {
+            event:
+                KeyEvent {
+                    physical_key: PhysicalKey::Code(KeyCode::KeyS),
+                    state: ElementState::Pressed,
+                    repeat: false,
+                    ..
+                },
+            ..
+        } = event
+        {
+            if self.modifiers.control_key() {
+                self.save_shader();
+                return true;
+            }
+        }
+        if let WindowEvent::KeyboardInput {
+            event:
+                KeyEvent {
+                    physical_key: PhysicalKey::Code(KeyCode::F1),
+                    state: ElementState::Pressed,
+                    repeat: false,
+                    ..
+                },
+            ..
+        } = event
+        {
+            self.ui_visible = !self.ui_visible;
+            return true;
+        }
+        // The resulting `Resized` event reconfigures the surface and targets
+        if let WindowEvent::KeyboardInput {
+            event:
+                KeyEvent {
+                    physical_key: PhysicalKey::Code(KeyCode::F11),
+                    state: ElementState::Pressed,
+                    repeat: false,
+                    ..
+                },
+            ..
+        } = event
+        {
+            let fullscreen = match window.fullscreen() {
+                Some(_) => None,
+                None => Some(Fullscreen::Borderless(None)),
+            };
+            window.set_fullscreen(fullscreen);
             return true;
         }
+        if self.ui_visible && self.ui.handle_input(window, event) {
+            return true;
+        }
+        if let WindowEvent::KeyboardInput {
+            event:
+                KeyEvent {
+                    physical_key: PhysicalKey::Code(key),
+                    state: ElementState::Pressed,
+                    repeat: false,
+                    ..
+                },
+            ..
+        } = event
+        {
+            let view = AxisView::for_numpad_key(*key, self.modifiers.control_key());
+            if let Some(view) = view.filter(|_| {
+                !self.editor_focused
+                    && self
+                        .camera_controller
+                        .key_bindings()
+                        .action_for(*key)
+                        .is_none()
+            }) {
+                self.snap_camera(view);
+                return true;
+            }
+        }
         match event {
+            WindowEvent::KeyboardInput {
+                event:
+                    KeyEvent {
+                        physical_key: PhysicalKey::Code(KeyCode::KeyP),
+                        state: ElementState::Pressed,
+                        repeat: false,
+                        ..
+                    },
+                ..
+            } if !self.editor_focused
+                && self
+                    .camera_controller
+                    .key_bindings()
+                    .action_for(KeyCode::KeyP)
+                    .is_none() =>
+            {
+                self.paused = !self.paused;
+                true
+            }
+            // Releases still go through, so a key held while clicking into
+            // the editor doesn't keep the camera moving
             WindowEvent::KeyboardInput {
                 event:
                     KeyEvent {
@@ -399,7 +722,9 @@ impl State {
                         ..
                     },
                 ..
-            } => self.camera_controller.process_keyboard(*key, *state),
+            } if !self.editor_focused || *state == ElementState::Released => {
+                self.camera_controller.process_keyboard(*key, *state)
+            }
             WindowEvent::MouseWheel { delta, .. } => {
                 self.camera_controller.process_scroll(delta);
                 true
@@ -410,291 +735,1659 @@ impl State {
                 ..
             } => {
                 self.mouse_pressed = *state == ElementState::Pressed;
+                if self.mouse_pressed {
+                    self.mouse_dragged = false;
+                    self.camera_controller.begin_drag();
+                } else if !self.mouse_dragged {
+                    self.pick(self.cursor_position);
+                }
                 true
             }
+            WindowEvent::CursorMoved { position, .. } => {
+                self.cursor_position = *position;
+                false
+            }
             _ => false,
         }
     }
 
+    /// Starts picking the node under `position`, it is selected by
+    /// `collect_pick` once the pick has been read back
+    fn pick(&mut self, position: PhysicalPosition<f64>) {
+        self.renderer.pick(position.x as u32, position.y as u32);
+    }
+
+    fn collect_pick(&mut self) {
+        let Some(pick_id) = self.renderer.picker.collect(&self.renderer.device) else {
+            return;
+        };
+        self.selected_node = pick_id.and_then(|id| self.renderer.scene_tree.node_from_pick_id(id));
+        self.selected_transform = None;
+        info!("Selected node {:?}", self.selected_node);
+    }
+
     fn process_mouse_motion(&mut self, mouse_dx: f64, mouse_dy: f64) {
-        if self.mouse_pressed {
-            self.camera_controller.process_mouse(mouse_dx, mouse_dy);
+        // Raw deltas are in physical pixels
+        let (mouse_dx, mouse_dy) = (mouse_dx / self.scale_factor, mouse_dy / self.scale_factor);
+        if self.mouse_pressed && self.camera_controller.process_mouse(mouse_dx, mouse_dy) {
+            self.mouse_dragged = true;
         }
     }
 
-    fn compile_shader(&mut self) {
-        info!("Compiling shader");
-        let shader = match Shader::new_wgsl(&self.device, "shader", &self.shader_source) {
-            Ok(s) => {
-                self.shader_compile_error = None;
-                s
-            }
-            Err(e) => {
-                self.shader_compile_error = Some(e.to_string());
-                return;
-            }
+    /// Opens the WGSL, GLSL or SPIR-V shader at `shader_file_path` in a new
+    /// tab and draws the model with it
+    pub fn load_shader<P: AsRef<Path>>(&mut self, shader_file_path: P) -> RendererResult<()> {
+        let path = shader_file_path.as_ref();
+        let is_spirv = path.extension().is_some_and(|ext| ext == "spv");
+        let (source, spirv) = if is_spirv {
+            let words = shader::spirv_words(&std::fs::read(path)?)?;
+            let source = format!(
+                "// Precompiled SPIR-V loaded from {}\n// Edit the original source and reload it to make changes\n",
+                path.display()
+            );
+            (source, Some(words))
+        } else {
+            (std::fs::read_to_string(path)?, None)
         };
-        if let Err(errors) = shader.layout_matches(&[
-            &get_texture_layout_desc(),
-            &PerspectiveCamera::layout_desc(),
-            &get_light_layout_desc(),
-            &self.cubemap.layout_desc(),
-        ]) {
-            self.shader_compile_error = Some(errors.iter().fold(String::new(), |mut acc, err| {
-                acc.push_str(err);
-                acc.push('\n');
-                acc
-            }));
+        let tab = &mut self.renderer.shader_tabs[self.renderer.active_shader_tab];
+        tab.source = source;
+        tab.set_spirv(spirv);
+        tab.set_path(shader_file_path.as_ref().to_path_buf());
+        tab.mark_saved();
+        self.renderer.compile_shader();
+
+        self.config.add_recent_shader(shader_file_path.as_ref());
+        if let Err(e) = self.config.save() {
+            error!("Unable to save config: {}", e);
+        }
+        Ok(())
+    }
+
+    fn load_compute_shader<P: AsRef<Path>>(&mut self, shader_file_path: P) -> RendererResult<()> {
+        let source = std::fs::read_to_string(shader_file_path.as_ref())?;
+        let name = shader_tab::name_from_path(shader_file_path.as_ref());
+        self.renderer.load_compute_shader(&name, &source)
+    }
+
+    /// Writes the active tab back to the file it came from, prompting for a
+    /// path if it doesn't have one yet
+    fn save_shader(&mut self) {
+        if self.renderer.shader_tabs[self.renderer.active_shader_tab].is_spirv() {
+            error!("SPIR-V shaders can't be saved from the editor");
             return;
         }
+        match self.renderer.shader_tabs[self.renderer.active_shader_tab].path() {
+            Some(path) => self.write_shader(path.to_path_buf()),
+            None => self.save_shader_as(),
+        }
+    }
 
-        let create_info = PipelineCreateInfo {
-            color_format: self.hdr.format(),
-            depth_format: Some(texture::Texture::DEPTH_FORMAT),
-            vertex_layouts: &[model::ModelVertex::layout(), InstanceRaw::desc()],
-            topology: wgpu::PrimitiveTopology::TriangleList,
-            shader: &shader,
-            label: Some("Normal Pipeline"),
-        };
-        self.render_pipeline.recreate(&self.device, create_info);
+    fn save_shader_as(&mut self) {
+        if self.renderer.shader_tabs[self.renderer.active_shader_tab].is_spirv() {
+            error!("SPIR-V shaders can't be saved from the editor");
+            return;
+        }
+        if let Some(path) = rfd::FileDialog::new().save_file() {
+            self.write_shader(path);
+        }
     }
 
-    fn load_shader<P: AsRef<Path>>(&mut self, shader_file_path: P) -> RendererResult<()> {
-        let source = std::fs::read_to_string(shader_file_path)?;
-        self.shader_source = source;
-        self.compile_shader();
-        Ok(())
+    fn write_shader(&mut self, path: PathBuf) {
+        let tab = &mut self.renderer.shader_tabs[self.renderer.active_shader_tab];
+        match std::fs::write(&path, &tab.source) {
+            Ok(()) => {
+                info!("Saved shader to {}", path.display());
+                tab.set_path(path);
+                tab.mark_saved();
+            }
+            Err(e) => error!("Could not save file! {}", e),
+        }
+    }
+
+    /// Advances the camera and the scene by `dt`
+    pub fn update(&mut self, dt: Duration) {
+        let dt = if self.paused {
+            if !std::mem::take(&mut self.step_requested) {
+                // Still pick up edits to the scene, just without advancing time
+                self.follow_camera_path(Duration::ZERO);
+                self.renderer.update(Duration::ZERO);
+                return;
+            }
+            FIXED_TIMESTEP
+        } else if self.fixed_timestep {
+            FIXED_TIMESTEP
+        } else {
+            dt
+        };
+        if !self.follow_camera_path(dt) {
+            // TODO This is a clumsy way to update the camera
+            self.renderer
+                .camera
+                .update(&mut self.camera_controller, dt, &self.renderer.queue);
+        }
+        self.renderer.update(dt);
     }
 
-    fn update(&mut self, dt: Duration) {
-        // TODO This is a clumsy way to update the camera
-        self.camera
-            .update(&mut self.camera_controller, dt, &self.queue);
+    /// Looks at the model along `view`'s axis, leaving the camera path
+    fn snap_camera(&mut self, view: AxisView) {
+        self.camera_path_time = None;
+        self.camera_path_playing = false;
+        self.renderer.snap_camera(view);
+    }
 
-        self.scene_tree.update_transforms();
-        self.model
-            .lock()
-            .expect("Mutex Poisoned")
-            .update_instance_buffer(&self.device, &self.queue);
+    /// Moves the camera along the camera path, advancing by `dt` while it is
+    /// playing. Returns `false` if the camera isn't following the path.
+    fn follow_camera_path(&mut self, dt: Duration) -> bool {
+        let Some(mut time) = self.camera_path_time else {
+            return false;
+        };
+        if self.camera_path_playing {
+            time += dt.as_secs_f32();
+            if time >= self.camera_path.duration() {
+                time = self.camera_path.duration();
+                self.camera_path_playing = false;
+            }
+        }
+        let Some(camera) = self.camera_path.evaluate(time) else {
+            // Every keyframe was removed, give the camera back to the
+            // controller
+            self.camera_path_time = None;
+            self.camera_path_playing = false;
+            return false;
+        };
+        self.camera_path_time = Some(time);
+        self.renderer.camera.set_pose(
+            &self.renderer.queue,
+            camera.position(),
+            camera.yaw(),
+            camera.pitch(),
+        );
+        true
+    }
 
-        // Update light
-        let old_position: cgmath::Vector3<_> = self.light.position.into();
-        self.light.position = (cgmath::Quaternion::from_axis_angle(
-            cgmath::Vector3::unit_y(),
-            cgmath::Deg(60.0 * dt.as_secs_f32()),
-        ) * old_position)
-            .into();
-        self.queue
-            .write_buffer(&self.light_buffer, 0, bytemuck::cast_slice(&[self.light]));
+    /// Counts a presented frame and shows the active shader and frame rate
+    /// in the window title
+    fn update_title(&mut self, window: &Window) {
+        self.frame_count += 1;
+        let elapsed = self.fps_timer.elapsed();
+        if elapsed >= FPS_UPDATE_INTERVAL {
+            self.fps = self.frame_count as f32 / elapsed.as_secs_f32();
+            self.frame_count = 0;
+            self.fps_timer = Instant::now();
+        }
+        let tab = &self.renderer.shader_tabs[self.renderer.active_shader_tab];
+        let title = format!("rust_shader_viewer - {} - {:.0} FPS", tab.name(), self.fps);
+        if title != self.title {
+            window.set_title(&title);
+            self.title = title;
+        }
     }
 
-    fn render(&mut self, window: &Window) -> Result<(), wgpu::SurfaceError> {
+    /// Draws a frame to the surface, including the UI
+    pub fn render(&mut self, window: &Window) -> Result<(), wgpu::SurfaceError> {
         let output = SurfaceTextureRenderTarget::new(&self.surface)?;
-        let mut cmd_encoder = self
-            .device
-            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
-                label: Some("Render Encoder"),
-            });
-        {
-            let model_guard = self.model.lock().expect("Poisoned Mutex");
-            let mut render_pass = cmd_encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                label: Some("Render Pass"),
-                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view: self.hdr.view(),
-                    resolve_target: None,
-                    ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(wgpu::Color::BLUE),
-                        store: wgpu::StoreOp::Store,
-                    },
-                })],
-                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
-                    view: &self.depth_texture.view,
-                    depth_ops: Some(wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(1.0),
-                        store: wgpu::StoreOp::Store,
-                    }),
-                    stencil_ops: None,
-                }),
-                timestamp_writes: None,
-                occlusion_query_set: None,
-            });
-            self.cubemap
-                .render(&mut render_pass, self.camera.bind_group());
-
-            render_pass.set_pipeline(self.light_render_pipeline.pipeline());
-            render_pass.draw_light_model(
-                &model_guard,
-                self.camera.bind_group(),
-                &self.light_bind_group,
-            );
+        self.render_to(window, &output);
+        output.present();
+        self.update_title(window);
+        Ok(())
+    }
 
-            render_pass.set_pipeline(self.render_pipeline.pipeline());
-            render_pass.draw_model_instanced(
-                &model_guard,
-                self.camera.bind_group(),
-                &self.light_bind_group,
-                self.cubemap.bind_group(),
-                0..model_guard.instances.len() as u32,
-            );
+    /// Draws a frame to `target`, including the UI. The target needs the
+    /// surface's size and format.
+    pub fn render_to(&mut self, window: &Window, target: &dyn RenderTarget) {
+        let mut cmd_encoder =
+            self.renderer
+                .device
+                .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                    label: Some("Render Encoder"),
+                });
+        let selected_instance = self
+            .selected_node
+            .and_then(|handle| self.renderer.scene_tree.get(&handle)?.instance_index());
+        self.renderer
+            .render_scene(&mut cmd_encoder, selected_instance);
+        for plugin in &mut self.render_pass_plugins {
+            plugin.record(RenderPassContext {
+                device: &self.renderer.device,
+                queue: &self.renderer.queue,
+                encoder: &mut cmd_encoder,
+                hdr_view: self.renderer.hdr.view(),
+                hdr_format: self.renderer.hdr.format(),
+                depth_view: &self.renderer.depth_texture.view,
+                camera_bind_group: self.renderer.camera.bind_group(),
+                camera_bind_group_layout: self.renderer.camera.layout(),
+            });
         }
+        self.renderer.dispatch_compute(&mut cmd_encoder);
+        // The UI is drawn after the TAA resolve so that it isn't jittered or
+        // blended. It does end up in the history, where the next resolve
+        // clamps it to the scene's colors.
+        self.renderer.hdr.post_process(&mut cmd_encoder);
         let size = self.surface.extent();
         let screen_descriptor = ScreenDescriptor {
             size_in_pixels: [size.width, size.height],
             pixels_per_point: window.scale_factor() as f32,
         };
         let mut picked_path: Option<PathBuf> = None;
-        let mut source = std::mem::take(&mut self.shader_source);
+        let mut picked_environment_path: Option<PathBuf> = None;
+        let mut added_environment_path: Option<PathBuf> = None;
+        let mut picked_compute_path: Option<PathBuf> = None;
+        let mut picked_surface_format = None;
+        let mut picked_shadow_resolution = None;
+        let mut picked_instance_layout = None;
+        let mut picked_exr_path: Option<PathBuf> = None;
+        let mut picked_anisotropy = None;
+        let mut picked_hdr_format = None;
+        let mut picked_taa = None;
+        let mut picked_dof = None;
+        let mut picked_output_transform = None;
+        let mut picked_light_node = None;
+        let mut picked_double_sided = None;
+        let mut picked_axis_view = None;
+        let light_node = self.renderer.light_node();
+        let editing_tab = self.renderer.active_shader_tab;
+        // The editor works on a copy, so the tab keeps its source whatever
+        // happens while the UI runs
+        let mut source = self.renderer.shader_tabs[editing_tab].source.clone();
         let mut shader_changed = false;
-        let draw_params = EguiDrawParams {
-            device: &self.device,
-            queue: &self.queue,
-            encoder: &mut cmd_encoder,
-            window,
-            view: self.hdr.view(),
-            screen_descriptor,
-            run_ui: |ui| {
-                egui::Window::new("Shader Editor")
-                    .resizable(true)
-                    .vscroll(true)
-                    .default_open(true)
-                    .show(ui, |ui| {
+        let mut save_shader = false;
+        let mut save_shader_as = false;
+        let mut reset_shader = false;
+        let mut selected_tab = editing_tab;
+        let mut new_tab = false;
+        let mut closed_tab = None;
+        let mut editor_focused = false;
+        let ui_visible = self.ui_visible;
+        let ui_frame = self.ui.run(window, |ui| {
+            if !ui_visible {
+                return;
+            }
+            egui::Window::new("Shader Editor")
+                .resizable(true)
+                .vscroll(true)
+                .default_open(true)
+                .show(ui, |ui| {
+                    ui.horizontal_wrapped(|ui| {
+                        for (i, tab) in self.renderer.shader_tabs.iter().enumerate() {
+                            ui.selectable_value(&mut selected_tab, i, tab.name());
+                        }
+                        if ui.button("+").on_hover_text("New tab").clicked() {
+                            new_tab = true;
+                        }
+                        if self.renderer.shader_tabs.len() > 1
+                            && ui.button("x").on_hover_text("Close tab").clicked()
+                        {
+                            closed_tab = Some(editing_tab);
+                        }
+                    });
+                    ui.separator();
+
+                    ui.horizontal(|ui| {
                         if ui.button("Load a new shader").clicked() {
                             if let Some(path) = rfd::FileDialog::new().pick_file() {
                                 picked_path = Some(path);
                             }
                         }
+                        ui.menu_button("Recent shaders", |ui| {
+                            let recent_shaders = self.config.recent_shaders();
+                            if recent_shaders.is_empty() {
+                                ui.label("No recent shaders");
+                            }
+                            for path in recent_shaders {
+                                if ui.button(path.display().to_string()).clicked() {
+                                    picked_path = Some(path.clone());
+                                    ui.close_menu();
+                                }
+                            }
+                        });
+                    });
+
+                    if self.renderer.shader_tabs[editing_tab].is_compiling() {
+                        ui.horizontal(|ui| {
+                            ui.spinner();
+                            ui.label("Compiling…");
+                        });
+                    }
 
-                        if let Some(err) = &self.shader_compile_error {
-                            ui.label(RichText::new(err).color(Color32::RED));
+                    let mut jump_to_error = None;
+                    let compile_error = self.renderer.shader_tabs[editing_tab].compile_error();
+                    let compiled_recently = self.renderer.shader_tabs[editing_tab]
+                        .last_compiled()
+                        .is_some_and(|time| time.elapsed() < COMPILED_MESSAGE_TIME);
+                    if compile_error.is_none() && compiled_recently {
+                        ui.label(RichText::new("Compiled OK").color(Color32::GREEN));
+                    }
+                    if let Some(err) = compile_error {
+                        match err.location {
+                            Some(location) => {
+                                let text = format!(
+                                    "{}:{}: {}",
+                                    location.line, location.column, err.message
+                                );
+                                if ui
+                                    .add(
+                                        egui::Label::new(RichText::new(text).color(Color32::RED))
+                                            .sense(egui::Sense::click()),
+                                    )
+                                    .on_hover_text("Click to jump to the error")
+                                    .clicked()
+                                {
+                                    jump_to_error = Some(location);
+                                }
+                            }
+                            None => {
+                                ui.label(RichText::new(&err.message).color(Color32::RED));
+                            }
                         }
+                    }
 
-                        ui.heading("Shader Source");
-                        let mut theme = CodeTheme::from_memory(ui.ctx());
-                        ui.collapsing("Theme Settings", |ui| {
-                            ui.group(|ui| {
-                                theme.ui(ui);
-                                theme.clone().store_in_memory(ui.ctx());
-                            })
-                        });
-                        let mut layouter = |ui: &egui::Ui, string: &str, wrap_width: f32| {
-                            let mut layout_job = highlight(ui.ctx(), &theme, string, "wgsl");
-                            layout_job.wrap.max_width = wrap_width;
-                            ui.fonts(|f| f.layout_job(layout_job))
-                        };
-                        let response = ui.add(
+                    ui.heading("Shader Source");
+                    let mut theme = CodeTheme::from_memory(ui.ctx());
+                    ui.collapsing("Theme Settings", |ui| {
+                        ui.group(|ui| {
+                            theme.ui(ui);
+                            theme.clone().store_in_memory(ui.ctx());
+                        })
+                    });
+                    let error_line = compile_error
+                        .and_then(|err| err.location)
+                        .map(|location| location.line);
+                    let mut layouter = |ui: &egui::Ui, string: &str, _wrap_width: f32| {
+                        let mut layout_job = highlight(ui.ctx(), &theme, string, "wgsl");
+                        // Wrapping would break the alignment with the line numbers
+                        layout_job.wrap.max_width = f32::INFINITY;
+                        if let Some(line) = error_line {
+                            highlight_line(
+                                &mut layout_job,
+                                line,
+                                Color32::from_rgba_unmultiplied(255, 0, 0, 48),
+                            );
+                        }
+                        ui.fonts(|f| f.layout_job(layout_job))
+                    };
+                    // Consumed before the editor sees it, which would insert a
+                    // newline
+                    if self.editor_focused
+                        && ui.input_mut(|input| {
+                            input.consume_key(egui::Modifiers::COMMAND, egui::Key::Enter)
+                        })
+                    {
+                        shader_changed = true;
+                    }
+                    let mut output = ui
+                        .horizontal_top(|ui| {
+                            line_number_gutter(ui, source.split('\n').count(), error_line);
                             egui::TextEdit::multiline(&mut source)
                                 .font(egui::TextStyle::Monospace)
                                 .code_editor()
                                 .lock_focus(true)
+                                .interactive(!self.renderer.shader_tabs[editing_tab].is_spirv())
                                 .desired_width(f32::INFINITY)
-                                .layouter(&mut layouter),
-                        );
-                        response.context_menu(|ui| {
-                            if ui.button("Recompile").clicked() {
-                                shader_changed = true;
+                                .layouter(&mut layouter)
+                                .show(ui)
+                        })
+                        .inner;
+                    if let Some(location) = jump_to_error {
+                        let start = CCursor::new(location.start);
+                        let end = CCursor::new(location.end);
+                        output
+                            .state
+                            .cursor
+                            .set_char_range(Some(CCursorRange::two(start, end)));
+                        output.state.store(ui.ctx(), output.response.id);
+                        output.response.request_focus();
+                        let cursor_rect = output
+                            .galley
+                            .pos_from_ccursor(start)
+                            .translate(output.galley_pos.to_vec2());
+                        ui.scroll_to_rect(cursor_rect, Some(egui::Align::Center));
+                    }
+                    let response = output.response;
+                    response.context_menu(|ui| {
+                        if ui.button("Recompile").clicked() {
+                            shader_changed = true;
+                        }
+                        if ui.button("Save").clicked() {
+                            save_shader = true;
+                            ui.close_menu();
+                        }
+                        if ui.button("Save As...").clicked() {
+                            save_shader_as = true;
+                            ui.close_menu();
+                        }
+                    });
+                    if !shader_changed {
+                        shader_changed = response.lost_focus();
+                    }
+                    editor_focused = response.has_focus();
+                    ui.horizontal(|ui| {
+                        if ui
+                            .button("Recompile shader")
+                            .on_hover_text("Ctrl+Enter")
+                            .clicked()
+                        {
+                            shader_changed = true;
+                        }
+                        if ui.button("Save shader").on_hover_text("Ctrl+S").clicked() {
+                            save_shader = true;
+                        }
+                        if ui.button("Save shader as...").clicked() {
+                            save_shader_as = true;
+                        }
+                        if ui.button("Reset to default shader").clicked() {
+                            if source != self.renderer.shader_tabs[editing_tab].saved_source() {
+                                self.confirm_shader_reset = true;
+                            } else {
+                                reset_shader = true;
+                            }
+                        }
+                    });
+                });
+            if self.confirm_shader_reset {
+                egui::Window::new("Reset shader?")
+                    .collapsible(false)
+                    .resizable(false)
+                    .show(ui, |ui| {
+                        ui.label("The shader has unsaved edits, which will be lost.");
+                        ui.horizontal(|ui| {
+                            if ui.button("Reset").clicked() {
+                                reset_shader = true;
+                                self.confirm_shader_reset = false;
                             }
-                            if ui.button("Save").clicked() {
-                                if let Some(path) = rfd::FileDialog::new().save_file() {
-                                    match std::fs::write(path, &source) {
-                                        Ok(_) => (),
-                                        Err(e) => error!("Could not save file! {}", e),
+                            if ui.button("Cancel").clicked() {
+                                self.confirm_shader_reset = false;
+                            }
+                        });
+                    });
+            }
+            egui::Window::new("Controls")
+                .resizable(true)
+                .default_open(false)
+                .show(ui, |ui| {
+                    ui.heading("Environment");
+                    if ui.button("Load environment map").clicked() {
+                        if let Some(path) = rfd::FileDialog::new()
+                            .add_filter("HDR image", &["hdr"])
+                            .pick_file()
+                        {
+                            picked_environment_path = Some(path);
+                        }
+                    }
+                    if ui
+                        .button("Add environment")
+                        .on_hover_text("Preload another environment to crossfade to")
+                        .clicked()
+                    {
+                        if let Some(path) = rfd::FileDialog::new()
+                            .add_filter("HDR image", &["hdr"])
+                            .pick_file()
+                        {
+                            added_environment_path = Some(path);
+                        }
+                    }
+                    let slot_names: Vec<String> = self
+                        .renderer
+                        .cubemap
+                        .slot_names()
+                        .map(str::to_owned)
+                        .collect();
+                    if slot_names.len() > 1 {
+                        let (mut slot_a, mut slot_b) = self.renderer.cubemap.slots();
+                        for (label, slot) in [("A", &mut slot_a), ("B", &mut slot_b)] {
+                            egui::ComboBox::from_label(format!("Environment {}", label))
+                                .selected_text(slot_names[*slot].as_str())
+                                .show_ui(ui, |ui| {
+                                    for (index, name) in slot_names.iter().enumerate() {
+                                        ui.selectable_value(slot, index, name.as_str());
+                                    }
+                                });
+                        }
+                        self.renderer
+                            .cubemap
+                            .set_slots(&self.renderer.device, slot_a, slot_b);
+                        let mut blend = self.renderer.cubemap.blend();
+                        if ui
+                            .add(egui::Slider::new(&mut blend, 0.0..=1.0).text("Crossfade"))
+                            .on_hover_text("Lighting follows whichever environment is closer")
+                            .changed()
+                        {
+                            self.renderer.cubemap.set_blend(&self.renderer.queue, blend);
+                        }
+                    }
+                    let current_size = self.renderer.cubemap.size();
+                    let max_size = cubemap::max_size(&self.renderer.device);
+                    egui::ComboBox::from_label("Environment size")
+                        .selected_text(format!("{0}x{0}", current_size))
+                        .show_ui(ui, |ui| {
+                            for size in CUBEMAP_SIZES {
+                                if size <= max_size
+                                    && ui
+                                        .selectable_label(
+                                            size == current_size,
+                                            format!("{0}x{0}", size),
+                                        )
+                                        .clicked()
+                                {
+                                    if let Err(e) =
+                                        self.renderer.cubemap.set_size(&self.renderer.device, size)
+                                    {
+                                        error!("{}", e);
                                     }
                                 }
                             }
+                        })
+                        .response
+                        .on_hover_text(
+                            "Cube face size used for environment maps loaded after this",
+                        );
+                    let mut sky_mode = self.renderer.cubemap.sky_mode();
+                    ui.horizontal(|ui| {
+                        if ui
+                            .radio(matches!(sky_mode, SkyMode::Environment), "Environment")
+                            .clicked()
+                        {
+                            sky_mode = SkyMode::Environment;
+                        }
+                        if ui
+                            .radio(matches!(sky_mode, SkyMode::SolidColor(_)), "Solid color")
+                            .clicked()
+                            && !matches!(sky_mode, SkyMode::SolidColor(_))
+                        {
+                            sky_mode = SkyMode::SolidColor([0.2, 0.2, 0.2]);
+                        }
+                        if ui
+                            .radio(matches!(sky_mode, SkyMode::Gradient { .. }), "Gradient")
+                            .clicked()
+                            && !matches!(sky_mode, SkyMode::Gradient { .. })
+                        {
+                            sky_mode = SkyMode::Gradient {
+                                top: [0.3, 0.5, 0.9],
+                                bottom: [0.8, 0.8, 0.8],
+                            };
+                        }
+                    });
+                    match &mut sky_mode {
+                        SkyMode::Environment => (),
+                        SkyMode::SolidColor(color) => {
+                            ui.horizontal(|ui| {
+                                ui.label("Color");
+                                ui.color_edit_button_rgb(color);
+                            });
+                        }
+                        SkyMode::Gradient { top, bottom } => {
+                            ui.horizontal(|ui| {
+                                ui.label("Top");
+                                ui.color_edit_button_rgb(top);
+                                ui.label("Bottom");
+                                ui.color_edit_button_rgb(bottom);
+                            });
+                        }
+                    }
+                    self.renderer
+                        .cubemap
+                        .set_sky_mode(&self.renderer.queue, sky_mode);
+                    let mut rotation = self.renderer.cubemap.rotation().0;
+                    let mut intensity = self.renderer.cubemap.intensity();
+                    if ui
+                        .add(
+                            egui::Slider::new(&mut rotation, -180.0..=180.0)
+                                .text("Rotation")
+                                .suffix("°"),
+                        )
+                        .changed()
+                    {
+                        self.renderer
+                            .cubemap
+                            .set_rotation(&self.renderer.queue, cgmath::Deg(rotation));
+                    }
+                    if ui
+                        .add(
+                            egui::Slider::new(&mut intensity, 0.0..=10.0)
+                                .logarithmic(true)
+                                .text("Intensity"),
+                        )
+                        .changed()
+                    {
+                        self.renderer
+                            .cubemap
+                            .set_intensity(&self.renderer.queue, intensity);
+                    }
+
+                    ui.heading("Grid");
+                    ui.checkbox(&mut self.renderer.show_grid, "Show grid");
+                    ui.add_enabled_ui(self.renderer.show_grid, |ui| {
+                        let mut spacing = self.renderer.grid.spacing();
+                        let mut color = self.renderer.grid.color();
+                        ui.horizontal(|ui| {
+                            ui.label("Spacing");
+                            ui.add(
+                                egui::DragValue::new(&mut spacing)
+                                    .speed(0.01)
+                                    .clamp_range(0.01..=100.0),
+                            );
+                            ui.label("Color");
+                            ui.color_edit_button_rgba_unmultiplied(&mut color);
                         });
-                        if !shader_changed {
-                            shader_changed = response.lost_focus();
+                        self.renderer
+                            .grid
+                            .set_spacing(&self.renderer.queue, spacing);
+                        self.renderer.grid.set_color(&self.renderer.queue, color);
+                    });
+
+                    ui.heading("Points and lines");
+                    let mut show_debug_primitives = self.renderer.show_debug_primitives();
+                    ui.add_enabled(
+                        self.debug_primitives_supported,
+                        egui::Checkbox::new(&mut show_debug_primitives, "Show points and lines"),
+                    )
+                    .on_hover_text("Draw the model's vertices or edges on top of it")
+                    .on_disabled_hover_text("Not supported by this adapter");
+                    if show_debug_primitives != self.renderer.show_debug_primitives() {
+                        self.renderer
+                            .set_show_debug_primitives(show_debug_primitives);
+                    }
+                    if let Some(debug_primitives) = self
+                        .renderer
+                        .debug_primitives
+                        .as_mut()
+                        .filter(|_| show_debug_primitives)
+                    {
+                        let mut mode = debug_primitives.mode();
+                        let mut size = debug_primitives.size();
+                        let mut color = debug_primitives.color();
+                        ui.horizontal(|ui| {
+                            ui.radio_value(&mut mode, DebugPrimitiveMode::Points, "Vertices");
+                            ui.radio_value(&mut mode, DebugPrimitiveMode::Lines, "Edges");
+                        });
+                        ui.horizontal(|ui| {
+                            ui.add(
+                                egui::Slider::new(&mut size, 1.0..=20.0)
+                                    .text("Size")
+                                    .suffix(" px"),
+                            );
+                            ui.label("Color");
+                            ui.color_edit_button_rgba_unmultiplied(&mut color);
+                        });
+                        debug_primitives.set_mode(&self.renderer.queue, mode);
+                        debug_primitives.set_size(&self.renderer.queue, size);
+                        debug_primitives.set_color(&self.renderer.queue, color);
+                    }
+
+                    ui.heading("Light");
+                    let mut marker_scale = self.renderer.light_marker.scale();
+                    if ui
+                        .add(
+                            egui::Slider::new(&mut marker_scale, 0.01..=1.0)
+                                .logarithmic(true)
+                                .text("Marker size"),
+                        )
+                        .changed()
+                    {
+                        self.renderer.light_marker.set_scale(marker_scale);
+                    }
+                    ui.checkbox(&mut self.renderer.animate_light, "Animate")
+                        .on_hover_text("Orbit the light when it isn't attached to a node");
+                    let mut double_sided = self.renderer.double_sided();
+                    if ui
+                        .checkbox(&mut double_sided, "Double-sided")
+                        .on_hover_text("Draw back faces, lit as if they faced the viewer")
+                        .changed()
+                    {
+                        picked_double_sided = Some(double_sided);
+                    }
+
+                    ui.heading("Instances");
+                    let mut instance_layout = self.renderer.instance_layout();
+                    ui.horizontal(|ui| {
+                        ui.radio_value(&mut instance_layout, InstanceLayout::None, "None");
+                        ui.radio_value(&mut instance_layout, InstanceLayout::Single, "Single");
+                        if ui
+                            .radio(
+                                matches!(instance_layout, InstanceLayout::Grid { .. }),
+                                "Grid",
+                            )
+                            .clicked()
+                            && !matches!(instance_layout, InstanceLayout::Grid { .. })
+                        {
+                            instance_layout = InstanceLayout::Grid {
+                                count: 10,
+                                spacing: 3.0,
+                            };
+                        }
+                    });
+                    if let InstanceLayout::Grid { count, spacing } = &mut instance_layout {
+                        ui.horizontal(|ui| {
+                            ui.label("Per row");
+                            ui.add(egui::DragValue::new(count).clamp_range(1..=50));
+                            ui.label("Spacing");
+                            ui.add(
+                                egui::DragValue::new(spacing)
+                                    .speed(0.1)
+                                    .clamp_range(0.1..=100.0),
+                            );
+                        });
+                    }
+                    if instance_layout != self.renderer.instance_layout() {
+                        picked_instance_layout = Some(instance_layout);
+                    }
+
+                    ui.heading("Selection");
+                    match self.selected_node.and_then(|handle| {
+                        self.renderer
+                            .scene_tree
+                            .get_mut(&handle)
+                            .map(|node| (handle, node))
+                    }) {
+                        Some((handle, mut node)) => {
+                            ui.label(format!("Selected {:?}", handle));
+                            let mut tint = node.get_tint();
+                            ui.horizontal(|ui| {
+                                ui.label("Tint");
+                                if ui.color_edit_button_rgba_unmultiplied(&mut tint).changed() {
+                                    node.set_tint(tint);
+                                }
+                            });
+                            let mut holds_light = light_node == Some(handle);
+                            if ui.checkbox(&mut holds_light, "Attach light").changed() {
+                                picked_light_node = Some(holds_light.then_some(handle));
+                            }
+                            if ui.button("Clear selection").clicked() {
+                                self.selected_node = None;
+                                self.selected_transform = None;
+                            }
+                        }
+                        None => {
+                            ui.label("Click an object to select it");
+                        }
+                    }
+                    ui.collapsing("Nodes", |ui| {
+                        let handles: Vec<_> = self.renderer.scene_tree.handles().collect();
+                        for handle in handles {
+                            if let Some(mut node) = self.renderer.scene_tree.get_mut(&handle) {
+                                let mut visible = node.is_visible();
+                                if ui.checkbox(&mut visible, format!("{:?}", handle)).changed() {
+                                    node.set_visible(visible);
+                                }
+                            }
+                        }
+                    });
+
+                    ui.heading("Camera");
+                    let camera = self.renderer.camera.camera();
+                    let mut position = camera.position();
+                    let mut yaw = cgmath::Deg::from(camera.yaw()).0;
+                    let mut pitch = cgmath::Deg::from(camera.pitch()).0;
+                    let mut fovy = cgmath::Deg::from(self.renderer.camera.projection().fovy()).0;
+                    let mut pose_changed = false;
+                    ui.horizontal(|ui| {
+                        ui.label("Position");
+                        for value in [&mut position.x, &mut position.y, &mut position.z] {
+                            pose_changed |=
+                                ui.add(egui::DragValue::new(value).speed(0.1)).changed();
+                        }
+                    });
+                    ui.horizontal(|ui| {
+                        ui.label("Yaw");
+                        pose_changed |=
+                            ui.add(egui::DragValue::new(&mut yaw).suffix("°")).changed();
+                        ui.label("Pitch");
+                        pose_changed |= ui
+                            .add(
+                                egui::DragValue::new(&mut pitch)
+                                    .suffix("°")
+                                    .clamp_range(-90.0..=90.0),
+                            )
+                            .changed();
+                    });
+                    if pose_changed {
+                        self.renderer.camera.set_pose(
+                            &self.renderer.queue,
+                            position,
+                            cgmath::Deg(yaw),
+                            cgmath::Deg(pitch),
+                        );
+                    }
+                    ui.horizontal(|ui| {
+                        ui.label("View");
+                        for view in AxisView::all() {
+                            if ui.button(view.name()).clicked() {
+                                picked_axis_view = Some(view);
+                            }
+                        }
+                    })
+                    .response
+                    .on_hover_text("Numpad 1, 3 and 7, with Ctrl for the opposite side");
+                    if ui
+                        .add(
+                            egui::Slider::new(&mut fovy, 6.0..=170.0)
+                                .text("FOV")
+                                .suffix("°"),
+                        )
+                        .changed()
+                    {
+                        self.renderer
+                            .camera
+                            .set_fovy(&self.renderer.queue, cgmath::Deg(fovy));
+                    }
+                    let mut zoom_mode = self.camera_controller.zoom_mode();
+                    ui.horizontal(|ui| {
+                        ui.label("Scroll");
+                        ui.radio_value(&mut zoom_mode, ZoomMode::Dolly, "Dolly");
+                        ui.radio_value(&mut zoom_mode, ZoomMode::Fov, "Field of view");
+                    });
+                    self.camera_controller.set_zoom_mode(zoom_mode);
+                    let projection = self.renderer.camera.projection();
+                    let (mut znear, mut zfar) = (projection.znear(), projection.zfar());
+                    ui.horizontal(|ui| {
+                        ui.label("Near");
+                        let near_changed = ui
+                            .add(
+                                egui::DragValue::new(&mut znear)
+                                    .speed(0.01)
+                                    .clamp_range(MIN_ZNEAR..=zfar - MIN_CLIP_DEPTH),
+                            )
+                            .changed();
+                        ui.label("Far");
+                        let far_changed = ui
+                            .add(
+                                egui::DragValue::new(&mut zfar)
+                                    .clamp_range(znear + MIN_CLIP_DEPTH..=MAX_ZFAR),
+                            )
+                            .changed();
+                        if near_changed || far_changed {
+                            // Moving near up can leave far too close to it
+                            let zfar = zfar.max(znear + MIN_CLIP_DEPTH);
+                            if let Err(e) = self.renderer.camera.set_clip_planes(
+                                &self.renderer.queue,
+                                znear,
+                                zfar,
+                            ) {
+                                error!("{}", e);
+                            }
                         }
+                    });
+                    ui.horizontal(|ui| {
+                        let mut split_screen = self.renderer.split_screen();
+                        if ui
+                            .checkbox(&mut split_screen, "Split screen")
+                            .on_hover_text("Show a second, fixed camera in the right half")
+                            .changed()
+                        {
+                            self.renderer.set_split_screen(split_screen);
+                        }
+                        if ui
+                            .add_enabled(split_screen, egui::Button::new("Move right camera here"))
+                            .clicked()
+                        {
+                            self.renderer.copy_camera_to_split();
+                        }
+                    });
+                    ui.collapsing("Path", |ui| {
                         ui.horizontal(|ui| {
-                            if ui.button("Recompile shader").clicked() {
-                                shader_changed = true;
+                            if ui.button("Add current view").clicked() {
+                                let time = if self.camera_path.is_empty() {
+                                    0.0
+                                } else {
+                                    self.camera_path.duration() + KEYFRAME_SPACING
+                                };
+                                self.camera_path.add(CameraKeyframe::from_camera(
+                                    time,
+                                    self.renderer.camera.camera(),
+                                ));
+                            }
+                            if ui
+                                .add_enabled(
+                                    !self.camera_path.is_empty(),
+                                    egui::Button::new("Clear"),
+                                )
+                                .clicked()
+                            {
+                                self.camera_path.clear();
+                                self.camera_path_time = None;
+                                self.camera_path_playing = false;
+                            }
+                        });
+                        let mut removed = None;
+                        let mut retimed = None;
+                        egui::Grid::new("camera_keyframes").show(ui, |ui| {
+                            for (index, keyframe) in self.camera_path.keyframes().iter().enumerate()
+                            {
+                                let mut time = keyframe.time;
+                                if ui
+                                    .add(
+                                        egui::DragValue::new(&mut time)
+                                            .speed(0.05)
+                                            .clamp_range(0.0..=f32::MAX)
+                                            .suffix(" s"),
+                                    )
+                                    .changed()
+                                {
+                                    retimed = Some((index, time));
+                                }
+                                let position = keyframe.position;
+                                ui.label(format!(
+                                    "({:.1}, {:.1}, {:.1})",
+                                    position.x, position.y, position.z
+                                ));
+                                if ui.small_button("Remove").clicked() {
+                                    removed = Some(index);
+                                }
+                                ui.end_row();
+                            }
+                        });
+                        if let Some((index, time)) = retimed {
+                            self.camera_path.set_time(index, time);
+                        }
+                        if let Some(index) = removed {
+                            self.camera_path.remove(index);
+                        }
+
+                        ui.add_enabled_ui(!self.camera_path.is_empty(), |ui| {
+                            let mut following = self.camera_path_time.is_some();
+                            if ui.checkbox(&mut following, "Follow path").changed() {
+                                self.camera_path_time = following.then_some(0.0);
+                                self.camera_path_playing = false;
                             }
-                            if ui.button("Save shader").clicked() {
-                                if let Some(path) = rfd::FileDialog::new().save_file() {
-                                    match std::fs::write(path, &source) {
-                                        Ok(_) => (),
-                                        Err(e) => error!("Could not save file! {}", e),
+                            ui.horizontal(|ui| {
+                                let label = if self.camera_path_playing {
+                                    "Pause"
+                                } else {
+                                    "Play"
+                                };
+                                if ui.button(label).clicked() {
+                                    self.camera_path_playing = !self.camera_path_playing;
+                                    let time = self.camera_path_time.unwrap_or(0.0);
+                                    // Start over once the end has been reached
+                                    let at_end = time >= self.camera_path.duration();
+                                    self.camera_path_time = Some(if at_end { 0.0 } else { time });
+                                }
+                                let mut time = self.camera_path_time.unwrap_or(0.0);
+                                if ui
+                                    .add(
+                                        egui::Slider::new(
+                                            &mut time,
+                                            0.0..=self.camera_path.duration(),
+                                        )
+                                        .suffix(" s"),
+                                    )
+                                    .changed()
+                                {
+                                    self.camera_path_time = Some(time);
+                                }
+                            });
+                        });
+                    });
+
+                    ui.heading("Shadows");
+                    let mut shadows_enabled = self.renderer.shadows_enabled();
+                    if ui.checkbox(&mut shadows_enabled, "Enabled").changed() {
+                        self.renderer.set_shadows_enabled(shadows_enabled);
+                    }
+                    let current_resolution = self.renderer.shadow_resolution();
+                    ui.add_enabled_ui(shadows_enabled, |ui| {
+                        egui::ComboBox::from_label("Resolution")
+                            .selected_text(format!("{0}x{0}", current_resolution))
+                            .show_ui(ui, |ui| {
+                                for size in SHADOW_MAP_SIZES {
+                                    if ui
+                                        .selectable_label(
+                                            size == current_resolution,
+                                            format!("{0}x{0}", size),
+                                        )
+                                        .clicked()
+                                    {
+                                        picked_shadow_resolution = Some(size);
                                     }
                                 }
+                            });
+                    });
+
+                    ui.heading("Display");
+                    ui.label(format!(
+                        "Adapter: {} ({:?})",
+                        self.adapter_info.name, self.adapter_info.backend
+                    ));
+                    let current_format = self.surface.format();
+                    egui::ComboBox::from_label("Surface format")
+                        .selected_text(format!("{:?}", current_format))
+                        .show_ui(ui, |ui| {
+                            for &format in &self.surface.capabilities().formats {
+                                let text = if format.is_srgb() {
+                                    format!("{:?}", format)
+                                } else {
+                                    format!("{:?} (linear)", format)
+                                };
+                                if ui
+                                    .selectable_label(format == current_format, text)
+                                    .clicked()
+                                    && format != current_format
+                                {
+                                    picked_surface_format = Some(format);
+                                }
                             }
                         });
+
+                    let mut gamma_in_shader =
+                        self.renderer.hdr.output_transform() == OutputTransform::Gamma22;
+                    if ui
+                        .checkbox(&mut gamma_in_shader, "Gamma encode in shader (2.2)")
+                        .on_hover_text("Set automatically for surface formats that aren't sRGB")
+                        .changed()
+                    {
+                        picked_output_transform = Some(if gamma_in_shader {
+                            OutputTransform::Gamma22
+                        } else {
+                            OutputTransform::HardwareSrgb
+                        });
+                    }
+
+                    let current_hdr_format = self.renderer.hdr.hdr_format();
+                    egui::ComboBox::from_label("HDR format")
+                        .selected_text(format!("{:?}", current_hdr_format))
+                        .show_ui(ui, |ui| {
+                            for format in HdrFormat::ALL {
+                                let supported = self.hdr_formats.contains(&format);
+                                let response = ui.add_enabled(
+                                    supported,
+                                    egui::SelectableLabel::new(
+                                        format == current_hdr_format,
+                                        format!("{:?}", format),
+                                    ),
+                                );
+                                if response
+                                    .on_disabled_hover_text("Not supported by this adapter")
+                                    .clicked()
+                                    && format != current_hdr_format
+                                {
+                                    picked_hdr_format = Some(format);
+                                }
+                            }
+                        });
+
+                    // Both only follow the main camera
+                    let split_screen = self.renderer.split_screen();
+                    let mut taa = self.renderer.taa_enabled();
+                    if ui
+                        .add_enabled(
+                            !split_screen,
+                            egui::Checkbox::new(&mut taa, "Temporal anti-aliasing"),
+                        )
+                        .on_hover_text(
+                            "Jitters the camera each frame and blends with earlier frames",
+                        )
+                        .on_disabled_hover_text("Off while the screen is split")
+                        .changed()
+                    {
+                        picked_taa = Some(taa);
+                    }
+
+                    let mut dof = self.renderer.dof_enabled();
+                    if ui
+                        .add_enabled(
+                            !split_screen,
+                            egui::Checkbox::new(&mut dof, "Depth of field"),
+                        )
+                        .on_disabled_hover_text("Off while the screen is split")
+                        .changed()
+                    {
+                        picked_dof = Some(dof);
+                    }
+                    ui.add_enabled_ui(dof, |ui| {
+                        let settings = &mut self.renderer.dof;
+                        ui.checkbox(&mut settings.auto_focus, "Focus on screen center");
+                        ui.add_enabled(
+                            !settings.auto_focus,
+                            egui::Slider::new(&mut settings.focus_distance, 0.1..=100.0)
+                                .logarithmic(true)
+                                .text("Focus distance"),
+                        );
+                        ui.add(
+                            egui::Slider::new(&mut settings.aperture, 0.0..=4.0).text("Aperture"),
+                        );
+                        ui.add(
+                            egui::Slider::new(&mut settings.max_radius, 1.0..=32.0)
+                                .text("Max blur radius (px)"),
+                        );
+                    });
+
+                    // Anisotropic filtering samples finer mip levels, so
+                    // it needs both adapter support and mipmapped textures
+                    let current_anisotropy = self.renderer.anisotropy_clamp();
+                    let has_mipmaps = self.renderer.model_has_mipmaps();
+                    ui.add_enabled_ui(self.max_anisotropy > 1 && has_mipmaps, |ui| {
+                        egui::ComboBox::from_label("Anisotropic filtering")
+                            .selected_text(format!("{}x", current_anisotropy))
+                            .show_ui(ui, |ui| {
+                                for level in ANISOTROPY_LEVELS
+                                    .into_iter()
+                                    .filter(|&level| level <= self.max_anisotropy)
+                                {
+                                    if ui
+                                        .selectable_label(
+                                            level == current_anisotropy,
+                                            format!("{}x", level),
+                                        )
+                                        .clicked()
+                                    {
+                                        picked_anisotropy = Some(level);
+                                    }
+                                }
+                            });
+                    });
+                    if self.max_anisotropy == 1 {
+                        ui.label("Anisotropic filtering is not supported by this adapter");
+                    } else if !has_mipmaps {
+                        ui.label("Anisotropic filtering needs textures with mipmaps");
+                    }
+
+                    if ui.button("Save HDR image (.exr)").clicked() {
+                        if let Some(path) = rfd::FileDialog::new()
+                            .add_filter("OpenEXR image", &["exr"])
+                            .set_file_name("render.exr")
+                            .save_file()
+                        {
+                            picked_exr_path = Some(path);
+                        }
+                    }
+
+                    ui.heading("Debug");
+                    match &self.renderer.gpu_timer {
+                        Some(gpu_timer) => match gpu_timer.timings() {
+                            Some(timings) => {
+                                ui.label(format!("Scene pass: {:.3} ms", timings.scene));
+                                ui.label(format!("Tonemap pass: {:.3} ms", timings.tonemap));
+                            }
+                            None => {
+                                ui.label("Waiting for GPU timings");
+                            }
+                        },
+                        None => {
+                            let frame_time = if self.fps > 0.0 {
+                                1000.0 / self.fps
+                            } else {
+                                0.0
+                            };
+                            ui.label(format!(
+                                "Frame time (CPU): {:.2} ms, GPU timestamps are unsupported",
+                                frame_time
+                            ));
+                        }
+                    }
+                    let (drawn, total) = self.renderer.instance_counts();
+                    ui.label(format!(
+                        "Instances: {} drawn, {} culled",
+                        drawn,
+                        total - drawn
+                    ));
+                    let stats = self.renderer.render_stats();
+                    ui.label(format!(
+                        "Draw calls: {}, material binds: {}, triangles: {}, instances: {}",
+                        stats.draw_calls, stats.material_binds, stats.triangles, stats.instances
+                    ));
+                    let surface_extent = self.surface.extent();
+                    ui.label(format!(
+                        "Surface: {}x{} {:?}",
+                        surface_extent.width,
+                        surface_extent.height,
+                        self.surface.format()
+                    ));
+                    ui.checkbox(&mut self.renderer.show_depth, "Visualize depth");
+                    ui.checkbox(&mut self.fixed_timestep, "Fixed timestep (1/60 s)");
+                    ui.checkbox(&mut self.throttle_unfocused, "Throttle when unfocused")
+                        .on_hover_text(format!(
+                            "Draw every {} ms while the window doesn't have focus",
+                            UNFOCUSED_FRAME_TIME.as_millis()
+                        ));
+                    ui.checkbox(&mut self.renderer.advance_time, "Advance shader time")
+                        .on_hover_text("Whether `time` and `iTime` uniforms keep counting");
+                    ui.horizontal(|ui| {
+                        ui.checkbox(&mut self.paused, "Paused (P)");
+                        if ui
+                            .add_enabled(self.paused, egui::Button::new("Step one frame"))
+                            .clicked()
+                        {
+                            self.step_requested = true;
+                        }
+                    });
+
+                    ui.heading("Mouse");
+                    let mut invert_y = self.camera_controller.invert_y();
+                    if ui.checkbox(&mut invert_y, "Invert Y").changed() {
+                        self.camera_controller.set_invert_y(invert_y);
+                    }
+                    let mut drag_threshold = self.camera_controller.drag_threshold();
+                    ui.horizontal(|ui| {
+                        ui.label("Drag threshold (px)");
+                        if ui
+                            .add(egui::DragValue::new(&mut drag_threshold).clamp_range(0.0..=50.0))
+                            .changed()
+                        {
+                            self.camera_controller.set_drag_threshold(drag_threshold);
+                        }
+                    });
+
+                    ui.heading("Key Bindings");
+                    egui::Grid::new("key_bindings").show(ui, |ui| {
+                        for action in CameraAction::all() {
+                            ui.label(action.name());
+                            let text = if self.rebinding_action == Some(action) {
+                                "Press a key...".to_string()
+                            } else {
+                                self.camera_controller
+                                    .key_bindings()
+                                    .keys_for(action)
+                                    .iter()
+                                    .map(|k| format!("{:?}", k))
+                                    .collect::<Vec<_>>()
+                                    .join(", ")
+                            };
+                            if ui.button(text).clicked() {
+                                self.rebinding_action = Some(action);
+                            }
+                            ui.end_row();
+                        }
+                    });
+                });
+            if let Some(uniforms) = self.renderer.shader_tabs[editing_tab].uniforms_mut() {
+                egui::Window::new("Shader Uniforms")
+                    .resizable(false)
+                    .show(ui, |ui| {
+                        ui.label(format!("var<uniform> {}", uniforms.name()));
+                        if let Some(copies) = uniforms.copies() {
+                            // Instance i of the model reads copy i % copies
+                            let mut copy = uniforms.editing_copy();
+                            ui.horizontal(|ui| {
+                                ui.label("Instance");
+                                ui.add(egui::DragValue::new(&mut copy).clamp_range(0..=copies - 1));
+                                ui.label(format!("of {}", copies));
+                            });
+                            uniforms.set_editing_copy(copy);
+                        }
+                        let mut changed = false;
+                        egui::Grid::new("shader_uniforms").show(ui, |ui| {
+                            for index in 0..uniforms.members().len() {
+                                let (member, value) = uniforms.member_mut(index);
+                                ui.label(&member.name);
+                                changed |= match value {
+                                    UniformValue::F32(value) if member.is_time() => {
+                                        // Set by the renderer every frame
+                                        ui.label(format!("{:.2}", value));
+                                        false
+                                    }
+                                    UniformValue::F32(value) => {
+                                        ui.add(egui::DragValue::new(value).speed(0.01)).changed()
+                                    }
+                                    UniformValue::I32(value) => {
+                                        ui.add(egui::DragValue::new(value)).changed()
+                                    }
+                                    UniformValue::U32(value) => {
+                                        ui.add(egui::DragValue::new(value)).changed()
+                                    }
+                                    UniformValue::Floats(values) if member.is_resolution() => {
+                                        // Set by the renderer every frame
+                                        let values: Vec<_> =
+                                            values.iter().map(|v| v.to_string()).collect();
+                                        ui.label(values.join(" x "));
+                                        false
+                                    }
+                                    UniformValue::Floats(values) if member.is_color() => {
+                                        match values {
+                                            [r, g, b] => {
+                                                let mut rgb = [*r, *g, *b];
+                                                let changed =
+                                                    ui.color_edit_button_rgb(&mut rgb).changed();
+                                                [*r, *g, *b] = rgb;
+                                                changed
+                                            }
+                                            [r, g, b, a] => {
+                                                let mut rgba = [*r, *g, *b, *a];
+                                                let changed = ui
+                                                    .color_edit_button_rgba_unmultiplied(&mut rgba)
+                                                    .changed();
+                                                [*r, *g, *b, *a] = rgba;
+                                                changed
+                                            }
+                                            _ => false,
+                                        }
+                                    }
+                                    UniformValue::Floats(values) => {
+                                        ui.horizontal(|ui| {
+                                            let mut changed = false;
+                                            for value in values {
+                                                changed |= ui
+                                                    .add(egui::DragValue::new(value).speed(0.01))
+                                                    .changed();
+                                            }
+                                            changed
+                                        })
+                                        .inner
+                                    }
+                                };
+                                ui.end_row();
+                            }
+                        });
+                        if changed {
+                            uniforms.mark_changed();
+                        }
+                    });
+            }
+            egui::Window::new("Compute")
+                .resizable(false)
+                .default_open(false)
+                .show(ui, |ui| {
+                    if ui.button("Load compute shader").clicked() {
+                        if let Some(path) = rfd::FileDialog::new()
+                            .add_filter("WGSL shader", &["wgsl"])
+                            .pick_file()
+                        {
+                            picked_compute_path = Some(path);
+                        }
+                    }
+                    if let Some(error) = &self.compute_error {
+                        ui.colored_label(egui::Color32::RED, error);
+                    }
+                    let Some(compute_view) = &mut self.renderer.compute_view else {
+                        ui.label("No compute shader loaded");
+                        return;
+                    };
+                    let [x, y, z] = compute_view.workgroup_size();
+                    ui.label(format!(
+                        "{} (workgroup size {}x{}x{})",
+                        compute_view.name(),
+                        x,
+                        y,
+                        z
+                    ));
+                    ui.checkbox(&mut compute_view.show_output, "Show output");
+                    ui.checkbox(
+                        &mut compute_view.auto_workgroup_count,
+                        "Cover the whole output",
+                    );
+                    ui.add_enabled_ui(!compute_view.auto_workgroup_count, |ui| {
+                        ui.horizontal(|ui| {
+                            ui.label("Workgroups");
+                            for count in &mut compute_view.workgroup_count {
+                                ui.add(egui::DragValue::new(count).clamp_range(1..=65535));
+                            }
+                        });
+                    });
+                });
+            let selected = match self.selected_node {
+                Some(handle) => self.renderer.scene_tree.get_mut(&handle),
+                None => None,
+            };
+            if let Some(mut node) = selected {
+                egui::Window::new("Transform")
+                    .resizable(false)
+                    .show(ui, |ui| {
+                        let components = self.selected_transform.get_or_insert_with(|| {
+                            TransformComponents::from_matrix(node.get_local_transform())
+                        });
+                        let mut changed = false;
+                        egui::Grid::new("transform").show(ui, |ui| {
+                            ui.label("Translation");
+                            for value in [
+                                &mut components.translation.x,
+                                &mut components.translation.y,
+                                &mut components.translation.z,
+                            ] {
+                                changed |= ui.add(egui::DragValue::new(value).speed(0.1)).changed();
+                            }
+                            ui.end_row();
+
+                            ui.label("Rotation");
+                            for value in [
+                                &mut components.rotation.x.0,
+                                &mut components.rotation.y.0,
+                                &mut components.rotation.z.0,
+                            ] {
+                                changed |= ui
+                                    .add(egui::DragValue::new(value).speed(1.0).suffix("°"))
+                                    .changed();
+                            }
+                            ui.end_row();
+
+                            ui.label("Scale");
+                            for value in [
+                                &mut components.scale.x,
+                                &mut components.scale.y,
+                                &mut components.scale.z,
+                            ] {
+                                // Keep the transform invertible
+                                changed |= ui
+                                    .add(
+                                        egui::DragValue::new(value)
+                                            .speed(0.01)
+                                            .clamp_range(0.001..=f32::MAX),
+                                    )
+                                    .changed();
+                            }
+                            ui.end_row();
+                        });
+                        if changed {
+                            node.update_local_transform(components.to_matrix());
+                        }
                     });
+            }
+        });
+        self.renderer.shader_tabs[editing_tab].source = source;
+        self.ui.draw(
+            EguiDrawParams {
+                device: &self.renderer.device,
+                queue: &self.renderer.queue,
+                encoder: &mut cmd_encoder,
+                view: self.renderer.hdr.output_view(),
+                screen_descriptor,
             },
-        };
-        self.ui.draw(draw_params);
-        self.hdr.process(&mut cmd_encoder, output.view());
+            ui_frame,
+        );
+        self.renderer.tonemap(&mut cmd_encoder, target.view());
 
-        self.queue.submit(std::iter::once(cmd_encoder.finish()));
-        output.present();
+        self.renderer
+            .queue
+            .submit(std::iter::once(cmd_encoder.finish()));
+        if let Some(gpu_timer) = &mut self.renderer.gpu_timer {
+            gpu_timer.collect(&self.renderer.device);
+        }
+        self.collect_pick();
 
-        // TODO this feels like it should be somewhere else
-        self.shader_source = source;
+        self.editor_focused = editor_focused;
+        if save_shader_as {
+            self.save_shader_as();
+        } else if save_shader {
+            self.save_shader();
+        }
+        if reset_shader {
+            self.renderer.shader_tabs[editing_tab].reset(default_shader());
+            shader_changed = true;
+        }
+        if shader_changed {
+            info!("Shader changed!");
+            self.renderer.compile_shader();
+        }
+        if let Some(tab) = closed_tab {
+            self.renderer.shader_tabs.remove(tab);
+            self.renderer.active_shader_tab = self
+                .renderer
+                .active_shader_tab
+                .min(self.renderer.shader_tabs.len() - 1);
+        } else if new_tab {
+            let name = format!("shader {}", self.renderer.shader_tabs.len() + 1);
+            self.renderer.add_shader_tab(name, default_shader());
+        } else {
+            self.renderer.active_shader_tab = selected_tab;
+        }
         if let Some(path_to_load) = &picked_path {
             if let Err(e) = self.load_shader(path_to_load) {
                 error!("Unable to load shader {}: {}", path_to_load.display(), e);
             }
         }
-        if shader_changed {
-            info!("Shader changed!");
-            self.compile_shader();
+        if let Some(path_to_load) = &picked_compute_path {
+            self.compute_error = match self.load_compute_shader(path_to_load) {
+                Ok(()) => None,
+                Err(e) => {
+                    error!(
+                        "Unable to load compute shader {}: {}",
+                        path_to_load.display(),
+                        e
+                    );
+                    Some(e.to_string())
+                }
+            };
+        }
+        if let Some(format) = picked_surface_format {
+            self.set_surface_format(format);
+        }
+        if let Some(size) = picked_shadow_resolution {
+            self.renderer.set_shadow_resolution(size);
+        }
+        if let Some(level) = picked_anisotropy {
+            self.renderer.set_anisotropy_clamp(level);
+        }
+        if let Some(transform) = picked_output_transform {
+            self.renderer
+                .hdr
+                .set_output_transform(&self.renderer.queue, transform);
+        }
+        if let Some(node) = picked_light_node {
+            self.renderer.set_light(node);
+        }
+        if let Some(view) = picked_axis_view {
+            self.snap_camera(view);
+        }
+        if let Some(double_sided) = picked_double_sided {
+            self.renderer.set_double_sided(double_sided);
+        }
+        if let Some(enabled) = picked_dof {
+            self.renderer.set_dof_enabled(enabled);
+        }
+        if let Some(enabled) = picked_taa {
+            self.renderer.set_taa_enabled(enabled);
+        }
+        if let Some(format) = picked_hdr_format {
+            self.renderer.set_hdr_format(format);
+            // The UI is drawn into the HDR target too
+            self.ui = EguiRenderer::new(
+                &self.renderer.device,
+                self.renderer.hdr.format(),
+                None,
+                1,
+                window,
+            );
+            self.config.set_hdr_format(format);
+            if let Err(e) = self.config.save() {
+                error!("Unable to save config: {}", e);
+            }
+        }
+        if let Some(path) = &picked_exr_path {
+            match self.renderer.save_exr(path) {
+                Ok(()) => info!("Saved HDR image to {}", path.display()),
+                Err(e) => error!("Unable to save HDR image {}: {}", path.display(), e),
+            }
+        }
+        if let Some(layout) = picked_instance_layout {
+            self.renderer.set_instance_layout(layout);
+            self.selected_node = None;
+            self.selected_transform = None;
+        }
+        if let Some(path_to_load) = &picked_environment_path {
+            if let Err(e) = self.renderer.load_environment(path_to_load) {
+                error!(
+                    "Unable to load environment map {}: {}",
+                    path_to_load.display(),
+                    e
+                );
+            }
         }
+        if let Some(path_to_add) = &added_environment_path {
+            if let Err(e) = self.renderer.add_environment(path_to_add) {
+                error!(
+                    "Unable to load environment map {}: {}",
+                    path_to_add.display(),
+                    e
+                );
+            }
+        }
+    }
+}
 
-        Ok(())
+/// What the viewer starts out showing, in a window or headless
+#[derive(Debug, Clone)]
+pub struct SceneOptions {
+    /// Shader to start with, the default shader is used if `None`
+    pub shader: Option<PathBuf>,
+    /// Model file, relative to the resources directory
+    pub model: String,
+    /// Equirectangular HDR image to light the scene with instead of the sky
+    pub environment: Option<PathBuf>,
+    /// Only consider adapters on these backends, `WGPU_BACKEND` is read if
+    /// `None`
+    pub backends: Option<wgpu::Backends>,
+    pub renderer: RendererConfig,
+}
+
+impl Default for SceneOptions {
+    fn default() -> Self {
+        Self {
+            shader: None,
+            model: "cube.obj".to_string(),
+            environment: None,
+            backends: None,
+            renderer: RendererConfig::default(),
+        }
     }
 }
 
-pub async fn run() -> RendererResult<()> {
-    let event_loop = EventLoop::new().unwrap();
-    let window = Arc::new(WindowBuilder::new().build(&event_loop).unwrap());
+/// Options for the interactive viewer
+#[derive(Debug, Default)]
+pub struct WindowArgs {
+    pub scene: SceneOptions,
+    /// Initial inner size in physical pixels, the platform picks if `None`
+    pub size: Option<(u32, u32)>,
+    pub maximized: bool,
+    /// Start in borderless fullscreen on the current monitor
+    pub fullscreen: bool,
+}
+
+/// Opens a window and runs the viewer in it until it is closed. Use `State`
+/// directly to run the viewer from an existing event loop.
+pub async fn run(args: WindowArgs) -> RendererResult<()> {
+    let event_loop = EventLoop::new()?;
+    let mut window_builder = WindowBuilder::new().with_maximized(args.maximized);
+    if let Some((width, height)) = args.size {
+        window_builder = window_builder.with_inner_size(PhysicalSize::new(width, height));
+    }
+    if args.fullscreen {
+        window_builder = window_builder.with_fullscreen(Some(Fullscreen::Borderless(None)));
+    }
+    let window = Arc::new(window_builder.build(&event_loop).unwrap());
 
     let window_id = window.id();
-    let mut state = State::new(window.clone(), std::env::args().nth(1)).await?;
-    let mut last_render_time = Instant::now();
+    let mut state = State::new(window.clone(), args.scene).await?;
+    // Maximizing or going fullscreen may already have changed the size
+    let size = window.inner_size();
+    if size != state.size {
+        state.resize(size);
+    }
 
     Ok(event_loop.run(move |event, control_flow| match event {
         Event::WindowEvent {
             ref event,
             window_id: win_id,
         } if win_id == window_id => {
-            if !state.input(&window, event) {
-                match event {
-                    WindowEvent::CloseRequested
-                    | WindowEvent::KeyboardInput {
-                        event:
-                            KeyEvent {
-                                state: ElementState::Pressed,
-                                physical_key: PhysicalKey::Code(KeyCode::Escape),
-                                ..
-                            },
-                        ..
-                    } => control_flow.exit(),
-                    WindowEvent::Resized(physical_size) => state.resize(*physical_size),
-                    WindowEvent::RedrawRequested => {
-                        let now = Instant::now();
-                        let dt = now - last_render_time;
-                        state.update(dt);
-                        last_render_time = now;
-                        match state.render(&window) {
-                            Ok(()) => (),
-                            Err(wgpu::SurfaceError::Lost) => state.resize(state.size),
-                            Err(wgpu::SurfaceError::OutOfMemory) => {
-                                error!("WGPU Error: Out of Memory!");
-                                control_flow.exit();
-                            }
-                            Err(e) => error!("{:?}", e),
-                        }
-                    }
-                    _ => (),
-                }
+            if state.window_event(&window, event) {
+                control_flow.exit();
             }
         }
-        Event::DeviceEvent {
-            event: DeviceEvent::MouseMotion { delta },
-            ..
-        } => {
-            state.process_mouse_motion(delta.0, delta.1);
-        }
-        Event::AboutToWait => window.request_redraw(),
+        Event::DeviceEvent { ref event, .. } => state.device_event(event),
+        Event::AboutToWait => match state.next_redraw() {
+            Some(at) if at <= Instant::now() => {
+                control_flow.set_control_flow(ControlFlow::Poll);
+                window.request_redraw();
+            }
+            Some(at) => control_flow.set_control_flow(ControlFlow::WaitUntil(at)),
+            None => control_flow.set_control_flow(ControlFlow::Wait),
+        },
         _ => (),
     })?)
 }
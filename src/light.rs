@@ -1,3 +1,13 @@
+use cgmath::{EuclideanSpace, Matrix4, Point3, Vector3};
+use wgpu::{util::DeviceExt, vertex_attr_array, VertexAttribute};
+
+use crate::{
+    model::{LightRenderer, Mesh, ModelVertex, Vertex},
+    pipeline::{PipelineCreateInfo, RenderPipeline},
+    shader::{self, Shader},
+    texture,
+};
+
 #[repr(C)]
 #[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct LightUniform {
@@ -17,3 +27,227 @@ impl LightUniform {
         }
     }
 }
+
+/// Lighting settings that apply to the whole scene
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct ShadingUniform {
+    /// Nonzero if back faces are drawn, lit as if their normal faced the
+    /// viewer
+    double_sided: u32,
+    _padding: [u32; 3],
+}
+
+impl ShadingUniform {
+    pub fn new(double_sided: bool) -> Self {
+        Self {
+            double_sided: double_sided.into(),
+            _padding: [0; 3],
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct LightMarkerInstance {
+    model: [[f32; 4]; 4],
+}
+
+impl LightMarkerInstance {
+    fn layout() -> wgpu::VertexBufferLayout<'static> {
+        const ATTRIBUTES: [VertexAttribute; 4] = vertex_attr_array![
+            // model
+            5 => Float32x4,
+            6 => Float32x4,
+            7 => Float32x4,
+            8 => Float32x4,
+        ];
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<LightMarkerInstance>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &ATTRIBUTES,
+        }
+    }
+}
+
+/// Vertices and indices of a unit UV sphere
+fn sphere(stacks: u32, sectors: u32) -> (Vec<ModelVertex>, Vec<u32>) {
+    let mut vertices = Vec::new();
+    for stack in 0..=stacks {
+        let v = stack as f32 / stacks as f32;
+        let phi = std::f32::consts::PI * v;
+        for sector in 0..=sectors {
+            let u = sector as f32 / sectors as f32;
+            let theta = std::f32::consts::TAU * u;
+            let normal = Vector3::new(phi.sin() * theta.cos(), phi.cos(), phi.sin() * theta.sin());
+            let tangent = Vector3::new(-theta.sin(), 0.0, theta.cos());
+            vertices.push(ModelVertex {
+                position: normal.into(),
+                tex_coords: [u, v],
+                normal: normal.into(),
+                tangent: tangent.into(),
+                bitangent: normal.cross(tangent).into(),
+            });
+        }
+    }
+    let mut indices = Vec::new();
+    for stack in 0..stacks {
+        for sector in 0..sectors {
+            let top = stack * (sectors + 1) + sector;
+            let bottom = top + sectors + 1;
+            indices.extend_from_slice(&[top, bottom, top + 1, top + 1, bottom, bottom + 1]);
+        }
+    }
+    (vertices, indices)
+}
+
+/// A small sphere drawn at the light's position in the light's color
+pub struct LightMarker {
+    mesh: Mesh,
+    instance_buffer: wgpu::Buffer,
+    pipeline: RenderPipeline,
+    scale: f32,
+}
+
+impl LightMarker {
+    pub fn new(
+        device: &wgpu::Device,
+        camera_bind_group_layout_desc: &wgpu::BindGroupLayoutDescriptor<'_>,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        light_bind_group_layout_desc: &wgpu::BindGroupLayoutDescriptor<'_>,
+        light_bind_group_layout: &wgpu::BindGroupLayout,
+        color_format: wgpu::TextureFormat,
+    ) -> Self {
+        let (vertices, indices) = sphere(8, 16);
+        let mesh = Mesh {
+            name: "Light Marker".to_string(),
+            vertex_buffer: device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("Light Marker Vertex Buffer"),
+                contents: bytemuck::cast_slice(&vertices),
+                usage: wgpu::BufferUsages::VERTEX,
+            }),
+            index_buffer: device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("Light Marker Index Buffer"),
+                contents: bytemuck::cast_slice(&indices),
+                usage: wgpu::BufferUsages::INDEX,
+            }),
+            number_of_elements: indices.len() as u32,
+            material: 0,
+            center: Point3::origin(),
+        };
+        let instance_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Light Marker Instance Buffer"),
+            size: std::mem::size_of::<LightMarkerInstance>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let pipeline = {
+            let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Light Pipeline Layout"),
+                bind_group_layouts: &[camera_bind_group_layout, light_bind_group_layout],
+                push_constant_ranges: &[],
+            });
+            let shader = Self::create_shader(device);
+            assert_eq!(
+                shader
+                    .layout_matches(&[camera_bind_group_layout_desc, light_bind_group_layout_desc]),
+                Ok(())
+            );
+            let vertex_layouts = [ModelVertex::layout(), LightMarkerInstance::layout()];
+            RenderPipeline::new(
+                device,
+                pipeline_layout,
+                Self::pipeline_create_info(&shader, &vertex_layouts, color_format),
+            )
+        };
+
+        Self {
+            mesh,
+            instance_buffer,
+            pipeline,
+            scale: 0.1,
+        }
+    }
+
+    fn create_shader(device: &wgpu::Device) -> Shader {
+        Shader::new_wgsl(
+            device,
+            "Light",
+            &shader::builtin_source(
+                "light_shader.wgsl",
+                include_str!(concat!(
+                    env!("CARGO_MANIFEST_DIR"),
+                    "/shaders/light_shader.wgsl"
+                )),
+            ),
+        )
+        .expect("Could not parse light shader")
+    }
+
+    fn pipeline_create_info<'a>(
+        shader: &'a Shader,
+        vertex_layouts: &'a [wgpu::VertexBufferLayout<'a>],
+        color_format: wgpu::TextureFormat,
+    ) -> PipelineCreateInfo<'a> {
+        PipelineCreateInfo {
+            color_format,
+            depth_format: Some(texture::Texture::DEPTH_FORMAT),
+            depth_write: true,
+            vertex_layouts,
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            cull_mode: Some(wgpu::Face::Back),
+            blend: Some(wgpu::BlendState::REPLACE),
+            shader,
+            label: Some("Light Pipeline"),
+        }
+    }
+
+    /// Rebuilds the pipeline to draw into a target of a new format
+    pub fn set_color_format(&mut self, device: &wgpu::Device, color_format: wgpu::TextureFormat) {
+        let shader = Self::create_shader(device);
+        let vertex_layouts = [ModelVertex::layout(), LightMarkerInstance::layout()];
+        self.pipeline
+            .recreate(
+                device,
+                Self::pipeline_create_info(&shader, &vertex_layouts, color_format),
+            )
+            .expect("Could not recreate light pipeline");
+    }
+
+    /// Radius of the sphere in world units
+    pub fn scale(&self) -> f32 {
+        self.scale
+    }
+
+    /// Takes effect on the next `update`
+    pub fn set_scale(&mut self, scale: f32) {
+        if scale > 0.0 {
+            self.scale = scale;
+        }
+    }
+
+    /// Moves the sphere to `light`
+    pub fn update(&self, queue: &wgpu::Queue, light: &LightUniform) {
+        let model =
+            Matrix4::from_translation(light.position.into()) * Matrix4::from_scale(self.scale);
+        queue.write_buffer(
+            &self.instance_buffer,
+            0,
+            bytemuck::cast_slice(&[LightMarkerInstance {
+                model: model.into(),
+            }]),
+        );
+    }
+
+    pub fn render<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        camera_bind_group: &'a wgpu::BindGroup,
+        light_bind_group: &'a wgpu::BindGroup,
+    ) {
+        render_pass.set_pipeline(self.pipeline.pipeline());
+        render_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
+        render_pass.draw_light_mesh(&self.mesh, camera_bind_group, light_bind_group);
+    }
+}
@@ -1,8 +1,126 @@
-use rust_shader_viewer::run;
+use std::{path::PathBuf, time::Duration};
+
+use clap::Parser;
+use rust_shader_viewer::{
+    run, run_headless, run_sequence, HeadlessArgs, InstanceLayout, RendererConfig, SceneOptions,
+    SequenceArgs, WindowArgs,
+};
+
+#[derive(Parser)]
+#[command(about = "Renders a model with a live-reloaded WGSL shader")]
+struct Args {
+    /// Shader to start with, kept for compatibility with `--shader`
+    #[arg(value_name = "SHADER", conflicts_with = "shader")]
+    positional_shader: Option<PathBuf>,
+    /// Shader to start with, the default shader is used if not given
+    #[arg(long)]
+    shader: Option<PathBuf>,
+    /// Model file, relative to the resources directory
+    #[arg(long, default_value = "cube.obj")]
+    model: String,
+    /// Equirectangular HDR image to light the scene with
+    #[arg(long = "env", value_name = "HDR")]
+    environment: Option<PathBuf>,
+    /// Instances of the model per row of a grid, 0 for none and 1 for a
+    /// single instance at the origin
+    #[arg(long, default_value_t = 1)]
+    instances: u32,
+    /// Window or output width in pixels
+    #[arg(long, requires = "height", value_parser = clap::value_parser!(u32).range(1..))]
+    width: Option<u32>,
+    /// Window or output height in pixels
+    #[arg(long, requires = "width", value_parser = clap::value_parser!(u32).range(1..))]
+    height: Option<u32>,
+    /// Only use adapters on these backends, e.g. `vulkan` or `dx12,metal`
+    #[arg(long, value_parser = parse_backends)]
+    backend: Option<wgpu::Backends>,
+    #[arg(long, conflicts_with = "fullscreen")]
+    maximized: bool,
+    /// Start in borderless fullscreen on the current monitor
+    #[arg(long)]
+    fullscreen: bool,
+    /// Render one frame to the PNG at `--out` instead of opening a window
+    #[arg(long, requires_all = ["out", "width"], conflicts_with = "sequence")]
+    headless: bool,
+    /// Render an animation into the directory at `--out` instead of opening
+    /// a window
+    #[arg(long, requires_all = ["out", "width"])]
+    sequence: bool,
+    /// Frames per second of a `--sequence`
+    #[arg(long, default_value_t = 30, value_parser = clap::value_parser!(u32).range(1..))]
+    fps: u32,
+    /// Length of a `--sequence` in seconds
+    #[arg(long, default_value_t = 5.0)]
+    seconds: f32,
+    /// Output of `--headless` or `--sequence`
+    #[arg(long)]
+    out: Option<PathBuf>,
+}
+
+fn parse_backends(s: &str) -> Result<wgpu::Backends, String> {
+    let backends = wgpu::util::parse_backends_from_comma_list(&s.to_lowercase());
+    if backends.is_empty() {
+        Err(format!("no known backend in '{}'", s))
+    } else {
+        Ok(backends)
+    }
+}
 
 pub fn main() {
     env_logger::init();
-    match pollster::block_on(run()) {
+    let args = Args::parse();
+    let scene = SceneOptions {
+        shader: args.shader.or(args.positional_shader),
+        model: args.model,
+        environment: args.environment,
+        backends: args.backend,
+        renderer: RendererConfig {
+            instance_layout: match args.instances {
+                0 => InstanceLayout::None,
+                1 => InstanceLayout::Single,
+                count => InstanceLayout::Grid {
+                    count,
+                    spacing: 3.0,
+                },
+            },
+            ..RendererConfig::default()
+        },
+    };
+    let size = args.width.zip(args.height);
+    let result = match (size, args.out) {
+        (Some((width, height)), Some(output)) if args.headless => {
+            pollster::block_on(run_headless(HeadlessArgs {
+                scene,
+                width,
+                height,
+                output,
+            }))
+        }
+        (Some((width, height)), Some(output_dir)) if args.sequence => {
+            let duration = match Duration::try_from_secs_f32(args.seconds) {
+                Ok(duration) => duration,
+                Err(e) => {
+                    eprintln!("Invalid --seconds: {}", e);
+                    return;
+                }
+            };
+            pollster::block_on(run_sequence(SequenceArgs {
+                scene,
+                width,
+                height,
+                fps: args.fps,
+                duration,
+                output_dir,
+            }))
+        }
+        _ => pollster::block_on(run(WindowArgs {
+            scene,
+            size,
+            maximized: args.maximized,
+            fullscreen: args.fullscreen,
+        })),
+    };
+    match result {
         Ok(_) => (),
         Err(e) => eprintln!("{}", e),
     }
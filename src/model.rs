@@ -1,11 +1,11 @@
 use std::ops::Range;
 
 use bytemuck::Zeroable;
-use cgmath::{Matrix, SquareMatrix};
+use cgmath::{EuclideanSpace, InnerSpace, Matrix, MetricSpace, Point3, SquareMatrix, Transform};
 use log::error;
 use wgpu::{util::DeviceExt, vertex_attr_array, VertexAttribute};
 
-use crate::texture;
+use crate::{frustum::Frustum, texture};
 
 pub trait Vertex {
     fn layout() -> wgpu::VertexBufferLayout<'static>;
@@ -46,71 +46,360 @@ pub struct Mesh {
     pub index_buffer: wgpu::Buffer,
     pub number_of_elements: u32,
     pub material: usize,
+    /// Average of the vertex positions, in model space
+    pub center: cgmath::Point3<f32>,
+}
+
+/// The textures a material samples, all in the material bind group
+pub struct MaterialTextures {
+    pub diffuse: texture::Texture,
+    pub normal: texture::Texture,
+    /// Roughness in the green channel and metallic in the blue channel, like
+    /// glTF. White if the material doesn't have one, so only the factors apply.
+    pub metallic_roughness: texture::Texture,
+    /// Ambient occlusion in the red channel, white if there is none
+    pub ambient_occlusion: texture::Texture,
+}
+
+/// Scalars the metallic-roughness texture is multiplied by, and the alpha
+/// test threshold
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct MaterialFactors {
+    pub metallic: f32,
+    pub roughness: f32,
+    /// Fragments whose diffuse alpha is below this are discarded, from the
+    /// shadow map and picking too, so 0 turns the test off
+    pub alpha_cutoff: f32,
+    _padding: f32,
+}
+
+impl MaterialFactors {
+    pub fn new(metallic: f32, roughness: f32, alpha_cutoff: f32) -> Self {
+        Self {
+            metallic,
+            roughness,
+            alpha_cutoff,
+            _padding: 0.0,
+        }
+    }
+}
+
+impl Default for MaterialFactors {
+    fn default() -> Self {
+        Self::new(0.0, 0.4, 0.0)
+    }
+}
+
+/// Binds the diffuse, normal, metallic-roughness and ambient occlusion
+/// textures in that order, followed by the factors
+fn create_material_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    textures: [&texture::Texture; 4],
+    factors_buffer: &wgpu::Buffer,
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: None,
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(&textures[0].view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::Sampler(&textures[0].sampler),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: wgpu::BindingResource::TextureView(&textures[1].view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 3,
+                resource: wgpu::BindingResource::Sampler(&textures[1].sampler),
+            },
+            wgpu::BindGroupEntry {
+                binding: 4,
+                resource: wgpu::BindingResource::TextureView(&textures[2].view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 5,
+                resource: wgpu::BindingResource::Sampler(&textures[2].sampler),
+            },
+            wgpu::BindGroupEntry {
+                binding: 6,
+                resource: wgpu::BindingResource::TextureView(&textures[3].view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 7,
+                resource: wgpu::BindingResource::Sampler(&textures[3].sampler),
+            },
+            wgpu::BindGroupEntry {
+                binding: 8,
+                resource: factors_buffer.as_entire_binding(),
+            },
+        ],
+    })
 }
 
 pub struct Material {
     pub name: String,
     pub diffuse_texture: texture::Texture,
     pub normal_texture: texture::Texture,
+    pub metallic_roughness_texture: texture::Texture,
+    pub ambient_occlusion_texture: texture::Texture,
+    pub factors_buffer: wgpu::Buffer,
     pub bind_group: wgpu::BindGroup,
+    /// Transparent materials are drawn after opaque ones with alpha blending
+    pub transparent: bool,
 }
 
 impl Material {
     pub fn new(
         device: &wgpu::Device,
         name: &str,
-        diffuse_texture: texture::Texture,
-        normal_texture: texture::Texture,
+        textures: MaterialTextures,
+        factors: MaterialFactors,
         layout: &wgpu::BindGroupLayout,
     ) -> Self {
-        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: None,
+        let MaterialTextures {
+            diffuse: diffuse_texture,
+            normal: normal_texture,
+            metallic_roughness: metallic_roughness_texture,
+            ambient_occlusion: ambient_occlusion_texture,
+        } = textures;
+        let factors_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{} Factors Buffer", name)),
+            contents: bytemuck::cast_slice(&[factors]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let bind_group = create_material_bind_group(
+            device,
             layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&diffuse_texture.view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&diffuse_texture.sampler),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 2,
-                    resource: wgpu::BindingResource::TextureView(&normal_texture.view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 3,
-                    resource: wgpu::BindingResource::Sampler(&normal_texture.sampler),
-                },
+            [
+                &diffuse_texture,
+                &normal_texture,
+                &metallic_roughness_texture,
+                &ambient_occlusion_texture,
             ],
-        });
+            &factors_buffer,
+        );
 
         Self {
             name: String::from(name),
             diffuse_texture,
             normal_texture,
+            metallic_roughness_texture,
+            ambient_occlusion_texture,
+            factors_buffer,
             bind_group,
+            transparent: false,
+        }
+    }
+
+    fn textures_mut(&mut self) -> [&mut texture::Texture; 4] {
+        [
+            &mut self.diffuse_texture,
+            &mut self.normal_texture,
+            &mut self.metallic_roughness_texture,
+            &mut self.ambient_occlusion_texture,
+        ]
+    }
+
+    fn textures(&self) -> [&texture::Texture; 4] {
+        [
+            &self.diffuse_texture,
+            &self.normal_texture,
+            &self.metallic_roughness_texture,
+            &self.ambient_occlusion_texture,
+        ]
+    }
+
+    /// Whether any of the textures has mip levels for anisotropic filtering
+    /// to use. Placeholder textures are a single texel and never do.
+    pub fn has_mipmaps(&self) -> bool {
+        self.textures().iter().any(|texture| texture.has_mipmaps())
+    }
+
+    /// Recreates the samplers with `anisotropy_clamp` and rebinds them
+    pub fn set_anisotropy_clamp(
+        &mut self,
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        anisotropy_clamp: u16,
+    ) {
+        for texture in self.textures_mut() {
+            texture.set_anisotropy_clamp(device, anisotropy_clamp);
         }
+        self.bind_group =
+            create_material_bind_group(device, layout, self.textures(), &self.factors_buffer);
+    }
+}
+
+/// Axis aligned bounding box
+#[derive(Clone, Copy, Debug)]
+pub struct Aabb {
+    pub min: Point3<f32>,
+    pub max: Point3<f32>,
+}
+
+impl Aabb {
+    /// A box containing nothing, which grows to fit the first point added
+    pub fn empty() -> Self {
+        Self {
+            min: Point3::new(f32::INFINITY, f32::INFINITY, f32::INFINITY),
+            max: Point3::new(f32::NEG_INFINITY, f32::NEG_INFINITY, f32::NEG_INFINITY),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.min.x > self.max.x
+    }
+
+    pub fn add_point(&mut self, point: Point3<f32>) {
+        self.min = Point3::new(
+            self.min.x.min(point.x),
+            self.min.y.min(point.y),
+            self.min.z.min(point.z),
+        );
+        self.max = Point3::new(
+            self.max.x.max(point.x),
+            self.max.y.max(point.y),
+            self.max.z.max(point.z),
+        );
+    }
+
+    /// Center and radius of a sphere enclosing the box
+    pub fn bounding_sphere(&self) -> (Point3<f32>, f32) {
+        if self.is_empty() {
+            return (Point3::new(0.0, 0.0, 0.0), 0.0);
+        }
+        (
+            self.min.midpoint(self.max),
+            (self.max - self.min).magnitude() / 2.0,
+        )
     }
 }
 
 pub struct InstanceId(usize);
 
+impl InstanceId {
+    pub fn index(&self) -> u32 {
+        self.0 as u32
+    }
+}
+
 pub struct Model {
     pub meshes: Vec<Mesh>,
     pub materials: Vec<Material>,
     pub instances: Vec<InstanceRaw>,
+    /// Whether each of `instances` is drawn, hidden instances are written to
+    /// the instance buffers with a zero transform
+    pub instances_visible: Vec<bool>,
     pub instance_buffer: wgpu::Buffer,
+    /// Bounds of every mesh, in model space
+    pub bounds: Aabb,
+    /// The instances that passed the last `cull`
+    pub visible_instance_buffer: wgpu::Buffer,
+    pub visible_instance_count: u32,
+    /// Index in `instances` of each instance in the visible instance buffer
+    pub visible_instance_indices: Vec<u32>,
 }
 
 impl Model {
+    fn is_transparent(&self, mesh: &Mesh) -> bool {
+        self.materials[mesh.material].transparent
+    }
+
+    pub fn has_transparent_meshes(&self) -> bool {
+        self.meshes.iter().any(|mesh| self.is_transparent(mesh))
+    }
+
+    pub fn has_mipmaps(&self) -> bool {
+        self.materials.iter().any(Material::has_mipmaps)
+    }
+
+    pub fn set_anisotropy_clamp(
+        &mut self,
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        anisotropy_clamp: u16,
+    ) {
+        for material in &mut self.materials {
+            material.set_anisotropy_clamp(device, layout, anisotropy_clamp);
+        }
+    }
+
+    /// Returns the opaque meshes grouped by material, so each material only
+    /// has to be bound once
+    pub fn opaque_meshes_by_material(&self) -> Vec<&Mesh> {
+        let meshes: Vec<_> = self
+            .meshes
+            .iter()
+            .filter(|mesh| !self.is_transparent(mesh))
+            .collect();
+        let materials: Vec<_> = meshes.iter().map(|mesh| mesh.material).collect();
+        group_by_material(&materials)
+            .into_iter()
+            .map(|index| meshes[index])
+            .collect()
+    }
+
+    /// Transform of the instance at `visible` in the visible instance buffer
+    fn visible_instance_transform(&self, visible: u32) -> cgmath::Matrix4<f32> {
+        self.visible_instance_indices
+            .get(visible as usize)
+            .and_then(|index| self.instances.get(*index as usize))
+            .map_or(cgmath::Matrix4::identity(), |instance| {
+                cgmath::Matrix4::from(instance.model)
+            })
+    }
+
+    /// Pairs each transparent mesh with each of `instances`, ordered
+    /// back-to-front as seen from `view_position`. The mesh centers are
+    /// placed by their instance's transform before sorting, so every pair
+    /// gets its own draw.
+    pub fn sorted_transparent_draws(
+        &self,
+        view_position: cgmath::Point3<f32>,
+        instances: Range<u32>,
+    ) -> Vec<(&Mesh, Range<u32>)> {
+        let mut draws: Vec<_> = self
+            .meshes
+            .iter()
+            .filter(|mesh| self.is_transparent(mesh))
+            .flat_map(|mesh| instances.clone().map(move |visible| (mesh, visible)))
+            .map(|(mesh, visible)| {
+                let center = self
+                    .visible_instance_transform(visible)
+                    .transform_point(mesh.center);
+                (view_position.distance2(center), mesh, visible)
+            })
+            .collect();
+        draws.sort_by(|a, b| b.0.total_cmp(&a.0));
+        draws
+            .into_iter()
+            .map(|(_, mesh, visible)| (mesh, visible..visible + 1))
+            .collect()
+    }
+
     pub fn new_instance(&mut self) -> InstanceId {
         let id = InstanceId(self.instances.len());
-        self.instances.push(InstanceRaw::zeroed());
+        self.instances.push(InstanceRaw {
+            tint: [1.0; 4],
+            ..InstanceRaw::zeroed()
+        });
+        self.instances_visible.push(true);
         id
     }
 
+    /// Removes every instance, invalidating all `InstanceId`s
+    pub fn clear_instances(&mut self) {
+        self.instances.clear();
+        self.instances_visible.clear();
+    }
+
     pub fn update_instance(&mut self, id: &InstanceId, transform: cgmath::Matrix4<f32>) {
         match self.instances.get_mut(id.0) {
             Some(instance) => {
@@ -133,24 +422,143 @@ impl Model {
         }
     }
 
+    /// Sets the color the instance's diffuse texture is multiplied by
+    pub fn update_instance_tint(&mut self, id: &InstanceId, tint: [f32; 4]) {
+        match self.instances.get_mut(id.0) {
+            Some(instance) => instance.tint = tint,
+            None => error!("No instance {} to tint", id.0),
+        }
+    }
+
+    /// Hides or shows the instance in every pass without removing it
+    pub fn update_instance_visibility(&mut self, id: &InstanceId, visible: bool) {
+        match self.instances_visible.get_mut(id.0) {
+            Some(instance_visible) => *instance_visible = visible,
+            None => error!("No instance {} to show or hide", id.0),
+        }
+    }
+
+    /// The instances as they are written to the instance buffer, with the
+    /// hidden ones zeroed so that they collapse to a point
+    fn shown_instances(&self) -> impl Iterator<Item = InstanceRaw> + '_ {
+        self.instances
+            .iter()
+            .zip(&self.instances_visible)
+            .map(|(instance, visible)| {
+                if *visible {
+                    *instance
+                } else {
+                    InstanceRaw::zeroed()
+                }
+            })
+    }
+
+    /// Sets the id written by the picking pass, 0 means not pickable
+    pub fn update_instance_pick_id(&mut self, id: &InstanceId, pick_id: u32) {
+        match self.instances.get_mut(id.0) {
+            Some(instance) => instance.pick_id = pick_id,
+            None => error!("No instance {} to set the pick id of", id.0),
+        }
+    }
+
     pub fn update_instance_buffer(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
-        // Resize buffer if needed
-        if self.instances.len() * std::mem::size_of::<InstanceRaw>()
-            != self.instance_buffer.size() as usize
+        let instances: Vec<_> = self.shown_instances().collect();
+        write_instances(
+            device,
+            queue,
+            &mut self.instance_buffer,
+            &instances,
+            "Instance Buffer",
+        );
+    }
+
+    /// Bounds of the shown instances, in world space
+    pub fn world_bounds(&self) -> Aabb {
+        let mut bounds = Aabb::empty();
+        if self.bounds.is_empty() {
+            return bounds;
+        }
+        let (min, max) = (self.bounds.min, self.bounds.max);
+        for (instance, _) in self
+            .instances
+            .iter()
+            .zip(&self.instances_visible)
+            .filter(|(_, visible)| **visible)
         {
-            self.instance_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                label: Some("Instance Buffer"),
-                contents: bytemuck::cast_slice(&self.instances),
-                usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
-            });
-        } else {
-            queue.write_buffer(
-                &self.instance_buffer,
-                0,
-                bytemuck::cast_slice(&self.instances),
-            );
+            let model = cgmath::Matrix4::from(instance.model);
+            for corner in 0..8 {
+                let point = Point3::new(
+                    if corner & 1 == 0 { min.x } else { max.x },
+                    if corner & 2 == 0 { min.y } else { max.y },
+                    if corner & 4 == 0 { min.z } else { max.z },
+                );
+                bounds.add_point(model.transform_point(point));
+            }
         }
+        bounds
+    }
+
+    /// Copies the instances whose bounding sphere intersects any of
+    /// `frustums` to the visible instance buffer, which
+    /// `draw_model_instanced` draws from
+    pub fn cull(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, frustums: &[Frustum]) {
+        let (center, radius) = self.bounds.bounding_sphere();
+        let (indices, visible): (Vec<_>, Vec<_>) = self
+            .instances
+            .iter()
+            .zip(&self.instances_visible)
+            .enumerate()
+            .filter(|(_, (_, visible))| **visible)
+            .map(|(index, (instance, _))| (index as u32, *instance))
+            .filter(|(_, instance)| {
+                let model = cgmath::Matrix4::from(instance.model);
+                let scale = [model.x, model.y, model.z]
+                    .iter()
+                    .map(|axis| axis.truncate().magnitude())
+                    .fold(0.0, f32::max);
+                let center = model.transform_point(center);
+                frustums
+                    .iter()
+                    .any(|frustum| frustum.intersects_sphere(center, radius * scale))
+            })
+            .unzip();
+        write_instances(
+            device,
+            queue,
+            &mut self.visible_instance_buffer,
+            &visible,
+            "Visible Instance Buffer",
+        );
+        self.visible_instance_count = visible.len() as u32;
+        self.visible_instance_indices = indices;
+    }
+
+    /// Range of the visible instance buffer to draw
+    pub fn visible_instances(&self) -> Range<u32> {
+        0..self.visible_instance_count
+    }
+}
+
+/// Writes `instances` to `buffer`, replacing it if it is the wrong size
+fn write_instances(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    buffer: &mut wgpu::Buffer,
+    instances: &[InstanceRaw],
+    label: &str,
+) {
+    // Keep room for one instance so that the buffer can still be bound
+    // while there are none
+    let size = instances.len().max(1) * std::mem::size_of::<InstanceRaw>();
+    if size != buffer.size() as usize {
+        *buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(label),
+            size: size as u64,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
     }
+    queue.write_buffer(buffer, 0, bytemuck::cast_slice(instances));
 }
 
 #[repr(C)]
@@ -158,20 +566,27 @@ impl Model {
 pub struct InstanceRaw {
     model: [[f32; 4]; 4],
     normal: [[f32; 3]; 3],
+    tint: [f32; 4],
+    pick_id: u32,
 }
 
 impl InstanceRaw {
     pub fn desc() -> wgpu::VertexBufferLayout<'static> {
         use std::mem;
-        const ATTRIBUTES: [VertexAttribute; 7] = vertex_attr_array![
+        const ATTRIBUTES: [VertexAttribute; 9] = vertex_attr_array![
             // model
             5 => Float32x4,
             6 => Float32x4,
             7 => Float32x4,
             8 => Float32x4,
+            // normal
             9 => Float32x3,
             10 => Float32x3,
             11 => Float32x3,
+            // tint
+            12 => Float32x4,
+            // pick id
+            13 => Uint32,
         ];
         wgpu::VertexBufferLayout {
             array_stride: mem::size_of::<InstanceRaw>() as u64,
@@ -181,6 +596,89 @@ impl InstanceRaw {
     }
 }
 
+/// Order in which to draw meshes using `materials`, so that the meshes
+/// sharing a material are drawn one after another. Meshes with the same
+/// material keep their order.
+fn group_by_material(materials: &[usize]) -> Vec<usize> {
+    let mut order: Vec<usize> = (0..materials.len()).collect();
+    order.sort_by_key(|&index| materials[index]);
+    order
+}
+
+/// Work submitted by draw calls, for profiling
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RenderStats {
+    pub draw_calls: u32,
+    /// Point and line draws add none
+    pub triangles: u64,
+    /// Instances of the model drawn, however many meshes each one has
+    pub instances: u64,
+    /// Times a material's bind group was bound
+    pub material_binds: u32,
+}
+
+impl RenderStats {
+    /// Counts one draw of `elements` indices or vertices, assembled as
+    /// `topology`, for each of `instances`
+    pub fn record(
+        &mut self,
+        elements: u32,
+        topology: wgpu::PrimitiveTopology,
+        instances: &Range<u32>,
+    ) {
+        let triangles = match topology {
+            wgpu::PrimitiveTopology::TriangleList => elements / 3,
+            wgpu::PrimitiveTopology::TriangleStrip => elements.saturating_sub(2),
+            wgpu::PrimitiveTopology::PointList
+            | wgpu::PrimitiveTopology::LineList
+            | wgpu::PrimitiveTopology::LineStrip => 0,
+        };
+        self.draw_calls += 1;
+        self.triangles += triangles as u64 * instances.len() as u64;
+    }
+}
+
+impl std::ops::AddAssign for RenderStats {
+    fn add_assign(&mut self, other: Self) {
+        self.draw_calls += other.draw_calls;
+        self.triangles += other.triangles;
+        self.instances += other.instances;
+        self.material_binds += other.material_binds;
+    }
+}
+
+/// A bind group holding a copy of its buffer for each instance, bound with a
+/// dynamic offset before each instance is drawn on its own
+#[derive(Clone, Copy, Debug)]
+pub struct PerInstanceBindGroup<'a> {
+    pub group: u32,
+    pub bind_group: &'a wgpu::BindGroup,
+    /// Bytes from one copy to the next
+    pub stride: u32,
+    /// Instances past the last copy wrap around to the first
+    pub copies: u32,
+}
+
+impl PerInstanceBindGroup<'_> {
+    /// Dynamic offset of the copy for the instance at `index` in
+    /// `Model::instances`
+    pub fn offset(&self, index: u32) -> u32 {
+        index % self.copies.max(1) * self.stride
+    }
+}
+
+/// Which of a model's instances to draw, and how
+#[derive(Clone, Debug)]
+pub struct InstanceDraw<'a> {
+    /// Indices of instances that passed `Model::cull`
+    pub instances: Range<u32>,
+    /// Draws each instance on its own with its copy of this bind group
+    pub per_instance: Option<PerInstanceBindGroup<'a>>,
+    /// How the bound pipeline assembles primitives, for `RenderStats`
+    pub topology: wgpu::PrimitiveTopology,
+}
+
+/// The draws return the `RenderStats` of what they recorded
 pub trait ModelRenderer<'a> {
     fn draw_mesh(
         &mut self,
@@ -189,7 +687,7 @@ pub trait ModelRenderer<'a> {
         camera_bind_group: &'a wgpu::BindGroup,
         light_bind_group: &'a wgpu::BindGroup,
         environment_bind_group: &'a wgpu::BindGroup,
-    ) {
+    ) -> RenderStats {
         self.draw_mesh_instanced(
             mesh,
             material,
@@ -197,9 +695,10 @@ pub trait ModelRenderer<'a> {
             light_bind_group,
             environment_bind_group,
             0..1,
-        );
+        )
     }
 
+    /// Binds the groups and draws `mesh`, counted as a triangle list
     fn draw_mesh_instanced(
         &mut self,
         mesh: &'a Mesh,
@@ -208,7 +707,15 @@ pub trait ModelRenderer<'a> {
         light_bind_group: &'a wgpu::BindGroup,
         environment_bind_group: &'a wgpu::BindGroup,
         instances: Range<u32>,
-    );
+    ) -> RenderStats;
+
+    /// Draws `mesh` with the bind groups that are already bound
+    fn draw_mesh_geometry(
+        &mut self,
+        mesh: &'a Mesh,
+        instances: Range<u32>,
+        topology: wgpu::PrimitiveTopology,
+    ) -> RenderStats;
 
     fn draw_model(
         &mut self,
@@ -216,24 +723,42 @@ pub trait ModelRenderer<'a> {
         camera_bind_group: &'a wgpu::BindGroup,
         light_bind_group: &'a wgpu::BindGroup,
         environment_bind_group: &'a wgpu::BindGroup,
-    ) {
+    ) -> RenderStats {
         self.draw_model_instanced(
             model,
             camera_bind_group,
             light_bind_group,
             environment_bind_group,
-            0..1,
-        );
+            InstanceDraw {
+                instances: 0..1,
+                per_instance: None,
+                topology: wgpu::PrimitiveTopology::TriangleList,
+            },
+        )
     }
 
+    /// Draws the model's opaque meshes, and counts the instances for both
+    /// this and `draw_model_transparent_instanced`
     fn draw_model_instanced(
         &mut self,
         model: &'a Model,
         camera_bind_group: &'a wgpu::BindGroup,
         light_bind_group: &'a wgpu::BindGroup,
         environment_bind_group: &'a wgpu::BindGroup,
-        instances: Range<u32>,
-    );
+        draw: InstanceDraw<'a>,
+    ) -> RenderStats;
+
+    /// Draws the model's transparent meshes back-to-front, should be called
+    /// after all opaque geometry with a blending pipeline bound
+    fn draw_model_transparent_instanced(
+        &mut self,
+        model: &'a Model,
+        view_position: cgmath::Point3<f32>,
+        camera_bind_group: &'a wgpu::BindGroup,
+        light_bind_group: &'a wgpu::BindGroup,
+        environment_bind_group: &'a wgpu::BindGroup,
+        draw: InstanceDraw<'a>,
+    ) -> RenderStats;
 }
 
 impl<'a, 'b> ModelRenderer<'b> for wgpu::RenderPass<'a>
@@ -248,14 +773,29 @@ where
         light_bind_group: &'b wgpu::BindGroup,
         environment_bind_group: &'b wgpu::BindGroup,
         instances: Range<u32>,
-    ) {
-        self.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
-        self.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+    ) -> RenderStats {
         self.set_bind_group(0, &material.bind_group, &[]);
         self.set_bind_group(1, camera_bind_group, &[]);
         self.set_bind_group(2, light_bind_group, &[]);
         self.set_bind_group(3, environment_bind_group, &[]);
+        let mut stats =
+            self.draw_mesh_geometry(mesh, instances, wgpu::PrimitiveTopology::TriangleList);
+        stats.material_binds += 1;
+        stats
+    }
+
+    fn draw_mesh_geometry(
+        &mut self,
+        mesh: &'b Mesh,
+        instances: Range<u32>,
+        topology: wgpu::PrimitiveTopology,
+    ) -> RenderStats {
+        self.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+        self.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+        let mut stats = RenderStats::default();
+        stats.record(mesh.number_of_elements, topology, &instances);
         self.draw_indexed(0..mesh.number_of_elements, 0, instances);
+        stats
     }
 
     fn draw_model_instanced(
@@ -264,21 +804,91 @@ where
         camera_bind_group: &'b wgpu::BindGroup,
         light_bind_group: &'b wgpu::BindGroup,
         environment_bind_group: &'b wgpu::BindGroup,
-        instances: Range<u32>,
-    ) {
-        self.set_vertex_buffer(1, model.instance_buffer.slice(..));
-        for mesh in &model.meshes {
-            let material = &model.materials[mesh.material];
-            self.draw_mesh_instanced(
-                mesh,
-                material,
-                camera_bind_group,
-                light_bind_group,
-                environment_bind_group,
-                instances.clone(),
+        draw: InstanceDraw<'b>,
+    ) -> RenderStats {
+        self.set_vertex_buffer(1, model.visible_instance_buffer.slice(..));
+        let draws = model
+            .opaque_meshes_by_material()
+            .into_iter()
+            .map(|mesh| (mesh, draw.instances.clone()))
+            .collect();
+        let mut stats = draw_meshes(
+            self,
+            model,
+            draws,
+            camera_bind_group,
+            light_bind_group,
+            environment_bind_group,
+            &draw,
+        );
+        stats.instances += draw.instances.len() as u64;
+        stats
+    }
+
+    fn draw_model_transparent_instanced(
+        &mut self,
+        model: &'b Model,
+        view_position: cgmath::Point3<f32>,
+        camera_bind_group: &'b wgpu::BindGroup,
+        light_bind_group: &'b wgpu::BindGroup,
+        environment_bind_group: &'b wgpu::BindGroup,
+        draw: InstanceDraw<'b>,
+    ) -> RenderStats {
+        self.set_vertex_buffer(1, model.visible_instance_buffer.slice(..));
+        draw_meshes(
+            self,
+            model,
+            model.sorted_transparent_draws(view_position, draw.instances.clone()),
+            camera_bind_group,
+            light_bind_group,
+            environment_bind_group,
+            &draw,
+        )
+    }
+}
+
+/// Draws each mesh of `draws` with its range of visible instances, in order,
+/// only binding a material when it differs from the previous mesh's.
+/// Returns the `RenderStats` of the draws, less the instances.
+fn draw_meshes<'a, 'b: 'a>(
+    render_pass: &mut wgpu::RenderPass<'a>,
+    model: &'b Model,
+    draws: Vec<(&'b Mesh, Range<u32>)>,
+    camera_bind_group: &'b wgpu::BindGroup,
+    light_bind_group: &'b wgpu::BindGroup,
+    environment_bind_group: &'b wgpu::BindGroup,
+    draw: &InstanceDraw<'b>,
+) -> RenderStats {
+    render_pass.set_bind_group(1, camera_bind_group, &[]);
+    render_pass.set_bind_group(2, light_bind_group, &[]);
+    render_pass.set_bind_group(3, environment_bind_group, &[]);
+    let mut stats = RenderStats::default();
+    let mut bound_material = None;
+    for (mesh, instances) in draws {
+        if bound_material != Some(mesh.material) {
+            render_pass.set_bind_group(0, &model.materials[mesh.material].bind_group, &[]);
+            bound_material = Some(mesh.material);
+            stats.material_binds += 1;
+        }
+        let Some(per_instance) = draw.per_instance else {
+            stats += render_pass.draw_mesh_geometry(mesh, instances, draw.topology);
+            continue;
+        };
+        for visible in instances.clone() {
+            let index = model
+                .visible_instance_indices
+                .get(visible as usize)
+                .copied()
+                .unwrap_or(visible);
+            render_pass.set_bind_group(
+                per_instance.group,
+                per_instance.bind_group,
+                &[per_instance.offset(index)],
             );
+            stats += render_pass.draw_mesh_geometry(mesh, visible..visible + 1, draw.topology);
         }
     }
+    stats
 }
 
 pub trait LightRenderer<'a> {
@@ -298,23 +908,6 @@ pub trait LightRenderer<'a> {
         light_bind_group: &'a wgpu::BindGroup,
         instances: Range<u32>,
     );
-
-    fn draw_light_model(
-        &mut self,
-        model: &'a Model,
-        camera_bind_group: &'a wgpu::BindGroup,
-        light_bind_group: &'a wgpu::BindGroup,
-    ) {
-        self.draw_light_model_instanced(model, camera_bind_group, light_bind_group, 0..1);
-    }
-
-    fn draw_light_model_instanced(
-        &mut self,
-        model: &'a Model,
-        camera_bind_group: &'a wgpu::BindGroup,
-        light_bind_group: &'a wgpu::BindGroup,
-        instances: Range<u32>,
-    );
 }
 
 impl<'a, 'b> LightRenderer<'b> for wgpu::RenderPass<'a>
@@ -334,22 +927,45 @@ where
         self.set_bind_group(1, light_bind_group, &[]);
         self.draw_indexed(0..mesh.number_of_elements, 0, instances);
     }
+}
 
-    fn draw_light_model_instanced(
-        &mut self,
-        model: &'b Model,
-        camera_bind_group: &'b wgpu::BindGroup,
-        light_bind_group: &'b wgpu::BindGroup,
-        instances: Range<u32>,
-    ) {
-        self.set_vertex_buffer(1, model.instance_buffer.slice(..));
-        for mesh in &model.meshes {
-            self.draw_light_mesh_instanced(
-                mesh,
-                camera_bind_group,
-                light_bind_group,
-                instances.clone(),
-            );
-        }
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Times the material changes from one mesh to the next in `order`,
+    /// which is how often its bind group is bound
+    fn material_binds(materials: &[usize], order: impl IntoIterator<Item = usize>) -> usize {
+        let mut bound = None;
+        order
+            .into_iter()
+            .filter(|&index| bound.replace(materials[index]) != Some(materials[index]))
+            .count()
+    }
+
+    #[test]
+    fn grouping_by_material_binds_each_material_once() {
+        // Meshes alternating between materials, as exported from a
+        // multi-material model
+        let materials = [0, 1, 0, 2, 1, 0, 2, 1];
+        let order = group_by_material(&materials);
+        assert_eq!(order, [0, 2, 5, 1, 4, 7, 3, 6]);
+        assert_eq!(material_binds(&materials, 0..materials.len()), 8);
+        assert_eq!(material_binds(&materials, order), 3);
+    }
+
+    #[test]
+    fn render_stats_count_triangles_by_topology() {
+        let mut stats = RenderStats::default();
+        stats.record(12, wgpu::PrimitiveTopology::TriangleList, &(0..2));
+        assert_eq!(stats.triangles, 8);
+        stats.record(6, wgpu::PrimitiveTopology::TriangleStrip, &(0..3));
+        assert_eq!(stats.triangles, 20);
+        stats.record(12, wgpu::PrimitiveTopology::LineList, &(0..2));
+        stats.record(12, wgpu::PrimitiveTopology::PointList, &(0..2));
+        assert_eq!(stats.triangles, 20);
+        assert_eq!(stats.draw_calls, 4);
+        // Instances are counted by the model draws, once for all meshes
+        assert_eq!(stats.instances, 0);
     }
 }
@@ -0,0 +1,338 @@
+use std::sync::mpsc;
+
+use log::error;
+
+use crate::{
+    model::{InstanceRaw, Model, ModelVertex, Vertex},
+    pipeline::{PipelineCreateInfo, RenderPipeline},
+    shader::Shader,
+    texture,
+    viewport::Viewport,
+};
+
+const PICK_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R32Uint;
+
+fn create_targets(
+    device: &wgpu::Device,
+    width: u32,
+    height: u32,
+) -> (texture::Texture, texture::Texture) {
+    let id_texture = texture::Texture::create_2d_texture(
+        device,
+        width,
+        height,
+        PICK_FORMAT,
+        wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+        wgpu::FilterMode::Nearest,
+        Some("Picker::id_texture"),
+    );
+    let depth_texture =
+        texture::Texture::create_depth_texture(device, &id_texture.size, "Picker::depth_texture");
+    (id_texture, depth_texture)
+}
+
+/// What `Picker::pick` draws the ids with, and the pixel to read back
+pub struct PickParams<'a> {
+    pub device: &'a wgpu::Device,
+    pub queue: &'a wgpu::Queue,
+    pub model: &'a Model,
+    pub camera_bind_group: &'a wgpu::BindGroup,
+    /// Part of the target the camera draws to
+    pub viewport: Viewport,
+    pub x: u32,
+    pub y: u32,
+}
+
+/// Renders the pick id of every instance to an offscreen target so that the
+/// node under the cursor can be read back, and draws the selection highlight
+pub struct Picker {
+    pipeline: RenderPipeline,
+    highlight_pipeline: RenderPipeline,
+    id_texture: texture::Texture,
+    depth_texture: texture::Texture,
+    readback_buffer: wgpu::Buffer,
+    /// Receives the result of mapping `readback_buffer` while a pick is
+    /// being read back
+    pending: Option<mpsc::Receiver<Result<(), wgpu::BufferAsyncError>>>,
+}
+
+impl Picker {
+    pub fn new(
+        device: &wgpu::Device,
+        extent: &wgpu::Extent3d,
+        camera_bind_group_layout_desc: &wgpu::BindGroupLayoutDescriptor<'_>,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        texture_bind_group_layout_desc: &wgpu::BindGroupLayoutDescriptor<'_>,
+        texture_bind_group_layout: &wgpu::BindGroupLayout,
+        color_format: wgpu::TextureFormat,
+    ) -> Self {
+        let (id_texture, depth_texture) = create_targets(device, extent.width, extent.height);
+
+        let readback_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Picker::readback_buffer"),
+            size: wgpu::COPY_BYTES_PER_ROW_ALIGNMENT as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        // The ids are drawn with each mesh's material for its alpha test
+        let create_pipeline_layout = |bind_group_layouts: &[&wgpu::BindGroupLayout]| {
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Picker::pipeline_layout"),
+                bind_group_layouts,
+                push_constant_ranges: &[],
+            })
+        };
+
+        let pipeline = {
+            let shader = Shader::new_wgsl(
+                device,
+                "picking",
+                include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/shaders/picking.wgsl")),
+            )
+            .expect("Could not parse picking shader");
+            assert_eq!(
+                shader.layout_matches(&[
+                    camera_bind_group_layout_desc,
+                    texture_bind_group_layout_desc
+                ]),
+                Ok(())
+            );
+            let create_info = PipelineCreateInfo {
+                color_format: PICK_FORMAT,
+                depth_format: Some(texture::Texture::DEPTH_FORMAT),
+                depth_write: true,
+                vertex_layouts: &[ModelVertex::layout(), InstanceRaw::desc()],
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                cull_mode: Some(wgpu::Face::Back),
+                // Integer targets can't be blended
+                blend: None,
+                shader: &shader,
+                label: Some("Picking Pipeline"),
+            };
+            RenderPipeline::new(
+                device,
+                create_pipeline_layout(&[camera_bind_group_layout, texture_bind_group_layout]),
+                create_info,
+            )
+        };
+
+        let highlight_pipeline = {
+            let shader = Self::create_highlight_shader(device);
+            assert_eq!(
+                shader.layout_matches(&[camera_bind_group_layout_desc]),
+                Ok(())
+            );
+            let vertex_layouts = [ModelVertex::layout(), InstanceRaw::desc()];
+            RenderPipeline::new(
+                device,
+                create_pipeline_layout(&[camera_bind_group_layout]),
+                Self::highlight_create_info(&shader, &vertex_layouts, color_format),
+            )
+        };
+
+        Self {
+            pipeline,
+            highlight_pipeline,
+            id_texture,
+            depth_texture,
+            readback_buffer,
+            pending: None,
+        }
+    }
+
+    fn create_highlight_shader(device: &wgpu::Device) -> Shader {
+        Shader::new_wgsl(
+            device,
+            "highlight",
+            include_str!(concat!(
+                env!("CARGO_MANIFEST_DIR"),
+                "/shaders/highlight.wgsl"
+            )),
+        )
+        .expect("Could not parse highlight shader")
+    }
+
+    fn highlight_create_info<'a>(
+        shader: &'a Shader,
+        vertex_layouts: &'a [wgpu::VertexBufferLayout<'a>],
+        color_format: wgpu::TextureFormat,
+    ) -> PipelineCreateInfo<'a> {
+        PipelineCreateInfo {
+            color_format,
+            depth_format: Some(texture::Texture::DEPTH_FORMAT),
+            depth_write: true,
+            vertex_layouts,
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            cull_mode: Some(wgpu::Face::Back),
+            blend: Some(wgpu::BlendState {
+                color: wgpu::BlendComponent {
+                    src_factor: wgpu::BlendFactor::One,
+                    dst_factor: wgpu::BlendFactor::One,
+                    operation: wgpu::BlendOperation::Add,
+                },
+                alpha: wgpu::BlendComponent::OVER,
+            }),
+            shader,
+            label: Some("Highlight Pipeline"),
+        }
+    }
+
+    /// Rebuilds the highlight pipeline to draw into a target of a new
+    /// format. The id pass draws into its own target, so it is unchanged.
+    pub fn set_color_format(&mut self, device: &wgpu::Device, color_format: wgpu::TextureFormat) {
+        let shader = Self::create_highlight_shader(device);
+        let vertex_layouts = [ModelVertex::layout(), InstanceRaw::desc()];
+        self.highlight_pipeline
+            .recreate(
+                device,
+                Self::highlight_create_info(&shader, &vertex_layouts, color_format),
+            )
+            .expect("Could not recreate highlight pipeline");
+    }
+
+    pub fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
+        (self.id_texture, self.depth_texture) = create_targets(device, width, height);
+    }
+
+    /// Starts reading back the pick id of the instance covering pixel (`x`,
+    /// `y`) when the camera draws to `viewport`, for `collect` to pick up.
+    /// Does nothing while an earlier pick is still being read back.
+    pub fn pick(&mut self, params: PickParams<'_>) {
+        let PickParams {
+            device,
+            queue,
+            model,
+            camera_bind_group,
+            viewport,
+            x,
+            y,
+        } = params;
+        if self.pending.is_some()
+            || x >= self.id_texture.size.width
+            || y >= self.id_texture.size.height
+        {
+            return;
+        }
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Picker::pick"),
+        });
+        {
+            let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Picker::pick"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &self.id_texture.view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: &self.depth_texture.view,
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(1.0),
+                        store: wgpu::StoreOp::Discard,
+                    }),
+                    stencil_ops: None,
+                }),
+                timestamp_writes: None,
+                occlusion_query_set: None,
+            });
+            viewport.apply(&mut pass);
+            pass.set_pipeline(self.pipeline.pipeline());
+            pass.set_bind_group(0, camera_bind_group, &[]);
+            pass.set_vertex_buffer(1, model.instance_buffer.slice(..));
+            for mesh in &model.meshes {
+                pass.set_bind_group(1, &model.materials[mesh.material].bind_group, &[]);
+                pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+                pass.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+                pass.draw_indexed(
+                    0..mesh.number_of_elements,
+                    0,
+                    0..model.instances.len() as u32,
+                );
+            }
+        }
+        encoder.copy_texture_to_buffer(
+            wgpu::ImageCopyTexture {
+                texture: &self.id_texture.texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d { x, y, z: 0 },
+                aspect: wgpu::TextureAspect::All,
+            },
+            wgpu::ImageCopyBuffer {
+                buffer: &self.readback_buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT),
+                    rows_per_image: None,
+                },
+            },
+            wgpu::Extent3d {
+                width: 1,
+                height: 1,
+                depth_or_array_layers: 1,
+            },
+        );
+        queue.submit(std::iter::once(encoder.finish()));
+
+        let (sender, receiver) = mpsc::channel();
+        self.readback_buffer
+            .slice(..)
+            .map_async(wgpu::MapMode::Read, move |result| {
+                let _ = sender.send(result);
+            });
+        self.pending = Some(receiver);
+    }
+
+    /// Picks up the result of the last `pick` without waiting on the GPU.
+    /// Returns `None` while it isn't ready or couldn't be read, otherwise
+    /// the pick id that was read, which is `None` if nothing was drawn under
+    /// the pixel.
+    pub fn collect(&mut self, device: &wgpu::Device) -> Option<Option<u32>> {
+        let receiver = self.pending.as_ref()?;
+        device.poll(wgpu::Maintain::Poll);
+        let result = match receiver.try_recv() {
+            Ok(result) => result,
+            Err(mpsc::TryRecvError::Empty) => return None,
+            Err(e @ mpsc::TryRecvError::Disconnected) => {
+                error!("Pick buffer was never mapped: {}", e);
+                self.pending = None;
+                return None;
+            }
+        };
+        self.pending = None;
+        if let Err(e) = result {
+            error!("Could not map pick buffer: {}", e);
+            return None;
+        }
+        let pick_id = {
+            let data = self.readback_buffer.slice(..).get_mapped_range();
+            u32::from_ne_bytes([data[0], data[1], data[2], data[3]])
+        };
+        self.readback_buffer.unmap();
+
+        Some((pick_id != 0).then_some(pick_id))
+    }
+
+    /// Draws a highlight over `instance`, must be called after the instance
+    /// has been drawn into the same depth buffer
+    pub fn render_highlight<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        model: &'a Model,
+        camera_bind_group: &'a wgpu::BindGroup,
+        instance: u32,
+    ) {
+        render_pass.set_pipeline(self.highlight_pipeline.pipeline());
+        render_pass.set_bind_group(0, camera_bind_group, &[]);
+        render_pass.set_vertex_buffer(1, model.instance_buffer.slice(..));
+        for mesh in &model.meshes {
+            render_pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+            render_pass.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+            render_pass.draw_indexed(0..mesh.number_of_elements, 0, instance..instance + 1);
+        }
+    }
+}
@@ -1,16 +1,57 @@
+use std::sync::{mpsc, Arc, Mutex, MutexGuard, PoisonError};
+
 use log::info;
 
-use crate::shader::Shader;
+use crate::{
+    error::{RendererError, RendererResult},
+    shader::{ComputeShader, Shader},
+};
 
 pub struct PipelineCreateInfo<'a> {
     pub color_format: wgpu::TextureFormat,
     pub depth_format: Option<wgpu::TextureFormat>,
+    /// Whether the pipeline writes depth when it has a depth format, the
+    /// depth test is done either way
+    pub depth_write: bool,
     pub vertex_layouts: &'a [wgpu::VertexBufferLayout<'a>],
     pub topology: wgpu::PrimitiveTopology,
+    pub cull_mode: Option<wgpu::Face>,
+    pub blend: Option<wgpu::BlendState>,
     pub shader: &'a Shader,
     pub label: Option<&'a str>,
 }
 
+/// Error scopes belong to the device rather than the thread, so a scope
+/// pushed while another thread has one open would catch that thread's
+/// errors, or lose its own
+static ERROR_SCOPE_LOCK: Mutex<()> = Mutex::new(());
+
+/// Hold this from pushing an error scope until it is popped
+pub fn lock_error_scopes() -> MutexGuard<'static, ()> {
+    ERROR_SCOPE_LOCK
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Runs `create`, turning any validation error it raises into an `Err`
+/// instead of leaving it to the uncaptured error handler
+fn validated<T>(device: &wgpu::Device, create: impl FnOnce() -> T) -> RendererResult<T> {
+    let _scope_lock = lock_error_scopes();
+    device.push_error_scope(wgpu::ErrorFilter::Validation);
+    let value = create();
+    match pollster::block_on(device.pop_error_scope()) {
+        Some(wgpu::Error::Validation {
+            source,
+            description,
+        }) => Err(RendererError::WgpuValidationError {
+            source,
+            description,
+        }),
+        Some(wgpu::Error::OutOfMemory { .. }) => unreachable!(),
+        None => Ok(value),
+    }
+}
+
 pub struct RenderPipeline {
     pipeline_layout: wgpu::PipelineLayout,
     pipeline: wgpu::RenderPipeline,
@@ -35,7 +76,7 @@ impl RenderPipeline {
                 entry_point: create_info.shader.get_fragment_entry_point(),
                 targets: &[Some(wgpu::ColorTargetState {
                     format: create_info.color_format,
-                    blend: Some(wgpu::BlendState::REPLACE),
+                    blend: create_info.blend,
                     write_mask: wgpu::ColorWrites::ALL,
                 })],
             }),
@@ -43,7 +84,7 @@ impl RenderPipeline {
                 topology: create_info.topology,
                 strip_index_format: None,
                 front_face: wgpu::FrontFace::Ccw,
-                cull_mode: Some(wgpu::Face::Back),
+                cull_mode: create_info.cull_mode,
                 polygon_mode: wgpu::PolygonMode::Fill,
                 unclipped_depth: false,
                 conservative: false,
@@ -52,7 +93,7 @@ impl RenderPipeline {
                 .depth_format
                 .map(|format| wgpu::DepthStencilState {
                     format,
-                    depth_write_enabled: true,
+                    depth_write_enabled: create_info.depth_write,
                     depth_compare: wgpu::CompareFunction::LessEqual,
                     stencil: wgpu::StencilState::default(),
                     bias: wgpu::DepthBiasState::default(),
@@ -79,13 +120,109 @@ impl RenderPipeline {
         }
     }
 
-    pub fn recreate(&mut self, device: &wgpu::Device, create_info: PipelineCreateInfo) {
+    /// Like `new`, but returns an error if wgpu rejects the pipeline
+    pub fn try_new(
+        device: &wgpu::Device,
+        layout: wgpu::PipelineLayout,
+        create_info: PipelineCreateInfo,
+    ) -> RendererResult<Self> {
         let name = create_info.shader.name();
-        self.pipeline = Self::create_pipeline(device, &self.pipeline_layout, create_info);
+        let pipeline = validated(device, || {
+            Self::create_pipeline(device, &layout, create_info)
+        })?;
+        info!("Pipeline created for shader {}", name);
+        Ok(Self {
+            pipeline_layout: layout,
+            pipeline,
+        })
+    }
+
+    /// Replaces the pipeline with one built from `create_info`. If wgpu
+    /// rejects the new pipeline the current one is left in place.
+    pub fn recreate(
+        &mut self,
+        device: &wgpu::Device,
+        create_info: PipelineCreateInfo,
+    ) -> RendererResult<()> {
+        let name = create_info.shader.name();
+        self.pipeline = validated(device, || {
+            Self::create_pipeline(device, &self.pipeline_layout, create_info)
+        })?;
         info!("Pipeline recreated for shader {}", name);
+        Ok(())
     }
 
     pub fn pipeline(&self) -> &wgpu::RenderPipeline {
         &self.pipeline
     }
 }
+
+/// Pipelines being built on a background thread, so that a slow driver
+/// compile doesn't stall the frame. The build's error scopes take the error
+/// scope lock, so it can't catch the errors of a compile on the main thread.
+pub struct PipelineTask<T> {
+    receiver: mpsc::Receiver<RendererResult<T>>,
+}
+
+impl<T: Send + 'static> PipelineTask<T> {
+    pub fn spawn(
+        device: Arc<wgpu::Device>,
+        build: impl FnOnce(&wgpu::Device) -> RendererResult<T> + Send + 'static,
+    ) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        std::thread::spawn(move || {
+            // Fails if the task was dropped, the result isn't wanted then
+            let _ = sender.send(build(&device));
+        });
+        Self { receiver }
+    }
+
+    /// Takes the result if the build has finished
+    pub fn try_take(&self) -> Option<RendererResult<T>> {
+        match self.receiver.try_recv() {
+            Ok(result) => Some(result),
+            Err(mpsc::TryRecvError::Empty) => None,
+            Err(mpsc::TryRecvError::Disconnected) => Some(Err(RendererError::ShaderCompile(
+                "Pipeline build thread panicked".to_string(),
+            ))),
+        }
+    }
+}
+
+pub struct ComputePipeline {
+    pipeline: wgpu::ComputePipeline,
+}
+
+impl ComputePipeline {
+    fn create_pipeline(
+        device: &wgpu::Device,
+        layout: &wgpu::PipelineLayout,
+        shader: &ComputeShader,
+        label: Option<&str>,
+    ) -> wgpu::ComputePipeline {
+        device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label,
+            layout: Some(layout),
+            module: shader.get_module(),
+            entry_point: shader.get_entry_point(),
+        })
+    }
+
+    /// Builds the pipeline, returning an error if wgpu rejects it
+    pub fn new(
+        device: &wgpu::Device,
+        layout: wgpu::PipelineLayout,
+        shader: &ComputeShader,
+        label: Option<&str>,
+    ) -> RendererResult<Self> {
+        let pipeline = validated(device, || {
+            Self::create_pipeline(device, &layout, shader, label)
+        })?;
+        info!("Compute pipeline created for shader {}", shader.name());
+        Ok(Self { pipeline })
+    }
+
+    pub fn pipeline(&self) -> &wgpu::ComputePipeline {
+        &self.pipeline
+    }
+}
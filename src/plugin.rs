@@ -0,0 +1,20 @@
+/// Everything a `RenderPassPlugin` gets to record its passes with
+pub struct RenderPassContext<'a> {
+    pub device: &'a wgpu::Device,
+    pub queue: &'a wgpu::Queue,
+    pub encoder: &'a mut wgpu::CommandEncoder,
+    /// The scene as drawn so far, before tonemapping
+    pub hdr_view: &'a wgpu::TextureView,
+    pub hdr_format: wgpu::TextureFormat,
+    /// Depth of the scene, in `texture::Texture::DEPTH_FORMAT`
+    pub depth_view: &'a wgpu::TextureView,
+    pub camera_bind_group: &'a wgpu::BindGroup,
+    /// For building pipelines that use `camera_bind_group`
+    pub camera_bind_group_layout: &'a wgpu::BindGroupLayout,
+}
+
+/// Records extra render passes each frame, after the scene is drawn and
+/// before it is tonemapped to the surface
+pub trait RenderPassPlugin {
+    fn record(&mut self, ctx: RenderPassContext<'_>);
+}
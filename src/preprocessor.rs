@@ -0,0 +1,210 @@
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+};
+
+use wgpu::naga::{front::wgsl, SourceLocation};
+
+use crate::error::{RendererError, RendererResult};
+
+const INCLUDE_DIRECTIVE: &str = "#include";
+
+/// Where a line of the preprocessed source came from
+#[derive(Debug)]
+struct LineOrigin {
+    /// `None` for the file being preprocessed, otherwise the included file
+    file: Option<PathBuf>,
+    /// 1-based line number in `file`
+    line: usize,
+    /// Byte offset of the line in `file`
+    offset: usize,
+    /// Byte length of the line, without the newline
+    len: usize,
+    /// Byte offset of the line in the preprocessed source
+    output_offset: usize,
+}
+
+/// WGSL source with all `#include "file.wgsl"` directives expanded
+#[derive(Debug, Default)]
+pub struct Preprocessed {
+    pub source: String,
+    lines: Vec<LineOrigin>,
+}
+
+impl Preprocessed {
+    /// Converts an error in the preprocessed source to one that points at the
+    /// original file and line
+    pub fn map_parse_error(&self, error: &wgsl::ParseError) -> RendererError {
+        let message = error.message().to_string();
+        let Some(location) = error.location(&self.source) else {
+            return RendererError::PreprocessedWgslParse {
+                message,
+                location: None,
+            };
+        };
+        let offset = location.offset as usize;
+        let index = self
+            .lines
+            .partition_point(|line| line.output_offset <= offset)
+            .saturating_sub(1);
+        let Some(origin) = self.lines.get(index) else {
+            return RendererError::PreprocessedWgslParse {
+                message,
+                location: None,
+            };
+        };
+        let column_offset = offset - origin.output_offset;
+        match &origin.file {
+            None => RendererError::PreprocessedWgslParse {
+                message,
+                location: Some(SourceLocation {
+                    line_number: origin.line as u32,
+                    line_position: location.line_position,
+                    offset: (origin.offset + column_offset) as u32,
+                    // Spans running past the end of the line may cross into
+                    // an included file
+                    length: location
+                        .length
+                        .min(origin.len.saturating_sub(column_offset) as u32),
+                }),
+            },
+            Some(file) => RendererError::PreprocessedWgslParse {
+                message: format!(
+                    "{}:{}:{}: {}",
+                    file.display(),
+                    origin.line,
+                    location.line_position,
+                    message
+                ),
+                location: None,
+            },
+        }
+    }
+}
+
+/// Expands `#include "file.wgsl"` directives in `source`. Included paths are
+/// relative to the including file, or to the working directory if `path` is
+/// `None`. Each file is only expanded the first time it is included, so a
+/// file included along several paths, by itself or in a cycle is defined
+/// once. Directives inside block comments are left alone.
+pub fn preprocess(source: &str, path: Option<&Path>) -> RendererResult<Preprocessed> {
+    let mut preprocessed = Preprocessed::default();
+    let mut included = HashSet::new();
+    if let Some(canonical) = path.and_then(|p| p.canonicalize().ok()) {
+        included.insert(canonical);
+    }
+    let dir = path
+        .and_then(Path::parent)
+        .unwrap_or_else(|| Path::new("."));
+    expand(&mut preprocessed, source, None, path, dir, &mut included)?;
+    Ok(preprocessed)
+}
+
+/// Block comment nesting depth at the end of `line`, given the depth at its
+/// start. WGSL block comments nest and `//` comments out the rest of a line.
+fn block_comment_depth(line: &str, mut depth: usize) -> usize {
+    let bytes = line.as_bytes();
+    let mut i = 0;
+    while i + 1 < bytes.len() {
+        match (bytes[i], bytes[i + 1]) {
+            (b'/', b'*') => {
+                depth += 1;
+                i += 2;
+            }
+            (b'*', b'/') if depth > 0 => {
+                depth -= 1;
+                i += 2;
+            }
+            (b'/', b'/') if depth == 0 => break,
+            _ => i += 1,
+        }
+    }
+    depth
+}
+
+fn expand(
+    preprocessed: &mut Preprocessed,
+    source: &str,
+    file: Option<&Path>,
+    display_path: Option<&Path>,
+    dir: &Path,
+    included: &mut HashSet<PathBuf>,
+) -> RendererResult<()> {
+    let display_name = display_path
+        .map(|p| p.display().to_string())
+        .unwrap_or_else(|| "<source>".to_string());
+    let mut offset = 0;
+    let mut comment_depth = 0;
+    for (index, line) in source.split_inclusive('\n').enumerate() {
+        let line_number = index + 1;
+        let line_offset = offset;
+        offset += line.len();
+        let in_comment = comment_depth > 0;
+        comment_depth = block_comment_depth(line, comment_depth);
+
+        let directive = if in_comment {
+            None
+        } else {
+            line.trim_start().strip_prefix(INCLUDE_DIRECTIVE)
+        };
+        let Some(rest) = directive else {
+            let content = line.trim_end_matches(['\n', '\r']);
+            preprocessed.lines.push(LineOrigin {
+                file: file.map(Path::to_path_buf),
+                line: line_number,
+                offset: line_offset,
+                len: content.len(),
+                output_offset: preprocessed.source.len(),
+            });
+            preprocessed.source.push_str(line);
+            if !line.ends_with('\n') {
+                preprocessed.source.push('\n');
+            }
+            continue;
+        };
+
+        let Some(name) = rest
+            .trim()
+            .strip_prefix('"')
+            .and_then(|r| r.strip_suffix('"'))
+        else {
+            return Err(RendererError::ShaderInclude(format!(
+                "{}:{}: expected {} \"file.wgsl\"",
+                display_name, line_number, INCLUDE_DIRECTIVE
+            )));
+        };
+        let include_path = dir.join(name);
+        let canonical = include_path.canonicalize().map_err(|e| {
+            RendererError::ShaderInclude(format!(
+                "{}:{}: unable to find {}: {}",
+                display_name,
+                line_number,
+                include_path.display(),
+                e
+            ))
+        })?;
+        if !included.insert(canonical.clone()) {
+            continue;
+        }
+        let included_source = std::fs::read_to_string(&canonical).map_err(|e| {
+            RendererError::ShaderInclude(format!(
+                "{}:{}: unable to read {}: {}",
+                display_name,
+                line_number,
+                canonical.display(),
+                e
+            ))
+        })?;
+
+        let included_dir = canonical.parent().unwrap_or_else(|| Path::new("."));
+        expand(
+            preprocessed,
+            &included_source,
+            Some(&canonical),
+            Some(&canonical),
+            included_dir,
+            included,
+        )?;
+    }
+    Ok(())
+}
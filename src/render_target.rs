@@ -1,5 +1,6 @@
-use crate::surface::Surface;
+use crate::{surface::Surface, texture};
 
+/// Something the tonemapping pass can write the final image to
 pub trait RenderTarget {
     fn view(&self) -> &wgpu::TextureView;
 }
@@ -28,3 +29,51 @@ impl RenderTarget for SurfaceTextureRenderTarget {
         &self.view
     }
 }
+
+/// A render target that owns its texture, for drawing offscreen. It can be
+/// sampled or copied from once drawn.
+pub struct TextureRenderTarget {
+    texture: texture::Texture,
+    format: wgpu::TextureFormat,
+}
+
+impl TextureRenderTarget {
+    pub fn new(
+        device: &wgpu::Device,
+        width: u32,
+        height: u32,
+        format: wgpu::TextureFormat,
+        label: Option<&str>,
+    ) -> Self {
+        let texture = texture::Texture::create_2d_texture(
+            device,
+            width,
+            height,
+            format,
+            wgpu::TextureUsages::RENDER_ATTACHMENT
+                | wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::COPY_SRC,
+            wgpu::FilterMode::Linear,
+            label,
+        );
+        Self { texture, format }
+    }
+
+    pub fn texture(&self) -> &texture::Texture {
+        &self.texture
+    }
+
+    pub fn format(&self) -> wgpu::TextureFormat {
+        self.format
+    }
+
+    pub fn size(&self) -> wgpu::Extent3d {
+        self.texture.size
+    }
+}
+
+impl RenderTarget for TextureRenderTarget {
+    fn view(&self) -> &wgpu::TextureView {
+        &self.texture.view
+    }
+}
@@ -0,0 +1,1541 @@
+use std::{
+    cell::Cell,
+    path::{Path, PathBuf},
+    sync::{atomic::AtomicBool, Arc, Mutex},
+    time::Duration,
+};
+
+use cgmath::prelude::*;
+use log::{error, info, warn};
+use wgpu::util::DeviceExt;
+
+use crate::{
+    camera::{AxisView, Camera, PerspectiveCamera, Projection},
+    compute_view::ComputeView,
+    cubemap::{self, CubeMapCreateInfo, CubeMapRenderer},
+    debug_primitives::DebugPrimitiveRenderer,
+    depth_visualizer::DepthVisualizer,
+    dof::DofSettings,
+    error::{RendererError, RendererResult},
+    frustum::Frustum,
+    gpu_timer::GpuTimer,
+    grid::GridRenderer,
+    hdr,
+    light::{LightMarker, LightUniform, ShadingUniform},
+    model::{self, InstanceDraw, ModelRenderer, RenderStats},
+    picking::{PickParams, Picker},
+    render_target::RenderTarget,
+    resources,
+    scene_tree::{NodeHandle, SceneTree},
+    shader::{self, buffer_binding_size},
+    shader_tab::ShaderTab,
+    shadow::{ShadowMap, ShadowUniform},
+    texture,
+    user_uniforms::UserUniforms,
+    viewport::Viewport,
+};
+
+const DEFAULT_SHADER: &str =
+    include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/shaders/shader.wgsl"));
+
+/// Source new shader tabs start with
+pub fn default_shader() -> String {
+    shader::builtin_source("shader.wgsl", DEFAULT_SHADER).into_owned()
+}
+
+const fn get_texture_layout_desc() -> wgpu::BindGroupLayoutDescriptor<'static> {
+    const LAYOUT_ENTRIES: [wgpu::BindGroupLayoutEntry; 9] = [
+        // diffuse texture
+        wgpu::BindGroupLayoutEntry {
+            binding: 0,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                multisampled: false,
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                view_dimension: wgpu::TextureViewDimension::D2,
+            },
+            count: None,
+        },
+        wgpu::BindGroupLayoutEntry {
+            binding: 1,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+            count: None,
+        },
+        // normal map
+        wgpu::BindGroupLayoutEntry {
+            binding: 2,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                multisampled: false,
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                view_dimension: wgpu::TextureViewDimension::D2,
+            },
+            count: None,
+        },
+        wgpu::BindGroupLayoutEntry {
+            binding: 3,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+            count: None,
+        },
+        // metallic-roughness texture
+        wgpu::BindGroupLayoutEntry {
+            binding: 4,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                multisampled: false,
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                view_dimension: wgpu::TextureViewDimension::D2,
+            },
+            count: None,
+        },
+        wgpu::BindGroupLayoutEntry {
+            binding: 5,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+            count: None,
+        },
+        // ambient occlusion texture
+        wgpu::BindGroupLayoutEntry {
+            binding: 6,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                multisampled: false,
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                view_dimension: wgpu::TextureViewDimension::D2,
+            },
+            count: None,
+        },
+        wgpu::BindGroupLayoutEntry {
+            binding: 7,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+            count: None,
+        },
+        // metallic, roughness and alpha cutoff factors
+        wgpu::BindGroupLayoutEntry {
+            binding: 8,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: buffer_binding_size::<model::MaterialFactors>(),
+            },
+            count: None,
+        },
+    ];
+    wgpu::BindGroupLayoutDescriptor {
+        label: Some("Bind Group Layout"),
+        entries: &LAYOUT_ENTRIES,
+    }
+}
+
+/// The light, plus its shadow map for shaders that want shadows
+const fn get_light_layout_desc() -> wgpu::BindGroupLayoutDescriptor<'static> {
+    const LAYOUT_ENTRIES: [wgpu::BindGroupLayoutEntry; 5] = [
+        wgpu::BindGroupLayoutEntry {
+            binding: 0,
+            visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: buffer_binding_size::<LightUniform>(),
+            },
+            count: None,
+        },
+        wgpu::BindGroupLayoutEntry {
+            binding: 1,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Depth,
+                view_dimension: wgpu::TextureViewDimension::D2,
+                multisampled: false,
+            },
+            count: None,
+        },
+        wgpu::BindGroupLayoutEntry {
+            binding: 2,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Comparison),
+            count: None,
+        },
+        wgpu::BindGroupLayoutEntry {
+            binding: 3,
+            visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: buffer_binding_size::<ShadowUniform>(),
+            },
+            count: None,
+        },
+        wgpu::BindGroupLayoutEntry {
+            binding: 4,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: buffer_binding_size::<ShadingUniform>(),
+            },
+            count: None,
+        },
+    ];
+    wgpu::BindGroupLayoutDescriptor {
+        label: Some("Light Bind Group Layout"),
+        entries: &LAYOUT_ENTRIES,
+    }
+}
+
+/// Everything the renderer draws with is core WebGPU
+const REQUIRED_FEATURES: wgpu::Features = wgpu::Features::empty();
+/// Extras that are enabled when the adapter supports them
+const OPTIONAL_FEATURES: wgpu::Features = wgpu::Features::POLYGON_MODE_LINE
+    .union(wgpu::Features::PUSH_CONSTANTS)
+    .union(wgpu::Features::FLOAT32_FILTERABLE)
+    .union(wgpu::Features::TIMESTAMP_QUERY);
+const MAX_PUSH_CONSTANT_SIZE: u32 = 128;
+
+/// Picks an adapter, compatible with `compatible_surface` if one is given.
+/// `backends`, or `WGPU_BACKEND` (e.g. `vulkan`, `dx12`, `metal`) if it is
+/// `None`, and `WGPU_POWER_PREF` (`low` or `high`) can be set to choose
+/// between adapters, any adapter is used if none match the requested backend.
+async fn select_adapter(
+    instance: &wgpu::Instance,
+    compatible_surface: Option<&wgpu::Surface<'_>>,
+    backends: Option<wgpu::Backends>,
+) -> RendererResult<wgpu::Adapter> {
+    let power_preference = wgpu::util::power_preference_from_env().unwrap_or_default();
+    if let Some(backends) = backends.or_else(wgpu::util::backend_bits_from_env) {
+        let preferred_type = match power_preference {
+            wgpu::PowerPreference::HighPerformance => Some(wgpu::DeviceType::DiscreteGpu),
+            wgpu::PowerPreference::LowPower => Some(wgpu::DeviceType::IntegratedGpu),
+            wgpu::PowerPreference::None => None,
+        };
+        let mut adapters: Vec<_> = instance
+            .enumerate_adapters(backends)
+            .into_iter()
+            .filter(|adapter| {
+                compatible_surface.is_none_or(|surface| adapter.is_surface_supported(surface))
+            })
+            .collect();
+        adapters.sort_by_key(|adapter| Some(adapter.get_info().device_type) != preferred_type);
+        match adapters.into_iter().next() {
+            Some(adapter) => return Ok(adapter),
+            None => warn!(
+                "No adapter found for backends {:?}, falling back to any backend",
+                backends
+            ),
+        }
+    }
+
+    instance
+        .request_adapter(&wgpu::RequestAdapterOptions {
+            power_preference,
+            compatible_surface,
+            force_fallback_adapter: false,
+        })
+        .await
+        .ok_or(RendererError::AdapterNotFound)
+}
+
+/// Picks an adapter with [`select_adapter`] and opens a device on it
+pub async fn request_device(
+    instance: &wgpu::Instance,
+    compatible_surface: Option<&wgpu::Surface<'_>>,
+    backends: Option<wgpu::Backends>,
+) -> RendererResult<(wgpu::Adapter, wgpu::Device, wgpu::Queue)> {
+    let adapter = select_adapter(instance, compatible_surface, backends).await?;
+    info!("Using adapter {:?}", adapter.get_info());
+
+    let features = REQUIRED_FEATURES | (adapter.features() & OPTIONAL_FEATURES);
+    info!("Enabling features {:?}", features);
+    let required_limits = if features.contains(wgpu::Features::PUSH_CONSTANTS) {
+        wgpu::Limits {
+            max_push_constant_size: adapter
+                .limits()
+                .max_push_constant_size
+                .min(MAX_PUSH_CONSTANT_SIZE),
+            ..Default::default()
+        }
+    } else {
+        wgpu::Limits::default()
+    };
+
+    let (device, queue) = adapter
+        .request_device(
+            &wgpu::DeviceDescriptor {
+                label: None,
+                required_features: features,
+                required_limits,
+            },
+            None,
+        )
+        .await?;
+    Ok((adapter, device, queue))
+}
+
+fn create_light_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    light_buffer: &wgpu::Buffer,
+    shadow_map: &ShadowMap,
+    shading_buffer: &wgpu::Buffer,
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("Light Bind Group"),
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: light_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::TextureView(shadow_map.view()),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: wgpu::BindingResource::Sampler(shadow_map.sampler()),
+            },
+            wgpu::BindGroupEntry {
+                binding: 3,
+                resource: shadow_map.uniform_buffer().as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 4,
+                resource: shading_buffer.as_entire_binding(),
+            },
+        ],
+    })
+}
+
+/// How instances of the model are placed in the scene
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum InstanceLayout {
+    None,
+    /// One instance at the origin
+    #[default]
+    Single,
+    /// `count` by `count` instances centered on the origin, each rotated
+    /// away from it
+    Grid {
+        count: u32,
+        spacing: f32,
+    },
+}
+
+impl InstanceLayout {
+    /// Adds a node with an instance of `model` for every position in the
+    /// layout
+    fn populate(&self, scene_tree: &mut SceneTree, model: &Arc<Mutex<model::Model>>) {
+        let transforms: Vec<cgmath::Matrix4<f32>> = match *self {
+            Self::None => Vec::new(),
+            Self::Single => vec![cgmath::Matrix4::identity()],
+            Self::Grid { count, spacing } => (0..count)
+                .flat_map(|z| (0..count).map(move |x| (x, z)))
+                .map(|(x, z)| {
+                    let x = spacing * (x as f32 - count as f32 / 2.0);
+                    let z = spacing * (z as f32 - count as f32 / 2.0);
+                    let position = cgmath::Vector3 { x, y: 0.0, z };
+                    let rotation = if position.is_zero() {
+                        cgmath::Quaternion::from_axis_angle(
+                            cgmath::Vector3::unit_z(),
+                            cgmath::Deg(0.0),
+                        )
+                    } else {
+                        cgmath::Quaternion::from_axis_angle(position.normalize(), cgmath::Deg(45.0))
+                    };
+                    cgmath::Matrix4::from_translation(position) * cgmath::Matrix4::from(rotation)
+                })
+                .collect(),
+        };
+        for transform in transforms {
+            let node_handle = scene_tree.new_node();
+            let mut node = scene_tree
+                .get_mut(&node_handle)
+                .expect("We just created this node!");
+            node.update_local_transform(transform);
+            node.set_model(model.clone());
+        }
+    }
+}
+
+/// Starting settings of a `Renderer` that would otherwise be fixed. The
+/// default is the scene the viewer has always started with.
+#[derive(Clone, Debug)]
+pub struct RendererConfig {
+    pub instance_layout: InstanceLayout,
+    /// Where the light starts out, it orbits the origin from there
+    pub light_position: [f32; 3],
+    pub light_color: [f32; 3],
+    /// Shows wherever nothing is drawn, including the sky
+    pub clear_color: wgpu::Color,
+}
+
+impl Default for RendererConfig {
+    fn default() -> Self {
+        Self {
+            instance_layout: InstanceLayout::default(),
+            light_position: [2.0, 2.0, 2.0],
+            light_color: [1.0, 1.0, 1.0],
+            clear_color: wgpu::Color::BLUE,
+        }
+    }
+}
+
+/// What `Renderer::new` draws and to what
+pub struct RendererCreateInfo<'a> {
+    /// Size of the output
+    pub extent: &'a wgpu::Extent3d,
+    /// Format of the output, which the scene is tonemapped to
+    pub output_format: wgpu::TextureFormat,
+    /// Format the scene is drawn to before tonemapping, which the device
+    /// has to support
+    pub hdr_format: hdr::HdrFormat,
+    /// A path and its source, the default shader is used if it is `None`
+    pub starting_shader: Option<(PathBuf, String)>,
+    pub model_file: &'a str,
+    pub config: &'a RendererConfig,
+}
+
+/// Owns the device and everything needed to draw the scene into the HDR
+/// target, independent of any window or surface
+pub struct Renderer {
+    /// Shared with the threads that build pipelines in the background
+    pub device: Arc<wgpu::Device>,
+    pub queue: wgpu::Queue,
+    pub camera: PerspectiveCamera,
+    /// Draws the right half of the target while split screen is on, the
+    /// main camera draws the left half
+    split_camera: Option<PerspectiveCamera>,
+    pub picker: Picker,
+    pub depth_texture: texture::Texture,
+    pub depth_visualizer: DepthVisualizer,
+    pub show_depth: bool,
+    texture_bind_group_layout: wgpu::BindGroupLayout,
+    light_bind_group_layout: wgpu::BindGroupLayout,
+    pub shader_tabs: Vec<ShaderTab>,
+    /// Index into `shader_tabs` of the tab whose pipelines draw the model
+    pub active_shader_tab: usize,
+    pub scene_tree: SceneTree,
+    pub model: Arc<Mutex<model::Model>>,
+    instance_layout: InstanceLayout,
+    anisotropy_clamp: u16,
+    light: LightUniform,
+    /// Node the light is attached to. The light orbits on its own without one.
+    light_node: Option<NodeHandle>,
+    /// Whether the light orbits, it stays put while this is off
+    pub animate_light: bool,
+    light_buffer: wgpu::Buffer,
+    pub light_marker: LightMarker,
+    light_bind_group: wgpu::BindGroup,
+    shadow_map: ShadowMap,
+    /// Back faces are drawn and lit instead of culled
+    double_sided: bool,
+    clear_color: wgpu::Color,
+    shading_buffer: wgpu::Buffer,
+    pub hdr: hdr::HdrPipeline,
+    pub cubemap: CubeMapRenderer,
+    pub grid: GridRenderer,
+    pub show_grid: bool,
+    /// Thick points or lines drawn over the model, created the first time
+    /// they are shown as not every adapter supports them
+    pub debug_primitives: Option<DebugPrimitiveRenderer>,
+    show_debug_primitives: bool,
+    /// Seconds fed to the shader's time uniform
+    time: f32,
+    /// Whether `time` advances, independently of the light animation
+    pub advance_time: bool,
+    /// User compute shader dispatched every frame, if one is loaded
+    pub compute_view: Option<ComputeView>,
+    /// Times the scene and tonemapping passes, if timestamp queries are
+    /// supported
+    pub gpu_timer: Option<GpuTimer>,
+    /// Used by the depth of field pass when it is enabled
+    pub dof: DofSettings,
+    /// Whether TAA and depth of field are turned on. They only run while
+    /// split screen is off, as both follow the main camera alone.
+    taa_enabled: bool,
+    dof_enabled: bool,
+    /// Model draws recorded by the last `render_scene`
+    render_stats: Cell<RenderStats>,
+}
+
+impl Renderer {
+    /// Sets up the scene described by `create_info` on `device`
+    pub async fn new(
+        device: wgpu::Device,
+        queue: wgpu::Queue,
+        create_info: RendererCreateInfo<'_>,
+    ) -> RendererResult<Self> {
+        let RendererCreateInfo {
+            extent,
+            output_format,
+            hdr_format,
+            starting_shader,
+            model_file,
+            config,
+        } = create_info;
+        let depth_texture =
+            texture::Texture::create_depth_texture(&device, extent, "depth_texture");
+
+        let texture_bind_group_layout_desc = get_texture_layout_desc();
+        let texture_bind_group_layout =
+            device.create_bind_group_layout(&texture_bind_group_layout_desc);
+
+        let camera = Camera::new((0.0, 5.0, 10.0), cgmath::Deg(-90.0), cgmath::Deg(-20.0));
+        let projection =
+            Projection::new(extent.width, extent.height, cgmath::Deg(45.0), 0.1, 100.0);
+
+        let camera = PerspectiveCamera::new(&device, camera, projection);
+
+        let mut scene_tree = SceneTree::default();
+
+        // Create instances
+        let model = Arc::new(Mutex::new(
+            resources::load_model(
+                model_file,
+                &device,
+                &queue,
+                &texture_bind_group_layout,
+                |_, _| (),
+                &AtomicBool::new(false),
+            )
+            .await?,
+        ));
+        let instance_layout = config.instance_layout;
+        instance_layout.populate(&mut scene_tree, &model);
+
+        let light = LightUniform::new(config.light_position, config.light_color);
+
+        let light_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Light Buffer"),
+            contents: bytemuck::cast_slice(&[light]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let light_bind_group_layout_desc = get_light_layout_desc();
+        let light_bind_group_layout =
+            device.create_bind_group_layout(&light_bind_group_layout_desc);
+
+        let shadow_map = ShadowMap::new(
+            &device,
+            light.position.into(),
+            &texture_bind_group_layout_desc,
+            &texture_bind_group_layout,
+        );
+
+        let shading_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Shading Buffer"),
+            contents: bytemuck::cast_slice(&[ShadingUniform::new(false)]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let light_bind_group = create_light_bind_group(
+            &device,
+            &light_bind_group_layout,
+            &light_buffer,
+            &shadow_map,
+            &shading_buffer,
+        );
+
+        let hdr = hdr::HdrPipeline::new(&device, extent, hdr_format, output_format);
+        let cubemap = CubeMapRenderer::new(
+            &device,
+            &queue,
+            CubeMapCreateInfo {
+                camera_bind_group_layout_desc: &PerspectiveCamera::layout_desc(),
+                camera_bind_group_layout: camera.layout(),
+                color_format: hdr.format(),
+                filename: "pure-sky.hdr",
+                size: cubemap::DEFAULT_CUBEMAP_SIZE,
+                label: Some("Sky Cubemap"),
+            },
+        )
+        .await?;
+        let grid = GridRenderer::new(
+            &device,
+            &PerspectiveCamera::layout_desc(),
+            camera.layout(),
+            hdr.format(),
+        );
+
+        let (shader_path, shader_source) = match starting_shader {
+            Some((path, source)) => (Some(path), source),
+            None => (None, default_shader()),
+        };
+
+        let mut shader_tab = ShaderTab::new(
+            &device,
+            "default".to_string(),
+            shader_source,
+            &[
+                &texture_bind_group_layout,
+                camera.layout(),
+                &light_bind_group_layout,
+                cubemap.layout(),
+            ],
+            &[
+                &texture_bind_group_layout_desc,
+                &PerspectiveCamera::layout_desc(),
+                &light_bind_group_layout_desc,
+                &cubemap.layout_desc(),
+            ],
+            hdr.format(),
+            Some(wgpu::Face::Back),
+        )
+        .map_err(|e| RendererError::ShaderCompile(e.message))?;
+        if let Some(path) = shader_path {
+            shader_tab.set_path(path);
+        }
+
+        let light_marker = LightMarker::new(
+            &device,
+            &PerspectiveCamera::layout_desc(),
+            camera.layout(),
+            &light_bind_group_layout_desc,
+            &light_bind_group_layout,
+            hdr.format(),
+        );
+        let picker = Picker::new(
+            &device,
+            extent,
+            &PerspectiveCamera::layout_desc(),
+            camera.layout(),
+            &texture_bind_group_layout_desc,
+            &texture_bind_group_layout,
+            hdr.format(),
+        );
+        let depth_visualizer =
+            DepthVisualizer::new(&device, &depth_texture, camera.projection(), hdr.format());
+        let gpu_timer = GpuTimer::new(&device, &queue);
+
+        Ok(Self {
+            device: Arc::new(device),
+            queue,
+            camera,
+            split_camera: None,
+            picker,
+            depth_texture,
+            depth_visualizer,
+            show_depth: false,
+            texture_bind_group_layout,
+            light_bind_group_layout,
+            shader_tabs: vec![shader_tab],
+            active_shader_tab: 0,
+            scene_tree,
+            model,
+            instance_layout,
+            anisotropy_clamp: 1,
+            light,
+            light_node: None,
+            animate_light: true,
+            light_buffer,
+            light_marker,
+            light_bind_group,
+            shadow_map,
+            double_sided: false,
+            clear_color: config.clear_color,
+            shading_buffer,
+            hdr,
+            cubemap,
+            grid,
+            show_grid: false,
+            debug_primitives: None,
+            show_debug_primitives: false,
+            time: 0.0,
+            advance_time: true,
+            compute_view: None,
+            gpu_timer,
+            dof: DofSettings::default(),
+            render_stats: Cell::new(RenderStats::default()),
+            taa_enabled: false,
+            dof_enabled: false,
+        })
+    }
+
+    pub fn resize(&mut self, width: u32, height: u32) {
+        let extent = wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        };
+        self.resize_cameras(width, height);
+        self.depth_texture =
+            texture::Texture::create_depth_texture(&self.device, &extent, "depth_texture");
+        self.depth_visualizer
+            .resize(&self.device, &self.depth_texture);
+        self.picker.resize(&self.device, width, height);
+        self.hdr
+            .resize(&self.device, width, height, &self.depth_texture);
+        if let Some(compute_view) = &mut self.compute_view {
+            compute_view.resize(&self.device, width, height);
+        }
+    }
+
+    /// Sizes the cameras' projections to their viewports
+    fn resize_cameras(&mut self, width: u32, height: u32) {
+        let [left, right] = Viewport::split(width, height);
+        match &mut self.split_camera {
+            Some(split_camera) => {
+                let (width, height) = left.size();
+                self.camera.resize(width, height);
+                // The halves differ by at most a pixel, so the left one's
+                // size does for both
+                if let Some(debug_primitives) = &mut self.debug_primitives {
+                    debug_primitives.resize(&self.queue, width, height);
+                }
+                let (width, height) = right.size();
+                split_camera.resize(width, height);
+                split_camera.write_uniform(&self.queue);
+            }
+            None => {
+                self.camera.resize(width, height);
+                if let Some(debug_primitives) = &mut self.debug_primitives {
+                    debug_primitives.resize(&self.queue, width, height);
+                }
+            }
+        }
+    }
+
+    pub fn show_debug_primitives(&self) -> bool {
+        self.show_debug_primitives
+    }
+
+    /// Shows or hides the points and lines drawn over the model. Only show
+    /// them if `debug_primitives::is_supported` by the adapter.
+    pub fn set_show_debug_primitives(&mut self, show: bool) {
+        self.show_debug_primitives = show;
+        if show && self.debug_primitives.is_none() {
+            let extent = self.extent();
+            let viewport_size = if self.split_screen() {
+                Viewport::split(extent.width, extent.height)[0].size()
+            } else {
+                (extent.width, extent.height)
+            };
+            self.debug_primitives = Some(DebugPrimitiveRenderer::new(
+                &self.device,
+                &self.model.lock().expect("Mutex Poisoned"),
+                &PerspectiveCamera::layout_desc(),
+                self.camera.layout(),
+                self.hdr.format(),
+                viewport_size,
+                self.shader_tabs[self.active_shader_tab].topology(),
+            ));
+        }
+    }
+
+    pub fn split_screen(&self) -> bool {
+        self.split_camera.is_some()
+    }
+
+    /// Draws the scene side by side from two cameras. The right one starts
+    /// out as a copy of the main camera and stays put while it moves.
+    pub fn set_split_screen(&mut self, enabled: bool) {
+        if enabled == self.split_screen() {
+            return;
+        }
+        self.split_camera = enabled.then(|| {
+            PerspectiveCamera::new(
+                &self.device,
+                self.camera.camera().clone(),
+                self.camera.projection().clone(),
+            )
+        });
+        let size = self.extent();
+        self.resize_cameras(size.width, size.height);
+        self.apply_effects();
+    }
+
+    /// Moves the right camera of the split screen to the main camera
+    pub fn copy_camera_to_split(&mut self) {
+        if let Some(split_camera) = &mut self.split_camera {
+            let camera = self.camera.camera();
+            split_camera.set_pose(&self.queue, camera.position(), camera.yaw(), camera.pitch());
+        }
+    }
+
+    /// The cameras the scene is drawn from, each with the part of the target
+    /// it draws to
+    pub fn viewports(&self) -> Vec<(&PerspectiveCamera, Viewport)> {
+        Self::camera_viewports(&self.camera, self.split_camera.as_ref(), self.extent())
+    }
+
+    fn camera_viewports<'a>(
+        camera: &'a PerspectiveCamera,
+        split_camera: Option<&'a PerspectiveCamera>,
+        size: wgpu::Extent3d,
+    ) -> Vec<(&'a PerspectiveCamera, Viewport)> {
+        match split_camera {
+            Some(split_camera) => {
+                let [left, right] = Viewport::split(size.width, size.height);
+                vec![(camera, left), (split_camera, right)]
+            }
+            None => vec![(camera, Viewport::full(size.width, size.height))],
+        }
+    }
+
+    /// Starts picking the instance drawn at pixel (`x`, `y`) of the target
+    /// by the camera whose viewport contains it, the result is read back by
+    /// `Picker::collect`
+    pub fn pick(&mut self, x: u32, y: u32) {
+        let viewports =
+            Self::camera_viewports(&self.camera, self.split_camera.as_ref(), self.extent());
+        let index = viewports
+            .iter()
+            .position(|(_, viewport)| viewport.contains(x as f32, y as f32))
+            .unwrap_or(0);
+        let (camera, viewport) = viewports[index];
+        let model_guard = self.model.lock().expect("Poisoned Mutex");
+        self.picker.pick(PickParams {
+            device: &self.device,
+            queue: &self.queue,
+            model: &model_guard,
+            camera_bind_group: camera.bind_group(),
+            viewport,
+            x,
+            y,
+        });
+    }
+
+    /// Size of the targets the scene is drawn to, kept in step with the
+    /// surface by `resize`
+    pub fn extent(&self) -> wgpu::Extent3d {
+        self.depth_texture.size
+    }
+
+    /// Advances the scene, the camera is updated separately by its controller
+    pub fn update(&mut self, dt: Duration) {
+        for tab in &mut self.shader_tabs {
+            tab.poll_compile();
+        }
+        if let Some(debug_primitives) = &mut self.debug_primitives {
+            debug_primitives.set_topology(
+                &self.queue,
+                self.shader_tabs[self.active_shader_tab].topology(),
+            );
+        }
+        match self.hdr.taa_mut() {
+            Some(taa) => taa.update(&self.queue, &mut self.camera),
+            None => self.camera.set_jitter(&self.queue, cgmath::Vector2::zero()),
+        }
+        if let Some(dof) = self.hdr.dof() {
+            dof.update(&self.queue, &self.dof, self.camera.projection());
+        }
+        self.scene_tree.update_transforms();
+        {
+            let mut model = self.model.lock().expect("Mutex Poisoned");
+            model.update_instance_buffer(&self.device, &self.queue);
+            let frustums: Vec<_> = self
+                .viewports()
+                .iter()
+                .map(|(camera, _)| Frustum::from_view_proj(camera.view_proj()))
+                .collect();
+            model.cull(&self.device, &self.queue, &frustums);
+        }
+
+        // Update light
+        let node_transform = self
+            .light_node
+            .and_then(|handle| self.scene_tree.get(&handle))
+            .and_then(|node| node.get_global_transform());
+        match node_transform {
+            Some(transform) => self.light.position = transform.w.truncate().into(),
+            None if self.animate_light => {
+                let old_position: cgmath::Vector3<_> = self.light.position.into();
+                self.light.position = (cgmath::Quaternion::from_axis_angle(
+                    cgmath::Vector3::unit_y(),
+                    cgmath::Deg(60.0 * dt.as_secs_f32()),
+                ) * old_position)
+                    .into();
+            }
+            None => (),
+        }
+        self.queue
+            .write_buffer(&self.light_buffer, 0, bytemuck::cast_slice(&[self.light]));
+        self.light_marker.update(&self.queue, &self.light);
+        self.shadow_map
+            .update(&self.queue, self.light.position.into());
+
+        if self.advance_time {
+            self.time += dt.as_secs_f32();
+        }
+        let extent = self.extent();
+        if let Some(uniforms) = self.shader_tabs[self.active_shader_tab].uniforms_mut() {
+            uniforms.set_resolution(extent.width, extent.height);
+            uniforms.set_time(self.time);
+            uniforms.upload(&self.queue);
+        }
+
+        if let Some(compute_view) = &mut self.compute_view {
+            compute_view.update(&self.queue, dt);
+        }
+    }
+
+    /// Renders the scene without highlights and writes the linear HDR target,
+    /// before tonemapping, to an OpenEXR file at `path`
+    pub fn save_exr<P: AsRef<Path>>(&self, path: P) -> RendererResult<()> {
+        let hdr_format = self.hdr.hdr_format();
+        let (width, height) = (self.hdr.width(), self.hdr.height());
+        let padded_bytes_per_row = (width * hdr_format.bytes_per_pixel())
+            .div_ceil(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT)
+            * wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+        let readback_buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Renderer::save_exr"),
+            size: (padded_bytes_per_row * height) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Renderer::save_exr"),
+            });
+        self.render_scene(&mut encoder, None);
+        encoder.copy_texture_to_buffer(
+            self.hdr.texture().as_image_copy(),
+            wgpu::ImageCopyBuffer {
+                buffer: &readback_buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded_bytes_per_row),
+                    rows_per_image: None,
+                },
+            },
+            wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+        );
+        self.queue.submit(std::iter::once(encoder.finish()));
+
+        let slice = readback_buffer.slice(..);
+        let (sender, receiver) = std::sync::mpsc::channel();
+        slice.map_async(wgpu::MapMode::Read, move |result| {
+            let _ = sender.send(result);
+        });
+        self.device.poll(wgpu::Maintain::Wait);
+        receiver
+            .recv()
+            .expect("Map callback dropped without being called")?;
+
+        let unpadded_bytes_per_row = (width * hdr_format.bytes_per_pixel()) as usize;
+        let pixels = slice
+            .get_mapped_range()
+            .chunks(padded_bytes_per_row as usize)
+            .flat_map(|row| hdr_format.decode_row(&row[..unpadded_bytes_per_row]))
+            .collect::<Vec<_>>();
+        readback_buffer.unmap();
+
+        exr::prelude::write_rgba_file(path, width as usize, height as usize, |x, y| {
+            let i = (y * width as usize + x) * 4;
+            (pixels[i], pixels[i + 1], pixels[i + 2], pixels[i + 3])
+        })?;
+        Ok(())
+    }
+
+    pub fn instance_layout(&self) -> InstanceLayout {
+        self.instance_layout
+    }
+
+    /// Replaces every node in the scene with the instances of `layout`.
+    /// Handles to the old nodes no longer refer to anything.
+    pub fn set_instance_layout(&mut self, layout: InstanceLayout) {
+        self.scene_tree = SceneTree::default();
+        self.light_node = None;
+        self.model.lock().expect("Mutex Poisoned").clear_instances();
+        layout.populate(&mut self.scene_tree, &self.model);
+        self.instance_layout = layout;
+    }
+
+    /// Takes the scene, cameras, shader tabs and settings of `old`, whose
+    /// device was lost, so only what lives on the GPU is rebuilt. `self`
+    /// must have been created for the same model and instance layout.
+    /// Debug primitives are left hidden, as the new adapter may not
+    /// support them.
+    pub fn restore_from(&mut self, old: Renderer) {
+        let output_transform = old.hdr.output_transform();
+        let shadows_enabled = old.shadows_enabled();
+        let shadow_resolution = old.shadow_resolution();
+        if old.compute_view.is_some() {
+            warn!("Dropping the compute shader made for the old device");
+        }
+        let Renderer {
+            camera,
+            split_camera,
+            shader_tabs,
+            active_shader_tab,
+            scene_tree,
+            light,
+            light_node,
+            animate_light,
+            show_depth,
+            double_sided,
+            anisotropy_clamp,
+            show_grid,
+            time,
+            advance_time,
+            dof,
+            taa_enabled,
+            dof_enabled,
+            clear_color,
+            ..
+        } = old;
+
+        self.camera = PerspectiveCamera::new(
+            &self.device,
+            camera.camera().clone(),
+            camera.projection().clone(),
+        );
+        self.split_camera = split_camera.map(|split_camera| {
+            PerspectiveCamera::new(
+                &self.device,
+                split_camera.camera().clone(),
+                split_camera.projection().clone(),
+            )
+        });
+        let size = self.extent();
+        self.resize_cameras(size.width, size.height);
+
+        self.model.lock().expect("Mutex Poisoned").clear_instances();
+        self.scene_tree = scene_tree;
+        self.scene_tree.move_to_model(&self.model);
+        self.light = light;
+        self.light_node = light_node;
+        self.animate_light = animate_light;
+
+        self.clear_color = clear_color;
+        self.show_depth = show_depth;
+        self.show_grid = show_grid;
+        self.time = time;
+        self.advance_time = advance_time;
+        self.dof = dof;
+        self.set_anisotropy_clamp(anisotropy_clamp);
+        self.set_shadows_enabled(shadows_enabled);
+        self.set_shadow_resolution(shadow_resolution);
+        self.hdr.set_output_transform(&self.queue, output_transform);
+        self.taa_enabled = taa_enabled;
+        self.dof_enabled = dof_enabled;
+        self.apply_effects();
+        // Compiles every tab when it changes, so it goes before the tabs
+        // are filled in
+        self.set_double_sided(double_sided);
+
+        // Each tab starts out as the default shader, which always builds,
+        // and is then compiled from its own source
+        for (index, tab) in shader_tabs.into_iter().enumerate() {
+            if index == self.shader_tabs.len() {
+                self.add_shader_tab(String::new(), default_shader());
+            }
+            let Some(new_tab) = self.shader_tabs.get_mut(index) else {
+                break;
+            };
+            new_tab.take_source(tab);
+            self.compile_tab(index);
+        }
+        self.active_shader_tab = active_shader_tab.min(self.shader_tabs.len() - 1);
+    }
+
+    pub fn light_node(&self) -> Option<NodeHandle> {
+        self.light_node
+    }
+
+    /// Attaches the light to `node`, so it sits at the node's world
+    /// translation, or detaches it with `None`
+    pub fn set_light(&mut self, node: Option<NodeHandle>) {
+        self.light_node = node;
+    }
+
+    pub fn anisotropy_clamp(&self) -> u16 {
+        self.anisotropy_clamp
+    }
+
+    /// Whether the model's textures have mipmaps, without them
+    /// `set_anisotropy_clamp` has no visible effect
+    pub fn model_has_mipmaps(&self) -> bool {
+        self.model.lock().expect("Mutex Poisoned").has_mipmaps()
+    }
+
+    /// Sets the anisotropic filtering of the model's textures. The caller
+    /// must check `anisotropy_clamp` against `texture::max_anisotropy`.
+    pub fn set_anisotropy_clamp(&mut self, anisotropy_clamp: u16) {
+        if anisotropy_clamp == self.anisotropy_clamp {
+            return;
+        }
+        self.model
+            .lock()
+            .expect("Mutex Poisoned")
+            .set_anisotropy_clamp(
+                &self.device,
+                &self.texture_bind_group_layout,
+                anisotropy_clamp,
+            );
+        self.anisotropy_clamp = anisotropy_clamp;
+    }
+
+    pub fn shadows_enabled(&self) -> bool {
+        self.shadow_map.enabled()
+    }
+
+    pub fn set_shadows_enabled(&mut self, enabled: bool) {
+        self.shadow_map.set_enabled(enabled);
+    }
+
+    /// Side length of the shadow map in texels
+    pub fn shadow_resolution(&self) -> u32 {
+        self.shadow_map.size()
+    }
+
+    pub fn set_shadow_resolution(&mut self, size: u32) {
+        if size == self.shadow_map.size() {
+            return;
+        }
+        self.shadow_map.set_size(&self.device, size);
+        self.light_bind_group = create_light_bind_group(
+            &self.device,
+            &self.light_bind_group_layout,
+            &self.light_buffer,
+            &self.shadow_map,
+            &self.shading_buffer,
+        );
+    }
+
+    /// Draws the scene into the HDR target, highlighting `selected_instance`
+    pub fn render_scene(&self, encoder: &mut wgpu::CommandEncoder, selected_instance: Option<u32>) {
+        {
+            let model_guard = self.model.lock().expect("Poisoned Mutex");
+            self.shadow_map.render(encoder, &model_guard);
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Render Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: self.hdr.view(),
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(self.clear_color),
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: &self.depth_texture.view,
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(1.0),
+                        store: wgpu::StoreOp::Store,
+                    }),
+                    stencil_ops: None,
+                }),
+                timestamp_writes: self.gpu_timer.as_ref().map(GpuTimer::scene_writes),
+                occlusion_query_set: None,
+            });
+            let shader_tab = &self.shader_tabs[self.active_shader_tab];
+            let mut stats = RenderStats::default();
+            for (camera, viewport) in self.viewports() {
+                viewport.apply(&mut render_pass);
+                self.light_marker.render(
+                    &mut render_pass,
+                    camera.bind_group(),
+                    &self.light_bind_group,
+                );
+
+                if let Some(uniforms) = shader_tab.uniforms() {
+                    // The model draws only set the renderer's groups, so this
+                    // stays bound for both pipelines. Per instance uniforms
+                    // are rebound for every instance.
+                    render_pass.set_bind_group(
+                        uniforms.group(),
+                        uniforms.bind_group(),
+                        uniforms.default_offsets(),
+                    );
+                }
+                let per_instance = shader_tab.uniforms().and_then(UserUniforms::per_instance);
+                render_pass.set_pipeline(shader_tab.render_pipeline().pipeline());
+                if shader_tab.is_fullscreen() {
+                    // Takes the place of the model, with the first material's
+                    // textures in group 0
+                    if let Some(material) = model_guard.materials.first() {
+                        render_pass.set_bind_group(0, &material.bind_group, &[]);
+                        render_pass.set_bind_group(1, camera.bind_group(), &[]);
+                        render_pass.set_bind_group(2, &self.light_bind_group, &[]);
+                        render_pass.set_bind_group(3, self.cubemap.bind_group(), &[]);
+                        render_pass.draw(0..3, 0..1);
+                        stats.record(3, wgpu::PrimitiveTopology::TriangleList, &(0..1));
+                    }
+                } else {
+                    stats += render_pass.draw_model_instanced(
+                        &model_guard,
+                        camera.bind_group(),
+                        &self.light_bind_group,
+                        self.cubemap.bind_group(),
+                        InstanceDraw {
+                            instances: model_guard.visible_instances(),
+                            per_instance,
+                            topology: shader_tab.topology(),
+                        },
+                    );
+                }
+
+                // Opaque geometry is drawn first so that the sky is only
+                // shaded where it's still visible, the fullscreen triangle
+                // hides all of it. Blended draws go over the sky.
+                self.cubemap.render(&mut render_pass, camera.bind_group());
+                if self.show_grid {
+                    self.grid.render(&mut render_pass, camera.bind_group());
+                }
+
+                if !shader_tab.is_fullscreen() {
+                    if model_guard.has_transparent_meshes() {
+                        if let Some(uniforms) = shader_tab.uniforms() {
+                            // The sky and grid pipelines have other layouts
+                            render_pass.set_bind_group(
+                                uniforms.group(),
+                                uniforms.bind_group(),
+                                uniforms.default_offsets(),
+                            );
+                        }
+                        render_pass
+                            .set_pipeline(shader_tab.transparent_render_pipeline().pipeline());
+                        stats += render_pass.draw_model_transparent_instanced(
+                            &model_guard,
+                            camera.camera().position(),
+                            camera.bind_group(),
+                            &self.light_bind_group,
+                            self.cubemap.bind_group(),
+                            InstanceDraw {
+                                instances: model_guard.visible_instances(),
+                                per_instance,
+                                topology: shader_tab.topology(),
+                            },
+                        );
+                    }
+
+                    if let Some(debug_primitives) = self
+                        .debug_primitives
+                        .as_ref()
+                        .filter(|_| self.show_debug_primitives)
+                    {
+                        debug_primitives.render(
+                            &mut render_pass,
+                            &model_guard,
+                            camera.bind_group(),
+                        );
+                    }
+                }
+
+                if let Some(instance) = selected_instance {
+                    self.picker.render_highlight(
+                        &mut render_pass,
+                        &model_guard,
+                        camera.bind_group(),
+                        instance,
+                    );
+                }
+            }
+            self.render_stats.set(stats);
+        }
+        if self.show_depth {
+            self.depth_visualizer
+                .update(&self.queue, self.camera.projection());
+            self.depth_visualizer.render(encoder, self.hdr.view());
+        }
+    }
+
+    /// Points the main camera along `view`'s axis at the shown instances
+    pub fn snap_camera(&mut self, view: AxisView) {
+        let bounds = self.model.lock().expect("Mutex Poisoned").world_bounds();
+        self.camera.snap_to_axis(&self.queue, view, &bounds);
+    }
+
+    /// Instances drawn after frustum culling, and the total
+    pub fn instance_counts(&self) -> (u32, u32) {
+        let model = self.model.lock().expect("Mutex Poisoned");
+        (model.visible_instance_count, model.instances.len() as u32)
+    }
+
+    /// Draw calls, triangles and instances of the model draws in the last
+    /// frame, summed over the viewports
+    pub fn render_stats(&self) -> RenderStats {
+        self.render_stats.get()
+    }
+
+    pub fn taa_enabled(&self) -> bool {
+        self.taa_enabled
+    }
+
+    pub fn dof_enabled(&self) -> bool {
+        self.dof_enabled
+    }
+
+    pub fn set_dof_enabled(&mut self, enabled: bool) {
+        self.dof_enabled = enabled;
+        self.apply_effects();
+    }
+
+    pub fn set_taa_enabled(&mut self, enabled: bool) {
+        self.taa_enabled = enabled;
+        self.apply_effects();
+    }
+
+    /// Runs TAA and depth of field when they are turned on and the screen
+    /// isn't split
+    fn apply_effects(&mut self) {
+        let split_screen = self.split_screen();
+        let taa = self.taa_enabled && !split_screen;
+        if taa != self.hdr.taa_enabled() {
+            self.hdr
+                .set_taa_enabled(&self.device, &self.depth_texture, taa);
+        }
+        let dof = self.dof_enabled && !split_screen;
+        if dof != self.hdr.dof().is_some() {
+            self.hdr
+                .set_dof_enabled(&self.device, &self.depth_texture, dof);
+            if let Some(dof) = self.hdr.dof() {
+                dof.update(&self.queue, &self.dof, self.camera.projection());
+            }
+        }
+    }
+
+    /// Switches the texture the scene is drawn to before tonemapping to a new
+    /// format, rebuilding only the pipelines that draw into it
+    pub fn set_hdr_format(&mut self, hdr_format: hdr::HdrFormat) {
+        if hdr_format == self.hdr.hdr_format() {
+            return;
+        }
+        self.hdr
+            .set_hdr_format(&self.device, hdr_format, &self.depth_texture);
+        let format = self.hdr.format();
+        self.cubemap.set_color_format(&self.device, format);
+        self.grid.set_color_format(&self.device, format);
+        self.light_marker.set_color_format(&self.device, format);
+        self.picker.set_color_format(&self.device, format);
+        if let Some(debug_primitives) = &mut self.debug_primitives {
+            debug_primitives.set_color_format(&self.device, format);
+        }
+        self.depth_visualizer.set_color_format(&self.device, format);
+        if let Some(compute_view) = &mut self.compute_view {
+            compute_view.set_color_format(&self.device, format);
+        }
+        for index in 0..self.shader_tabs.len() {
+            self.compile_tab(index);
+        }
+    }
+
+    /// Draws the scene and tonemaps it into `target`, which needs the
+    /// renderer's output format
+    pub fn render_to(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        target: &dyn RenderTarget,
+        selected_instance: Option<u32>,
+    ) {
+        self.render_scene(encoder, selected_instance);
+        self.hdr.post_process(encoder);
+        self.tonemap(encoder, target.view());
+    }
+
+    /// Tonemaps the HDR target into `output` and resolves the frame's pass
+    /// timings
+    pub fn tonemap(&self, encoder: &mut wgpu::CommandEncoder, output: &wgpu::TextureView) {
+        self.hdr.process(
+            encoder,
+            output,
+            self.gpu_timer.as_ref().map(GpuTimer::tonemap_writes),
+        );
+        if let Some(gpu_timer) = &self.gpu_timer {
+            gpu_timer.resolve(encoder);
+        }
+    }
+
+    /// Replaces the compute shader, keeping the previous one on failure
+    pub fn load_compute_shader(&mut self, name: &str, source: &str) -> RendererResult<()> {
+        let size = self.depth_texture.size;
+        let mut compute_view = ComputeView::new(
+            &self.device,
+            name,
+            source,
+            size.width,
+            size.height,
+            self.hdr.format(),
+        )?;
+        if let Some(old) = &self.compute_view {
+            compute_view.show_output = old.show_output;
+        }
+        self.compute_view = Some(compute_view);
+        Ok(())
+    }
+
+    /// Runs the compute shader and draws its output over the HDR target
+    pub fn dispatch_compute(&mut self, encoder: &mut wgpu::CommandEncoder) {
+        let Some(compute_view) = &mut self.compute_view else {
+            return;
+        };
+        compute_view.dispatch(encoder);
+        if compute_view.show_output {
+            compute_view.render(encoder, self.hdr.view());
+        }
+    }
+
+    pub fn compile_shader(&mut self) {
+        info!("Compiling shader");
+        self.compile_tab(self.active_shader_tab);
+    }
+
+    fn compile_tab(&mut self, index: usize) {
+        let cubemap_layout_desc = self.cubemap.layout_desc();
+        let cull_mode = self.cull_mode();
+        self.shader_tabs[index].compile(
+            &self.device,
+            &[
+                &self.texture_bind_group_layout,
+                self.camera.layout(),
+                &self.light_bind_group_layout,
+                self.cubemap.layout(),
+            ],
+            &[
+                &get_texture_layout_desc(),
+                &PerspectiveCamera::layout_desc(),
+                &get_light_layout_desc(),
+                &cubemap_layout_desc,
+            ],
+            self.hdr.format(),
+            cull_mode,
+        );
+    }
+
+    /// Faces the shader tabs' pipelines cull
+    fn cull_mode(&self) -> Option<wgpu::Face> {
+        if self.double_sided {
+            None
+        } else {
+            Some(wgpu::Face::Back)
+        }
+    }
+
+    pub fn double_sided(&self) -> bool {
+        self.double_sided
+    }
+
+    /// Draws back faces with their normals flipped toward the viewer,
+    /// rebuilding every tab's pipelines without culling
+    pub fn set_double_sided(&mut self, double_sided: bool) {
+        if double_sided == self.double_sided {
+            return;
+        }
+        self.double_sided = double_sided;
+        self.queue.write_buffer(
+            &self.shading_buffer,
+            0,
+            bytemuck::cast_slice(&[ShadingUniform::new(double_sided)]),
+        );
+        for index in 0..self.shader_tabs.len() {
+            self.compile_tab(index);
+        }
+    }
+
+    pub fn add_shader_tab(&mut self, name: String, source: String) {
+        let cubemap_layout_desc = self.cubemap.layout_desc();
+        let tab = ShaderTab::new(
+            &self.device,
+            name,
+            source,
+            &[
+                &self.texture_bind_group_layout,
+                self.camera.layout(),
+                &self.light_bind_group_layout,
+                self.cubemap.layout(),
+            ],
+            &[
+                &get_texture_layout_desc(),
+                &PerspectiveCamera::layout_desc(),
+                &get_light_layout_desc(),
+                &cubemap_layout_desc,
+            ],
+            self.hdr.format(),
+            self.cull_mode(),
+        );
+        match tab {
+            Ok(tab) => {
+                self.shader_tabs.push(tab);
+                self.active_shader_tab = self.shader_tabs.len() - 1;
+            }
+            Err(e) => error!("Unable to create shader tab: {}", e.message),
+        }
+    }
+
+    /// Replaces environment A with an equirectangular HDR image
+    pub fn load_environment<P: AsRef<Path>>(&mut self, hdr_file_path: P) -> RendererResult<()> {
+        let bytes = std::fs::read(&hdr_file_path)?;
+        self.cubemap.reload(
+            &self.device,
+            &self.queue,
+            &cubemap::slot_name(hdr_file_path.as_ref()),
+            &bytes,
+            Some("Sky Cubemap"),
+        )
+    }
+
+    /// Preloads an equirectangular HDR image into a new environment slot
+    /// and makes it environment B
+    pub fn add_environment<P: AsRef<Path>>(&mut self, hdr_file_path: P) -> RendererResult<()> {
+        let bytes = std::fs::read(&hdr_file_path)?;
+        let slot = self.cubemap.add_slot(
+            &self.device,
+            &self.queue,
+            &cubemap::slot_name(hdr_file_path.as_ref()),
+            &bytes,
+            Some("Sky Cubemap"),
+        )?;
+        let (slot_a, _) = self.cubemap.slots();
+        self.cubemap.set_slots(&self.device, slot_a, slot);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shader::reflect_wgsl;
+
+    #[test]
+    fn default_shader_matches_the_pipeline_layouts() {
+        let reflection = reflect_wgsl(&default_shader()).expect("shader.wgsl should parse");
+        assert_eq!(
+            reflection.layout_matches(&[
+                &get_texture_layout_desc(),
+                &PerspectiveCamera::layout_desc(),
+                &get_light_layout_desc(),
+                &cubemap::get_environment_layout_desc(),
+            ]),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn alpha_tested_depth_passes_match_their_pipeline_layouts() {
+        let shadow = reflect_wgsl(include_str!(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/shaders/shadow_depth.wgsl"
+        )))
+        .expect("shadow_depth.wgsl should parse");
+        assert_eq!(
+            shadow.layout_matches(&[
+                &crate::shadow::get_shadow_layout_desc(),
+                &get_texture_layout_desc(),
+            ]),
+            Ok(())
+        );
+        let picking = reflect_wgsl(include_str!(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/shaders/picking.wgsl"
+        )))
+        .expect("picking.wgsl should parse");
+        assert_eq!(
+            picking.layout_matches(&[
+                &PerspectiveCamera::layout_desc(),
+                &get_texture_layout_desc()
+            ]),
+            Ok(())
+        );
+    }
+}
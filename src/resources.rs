@@ -1,11 +1,14 @@
 use std::io::{BufReader, Cursor};
 use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
 
+use cgmath::EuclideanSpace;
 use image::codecs::hdr::HdrDecoder;
+use log::warn;
 use wgpu::util::DeviceExt;
 
-use crate::error::RendererResult;
-use crate::texture::CubeTextureCreate2dParams;
+use crate::error::{RendererError, RendererResult};
+use crate::texture::{ColorSpace, CubeTextureCreate2dParams};
 use crate::{model, texture};
 
 fn get_path_for_file(file_name: &str) -> PathBuf {
@@ -26,17 +29,124 @@ pub async fn load_texture(
     file_name: &str,
     device: &wgpu::Device,
     queue: &wgpu::Queue,
-    is_normal_map: bool,
+    color_space: ColorSpace,
 ) -> RendererResult<texture::Texture> {
     let data = load_binary(file_name).await?;
-    texture::Texture::from_bytes(device, queue, &data, file_name, is_normal_map)
+    texture::Texture::from_bytes(device, queue, &data, file_name, color_space)
 }
 
+/// A 1x1 white texture for materials that don't provide a map, so that
+/// multiplying by it changes nothing
+fn white_texture(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    label: &str,
+) -> RendererResult<texture::Texture> {
+    solid_texture(device, queue, label, [255; 4])
+}
+
+fn solid_texture(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    label: &str,
+    color: [u8; 4],
+) -> RendererResult<texture::Texture> {
+    let image = image::RgbaImage::from_pixel(1, 1, image::Rgba(color));
+    texture::Texture::from_image(
+        device,
+        queue,
+        &image::DynamicImage::ImageRgba8(image),
+        label,
+        ColorSpace::Linear,
+    )
+}
+
+/// Loads `file_name` as a single channel image, if there is one
+async fn load_luma(file_name: Option<&str>) -> RendererResult<Option<image::GrayImage>> {
+    match file_name {
+        Some(file_name) => {
+            let bytes = load_binary(file_name).await?;
+            Ok(Some(image::load_from_memory(&bytes)?.to_luma8()))
+        }
+        None => Ok(None),
+    }
+}
+
+/// Packs separate metallic and roughness maps into one texture the way
+/// glTF does, roughness in green and metallic in blue. A missing map is
+/// left white so only its factor applies.
+async fn load_metallic_roughness_texture(
+    metallic_file: Option<&str>,
+    roughness_file: Option<&str>,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+) -> RendererResult<texture::Texture> {
+    let metallic = load_luma(metallic_file).await?;
+    let roughness = load_luma(roughness_file).await?;
+    let (width, height) = match (&metallic, &roughness) {
+        (Some(map), _) | (None, Some(map)) => map.dimensions(),
+        (None, None) => return white_texture(device, queue, "metallic_roughness"),
+    };
+    // Both maps are sampled at the same coordinates, so they need to match
+    let resized = |map: Option<image::GrayImage>| {
+        map.map(|map| {
+            if map.dimensions() == (width, height) {
+                map
+            } else {
+                image::imageops::resize(&map, width, height, image::imageops::FilterType::Triangle)
+            }
+        })
+    };
+    let metallic = resized(metallic);
+    let roughness = resized(roughness);
+    let packed = image::RgbaImage::from_fn(width, height, |x, y| {
+        let value =
+            |map: &Option<image::GrayImage>| map.as_ref().map_or(255, |m| m.get_pixel(x, y)[0]);
+        image::Rgba([0, value(&roughness), value(&metallic), 255])
+    });
+    let label = metallic_file
+        .or(roughness_file)
+        .unwrap_or("metallic_roughness");
+    texture::Texture::from_image(
+        device,
+        queue,
+        &image::DynamicImage::ImageRgba8(packed),
+        label,
+        ColorSpace::Linear,
+    )
+}
+
+/// Plain white material for meshes without a usable one
+fn default_material(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    layout: &wgpu::BindGroupLayout,
+) -> RendererResult<model::Material> {
+    Ok(model::Material::new(
+        device,
+        "default",
+        model::MaterialTextures {
+            diffuse: white_texture(device, queue, "default_diffuse")?,
+            normal: solid_texture(device, queue, "default_normal", [128, 128, 255, 255])?,
+            metallic_roughness: white_texture(device, queue, "default_metallic_roughness")?,
+            ambient_occlusion: white_texture(device, queue, "default_ambient_occlusion")?,
+        },
+        model::MaterialFactors::default(),
+        layout,
+    ))
+}
+
+/// Loads an OBJ model and its materials. `progress` is called with the
+/// number of meshes processed so far and the total after each mesh. Setting
+/// `cancel` stops the load before the next material or mesh with
+/// `RendererError::Cancelled`.
 pub async fn load_model(
     file_name: &str,
     device: &wgpu::Device,
     queue: &wgpu::Queue,
     layout: &wgpu::BindGroupLayout,
+    mut progress: impl FnMut(usize, usize),
+    cancel: &AtomicBool,
 ) -> RendererResult<model::Model> {
     let obj_text = load_string(file_name).await?;
     let obj_cursor = Cursor::new(obj_text);
@@ -57,34 +167,95 @@ pub async fn load_model(
     .await?;
 
     let mut materials = vec![];
+    // Materials that fail to load are skipped, so the OBJ's material indices
+    // are mapped to the ones that made it into `materials`
+    let mut material_indices = vec![];
     for m in obj_materials? {
+        if cancel.load(Ordering::Relaxed) {
+            return Err(RendererError::Cancelled);
+        }
+        material_indices.push(None);
         let diffuse_texture = match m.diffuse_texture {
-            Some(tex_file_name) => load_texture(&tex_file_name, device, queue, false).await?,
+            Some(tex_file_name) => {
+                load_texture(&tex_file_name, device, queue, ColorSpace::Srgb).await?
+            }
             None => {
                 eprintln!("Error: material {} has no file!", m.name);
                 continue;
             }
         };
         let normal_texture = match m.normal_texture {
-            Some(tex_file_name) => load_texture(&tex_file_name, device, queue, true).await?,
+            Some(tex_file_name) => {
+                load_texture(&tex_file_name, device, queue, ColorSpace::Linear).await?
+            }
             None => {
                 eprintln!("Error: material {} has no file!", m.name);
                 continue;
             }
         };
 
-        materials.push(model::Material::new(
+        // PBR extension to MTL: Pm/Pr are the metallic and roughness factors,
+        // map_Pm/map_Pr their maps. The ambient map is used for occlusion.
+        // alpha_cutoff makes the material masked, like glTF's alphaCutoff.
+        let param = |key: &str| m.unknown_param.get(key).map(String::as_str);
+        let metallic_roughness_texture =
+            load_metallic_roughness_texture(param("map_Pm"), param("map_Pr"), device, queue)
+                .await?;
+        let ambient_occlusion_texture = match &m.ambient_texture {
+            Some(tex_file_name) => {
+                load_texture(tex_file_name, device, queue, ColorSpace::Linear).await?
+            }
+            None => white_texture(device, queue, "ambient_occlusion")?,
+        };
+        let defaults = model::MaterialFactors::default();
+        let factor = |key: &str, default: f32| {
+            param(key)
+                .and_then(|value| value.trim().parse().ok())
+                .unwrap_or(default)
+        };
+        // A map on its own is used as is, rather than scaled by the default
+        let map_default = |map: &str, default: f32| match param(map) {
+            Some(_) => 1.0,
+            None => default,
+        };
+        let factors = model::MaterialFactors::new(
+            factor("Pm", map_default("map_Pm", defaults.metallic)),
+            factor("Pr", map_default("map_Pr", defaults.roughness)),
+            factor("alpha_cutoff", defaults.alpha_cutoff),
+        );
+
+        let mut material = model::Material::new(
             device,
             &m.name,
-            diffuse_texture,
-            normal_texture,
+            model::MaterialTextures {
+                diffuse: diffuse_texture,
+                normal: normal_texture,
+                metallic_roughness: metallic_roughness_texture,
+                ambient_occlusion: ambient_occlusion_texture,
+            },
+            factors,
             layout,
-        ));
+        );
+        material.transparent = m.dissolve.is_some_and(|d| d < 1.0);
+        if let Some(index) = material_indices.last_mut() {
+            *index = Some(materials.len());
+        }
+        materials.push(material);
+    }
+    if materials.is_empty() && !models.is_empty() {
+        warn!("{} has no usable materials, using a plain one", file_name);
+        materials.push(default_material(device, queue, layout)?);
     }
 
+    let mesh_count = models.len();
+    let mut bounds = model::Aabb::empty();
     let meshes = models
         .into_iter()
-        .map(|m| {
+        .enumerate()
+        .map(|(i, m)| {
+            if cancel.load(Ordering::Relaxed) {
+                return Err(RendererError::Cancelled);
+            }
             let mut vertices = (0..(m.mesh.positions.len() / 3))
                 .map(|i| model::ModelVertex {
                     position: [
@@ -102,6 +273,9 @@ pub async fn load_model(
                     bitangent: [0.0; 3],
                 })
                 .collect::<Vec<_>>();
+            for vertex in &vertices {
+                bounds.add_point(vertex.position.into());
+            }
 
             let indices = &m.mesh.indices;
             let mut triangles_included = vec![0; vertices.len()];
@@ -153,38 +327,72 @@ pub async fn load_model(
                 v.bitangent = (cgmath::Vector3::from(v.bitangent) * denom).into();
             }
 
+            let center = vertices
+                .iter()
+                .fold(cgmath::Vector3::new(0.0, 0.0, 0.0), |acc, v| {
+                    acc + cgmath::Vector3::from(v.position)
+                })
+                / (vertices.len().max(1) as f32);
+
             let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
                 label: Some(&format!("{} Vertex Buffer", file_name)),
                 contents: bytemuck::cast_slice(&vertices),
-                usage: wgpu::BufferUsages::VERTEX,
+                // Also read by the debug primitive overlay
+                usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::STORAGE,
             });
 
             let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
                 label: Some(&format!("{} Index Buffer", file_name)),
                 contents: bytemuck::cast_slice(&m.mesh.indices),
-                usage: wgpu::BufferUsages::INDEX,
+                usage: wgpu::BufferUsages::INDEX | wgpu::BufferUsages::STORAGE,
             });
 
-            model::Mesh {
+            let mesh = model::Mesh {
                 name: file_name.to_string(),
                 vertex_buffer,
                 index_buffer,
                 number_of_elements: m.mesh.indices.len() as u32,
-                material: m.mesh.material_id.unwrap_or(0),
-            }
+                material: match m
+                    .mesh
+                    .material_id
+                    .and_then(|id| material_indices.get(id).copied().flatten())
+                {
+                    Some(index) => index,
+                    None => {
+                        warn!(
+                            "Mesh {} in {} has no usable material ({:?}), using material 0",
+                            m.name, file_name, m.mesh.material_id
+                        );
+                        0
+                    }
+                },
+                center: cgmath::Point3::from_vec(center),
+            };
+            progress(i + 1, mesh_count);
+            Ok(mesh)
         })
-        .collect::<Vec<_>>();
+        .collect::<RendererResult<Vec<_>>>()?;
 
     Ok(model::Model {
         meshes,
         materials,
         instances: Default::default(),
+        instances_visible: Default::default(),
         instance_buffer: device.create_buffer(&wgpu::BufferDescriptor {
             label: Some("Instance Buffer"),
             size: 0,
             usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
             mapped_at_creation: false,
         }),
+        bounds,
+        visible_instance_buffer: device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Visible Instance Buffer"),
+            size: 0,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        }),
+        visible_instance_count: 0,
+        visible_instance_indices: Vec::new(),
     })
 }
 
@@ -259,15 +467,13 @@ impl HdrLoader {
         let hdr_decoder = HdrDecoder::new(Cursor::new(data))?;
         let meta = hdr_decoder.metadata();
         let mut pixels = vec![[0.0, 0.0, 0.0, 0.0]; meta.width as usize * meta.height as usize];
-        hdr_decoder
-            .read_image_transform(
-                |pix| {
-                    let rgb = pix.to_hdr();
-                    [rgb.0[0], rgb.0[1], rgb.0[2], 1.0f32]
-                },
-                &mut pixels[..],
-            )
-            .expect("Could not decode hdr image!");
+        hdr_decoder.read_image_transform(
+            |pix| {
+                let rgb = pix.to_hdr();
+                [rgb.0[0], rgb.0[1], rgb.0[2], 1.0f32]
+            },
+            &mut pixels[..],
+        )?;
 
         let src = texture::Texture::create_2d_texture(
             device,
@@ -302,7 +508,9 @@ impl HdrLoader {
                 height: dst_size,
                 format: self.texture_format,
                 mip_level_count: 1,
-                usage: wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::TEXTURE_BINDING,
+                usage: wgpu::TextureUsages::STORAGE_BINDING
+                    | wgpu::TextureUsages::TEXTURE_BINDING
+                    | wgpu::TextureUsages::COPY_SRC,
                 mag_filter: wgpu::FilterMode::Nearest,
                 label,
             };
@@ -337,7 +545,7 @@ impl HdrLoader {
                 ..Default::default()
             });
 
-            let num_workgroups = (dst_size + 15) / 16;
+            let num_workgroups = dst_size.div_ceil(16);
             pass.set_pipeline(&self.equirect_to_cubemap);
             pass.set_bind_group(0, &bind_group, &[]);
             pass.dispatch_workgroups(num_workgroups, num_workgroups, 6);
@@ -347,4 +555,70 @@ impl HdrLoader {
 
         Ok(dst)
     }
+
+    /// A plain sky fading from `top` overhead to `bottom` underfoot, for when
+    /// no environment image can be loaded
+    pub fn gradient_cube(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        top: [f32; 3],
+        bottom: [f32; 3],
+        label: Option<&str>,
+    ) -> texture::CubeTexture {
+        const SIZE: u32 = 64;
+        let cube = texture::CubeTexture::create_2d(CubeTextureCreate2dParams {
+            device,
+            width: SIZE,
+            height: SIZE,
+            format: self.texture_format,
+            mip_level_count: 1,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::COPY_DST
+                | wgpu::TextureUsages::COPY_SRC,
+            // Sampled through a non-filtering sampler binding, like the
+            // converted environment maps
+            mag_filter: wgpu::FilterMode::Nearest,
+            label,
+        });
+
+        let lerp = |t: f32| {
+            let [r, g, b] = std::array::from_fn(|i| top[i] + (bottom[i] - top[i]) * t);
+            [r, g, b, 1.0f32]
+        };
+        // Faces are ordered +X, -X, +Y, -Y, +Z, -Z, with rows running from
+        // top to bottom on the side faces
+        let mut pixels = Vec::with_capacity((SIZE * SIZE * 6) as usize);
+        for face in 0..6 {
+            for row in 0..SIZE {
+                let color = match face {
+                    2 => lerp(0.0),
+                    3 => lerp(1.0),
+                    _ => lerp(row as f32 / (SIZE - 1) as f32),
+                };
+                pixels.extend(std::iter::repeat_n(color, SIZE as usize));
+            }
+        }
+
+        queue.write_texture(
+            wgpu::ImageCopyTexture {
+                texture: cube.texture(),
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+                aspect: wgpu::TextureAspect::All,
+            },
+            bytemuck::cast_slice(&pixels),
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(SIZE * std::mem::size_of::<[f32; 4]>() as u32),
+                rows_per_image: Some(SIZE),
+            },
+            wgpu::Extent3d {
+                width: SIZE,
+                height: SIZE,
+                depth_or_array_layers: 6,
+            },
+        );
+        cube
+    }
 }
@@ -3,13 +3,55 @@ use std::{
     sync::{Arc, Mutex},
 };
 
-use cgmath::SquareMatrix;
+use cgmath::{Deg, Euler, InnerSpace, Matrix3, Quaternion, SquareMatrix, Vector3};
 use log::warn;
 
 use crate::model::{InstanceId, Model};
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct NodeHandle(usize);
 
+/// A transform split into translation, rotation and scale for editing
+#[derive(Clone, Copy, Debug)]
+pub struct TransformComponents {
+    pub translation: Vector3<f32>,
+    pub rotation: Euler<Deg<f32>>,
+    pub scale: Vector3<f32>,
+}
+
+impl TransformComponents {
+    /// Decomposes a matrix built as translation * rotation * scale. Any shear
+    /// is lost.
+    pub fn from_matrix(matrix: cgmath::Matrix4<f32>) -> Self {
+        let translation = matrix.w.truncate();
+        let x = matrix.x.truncate();
+        let y = matrix.y.truncate();
+        let z = matrix.z.truncate();
+        let mut scale = Vector3::new(x.magnitude(), y.magnitude(), z.magnitude());
+        // A negative determinant means the transform mirrors
+        if Matrix3::from_cols(x, y, z).determinant() < 0.0 {
+            scale.x = -scale.x;
+        }
+        let rotation = if scale.x == 0.0 || scale.y == 0.0 || scale.z == 0.0 {
+            Quaternion::new(1.0, 0.0, 0.0, 0.0)
+        } else {
+            Quaternion::from(Matrix3::from_cols(x / scale.x, y / scale.y, z / scale.z))
+        };
+        let rotation = Euler::from(rotation);
+        Self {
+            translation,
+            rotation: Euler::new(rotation.x.into(), rotation.y.into(), rotation.z.into()),
+            scale,
+        }
+    }
+
+    pub fn to_matrix(&self) -> cgmath::Matrix4<f32> {
+        cgmath::Matrix4::from_translation(self.translation)
+            * cgmath::Matrix4::from(Quaternion::from(self.rotation))
+            * cgmath::Matrix4::from_nonuniform_scale(self.scale.x, self.scale.y, self.scale.z)
+    }
+}
+
 pub struct SceneNode {
     local_transform: cgmath::Matrix4<f32>,
     cached_transform: cgmath::Matrix4<f32>,
@@ -18,6 +60,9 @@ pub struct SceneNode {
     children: Vec<NodeHandle>,
     model: Option<Arc<Mutex<Model>>>,
     instance_id: Option<InstanceId>,
+    tint: [f32; 4],
+    visible: bool,
+    pick_id: u32,
 }
 
 impl Default for SceneNode {
@@ -30,6 +75,9 @@ impl Default for SceneNode {
             children: Default::default(),
             model: Default::default(),
             instance_id: Default::default(),
+            tint: [1.0; 4],
+            visible: true,
+            pick_id: 0,
         }
     }
 }
@@ -55,6 +103,40 @@ impl SceneNode {
         }
     }
 
+    pub fn get_tint(&self) -> [f32; 4] {
+        self.tint
+    }
+
+    pub fn set_tint(&mut self, tint: [f32; 4]) {
+        self.tint = tint;
+        if let (Some(model), Some(id)) = (&self.model, &self.instance_id) {
+            model
+                .lock()
+                .expect("Poisoned Mutex")
+                .update_instance_tint(id, tint);
+        }
+    }
+
+    pub fn is_visible(&self) -> bool {
+        self.visible
+    }
+
+    /// Hides or shows this node's instance, keeping it in the scene
+    pub fn set_visible(&mut self, visible: bool) {
+        self.visible = visible;
+        if let (Some(model), Some(id)) = (&self.model, &self.instance_id) {
+            model
+                .lock()
+                .expect("Poisoned Mutex")
+                .update_instance_visibility(id, visible);
+        }
+    }
+
+    /// Index of this node's instance in its model's instance buffer
+    pub fn instance_index(&self) -> Option<u32> {
+        self.instance_id.as_ref().map(InstanceId::index)
+    }
+
     pub fn set_model(&mut self, model: Arc<Mutex<Model>>) {
         if let Some(_old_model) = self.model.take() {
             panic!("Cannot handle removing an old model yet!");
@@ -63,6 +145,9 @@ impl SceneNode {
             let mut guard = model.lock().expect("Poisoned Mutex");
             let id = guard.new_instance();
             guard.update_instance(&id, self.cached_transform);
+            guard.update_instance_tint(&id, self.tint);
+            guard.update_instance_visibility(&id, self.visible);
+            guard.update_instance_pick_id(&id, self.pick_id);
             self.instance_id = Some(id);
         }
         self.model = Some(model);
@@ -100,11 +185,38 @@ impl SceneTree {
 
     pub fn new_node(&mut self) -> NodeHandle {
         let node_index = self.nodes.len();
-        let new_node = RefCell::new(SceneNode::default());
+        let new_node = RefCell::new(SceneNode {
+            // 0 is reserved for "nothing picked"
+            pick_id: node_index as u32 + 1,
+            ..Default::default()
+        });
         self.nodes.push(new_node);
         NodeHandle(node_index)
     }
 
+    /// Gives every node that has an instance a new one in `model`, keeping
+    /// its transform, tint and visibility. `model` must have no instances,
+    /// the old model's instance ids are forgotten.
+    pub fn move_to_model(&mut self, model: &Arc<Mutex<Model>>) {
+        for node in &self.nodes {
+            let mut node = node.borrow_mut();
+            if node.model.take().is_some() {
+                node.instance_id = None;
+                node.set_model(model.clone());
+            }
+        }
+    }
+
+    /// Every node in the tree, in the order they were created
+    pub fn handles(&self) -> impl Iterator<Item = NodeHandle> {
+        (0..self.nodes.len()).map(NodeHandle)
+    }
+
+    pub fn node_from_pick_id(&self, pick_id: u32) -> Option<NodeHandle> {
+        let index = (pick_id as usize).checked_sub(1)?;
+        (index < self.nodes.len()).then_some(NodeHandle(index))
+    }
+
     pub fn update_transforms(&mut self) {
         let mut to_update: Vec<_> = self
             .nodes
@@ -143,6 +255,8 @@ impl SceneTree {
                 } else {
                     warn!("Node {} is it's own parent!", index);
                 }
+            } else {
+                node.refresh_transform(cgmath::Matrix4::identity());
             }
             for child_index in &node.children {
                 to_update.push(child_index.0)
@@ -1,13 +1,50 @@
+use std::{borrow::Cow, path::Path};
+
 use log::{error, info, warn};
 
-use wgpu::naga::front::{glsl, wgsl};
+use wgpu::naga::front::{glsl, spv, wgsl};
 
-use crate::error::{RendererError, RendererResult};
+use crate::{
+    error::{RendererError, RendererResult},
+    pipeline::lock_error_scopes,
+    preprocessor,
+};
 
+/// Source of one of the viewer's own shaders in `shaders/`. Debug builds
+/// read the file at runtime so it can be edited without a rebuild, using
+/// `embedded` if it can't be read. Release builds always use `embedded`.
+pub fn builtin_source(file_name: &str, embedded: &'static str) -> Cow<'static, str> {
+    if cfg!(debug_assertions) {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("shaders")
+            .join(file_name);
+        match std::fs::read_to_string(&path) {
+            Ok(source) => {
+                info!(
+                    "Loaded built-in shader {} from {}",
+                    file_name,
+                    path.display()
+                );
+                return Cow::Owned(source);
+            }
+            Err(e) => warn!(
+                "Unable to read built-in shader {} ({}), using the embedded copy",
+                path.display(),
+                e
+            ),
+        }
+    } else {
+        info!("Using embedded built-in shader {}", file_name);
+    }
+    Cow::Borrowed(embedded)
+}
+
+/// Finds the vertex and fragment entry points. The vertex entry point is
+/// `None` for fragment only shaders, which get a fullscreen triangle instead.
 fn get_entry_points(
     name: &str,
     modules: &[&wgpu::naga::Module],
-) -> RendererResult<(String, String)> {
+) -> RendererResult<(Option<String>, String)> {
     let mut vertex_entry_point: Option<String> = None;
     let mut fragment_entry_point: Option<String> = None;
     for module in modules {
@@ -39,11 +76,12 @@ fn get_entry_points(
                 "Shader has no entry points!".into(),
             ))
         }
-        (None, Some(_)) => {
-            error!("Shader {} has no vertex entry point!", name);
-            Err(RendererError::ShaderCompile(
-                "Shader has no vertex entry point!".into(),
-            ))
+        (None, Some(s)) => {
+            info!(
+                "Shader {} has no vertex entry point, drawing it over the whole screen",
+                name
+            );
+            Ok((None, s))
         }
         (Some(_), None) => {
             error!("Shader {} has no fragment entry point!", name);
@@ -51,35 +89,99 @@ fn get_entry_points(
                 "Shader has no fragment entry point!".into(),
             ))
         }
-        (Some(v), Some(s)) => Ok((v, s)),
+        (Some(v), Some(s)) => Ok((Some(v), s)),
+    }
+}
+
+/// Finds the compute entry point and its workgroup size
+fn get_compute_entry_point(
+    name: &str,
+    module: &wgpu::naga::Module,
+) -> RendererResult<(String, [u32; 3])> {
+    let mut entry_points = module
+        .entry_points
+        .iter()
+        .filter(|entry_point| entry_point.stage == wgpu::naga::ShaderStage::Compute);
+    let Some(entry_point) = entry_points.next() else {
+        error!("Shader {} has no compute entry point!", name);
+        return Err(RendererError::ShaderCompile(
+            "Shader has no compute entry point!".into(),
+        ));
+    };
+    if entry_points.next().is_some() {
+        warn!("Shader {} has more than one compute entry point!", name);
     }
+    Ok((entry_point.name.clone(), entry_point.workgroup_size))
 }
 
-#[derive(Debug, Default, PartialEq, Eq)]
-struct OwningBindGroupLayoutDescriptor {
+/// A bind group layout found by reflection, owning its entries
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct OwningBindGroupLayoutDescriptor {
     label: Option<String>,
     entries: Vec<wgpu::BindGroupLayoutEntry>,
+    /// Name of the global bound by each entry in `entries`
+    names: Vec<Option<String>>,
+}
+
+/// A binding declared by a shader, as found by reflection
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReflectedBinding {
+    pub group: u32,
+    pub binding: u32,
+    /// Name of the global variable, if it has one
+    pub name: Option<String>,
+    pub visibility: wgpu::ShaderStages,
+    pub ty: wgpu::BindingType,
+}
+
+fn get_layout_descs(
+    layout: &[OwningBindGroupLayoutDescriptor],
+) -> Vec<wgpu::BindGroupLayoutDescriptor> {
+    layout
+        .iter()
+        .map(|d| wgpu::BindGroupLayoutDescriptor {
+            label: d.label.as_deref(),
+            entries: &d.entries,
+        })
+        .collect()
+}
+
+fn get_reflected_bindings(layout: &[OwningBindGroupLayoutDescriptor]) -> Vec<ReflectedBinding> {
+    layout
+        .iter()
+        .enumerate()
+        .flat_map(|(group, d)| {
+            d.entries
+                .iter()
+                .zip(&d.names)
+                .map(move |(entry, name)| ReflectedBinding {
+                    group: group as u32,
+                    binding: entry.binding,
+                    name: name.clone(),
+                    visibility: entry.visibility,
+                    ty: entry.ty,
+                })
+        })
+        .collect()
 }
 
 impl OwningBindGroupLayoutDescriptor {
     fn check_compatible(&self, other: &wgpu::BindGroupLayoutDescriptor) -> Result<(), Vec<String>> {
         let mut errors = vec![];
         let name = self.label.as_deref().unwrap_or("<No Name>");
-        if self.entries.len() != other.entries.len() {
-            errors.push(format!("{}: Not enough bindings!", name))
-        }
-        let mut my_entries_sorted = self.entries.clone();
-        my_entries_sorted.sort_by(|ent1, ent2| ent1.binding.cmp(&ent2.binding));
-        let mut other_entries_sorted = other.entries.to_owned();
-        other_entries_sorted.sort_by(|ent1, ent2| ent1.binding.cmp(&ent2.binding));
-        for (entry1, entry2) in my_entries_sorted.iter().zip(other_entries_sorted) {
-            if entry1.binding != entry2.binding {
+        // The pipeline is allowed to provide bindings that the shader doesn't use
+        for entry1 in &self.entries {
+            let Some(entry2) = other
+                .entries
+                .iter()
+                .find(|entry2| entry2.binding == entry1.binding)
+            else {
                 errors.push(format!(
-                    "{}: Binding numbers don't match: {} and {}",
-                    name, entry1.binding, entry2.binding
+                    "{}: Binding {} is not provided by the pipeline",
+                    name, entry1.binding
                 ));
                 continue;
-            }
+            };
             // TODO figure out how to do this
             if !entry2.visibility.intersects(entry1.visibility) {
                 errors.push(format!(
@@ -91,6 +193,30 @@ impl OwningBindGroupLayoutDescriptor {
             if entry1.ty != entry2.ty {
                 use wgpu::{BindingType, SamplerBindingType, TextureSampleType};
                 match (entry1.ty, entry2.ty) {
+                    // A layout without a size leaves the check to wgpu when
+                    // the bind group is used
+                    (
+                        BindingType::Buffer {
+                            ty: ty1,
+                            has_dynamic_offset: dynamic1,
+                            min_binding_size: required,
+                        },
+                        BindingType::Buffer {
+                            ty: ty2,
+                            has_dynamic_offset: dynamic2,
+                            min_binding_size: provided,
+                        },
+                    ) if ty1 == ty2 && dynamic1 == dynamic2 => {
+                        if let (Some(required), Some(provided)) = (required, provided) {
+                            if required > provided {
+                                errors.push(format!(
+                                    "{}: Binding {} needs a buffer of at least {} bytes, but the pipeline provides {} bytes",
+                                    name, entry1.binding, required, provided
+                                ));
+                                continue;
+                            }
+                        }
+                    }
                     (
                         BindingType::Texture {
                             sample_type: TextureSampleType::Float { filterable: true },
@@ -105,6 +231,22 @@ impl OwningBindGroupLayoutDescriptor {
                         BindingType::Sampler(SamplerBindingType::Filtering),
                         BindingType::Sampler(SamplerBindingType::NonFiltering),
                     ) => (),
+                    // `sampler_comparison` can only be bound to a comparison
+                    // sampler, and a comparison sampler to nothing else
+                    (
+                        BindingType::Sampler(SamplerBindingType::Comparison),
+                        BindingType::Sampler(_),
+                    )
+                    | (
+                        BindingType::Sampler(_),
+                        BindingType::Sampler(SamplerBindingType::Comparison),
+                    ) => {
+                        errors.push(format!(
+                            "{}: Binding {} is a comparison sampler in only one of the layouts: {:?} and {:?}",
+                            name, entry1.binding, entry1.ty, entry2.ty
+                        ));
+                        continue;
+                    }
                     _ => {
                         errors.push(format!(
                             "{}: Binding types don't match: {:?} and {:?}",
@@ -138,26 +280,117 @@ impl OwningBindGroupLayoutDescriptor {
     }
 }
 
-fn naga_type_to_binding_group_type(ty: &wgpu::naga::Type) -> wgpu::BindingType {
-    use wgpu::naga::TypeInner;
-    match ty.inner {
+/// The texture format of a storage texture declared with `format`. Formats
+/// that can't be bound as storage textures without device features the
+/// viewer doesn't request are a compile error.
+fn naga_storage_format_to_texture_format(
+    format: wgpu::naga::StorageFormat,
+) -> RendererResult<wgpu::TextureFormat> {
+    use wgpu::naga::StorageFormat;
+    use wgpu::TextureFormat;
+    let texture_format = match format {
+        StorageFormat::R8Unorm => TextureFormat::R8Unorm,
+        StorageFormat::R8Snorm => TextureFormat::R8Snorm,
+        StorageFormat::R8Uint => TextureFormat::R8Uint,
+        StorageFormat::R8Sint => TextureFormat::R8Sint,
+        StorageFormat::R16Uint => TextureFormat::R16Uint,
+        StorageFormat::R16Sint => TextureFormat::R16Sint,
+        StorageFormat::R16Float => TextureFormat::R16Float,
+        StorageFormat::Rg8Unorm => TextureFormat::Rg8Unorm,
+        StorageFormat::Rg8Snorm => TextureFormat::Rg8Snorm,
+        StorageFormat::Rg8Uint => TextureFormat::Rg8Uint,
+        StorageFormat::Rg8Sint => TextureFormat::Rg8Sint,
+        StorageFormat::R32Uint => TextureFormat::R32Uint,
+        StorageFormat::R32Sint => TextureFormat::R32Sint,
+        StorageFormat::R32Float => TextureFormat::R32Float,
+        StorageFormat::Rg16Uint => TextureFormat::Rg16Uint,
+        StorageFormat::Rg16Sint => TextureFormat::Rg16Sint,
+        StorageFormat::Rg16Float => TextureFormat::Rg16Float,
+        StorageFormat::Rgba8Unorm => TextureFormat::Rgba8Unorm,
+        StorageFormat::Rgba8Snorm => TextureFormat::Rgba8Snorm,
+        StorageFormat::Rgba8Uint => TextureFormat::Rgba8Uint,
+        StorageFormat::Rgba8Sint => TextureFormat::Rgba8Sint,
+        StorageFormat::Bgra8Unorm => TextureFormat::Bgra8Unorm,
+        StorageFormat::Rgb10a2Uint => TextureFormat::Rgb10a2Uint,
+        StorageFormat::Rgb10a2Unorm => TextureFormat::Rgb10a2Unorm,
+        StorageFormat::Rg11b10Float => TextureFormat::Rg11b10Float,
+        StorageFormat::Rg32Uint => TextureFormat::Rg32Uint,
+        StorageFormat::Rg32Sint => TextureFormat::Rg32Sint,
+        StorageFormat::Rg32Float => TextureFormat::Rg32Float,
+        StorageFormat::Rgba16Uint => TextureFormat::Rgba16Uint,
+        StorageFormat::Rgba16Sint => TextureFormat::Rgba16Sint,
+        StorageFormat::Rgba16Float => TextureFormat::Rgba16Float,
+        StorageFormat::Rgba32Uint => TextureFormat::Rgba32Uint,
+        StorageFormat::Rgba32Sint => TextureFormat::Rgba32Sint,
+        StorageFormat::Rgba32Float => TextureFormat::Rgba32Float,
+        StorageFormat::R16Unorm => TextureFormat::R16Unorm,
+        StorageFormat::R16Snorm => TextureFormat::R16Snorm,
+        StorageFormat::Rg16Unorm => TextureFormat::Rg16Unorm,
+        StorageFormat::Rg16Snorm => TextureFormat::Rg16Snorm,
+        StorageFormat::Rgba16Unorm => TextureFormat::Rgba16Unorm,
+        StorageFormat::Rgba16Snorm => TextureFormat::Rgba16Snorm,
+    };
+    // None of the features the device is requested with add storage formats
+    if !texture_format
+        .guaranteed_format_features(wgpu::Features::empty())
+        .allowed_usages
+        .contains(wgpu::TextureUsages::STORAGE_BINDING)
+    {
+        return Err(RendererError::ShaderCompile(format!(
+            "Storage textures can't have the format {:?}",
+            format
+        )));
+    }
+    Ok(texture_format)
+}
+
+/// `min_binding_size` of a buffer holding a `T`. Layouts the renderer
+/// provides set it so that `layout_matches` reports shaders expecting a
+/// bigger struct, instead of wgpu failing when the pipeline is created.
+pub const fn buffer_binding_size<T>() -> Option<wgpu::BufferSize> {
+    wgpu::BufferSize::new(std::mem::size_of::<T>() as u64)
+}
+
+/// The binding type a global of type `ty` needs. Buffers get the size of
+/// `ty` as their `min_binding_size`, counting one element of a runtime sized
+/// array. Fails for storage textures of a format that can't be bound.
+fn naga_type_to_binding_group_type(
+    ty: &wgpu::naga::Type,
+    space: wgpu::naga::AddressSpace,
+    gctx: wgpu::naga::proc::GlobalCtx,
+) -> RendererResult<wgpu::BindingType> {
+    use wgpu::naga::{AddressSpace, StorageAccess, TypeInner};
+    let binding_type = match ty.inner {
         TypeInner::Scalar(_)
         | TypeInner::Vector { .. }
         | TypeInner::Matrix { .. }
         | TypeInner::Atomic(_)
         | TypeInner::Array { .. }
         | TypeInner::Struct { .. } => wgpu::BindingType::Buffer {
-            ty: wgpu::BufferBindingType::Uniform,
+            ty: match space {
+                AddressSpace::Storage { access } => wgpu::BufferBindingType::Storage {
+                    read_only: !access.contains(StorageAccess::STORE),
+                },
+                _ => wgpu::BufferBindingType::Uniform,
+            },
             has_dynamic_offset: false,
-            min_binding_size: None,
+            min_binding_size: wgpu::BufferSize::new(ty.inner.size(gctx) as u64),
         },
         TypeInner::Image {
             dim,
-            arrayed: _,
+            arrayed,
             class,
         } => {
             use wgpu::naga::{ImageClass, ImageDimension, ScalarKind};
-            use wgpu::{TextureSampleType, TextureViewDimension};
+            use wgpu::{StorageTextureAccess, TextureSampleType, TextureViewDimension};
+            let view_dimension = match (dim, arrayed) {
+                (ImageDimension::D1, _) => TextureViewDimension::D1,
+                (ImageDimension::D2, false) => TextureViewDimension::D2,
+                (ImageDimension::D2, true) => TextureViewDimension::D2Array,
+                (ImageDimension::D3, _) => TextureViewDimension::D3,
+                (ImageDimension::Cube, false) => TextureViewDimension::Cube,
+                (ImageDimension::Cube, true) => TextureViewDimension::CubeArray,
+            };
             let (sample_type, multisampled) = match class {
                 ImageClass::Sampled { kind, multi } => match kind {
                     ScalarKind::Sint => (TextureSampleType::Sint, multi),
@@ -168,19 +401,21 @@ fn naga_type_to_binding_group_type(ty: &wgpu::naga::Type) -> wgpu::BindingType {
                     ScalarKind::AbstractFloat => unreachable!(),
                 },
                 ImageClass::Depth { multi } => (TextureSampleType::Depth, multi),
-                ImageClass::Storage {
-                    format: _,
-                    access: _,
-                } => {
-                    todo!("How to deal with storage images")
+                ImageClass::Storage { format, access } => {
+                    let access = if access.contains(StorageAccess::LOAD | StorageAccess::STORE) {
+                        StorageTextureAccess::ReadWrite
+                    } else if access.contains(StorageAccess::STORE) {
+                        StorageTextureAccess::WriteOnly
+                    } else {
+                        StorageTextureAccess::ReadOnly
+                    };
+                    return Ok(wgpu::BindingType::StorageTexture {
+                        access,
+                        format: naga_storage_format_to_texture_format(format)?,
+                        view_dimension,
+                    });
                 }
             };
-            let view_dimension = match dim {
-                ImageDimension::D1 => TextureViewDimension::D1,
-                ImageDimension::D2 => TextureViewDimension::D2,
-                ImageDimension::D3 => TextureViewDimension::D3,
-                ImageDimension::Cube => TextureViewDimension::Cube,
-            };
             wgpu::BindingType::Texture {
                 sample_type,
                 view_dimension,
@@ -195,12 +430,16 @@ fn naga_type_to_binding_group_type(ty: &wgpu::naga::Type) -> wgpu::BindingType {
             }
         }
         _ => todo!("Unimplemented Type for uniform: {:?}", ty.inner),
-    }
+    };
+    Ok(binding_type)
 }
 
+/// Reflects the bind group layouts used by `modules`. Groups have to be
+/// numbered from 0 without gaps, a skipped group is an error rather than an
+/// empty layout.
 fn get_binding_layout(
     modules: &[(&str, wgpu::ShaderStages, &wgpu::naga::Module)],
-) -> Vec<OwningBindGroupLayoutDescriptor> {
+) -> RendererResult<Vec<OwningBindGroupLayoutDescriptor>> {
     let mut layouts = Vec::new();
     for (name, shader_type, module) in modules {
         for (_handle, global) in module.global_variables.iter() {
@@ -234,10 +473,11 @@ fn get_binding_layout(
                     }
                     s.push_str(name);
                 }
+                entry.names.push(global.name.clone());
                 entry.entries.push(wgpu::BindGroupLayoutEntry {
                     binding: binding.binding,
                     visibility: *shader_type,
-                    ty: naga_type_to_binding_group_type(typ),
+                    ty: naga_type_to_binding_group_type(typ, global.space, module.to_ctx())?,
                     count: None,
                 })
             } else {
@@ -245,14 +485,376 @@ fn get_binding_layout(
             }
         }
     }
-    layouts
+    if let Some(unused) = layouts.iter().position(|layout| layout.entries.is_empty()) {
+        let name = modules.first().map_or("<No Name>", |(name, _, _)| *name);
+        let last = layouts.len() - 1;
+        error!(
+            "Shader {} doesn't use group {} but binds group {}!",
+            name, unused, last
+        );
+        return Err(RendererError::ShaderCompile(format!(
+            "Group {} is unused but group {} is bound, groups must be numbered from 0 without gaps",
+            unused, last
+        )));
+    }
+    Ok(layouts)
+}
+
+/// Type of a uniform struct member that can be edited from the UI
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UniformMemberType {
+    F32,
+    I32,
+    U32,
+    Vec2,
+    Vec3,
+    Vec4,
+}
+
+impl UniformMemberType {
+    fn from_naga(inner: &wgpu::naga::TypeInner) -> Option<Self> {
+        use wgpu::naga::{ScalarKind, TypeInner, VectorSize};
+        match *inner {
+            TypeInner::Scalar(scalar) if scalar.width == 4 => match scalar.kind {
+                ScalarKind::Float => Some(Self::F32),
+                ScalarKind::Sint => Some(Self::I32),
+                ScalarKind::Uint => Some(Self::U32),
+                _ => None,
+            },
+            TypeInner::Vector { size, scalar }
+                if scalar.kind == ScalarKind::Float && scalar.width == 4 =>
+            {
+                Some(match size {
+                    VectorSize::Bi => Self::Vec2,
+                    VectorSize::Tri => Self::Vec3,
+                    VectorSize::Quad => Self::Vec4,
+                })
+            }
+            _ => None,
+        }
+    }
+
+    /// Number of 4 byte components
+    pub fn components(self) -> usize {
+        match self {
+            Self::F32 | Self::I32 | Self::U32 => 1,
+            Self::Vec2 => 2,
+            Self::Vec3 => 3,
+            Self::Vec4 => 4,
+        }
+    }
+}
+
+/// A member of a uniform struct declared by a shader
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UniformMember {
+    pub name: String,
+    /// Byte offset in the struct
+    pub offset: u32,
+    pub ty: UniformMemberType,
+}
+
+impl UniformMember {
+    /// `vec3` and `vec4` members named like colors get a color picker
+    pub fn is_color(&self) -> bool {
+        let name = self.name.to_lowercase();
+        matches!(self.ty, UniformMemberType::Vec3 | UniformMemberType::Vec4)
+            && (name.contains("color") || name.contains("colour"))
+    }
+
+    /// `vec2` and `vec3` members named `resolution` or `iResolution` are set
+    /// to the size of the render target by the renderer
+    pub fn is_resolution(&self) -> bool {
+        let name = self.name.to_lowercase();
+        matches!(self.ty, UniformMemberType::Vec2 | UniformMemberType::Vec3)
+            && (name == "resolution" || name == "iresolution")
+    }
+
+    /// `f32` members named `time` or `iTime` are set to the seconds the
+    /// renderer has been running for
+    pub fn is_time(&self) -> bool {
+        let name = self.name.to_lowercase();
+        self.ty == UniformMemberType::F32 && (name == "time" || name == "itime")
+    }
+}
+
+/// A `var<uniform>` whose type is a struct. Only members of a type in
+/// `UniformMemberType` are listed, any others are left zeroed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UniformStruct {
+    pub group: u32,
+    pub binding: u32,
+    pub name: String,
+    /// Size of the struct in bytes
+    pub size: u32,
+    pub members: Vec<UniformMember>,
+}
+
+fn get_uniform_structs(modules: &[&wgpu::naga::Module]) -> Vec<UniformStruct> {
+    use wgpu::naga::{AddressSpace, TypeInner};
+    let mut uniforms: Vec<UniformStruct> = Vec::new();
+    for module in modules {
+        for (_handle, global) in module.global_variables.iter() {
+            let (AddressSpace::Uniform, Some(binding)) = (global.space, &global.binding) else {
+                continue;
+            };
+            let TypeInner::Struct { members, span } = &module.types[global.ty].inner else {
+                continue;
+            };
+            if uniforms
+                .iter()
+                .any(|u| u.group == binding.group && u.binding == binding.binding)
+            {
+                continue;
+            }
+            let members = members
+                .iter()
+                .filter_map(|member| {
+                    let ty = UniformMemberType::from_naga(&module.types[member.ty].inner);
+                    if ty.is_none() {
+                        info!(
+                            "Uniform member {} has a type that can't be edited, skipping",
+                            member.name.as_deref().unwrap_or("<Unnamed>")
+                        );
+                    }
+                    Some(UniformMember {
+                        name: member.name.clone().unwrap_or_default(),
+                        offset: member.offset,
+                        ty: ty?,
+                    })
+                })
+                .collect();
+            uniforms.push(UniformStruct {
+                group: binding.group,
+                binding: binding.binding,
+                name: global.name.clone().unwrap_or_default(),
+                size: *span,
+                members,
+            });
+        }
+    }
+    uniforms
+}
+
+/// A `@location` input of the vertex entry point, read from a vertex buffer
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VertexInput {
+    pub location: u32,
+    /// Name of the argument or struct member
+    pub name: String,
+}
+
+/// Lists the `@location` inputs of `entry_point`, including the members of
+/// struct arguments, in location order
+fn get_vertex_inputs(modules: &[&wgpu::naga::Module], entry_point: &str) -> Vec<VertexInput> {
+    use wgpu::naga::{Binding, ShaderStage, TypeInner};
+    let mut inputs = Vec::new();
+    for module in modules {
+        let Some(entry_point) = module
+            .entry_points
+            .iter()
+            .find(|ep| ep.stage == ShaderStage::Vertex && ep.name == entry_point)
+        else {
+            continue;
+        };
+        for argument in &entry_point.function.arguments {
+            let name = argument.name.clone().unwrap_or_default();
+            match (&argument.binding, &module.types[argument.ty].inner) {
+                (Some(Binding::Location { location, .. }), _) => inputs.push(VertexInput {
+                    location: *location,
+                    name,
+                }),
+                (None, TypeInner::Struct { members, .. }) => {
+                    inputs.extend(members.iter().filter_map(|member| {
+                        let Some(Binding::Location { location, .. }) = member.binding else {
+                            return None;
+                        };
+                        Some(VertexInput {
+                            location,
+                            name: format!("{}.{}", name, member.name.as_deref().unwrap_or("")),
+                        })
+                    }))
+                }
+                _ => (),
+            }
+        }
+    }
+    inputs.sort_by_key(|input| input.location);
+    inputs
+}
+
+/// What reflection finds in a shader. Building it is CPU only naga work, so
+/// it doesn't need a device.
+#[derive(Debug, Clone)]
+pub struct ReflectionInfo {
+    /// `None` for fragment only shaders
+    pub vertex_entry_point: Option<String>,
+    pub fragment_entry_point: String,
+    /// Bind group layouts, indexed by group
+    pub layout: Vec<OwningBindGroupLayoutDescriptor>,
+    pub uniforms: Vec<UniformStruct>,
+    /// Empty for fragment only shaders
+    pub vertex_inputs: Vec<VertexInput>,
+}
+
+impl ReflectionInfo {
+    fn from_modules(
+        name: &str,
+        modules: &[(&str, wgpu::ShaderStages, &wgpu::naga::Module)],
+    ) -> RendererResult<Self> {
+        let naga_modules: Vec<_> = modules.iter().map(|(_, _, module)| *module).collect();
+        let (vertex_entry_point, fragment_entry_point) = get_entry_points(name, &naga_modules)?;
+        let layout = get_binding_layout(modules)?;
+        info!("Layout for shader {}: {:?}", name, layout);
+        let vertex_inputs = vertex_entry_point
+            .as_deref()
+            .map(|entry_point| get_vertex_inputs(&naga_modules, entry_point))
+            .unwrap_or_default();
+        Ok(Self {
+            vertex_entry_point,
+            fragment_entry_point,
+            layout,
+            uniforms: get_uniform_structs(&naga_modules),
+            vertex_inputs,
+        })
+    }
+
+    pub fn layout_descs(&self) -> Vec<wgpu::BindGroupLayoutDescriptor> {
+        get_layout_descs(&self.layout)
+    }
+
+    /// Expects the buffer at `group`, `binding` to be bound with a dynamic
+    /// offset, which reflection can't tell from the source. Returns false if
+    /// there is no buffer there.
+    pub fn set_dynamic_offset(&mut self, group: u32, binding: u32) -> bool {
+        let entry = self
+            .layout
+            .get_mut(group as usize)
+            .and_then(|layout| layout.entries.iter_mut().find(|e| e.binding == binding));
+        match entry.map(|entry| &mut entry.ty) {
+            Some(wgpu::BindingType::Buffer {
+                has_dynamic_offset, ..
+            }) => {
+                *has_dynamic_offset = true;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Checks the reflected layout against the layouts a pipeline provides
+    pub fn layout_matches(
+        &self,
+        other: &[&wgpu::BindGroupLayoutDescriptor<'_>],
+    ) -> Result<(), Vec<String>> {
+        // A different number of groups is reported on its own, since
+        // comparing the groups that do line up would only list unrelated
+        // mismatches
+        if self.layout.len() != other.len() {
+            return Err(vec![format!(
+                "Shader declares {} bind groups, but the pipeline provides {}",
+                self.layout.len(),
+                other.len()
+            )]);
+        }
+        let errors = self
+            .layout
+            .iter()
+            .zip(other.iter())
+            .map(|(my_desc, other_desc)| my_desc.check_compatible(other_desc))
+            .filter_map(|res| res.err())
+            .fold(Vec::with_capacity(self.layout.len()), |mut acc, v| {
+                acc.extend(v);
+                acc
+            });
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Checks that the vertex buffers a pipeline provides have an attribute
+    /// for every input of the vertex entry point
+    pub fn vertex_inputs_match(
+        &self,
+        layouts: &[wgpu::VertexBufferLayout<'_>],
+    ) -> Result<(), Vec<String>> {
+        let locations: Vec<u32> = layouts
+            .iter()
+            .flat_map(|layout| layout.attributes)
+            .map(|attribute| attribute.shader_location)
+            .collect();
+        let last_location = locations.iter().max();
+        let errors: Vec<String> = self
+            .vertex_inputs
+            .iter()
+            .filter(|input| !locations.contains(&input.location))
+            .map(|input| match last_location {
+                Some(last) if input.location > *last => format!(
+                    "Shader expects @location({}) for {}, but the vertex layout stops at {}",
+                    input.location, input.name, last
+                ),
+                Some(_) => format!(
+                    "Shader expects @location({}) for {}, which the vertex layout doesn't provide",
+                    input.location, input.name
+                ),
+                None => format!(
+                    "Shader expects @location({}) for {}, but there are no vertex buffers",
+                    input.location, input.name
+                ),
+            })
+            .collect();
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+/// Parses WGSL `source` and reflects its entry points, bind group layouts
+/// and uniform structs
+pub fn reflect_wgsl(source: &str) -> RendererResult<ReflectionInfo> {
+    let mut frontend = wgsl::Frontend::new();
+    let module = frontend.parse(source)?;
+    ReflectionInfo::from_modules(
+        "<source>",
+        &[("<source>", wgpu::ShaderStages::VERTEX_FRAGMENT, &module)],
+    )
+}
+
+/// Magic number at the start of every SPIR-V module
+const SPIRV_MAGIC: u32 = 0x0723_0203;
+
+/// Converts the contents of a `.spv` file to SPIR-V words
+pub fn spirv_words(bytes: &[u8]) -> RendererResult<Vec<u32>> {
+    if !bytes.len().is_multiple_of(4) {
+        return Err(RendererError::InvalidSpirv(format!(
+            "length {} is not a multiple of 4",
+            bytes.len()
+        )));
+    }
+    let words: Vec<u32> = bytes
+        .chunks_exact(4)
+        .map(|chunk| u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]))
+        .collect();
+    match words.first() {
+        Some(&SPIRV_MAGIC) => Ok(words),
+        Some(&magic) if magic.swap_bytes() == SPIRV_MAGIC => {
+            Ok(words.into_iter().map(u32::swap_bytes).collect())
+        }
+        _ => Err(RendererError::InvalidSpirv("missing magic number".into())),
+    }
 }
 
 enum ShaderInput<'a> {
     Wgsl(wgpu::ShaderSource<'a>),
+    SpirV(wgpu::ShaderSource<'a>),
+    /// Boxed as it holds two sources
     Glsl {
-        vertex: wgpu::ShaderSource<'a>,
-        fragment: wgpu::ShaderSource<'a>,
+        vertex: Box<wgpu::ShaderSource<'a>>,
+        fragment: Box<wgpu::ShaderSource<'a>>,
     },
 }
 
@@ -260,29 +862,35 @@ enum ShaderModule {
     Wgsl {
         module: wgpu::ShaderModule,
     },
+    SpirV {
+        module: wgpu::ShaderModule,
+    },
     Glsl {
         vertex: wgpu::ShaderModule,
         fragment: wgpu::ShaderModule,
     },
 }
 
+/// Entry point of `shaders/fullscreen.wgsl`
+const FULLSCREEN_VERTEX_ENTRY_POINT: &str = "vs_main";
+
 pub struct Shader {
     name: String,
     vertex_entry_point: String,
-    fragment_entry_point: String,
     module: ShaderModule,
-    layout: Vec<OwningBindGroupLayoutDescriptor>,
+    /// Vertex stage covering the screen, used when the shader has none
+    fullscreen_vertex: Option<wgpu::ShaderModule>,
+    reflection: ReflectionInfo,
 }
 
 impl Shader {
     fn new(
         device: &wgpu::Device,
         name: &str,
-        vertex_entry_point: &str,
-        fragment_entry_point: &str,
         source: ShaderInput,
-        layout: Vec<OwningBindGroupLayoutDescriptor>,
+        reflection: ReflectionInfo,
     ) -> RendererResult<Self> {
+        let _scope_lock = lock_error_scopes();
         device.push_error_scope(wgpu::ErrorFilter::Validation);
         let module = match source {
             ShaderInput::Wgsl(source) => {
@@ -294,14 +902,23 @@ impl Shader {
                     module: device.create_shader_module(desc),
                 }
             }
+            ShaderInput::SpirV(source) => {
+                let desc = wgpu::ShaderModuleDescriptor {
+                    label: Some(name),
+                    source,
+                };
+                ShaderModule::SpirV {
+                    module: device.create_shader_module(desc),
+                }
+            }
             ShaderInput::Glsl { vertex, fragment } => {
                 let vert_desc = wgpu::ShaderModuleDescriptor {
                     label: Some(name),
-                    source: vertex,
+                    source: *vertex,
                 };
                 let frag_desc = wgpu::ShaderModuleDescriptor {
                     label: Some(name),
-                    source: fragment,
+                    source: *fragment,
                 };
                 ShaderModule::Glsl {
                     vertex: device.create_shader_module(vert_desc),
@@ -309,6 +926,18 @@ impl Shader {
                 }
             }
         };
+        let fullscreen_vertex = reflection.vertex_entry_point.is_none().then(|| {
+            device.create_shader_module(wgpu::ShaderModuleDescriptor {
+                label: Some("Fullscreen vertex"),
+                source: wgpu::ShaderSource::Wgsl(builtin_source(
+                    "fullscreen.wgsl",
+                    include_str!(concat!(
+                        env!("CARGO_MANIFEST_DIR"),
+                        "/shaders/fullscreen.wgsl"
+                    )),
+                )),
+            })
+        });
         let error = pollster::block_on(device.pop_error_scope());
         match error {
             Some(wgpu::Error::Validation {
@@ -325,26 +954,72 @@ impl Shader {
         }
         Ok(Self {
             name: name.into(),
-            vertex_entry_point: vertex_entry_point.to_owned(),
-            fragment_entry_point: fragment_entry_point.to_owned(),
+            vertex_entry_point: reflection
+                .vertex_entry_point
+                .as_deref()
+                .unwrap_or(FULLSCREEN_VERTEX_ENTRY_POINT)
+                .to_owned(),
             module,
-            layout,
+            fullscreen_vertex,
+            reflection,
         })
     }
 
     pub fn new_wgsl(device: &wgpu::Device, name: &str, source: &str) -> RendererResult<Self> {
+        let reflection = reflect_wgsl(source)?;
+        Self::new(
+            device,
+            name,
+            ShaderInput::Wgsl(wgpu::ShaderSource::Wgsl(source.into())),
+            reflection,
+        )
+    }
+
+    /// Like `new_wgsl`, but first expands `#include "file.wgsl"` directives
+    /// relative to `path`. Parse errors point at the original files.
+    pub fn new_wgsl_with_includes(
+        device: &wgpu::Device,
+        name: &str,
+        source: &str,
+        path: Option<&Path>,
+    ) -> RendererResult<Self> {
+        let preprocessed = preprocessor::preprocess(source, path)?;
         let mut frontend = wgsl::Frontend::new();
-        let module = frontend.parse(source)?;
-        let (vertex_entry_point, fragment_entry_point) = get_entry_points(name, &[&module])?;
-        let layout = get_binding_layout(&[(name, wgpu::ShaderStages::VERTEX_FRAGMENT, &module)]);
-        info!("Layout for shader {}: {:?}", name, layout);
+        let module = frontend
+            .parse(&preprocessed.source)
+            .map_err(|e| preprocessed.map_parse_error(&e))?;
+        Self::from_wgsl_module(device, name, &preprocessed.source, &module)
+    }
+
+    fn from_wgsl_module(
+        device: &wgpu::Device,
+        name: &str,
+        source: &str,
+        module: &wgpu::naga::Module,
+    ) -> RendererResult<Self> {
+        let reflection = ReflectionInfo::from_modules(
+            name,
+            &[(name, wgpu::ShaderStages::VERTEX_FRAGMENT, module)],
+        )?;
         Self::new(
             device,
             name,
-            &vertex_entry_point,
-            &fragment_entry_point,
             ShaderInput::Wgsl(wgpu::ShaderSource::Wgsl(source.into())),
-            layout,
+            reflection,
+        )
+    }
+
+    pub fn new_spirv(device: &wgpu::Device, name: &str, words: &[u32]) -> RendererResult<Self> {
+        let module = spv::Frontend::new(words.iter().cloned(), &spv::Options::default()).parse()?;
+        let reflection = ReflectionInfo::from_modules(
+            name,
+            &[(name, wgpu::ShaderStages::VERTEX_FRAGMENT, &module)],
+        )?;
+        Self::new(
+            device,
+            name,
+            ShaderInput::SpirV(wgpu::ShaderSource::SpirV(words.into())),
+            reflection,
         )
     }
 
@@ -363,31 +1038,29 @@ impl Shader {
             &glsl::Options::from(wgpu::naga::ShaderStage::Vertex),
             fragment_source,
         )?;
-        let (vertex_entry_point, fragment_entry_point) =
-            get_entry_points(name, &[&vert_module, &frag_module])?;
-        let layout = get_binding_layout(&[
-            (name, wgpu::ShaderStages::VERTEX, &vert_module),
-            (name, wgpu::ShaderStages::FRAGMENT, &frag_module),
-        ]);
-        info!("Layout for shader {}: {:?}", name, layout);
+        let reflection = ReflectionInfo::from_modules(
+            name,
+            &[
+                (name, wgpu::ShaderStages::VERTEX, &vert_module),
+                (name, wgpu::ShaderStages::FRAGMENT, &frag_module),
+            ],
+        )?;
         Self::new(
             device,
             name,
-            &vertex_entry_point,
-            &fragment_entry_point,
             ShaderInput::Glsl {
-                vertex: wgpu::ShaderSource::Glsl {
+                vertex: Box::new(wgpu::ShaderSource::Glsl {
                     shader: vertex_source.into(),
                     stage: wgpu::naga::ShaderStage::Vertex,
                     defines: Default::default(),
-                },
-                fragment: wgpu::ShaderSource::Glsl {
+                }),
+                fragment: Box::new(wgpu::ShaderSource::Glsl {
                     shader: fragment_source.into(),
                     stage: wgpu::naga::ShaderStage::Fragment,
                     defines: Default::default(),
-                },
+                }),
             },
-            layout,
+            reflection,
         )
     }
 
@@ -395,9 +1068,18 @@ impl Shader {
         &self.name
     }
 
+    /// Whether the shader only has a fragment stage and should be drawn with
+    /// `draw(0..3, 0..1)` and no vertex buffers
+    pub fn is_fullscreen(&self) -> bool {
+        self.fullscreen_vertex.is_some()
+    }
+
     pub fn get_vertex_module(&self) -> &wgpu::ShaderModule {
+        if let Some(module) = &self.fullscreen_vertex {
+            return module;
+        }
         match &self.module {
-            ShaderModule::Wgsl { module } => module,
+            ShaderModule::Wgsl { module } | ShaderModule::SpirV { module } => module,
             ShaderModule::Glsl { vertex, .. } => vertex,
         }
     }
@@ -408,46 +1090,123 @@ impl Shader {
 
     pub fn get_fragment_module(&self) -> &wgpu::ShaderModule {
         match &self.module {
-            ShaderModule::Wgsl { module } => module,
+            ShaderModule::Wgsl { module } | ShaderModule::SpirV { module } => module,
             ShaderModule::Glsl { fragment, .. } => fragment,
         }
     }
 
     pub fn get_fragment_entry_point(&self) -> &str {
-        &self.fragment_entry_point
+        &self.reflection.fragment_entry_point
     }
 
     pub fn get_layout(&self) -> Vec<wgpu::BindGroupLayoutDescriptor> {
-        self.layout
-            .iter()
-            .map(|d| wgpu::BindGroupLayoutDescriptor {
-                label: None,
-                entries: &d.entries,
-            })
-            .collect()
+        self.reflection.layout_descs()
+    }
+
+    /// Every binding the shader declares, ordered by group and then by the
+    /// order they appear in the source
+    pub fn reflected_groups(&self) -> Vec<ReflectedBinding> {
+        get_reflected_bindings(&self.reflection.layout)
+    }
+
+    /// Uniform structs the shader declares, with their editable members
+    pub fn uniforms(&self) -> &[UniformStruct] {
+        &self.reflection.uniforms
+    }
+
+    pub fn reflection(&self) -> &ReflectionInfo {
+        &self.reflection
+    }
+
+    /// See `ReflectionInfo::set_dynamic_offset`
+    pub fn set_dynamic_offset(&mut self, group: u32, binding: u32) -> bool {
+        self.reflection.set_dynamic_offset(group, binding)
     }
 
     pub fn layout_matches(
         &self,
         other: &[&wgpu::BindGroupLayoutDescriptor<'_>],
     ) -> Result<(), Vec<String>> {
-        if self.layout.len() != other.len() {
-            return Err(vec!["Number of groups does not match!".to_string()]);
-        }
-        let errors = self
-            .layout
-            .iter()
-            .zip(other.iter())
-            .map(|(my_desc, other_desc)| my_desc.check_compatible(other_desc))
-            .filter_map(|res| res.err())
-            .fold(Vec::with_capacity(self.layout.len()), |mut acc, v| {
-                acc.extend(v);
-                acc
-            });
-        if errors.is_empty() {
-            Ok(())
-        } else {
-            Err(errors)
+        self.reflection.layout_matches(other)
+    }
+
+    pub fn vertex_inputs_match(
+        &self,
+        layouts: &[wgpu::VertexBufferLayout<'_>],
+    ) -> Result<(), Vec<String>> {
+        self.reflection.vertex_inputs_match(layouts)
+    }
+}
+
+/// A compute shader and the bind group layout reflected from it
+pub struct ComputeShader {
+    name: String,
+    entry_point: String,
+    workgroup_size: [u32; 3],
+    module: wgpu::ShaderModule,
+    layout: Vec<OwningBindGroupLayoutDescriptor>,
+}
+
+impl ComputeShader {
+    pub fn new_wgsl(device: &wgpu::Device, name: &str, source: &str) -> RendererResult<Self> {
+        let mut frontend = wgsl::Frontend::new();
+        let module = frontend.parse(source)?;
+        let (entry_point, workgroup_size) = get_compute_entry_point(name, &module)?;
+        let layout = get_binding_layout(&[(name, wgpu::ShaderStages::COMPUTE, &module)])?;
+        info!("Layout for compute shader {}: {:?}", name, layout);
+
+        let _scope_lock = lock_error_scopes();
+        device.push_error_scope(wgpu::ErrorFilter::Validation);
+        let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some(name),
+            source: wgpu::ShaderSource::Wgsl(source.into()),
+        });
+        match pollster::block_on(device.pop_error_scope()) {
+            Some(wgpu::Error::Validation {
+                source,
+                description,
+            }) => {
+                return Err(RendererError::WgpuValidationError {
+                    source,
+                    description,
+                })
+            }
+            Some(wgpu::Error::OutOfMemory { .. }) => unreachable!(),
+            None => (),
         }
+
+        Ok(Self {
+            name: name.into(),
+            entry_point,
+            workgroup_size,
+            module,
+            layout,
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn get_module(&self) -> &wgpu::ShaderModule {
+        &self.module
+    }
+
+    pub fn get_entry_point(&self) -> &str {
+        &self.entry_point
+    }
+
+    pub fn workgroup_size(&self) -> [u32; 3] {
+        self.workgroup_size
+    }
+
+    pub fn get_layout(&self) -> Vec<wgpu::BindGroupLayoutDescriptor> {
+        get_layout_descs(&self.layout)
+    }
+
+    /// Every binding the shader declares, ordered by group and then by the
+    /// order they appear in the source
+    pub fn reflected_groups(&self) -> Vec<ReflectedBinding> {
+        get_reflected_bindings(&self.layout)
     }
 }
@@ -0,0 +1,727 @@
+use std::{
+    collections::{hash_map::DefaultHasher, VecDeque},
+    hash::{Hash, Hasher},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Instant,
+};
+
+use log::info;
+
+use crate::{
+    error::RendererResult,
+    error::{ShaderCompileError, ShaderErrorLocation},
+    model::{InstanceRaw, ModelVertex, Vertex},
+    pipeline::{PipelineCreateInfo, PipelineTask, RenderPipeline},
+    preprocessor,
+    shader::Shader,
+    texture,
+    user_uniforms::{get_user_uniform_layout_desc, UserUniforms},
+};
+
+/// Parses the shader and checks it against `layout_descs`. With
+/// `dynamic_uniforms`, the shader's own uniform struct is bound with a
+/// dynamic offset.
+fn create_shader(
+    device: &wgpu::Device,
+    name: &str,
+    source: &str,
+    path: Option<&Path>,
+    spirv: Option<&[u32]>,
+    layout_descs: &[&wgpu::BindGroupLayoutDescriptor<'_>],
+    dynamic_uniforms: bool,
+) -> Result<Shader, ShaderCompileError> {
+    let mut shader = match spirv {
+        Some(words) => Shader::new_spirv(device, name, words),
+        None => Shader::new_wgsl_with_includes(device, name, source, path),
+    }
+    .map_err(|e| ShaderCompileError::new(&e, source))?;
+
+    // A shader may declare one extra group for its own uniform struct. Any
+    // more than that is reported as a count mismatch, rather than as a
+    // confusing error about the first extra group's contents.
+    let group_count = shader.get_layout().len();
+    if group_count > layout_descs.len() + 1 {
+        return Err(ShaderCompileError::from(format!(
+            "Shader declares {} bind groups, but the pipeline provides {} plus one for a uniform struct",
+            group_count,
+            layout_descs.len()
+        )));
+    }
+    let user_layout_desc = get_user_uniform_layout_desc(dynamic_uniforms);
+    let mut layout_descs = layout_descs.to_vec();
+    if dynamic_uniforms && group_count <= layout_descs.len() {
+        return Err(ShaderCompileError::from(format!(
+            "{} needs a uniform struct at group {}, binding 0",
+            DYNAMIC_UNIFORMS_DIRECTIVE,
+            layout_descs.len()
+        )));
+    }
+    if shader.is_fullscreen() && group_count < layout_descs.len() {
+        // Fullscreen effects often don't need the model's groups, so they
+        // only have to declare the groups they use
+        layout_descs.truncate(group_count);
+    } else if group_count > layout_descs.len() {
+        let group = layout_descs.len() as u32;
+        if !shader
+            .uniforms()
+            .iter()
+            .any(|u| u.group == group && u.binding == 0)
+        {
+            return Err(ShaderCompileError::from(format!(
+                "Group {} may only contain a uniform struct at binding 0",
+                group
+            )));
+        }
+        if dynamic_uniforms {
+            shader.set_dynamic_offset(group, 0);
+        }
+        layout_descs.push(&user_layout_desc);
+    }
+    shader
+        .layout_matches(&layout_descs)
+        .map_err(|errors| ShaderCompileError::from(errors.join("\n")))?;
+    if !shader.is_fullscreen() {
+        // Caught here, wgpu only reports a missing attribute when the
+        // pipeline is created
+        shader
+            .vertex_inputs_match(&[ModelVertex::layout(), InstanceRaw::desc()])
+            .map_err(|errors| ShaderCompileError::from(errors.join("\n")))?;
+    }
+    Ok(shader)
+}
+
+const TOPOLOGY_DIRECTIVE: &str = "//!topology";
+const DYNAMIC_UNIFORMS_DIRECTIVE: &str = "//!dynamic_uniforms";
+/// Most copies `//!dynamic_uniforms` may ask for
+const MAX_UNIFORM_COPIES: u32 = 4096;
+
+/// Finds a `<directive> <argument>` line in the comments at the top of
+/// `source`. Returns the trimmed argument, and where the line is for
+/// reporting a bad argument.
+fn find_directive<'a>(source: &'a str, directive: &str) -> Option<(&'a str, ShaderErrorLocation)> {
+    let mut line_start = 0;
+    for (index, raw_line) in source.split_inclusive('\n').enumerate() {
+        let line = raw_line.trim_end_matches(['\r', '\n']);
+        let trimmed = line.trim();
+        if !trimmed.is_empty() && !trimmed.starts_with("//") {
+            break;
+        }
+        if let Some(argument) = trimmed.strip_prefix(directive) {
+            let start = source[..line_start].chars().count();
+            let location = ShaderErrorLocation {
+                line: index + 1,
+                column: 1,
+                start,
+                end: start + line.chars().count(),
+            };
+            return Some((argument.trim(), location));
+        }
+        line_start += raw_line.len();
+    }
+    None
+}
+
+/// Reads the topology from a `//!topology <name>` line in the comments at
+/// the top of `source`, defaulting to a triangle list. Point and line
+/// topologies draw the model's indices as points or pairs of lines.
+fn parse_topology(source: &str) -> Result<wgpu::PrimitiveTopology, ShaderCompileError> {
+    let Some((name, location)) = find_directive(source, TOPOLOGY_DIRECTIVE) else {
+        return Ok(wgpu::PrimitiveTopology::TriangleList);
+    };
+    match name {
+        "points" => Ok(wgpu::PrimitiveTopology::PointList),
+        "lines" => Ok(wgpu::PrimitiveTopology::LineList),
+        "line_strip" => Ok(wgpu::PrimitiveTopology::LineStrip),
+        "triangles" => Ok(wgpu::PrimitiveTopology::TriangleList),
+        "triangle_strip" => Ok(wgpu::PrimitiveTopology::TriangleStrip),
+        name => Err(ShaderCompileError {
+            message: format!(
+                "Unknown topology \"{}\", expected one of points, lines, line_strip, triangles or triangle_strip",
+                name
+            ),
+            location: Some(location),
+        }),
+    }
+}
+
+/// Reads a `//!dynamic_uniforms <copies>` line from the comments at the top
+/// of `source`. It gives the shader's uniform struct a copy per instance,
+/// bound with a dynamic offset, and instances past the last copy reuse the
+/// copies from the start.
+fn parse_dynamic_uniforms(source: &str) -> Result<Option<u32>, ShaderCompileError> {
+    let Some((copies, location)) = find_directive(source, DYNAMIC_UNIFORMS_DIRECTIVE) else {
+        return Ok(None);
+    };
+    match copies.parse() {
+        Ok(copies) if (1..=MAX_UNIFORM_COPIES).contains(&copies) => Ok(Some(copies)),
+        _ => Err(ShaderCompileError {
+            message: format!(
+                "{} expects a number of copies from 1 to {}, not \"{}\"",
+                DYNAMIC_UNIFORMS_DIRECTIVE, MAX_UNIFORM_COPIES, copies
+            ),
+            location: Some(location),
+        }),
+    }
+}
+
+/// Builds the create infos for the opaque and transparent pipelines
+fn pipeline_create_infos<'a>(
+    shader: &'a Shader,
+    vertex_layouts: &'a [wgpu::VertexBufferLayout<'a>],
+    color_format: wgpu::TextureFormat,
+    topology: wgpu::PrimitiveTopology,
+    cull_mode: Option<wgpu::Face>,
+) -> (PipelineCreateInfo<'a>, PipelineCreateInfo<'a>) {
+    let create_info = PipelineCreateInfo {
+        color_format,
+        depth_format: Some(texture::Texture::DEPTH_FORMAT),
+        depth_write: true,
+        vertex_layouts,
+        topology,
+        cull_mode,
+        blend: Some(wgpu::BlendState::REPLACE),
+        shader,
+        label: Some("Normal Pipeline"),
+    };
+    let transparent_create_info = PipelineCreateInfo {
+        // Sorting by center can't order overlapping surfaces, which would
+        // hide each other
+        depth_write: false,
+        blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+        label: Some("Transparent Pipeline"),
+        ..create_info
+    };
+    (create_info, transparent_create_info)
+}
+
+/// A shader that has been checked against the renderer's layouts, with
+/// everything but its pipelines created
+struct Prepared {
+    shader: Shader,
+    topology: wgpu::PrimitiveTopology,
+    uniforms: Option<UserUniforms>,
+    pipeline_layouts: [wgpu::PipelineLayout; 2],
+}
+
+/// Builds the opaque and transparent pipelines, the slow part of a compile
+fn build_pipelines(
+    device: &wgpu::Device,
+    shader: &Shader,
+    topology: wgpu::PrimitiveTopology,
+    pipeline_layouts: [wgpu::PipelineLayout; 2],
+    color_format: wgpu::TextureFormat,
+    cull_mode: Option<wgpu::Face>,
+) -> RendererResult<(RenderPipeline, RenderPipeline)> {
+    let model_vertex_layouts = [ModelVertex::layout(), InstanceRaw::desc()];
+    // Fragment only shaders are drawn as one triangle covering the screen
+    let (vertex_layouts, topology): (&[_], _) = if shader.is_fullscreen() {
+        (&[], wgpu::PrimitiveTopology::TriangleList)
+    } else {
+        (&model_vertex_layouts, topology)
+    };
+    let (create_info, transparent_create_info) =
+        pipeline_create_infos(shader, vertex_layouts, color_format, topology, cull_mode);
+    let [layout, transparent_layout] = pipeline_layouts;
+    let render_pipeline = RenderPipeline::try_new(device, layout, create_info)?;
+    let transparent_render_pipeline =
+        RenderPipeline::try_new(device, transparent_layout, transparent_create_info)?;
+    Ok((render_pipeline, transparent_render_pipeline))
+}
+
+/// Everything built from one successful compile
+struct Compiled {
+    /// Drawn over the whole screen instead of on the model
+    fullscreen: bool,
+    topology: wgpu::PrimitiveTopology,
+    uniforms: Option<UserUniforms>,
+    render_pipeline: RenderPipeline,
+    transparent_render_pipeline: RenderPipeline,
+}
+
+/// The source and settings a tab compiles from
+struct CompileInput<'a> {
+    name: &'a str,
+    source: &'a str,
+    path: Option<&'a Path>,
+    spirv: Option<&'a [u32]>,
+    /// Faces the pipelines skip drawing, `None` for double-sided models
+    cull_mode: Option<wgpu::Face>,
+}
+
+impl CompileInput<'_> {
+    /// Identifies what the input compiles to. Includes are expanded first so
+    /// that editing an included file changes the hash.
+    fn hash(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        match self.spirv {
+            Some(words) => words.hash(&mut hasher),
+            None => match preprocessor::preprocess(self.source, self.path) {
+                Ok(preprocessed) => preprocessed.source.hash(&mut hasher),
+                Err(_) => self.source.hash(&mut hasher),
+            },
+        }
+        self.cull_mode.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    /// Parses the shader and checks it against the renderer's layouts,
+    /// leaving the pipelines to be built
+    fn prepare(
+        &self,
+        device: &wgpu::Device,
+        bind_group_layouts: &[&wgpu::BindGroupLayout],
+        layout_descs: &[&wgpu::BindGroupLayoutDescriptor<'_>],
+        previous_uniforms: Option<&UserUniforms>,
+    ) -> Result<Prepared, ShaderCompileError> {
+        // SPIR-V has no comments to read a directive from
+        let (topology, uniform_copies) = match self.spirv {
+            Some(_) => (wgpu::PrimitiveTopology::TriangleList, None),
+            None => (
+                parse_topology(self.source)?,
+                parse_dynamic_uniforms(self.source)?,
+            ),
+        };
+        let shader = create_shader(
+            device,
+            self.name,
+            self.source,
+            self.path,
+            self.spirv,
+            layout_descs,
+            uniform_copies.is_some(),
+        )?;
+
+        let user_group = layout_descs.len() as u32;
+        let uniforms = shader
+            .uniforms()
+            .iter()
+            .find(|u| u.group == user_group && u.binding == 0)
+            .map(|reflection| {
+                UserUniforms::new(
+                    device,
+                    reflection.clone(),
+                    uniform_copies,
+                    previous_uniforms,
+                )
+            });
+
+        let mut bind_group_layouts = bind_group_layouts.to_vec();
+        if let Some(uniforms) = &uniforms {
+            bind_group_layouts.push(uniforms.layout());
+        }
+        let create_pipeline_layout = || {
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Pipeline layout"),
+                bind_group_layouts: &bind_group_layouts,
+                push_constant_ranges: &[],
+            })
+        };
+        let pipeline_layouts = [create_pipeline_layout(), create_pipeline_layout()];
+
+        Ok(Prepared {
+            shader,
+            topology,
+            uniforms,
+            pipeline_layouts,
+        })
+    }
+}
+
+/// A compile whose pipelines are being built on a background thread
+struct PendingCompile {
+    hash: u64,
+    /// Source the compile started from, for locating errors
+    source: String,
+    fullscreen: bool,
+    topology: wgpu::PrimitiveTopology,
+    uniforms: Option<UserUniforms>,
+    task: PipelineTask<(RenderPipeline, RenderPipeline)>,
+}
+
+/// How many previous builds a tab keeps around besides the current one
+const RECENT_COMPILES: usize = 4;
+
+/// Names a shader after the file it was loaded from
+pub fn name_from_path(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| path.display().to_string())
+}
+
+/// A tab's current build and a few earlier ones, each keyed by a hash of
+/// what it was built from. Only successful builds are put in, so a compile
+/// that fails leaves the last good build current.
+struct Builds<T> {
+    current: T,
+    current_hash: u64,
+    /// Earlier builds, most recently used first
+    recent: VecDeque<(u64, T)>,
+}
+
+impl<T> Builds<T> {
+    fn new(hash: u64, build: T) -> Self {
+        Self {
+            current: build,
+            current_hash: hash,
+            recent: VecDeque::new(),
+        }
+    }
+
+    fn current(&self) -> &T {
+        &self.current
+    }
+
+    fn current_mut(&mut self) -> &mut T {
+        &mut self.current
+    }
+
+    fn is_current(&self, hash: u64) -> bool {
+        hash == self.current_hash
+    }
+
+    /// Makes `build` current, keeping the old one as recent
+    fn replace(&mut self, hash: u64, build: T) {
+        let previous = std::mem::replace(&mut self.current, build);
+        let previous_hash = std::mem::replace(&mut self.current_hash, hash);
+        self.recent.push_front((previous_hash, previous));
+        self.recent.truncate(RECENT_COMPILES);
+    }
+
+    /// Makes the earlier build with `hash` current again, if it was kept
+    fn restore(&mut self, hash: u64) -> bool {
+        let Some(index) = self.recent.iter().position(|(h, _)| *h == hash) else {
+            return false;
+        };
+        let (_, build) = self.recent.remove(index).expect("Index is in bounds");
+        self.replace(hash, build);
+        true
+    }
+}
+
+/// One tab of the shader editor. Every tab owns its source and the pipelines
+/// built from it, so switching tabs doesn't need a recompile.
+///
+/// Compiling is all or nothing: the pipelines are only replaced once the new
+/// shader has parsed, matched the renderer's bind group layouts and been
+/// accepted by wgpu for both pipelines. Otherwise the tab keeps drawing with
+/// the pipelines from the last good shader and reports the error. Parsing
+/// happens straight away, the pipelines are built on a background thread and
+/// swapped in by `poll_compile` once they are ready.
+///
+/// Builds are keyed by a hash of their source. Compiling source that matches
+/// the current build does nothing, and a few earlier builds are kept so that
+/// switching back to one of them doesn't need a recompile.
+pub struct ShaderTab {
+    name: String,
+    /// File the source was last loaded from or saved to
+    path: Option<PathBuf>,
+    pub source: String,
+    /// `source` as of the last load or save, to tell if it has been edited
+    saved_source: String,
+    /// Precompiled module used instead of `source`, which then only
+    /// describes where it came from
+    spirv: Option<Vec<u32>>,
+    compile_error: Option<ShaderCompileError>,
+    builds: Builds<Compiled>,
+    /// When a compile last succeeded, `None` until the first one after the
+    /// tab was created
+    last_compiled: Option<Instant>,
+    pending: Option<PendingCompile>,
+}
+
+impl ShaderTab {
+    /// Creates a tab, failing if `source` can't be used to draw models
+    pub fn new(
+        device: &wgpu::Device,
+        name: String,
+        source: String,
+        bind_group_layouts: &[&wgpu::BindGroupLayout],
+        layout_descs: &[&wgpu::BindGroupLayoutDescriptor<'_>],
+        color_format: wgpu::TextureFormat,
+        cull_mode: Option<wgpu::Face>,
+    ) -> Result<Self, ShaderCompileError> {
+        let input = CompileInput {
+            name: &name,
+            source: &source,
+            path: None,
+            spirv: None,
+            cull_mode,
+        };
+        let compiled_hash = input.hash();
+        let prepared = input.prepare(device, bind_group_layouts, layout_descs, None)?;
+        let fullscreen = prepared.shader.is_fullscreen();
+        let (render_pipeline, transparent_render_pipeline) = build_pipelines(
+            device,
+            &prepared.shader,
+            prepared.topology,
+            prepared.pipeline_layouts,
+            color_format,
+            cull_mode,
+        )
+        .map_err(|e| ShaderCompileError::new(&e, &source))?;
+        let compiled = Compiled {
+            fullscreen,
+            topology: prepared.topology,
+            uniforms: prepared.uniforms,
+            render_pipeline,
+            transparent_render_pipeline,
+        };
+
+        Ok(Self {
+            name,
+            path: None,
+            saved_source: source.clone(),
+            source,
+            spirv: None,
+            compile_error: None,
+            builds: Builds::new(compiled_hash, compiled),
+            last_compiled: None,
+            pending: None,
+        })
+    }
+
+    /// Starts rebuilding the pipelines from the current source, replacing
+    /// any compile that is still running. On failure the previous pipelines
+    /// are kept and the error is stored on the tab.
+    pub fn compile(
+        &mut self,
+        device: &Arc<wgpu::Device>,
+        bind_group_layouts: &[&wgpu::BindGroupLayout],
+        layout_descs: &[&wgpu::BindGroupLayoutDescriptor<'_>],
+        color_format: wgpu::TextureFormat,
+        cull_mode: Option<wgpu::Face>,
+    ) {
+        let input = CompileInput {
+            name: &self.name,
+            source: &self.source,
+            path: self.path.as_deref(),
+            spirv: self.spirv.as_deref(),
+            cull_mode,
+        };
+        let hash = input.hash();
+        if self.pending.as_ref().is_some_and(|p| p.hash == hash) {
+            info!("Shader {} is already compiling", self.name);
+            return;
+        }
+        self.pending = None;
+        if self.builds.is_current(hash) {
+            info!("Shader {} is unchanged, not recompiling", self.name);
+            self.compile_error = None;
+            return;
+        }
+        if self.builds.restore(hash) {
+            info!("Reusing earlier build of shader {}", self.name);
+            self.compiled_replaced();
+            return;
+        }
+
+        let prepared = match input.prepare(
+            device,
+            bind_group_layouts,
+            layout_descs,
+            self.builds.current().uniforms.as_ref(),
+        ) {
+            Ok(prepared) => prepared,
+            Err(e) => {
+                self.compile_error = Some(e);
+                return;
+            }
+        };
+        let Prepared {
+            shader,
+            topology,
+            uniforms,
+            pipeline_layouts,
+        } = prepared;
+        let fullscreen = shader.is_fullscreen();
+        let task = PipelineTask::spawn(device.clone(), move |device| {
+            build_pipelines(
+                device,
+                &shader,
+                topology,
+                pipeline_layouts,
+                color_format,
+                cull_mode,
+            )
+        });
+        self.pending = Some(PendingCompile {
+            hash,
+            source: self.source.clone(),
+            fullscreen,
+            topology,
+            uniforms,
+            task,
+        });
+    }
+
+    /// Swaps in the pipelines of a finished background compile
+    pub fn poll_compile(&mut self) {
+        let Some(result) = self.pending.as_ref().and_then(|p| p.task.try_take()) else {
+            return;
+        };
+        let pending = self.pending.take().expect("Compile is pending");
+        match result {
+            Ok((render_pipeline, transparent_render_pipeline)) => {
+                info!("Finished compiling shader {}", self.name);
+                let compiled = Compiled {
+                    fullscreen: pending.fullscreen,
+                    topology: pending.topology,
+                    uniforms: pending.uniforms,
+                    render_pipeline,
+                    transparent_render_pipeline,
+                };
+                self.builds.replace(pending.hash, compiled);
+                self.compiled_replaced();
+            }
+            Err(e) => self.compile_error = Some(ShaderCompileError::new(&e, &pending.source)),
+        }
+    }
+
+    /// Whether pipelines are being built in the background, the previous
+    /// ones are drawn with until they are ready
+    pub fn is_compiling(&self) -> bool {
+        self.pending.is_some()
+    }
+
+    /// Records that a build has been swapped in, clearing any earlier error
+    fn compiled_replaced(&mut self) {
+        self.compile_error = None;
+        self.last_compiled = Some(Instant::now());
+    }
+
+    pub fn last_compiled(&self) -> Option<Instant> {
+        self.last_compiled
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    /// Associates the tab with a file, renaming it after the file
+    pub fn set_path(&mut self, path: PathBuf) {
+        self.name = name_from_path(&path);
+        self.path = Some(path);
+    }
+
+    pub fn saved_source(&self) -> &str {
+        &self.saved_source
+    }
+
+    /// Records the current source as matching what's on disk
+    pub fn mark_saved(&mut self) {
+        self.saved_source = self.source.clone();
+    }
+
+    /// Replaces the source with `source`, forgetting the file and any SPIR-V
+    /// it was loaded from so that saving asks for a new path
+    pub fn reset(&mut self, source: String) {
+        self.source = source;
+        self.mark_saved();
+        self.path = None;
+        self.spirv = None;
+    }
+
+    /// Takes the name, source and file of `old`, a tab whose pipelines were
+    /// made for another device. The current pipelines are drawn with until
+    /// the tab is compiled again.
+    pub fn take_source(&mut self, old: ShaderTab) {
+        self.name = old.name;
+        self.path = old.path;
+        self.source = old.source;
+        self.saved_source = old.saved_source;
+        self.spirv = old.spirv;
+    }
+
+    /// Makes the tab compile from SPIR-V words instead of its WGSL source
+    pub fn set_spirv(&mut self, spirv: Option<Vec<u32>>) {
+        self.spirv = spirv;
+    }
+
+    pub fn is_spirv(&self) -> bool {
+        self.spirv.is_some()
+    }
+
+    pub fn compile_error(&self) -> Option<&ShaderCompileError> {
+        self.compile_error.as_ref()
+    }
+
+    /// Values for the shader's own uniform struct, if it declares one
+    pub fn uniforms(&self) -> Option<&UserUniforms> {
+        self.builds.current().uniforms.as_ref()
+    }
+
+    pub fn uniforms_mut(&mut self) -> Option<&mut UserUniforms> {
+        self.builds.current_mut().uniforms.as_mut()
+    }
+
+    /// Whether the shader has only a fragment stage, so its pipelines draw a
+    /// fullscreen triangle rather than the model
+    pub fn is_fullscreen(&self) -> bool {
+        self.builds.current().fullscreen
+    }
+
+    /// How the model pipelines assemble primitives, fullscreen shaders are
+    /// always drawn as a triangle list
+    pub fn topology(&self) -> wgpu::PrimitiveTopology {
+        self.builds.current().topology
+    }
+
+    pub fn render_pipeline(&self) -> &RenderPipeline {
+        &self.builds.current().render_pipeline
+    }
+
+    pub fn transparent_render_pipeline(&self) -> &RenderPipeline {
+        &self.builds.current().transparent_render_pipeline
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builds_keep_the_last_good_build_and_earlier_ones() {
+        let mut builds = Builds::new(1, "first");
+        builds.replace(2, "second");
+        assert!(builds.is_current(2));
+        assert_eq!(*builds.current(), "second");
+
+        // A failed compile never reaches `replace`, and the build it would
+        // have made isn't kept
+        assert!(!builds.restore(3));
+        assert_eq!(*builds.current(), "second");
+
+        assert!(builds.restore(1));
+        assert_eq!(*builds.current(), "first");
+        assert!(builds.restore(2));
+        assert_eq!(*builds.current(), "second");
+    }
+
+    #[test]
+    fn builds_forget_the_oldest_builds() {
+        let mut builds = Builds::new(0, 0);
+        for hash in 1..=RECENT_COMPILES as u64 + 1 {
+            builds.replace(hash, hash);
+        }
+        assert!(!builds.restore(0));
+        assert!(builds.restore(1));
+        assert_eq!(*builds.current(), 1);
+    }
+
+    #[test]
+    fn directive_locations_count_crlf_line_endings() {
+        for newline in ["\n", "\r\n"] {
+            let source = format!("// header{0}//!topology lines{0}fn main() {{}}", newline);
+            let (name, location) = find_directive(&source, TOPOLOGY_DIRECTIVE).unwrap();
+            assert_eq!(name, "lines");
+            assert_eq!(location.line, 2);
+            assert_eq!(location.start, 9 + newline.len());
+            assert_eq!(&source[location.start..location.end], "//!topology lines");
+        }
+    }
+}
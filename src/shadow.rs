@@ -0,0 +1,268 @@
+use cgmath::{Matrix4, Point3, Vector3};
+use wgpu::util::DeviceExt;
+
+use crate::{
+    model::{InstanceRaw, Model, ModelVertex, Vertex},
+    shader::Shader,
+    texture,
+};
+
+/// Resolutions offered in the UI, in texels along each side
+pub const SHADOW_MAP_SIZES: [u32; 4] = [512, 1024, 2048, 4096];
+const DEFAULT_SHADOW_MAP_SIZE: u32 = 2048;
+
+/// cgmath produces OpenGL style projections with a depth range of -1 to 1,
+/// but the shadow map is sampled with wgpu's 0 to 1
+#[rustfmt::skip]
+const OPENGL_TO_WGPU_MATRIX: Matrix4<f32> = Matrix4::new(
+    1.0, 0.0, 0.0, 0.0,
+    0.0, 1.0, 0.0, 0.0,
+    0.0, 0.0, 0.5, 0.0,
+    0.0, 0.0, 0.5, 1.0,
+);
+
+pub const fn get_shadow_layout_desc() -> wgpu::BindGroupLayoutDescriptor<'static> {
+    const LAYOUT_ENTRIES: [wgpu::BindGroupLayoutEntry; 1] = [wgpu::BindGroupLayoutEntry {
+        binding: 0,
+        visibility: wgpu::ShaderStages::VERTEX,
+        ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Uniform,
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        count: None,
+    }];
+    wgpu::BindGroupLayoutDescriptor {
+        label: Some("ShadowMap::layout"),
+        entries: &LAYOUT_ENTRIES,
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct ShadowUniform {
+    light_view_proj: [[f32; 4]; 4],
+    /// Nonzero if the shadow map is rendered and should be sampled
+    enabled: u32,
+    _padding: [u32; 3],
+}
+
+impl ShadowUniform {
+    fn new(light_position: Point3<f32>, enabled: bool) -> Self {
+        let view = Matrix4::look_at_rh(
+            light_position,
+            Point3::new(0.0, 0.0, 0.0),
+            Vector3::unit_y(),
+        );
+        let proj = cgmath::perspective(cgmath::Deg(120.0), 1.0, 0.1, 100.0);
+        Self {
+            light_view_proj: (OPENGL_TO_WGPU_MATRIX * proj * view).into(),
+            enabled: enabled as u32,
+            _padding: [0; 3],
+        }
+    }
+}
+
+fn create_shadow_texture(device: &wgpu::Device, size: u32) -> texture::Texture {
+    texture::Texture::create_depth_texture(
+        device,
+        &wgpu::Extent3d {
+            width: size,
+            height: size,
+            depth_or_array_layers: 1,
+        },
+        "ShadowMap::texture",
+    )
+}
+
+/// Renders the depth of the scene as seen from the light. Shaders can sample
+/// it through the light bind group with a comparison sampler.
+///
+/// Shadows start disabled, while disabled the depth pass is skipped and the
+/// uniform tells shaders not to sample the map.
+pub struct ShadowMap {
+    pipeline: wgpu::RenderPipeline,
+    texture: texture::Texture,
+    size: u32,
+    enabled: bool,
+    uniform_buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+}
+
+impl ShadowMap {
+    /// The depth pass binds each mesh's material to apply its alpha test,
+    /// so it needs the material bind group layout
+    pub fn new(
+        device: &wgpu::Device,
+        light_position: Point3<f32>,
+        texture_bind_group_layout_desc: &wgpu::BindGroupLayoutDescriptor<'_>,
+        texture_bind_group_layout: &wgpu::BindGroupLayout,
+    ) -> Self {
+        let size = DEFAULT_SHADOW_MAP_SIZE;
+        let enabled = false;
+        let texture = create_shadow_texture(device, size);
+
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("ShadowMap::uniform_buffer"),
+            contents: bytemuck::cast_slice(&[ShadowUniform::new(light_position, enabled)]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let desc = get_shadow_layout_desc();
+        let layout = device.create_bind_group_layout(&desc);
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("ShadowMap::bind_group"),
+            layout: &layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: uniform_buffer.as_entire_binding(),
+            }],
+        });
+
+        let shader = Shader::new_wgsl(
+            device,
+            "shadow_depth",
+            include_str!(concat!(
+                env!("CARGO_MANIFEST_DIR"),
+                "/shaders/shadow_depth.wgsl"
+            )),
+        )
+        .expect("Could not parse shadow depth shader");
+        assert_eq!(
+            shader.layout_matches(&[&desc, texture_bind_group_layout_desc]),
+            Ok(())
+        );
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("ShadowMap::pipeline_layout"),
+            bind_group_layouts: &[&layout, texture_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        // `RenderPipeline` always has a color target, this pass only needs depth
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Shadow Pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: shader.get_vertex_module(),
+                entry_point: shader.get_vertex_entry_point(),
+                buffers: &[ModelVertex::layout(), InstanceRaw::desc()],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: shader.get_fragment_module(),
+                entry_point: shader.get_fragment_entry_point(),
+                targets: &[],
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: Some(wgpu::Face::Back),
+                polygon_mode: wgpu::PolygonMode::Fill,
+                unclipped_depth: false,
+                conservative: false,
+            },
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: texture::Texture::DEPTH_FORMAT,
+                depth_write_enabled: true,
+                depth_compare: wgpu::CompareFunction::LessEqual,
+                stencil: wgpu::StencilState::default(),
+                // Keeps surfaces from shadowing themselves
+                bias: wgpu::DepthBiasState {
+                    constant: 2,
+                    slope_scale: 2.0,
+                    clamp: 0.0,
+                },
+            }),
+            multisample: wgpu::MultisampleState {
+                count: 1,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+            multiview: None,
+        });
+
+        Self {
+            pipeline,
+            texture,
+            size,
+            enabled,
+            uniform_buffer,
+            bind_group,
+        }
+    }
+
+    pub fn update(&self, queue: &wgpu::Queue, light_position: Point3<f32>) {
+        queue.write_buffer(
+            &self.uniform_buffer,
+            0,
+            bytemuck::cast_slice(&[ShadowUniform::new(light_position, self.enabled)]),
+        );
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Takes effect on the next `update`
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+
+    /// Recreates the depth texture, so any bind group using `view` has to be
+    /// recreated as well
+    pub fn set_size(&mut self, device: &wgpu::Device, size: u32) {
+        self.texture = create_shadow_texture(device, size);
+        self.size = size;
+    }
+
+    /// Does nothing while shadows are disabled
+    pub fn render(&self, encoder: &mut wgpu::CommandEncoder, model: &Model) {
+        if !self.enabled {
+            return;
+        }
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("ShadowMap::render"),
+            color_attachments: &[],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &self.texture.view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: wgpu::StoreOp::Store,
+                }),
+                stencil_ops: None,
+            }),
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0, &self.bind_group, &[]);
+        pass.set_vertex_buffer(1, model.instance_buffer.slice(..));
+        for mesh in &model.meshes {
+            pass.set_bind_group(1, &model.materials[mesh.material].bind_group, &[]);
+            pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+            pass.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+            pass.draw_indexed(
+                0..mesh.number_of_elements,
+                0,
+                0..model.instances.len() as u32,
+            );
+        }
+    }
+
+    pub fn view(&self) -> &wgpu::TextureView {
+        &self.texture.view
+    }
+
+    /// Compares with `LessEqual`
+    pub fn sampler(&self) -> &wgpu::Sampler {
+        &self.texture.sampler
+    }
+
+    /// Holds the light's view projection matrix
+    pub fn uniform_buffer(&self) -> &wgpu::Buffer {
+        &self.uniform_buffer
+    }
+}
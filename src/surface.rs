@@ -1,3 +1,5 @@
+use crate::error::{RendererError, RendererResult};
+
 pub struct Surface {
     surface: wgpu::Surface<'static>,
     capabilities: wgpu::SurfaceCapabilities,
@@ -37,6 +39,20 @@ impl Surface {
         self.surface.configure(device, &self.config);
     }
 
+    /// Switches to `format`, which must be one of the formats in `capabilities`
+    pub fn set_format(
+        &mut self,
+        format: wgpu::TextureFormat,
+        device: &wgpu::Device,
+    ) -> RendererResult<()> {
+        if !self.capabilities.formats.contains(&format) {
+            return Err(RendererError::UnsupportedSurfaceFormat(format));
+        }
+        self.config.format = format;
+        self.surface.configure(device, &self.config);
+        Ok(())
+    }
+
     pub fn extent(&self) -> wgpu::Extent3d {
         wgpu::Extent3d {
             width: self.config.width,
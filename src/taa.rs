@@ -0,0 +1,305 @@
+use cgmath::{Matrix4, SquareMatrix, Vector2};
+use wgpu::{util::DeviceExt, Operations};
+
+use crate::{
+    camera::PerspectiveCamera,
+    pipeline::{PipelineCreateInfo, RenderPipeline},
+    shader::{self, Shader},
+    texture,
+};
+
+/// Length of the jitter sequence
+const JITTER_SAMPLES: u32 = 8;
+/// Share of the history kept each frame once there is one
+const HISTORY_WEIGHT: f32 = 0.9;
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct TaaUniform {
+    inv_view_proj: [[f32; 4]; 4],
+    prev_view_proj: [[f32; 4]; 4],
+    history_weight: f32,
+    _padding: [f32; 3],
+}
+
+impl Default for TaaUniform {
+    fn default() -> Self {
+        Self {
+            inv_view_proj: Matrix4::identity().into(),
+            prev_view_proj: Matrix4::identity().into(),
+            history_weight: 0.0,
+            _padding: [0.0; 3],
+        }
+    }
+}
+
+/// Element `index` of the Halton sequence in `base`, in [0, 1)
+fn halton(mut index: u32, base: u32) -> f32 {
+    let mut fraction = 1.0;
+    let mut result = 0.0;
+    while index > 0 {
+        fraction /= base as f32;
+        result += fraction * (index % base) as f32;
+        index /= base;
+    }
+    result
+}
+
+/// Temporal anti-aliasing. The camera is jittered by a different sub-pixel
+/// offset every frame and each frame is blended into the reprojected result
+/// of the previous ones, which are kept in a pair of history textures that
+/// take turns being read and written.
+pub struct Taa {
+    pipeline: RenderPipeline,
+    history: [texture::Texture; 2],
+    /// `resolve_bind_groups[i]` reads `history[1 - i]`
+    resolve_bind_groups: [wgpu::BindGroup; 2],
+    /// `output_bind_groups[i]` tonemaps `history[i]`
+    output_bind_groups: [wgpu::BindGroup; 2],
+    uniform: TaaUniform,
+    uniform_buffer: wgpu::Buffer,
+    /// Index of the history texture written this frame
+    current: usize,
+    frame: u32,
+    width: u32,
+    height: u32,
+    prev_view_proj: Option<Matrix4<f32>>,
+}
+
+impl Taa {
+    /// Resolves `hdr_texture`, reprojecting with `depth_texture`. The
+    /// history textures are tonemapped with `create_output_bind_group`.
+    pub fn new(
+        device: &wgpu::Device,
+        hdr_texture: &texture::Texture,
+        depth_texture: &texture::Texture,
+        format: wgpu::TextureFormat,
+        create_output_bind_group: impl Fn(&texture::Texture) -> wgpu::BindGroup,
+    ) -> Self {
+        let width = hdr_texture.size.width;
+        let height = hdr_texture.size.height;
+        let desc = wgpu::BindGroupLayoutDescriptor {
+            label: Some("Taa::layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Depth,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 4,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        };
+        let layout = device.create_bind_group_layout(&desc);
+
+        let uniform = TaaUniform::default();
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Taa::uniform_buffer"),
+            contents: bytemuck::cast_slice(&[uniform]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let create_history = || {
+            texture::Texture::create_2d_texture(
+                device,
+                width,
+                height,
+                format,
+                wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::RENDER_ATTACHMENT,
+                wgpu::FilterMode::Linear,
+                Some("Taa::history"),
+            )
+        };
+        let history = [create_history(), create_history()];
+        let create_resolve_bind_group = |previous: &texture::Texture| {
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("Taa::resolve_bind_group"),
+                layout: &layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(&hdr_texture.view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::TextureView(&previous.view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: wgpu::BindingResource::Sampler(&previous.sampler),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 3,
+                        resource: wgpu::BindingResource::TextureView(&depth_texture.view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 4,
+                        resource: uniform_buffer.as_entire_binding(),
+                    },
+                ],
+            })
+        };
+        let resolve_bind_groups = [
+            create_resolve_bind_group(&history[1]),
+            create_resolve_bind_group(&history[0]),
+        ];
+        let output_bind_groups = [
+            create_output_bind_group(&history[0]),
+            create_output_bind_group(&history[1]),
+        ];
+
+        let shader = Shader::new_wgsl(
+            device,
+            "taa",
+            &shader::builtin_source(
+                "taa.wgsl",
+                include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/shaders/taa.wgsl")),
+            ),
+        )
+        .expect("Could not parse taa shader");
+        assert_eq!(shader.layout_matches(&[&desc]), Ok(()));
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: None,
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let create_info = PipelineCreateInfo {
+            color_format: format,
+            depth_format: None,
+            depth_write: true,
+            vertex_layouts: &[],
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            cull_mode: Some(wgpu::Face::Back),
+            blend: Some(wgpu::BlendState::REPLACE),
+            shader: &shader,
+            label: Some("TAA Pipeline"),
+        };
+        let pipeline = RenderPipeline::new(device, pipeline_layout, create_info);
+
+        Self {
+            pipeline,
+            history,
+            resolve_bind_groups,
+            output_bind_groups,
+            uniform,
+            uniform_buffer,
+            current: 0,
+            frame: 0,
+            width,
+            height,
+            prev_view_proj: None,
+        }
+    }
+
+    /// Moves on to the next frame, jittering `camera` by the next offset in
+    /// the sequence
+    pub fn update(&mut self, queue: &wgpu::Queue, camera: &mut PerspectiveCamera) {
+        self.current = 1 - self.current;
+        self.frame = self.frame % JITTER_SAMPLES + 1;
+        // Offsets within a pixel, converted to normalized device coordinates
+        let jitter = Vector2::new(
+            (halton(self.frame, 2) - 0.5) * 2.0 / self.width as f32,
+            (halton(self.frame, 3) - 0.5) * 2.0 / self.height as f32,
+        );
+        camera.set_jitter(queue, jitter);
+
+        let view_proj = camera.unjittered_view_proj();
+        self.uniform.inv_view_proj = camera
+            .view_proj()
+            .invert()
+            .expect("View projection matrix was singular!")
+            .into();
+        match self.prev_view_proj {
+            Some(prev_view_proj) => {
+                self.uniform.prev_view_proj = prev_view_proj.into();
+                self.uniform.history_weight = HISTORY_WEIGHT;
+            }
+            None => self.uniform.history_weight = 0.0,
+        }
+        self.prev_view_proj = Some(view_proj);
+        queue.write_buffer(
+            &self.uniform_buffer,
+            0,
+            bytemuck::cast_slice(&[self.uniform]),
+        );
+    }
+
+    /// Blends the HDR texture into the history
+    pub fn resolve(&self, encoder: &mut wgpu::CommandEncoder) {
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Taa::resolve"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: &self.history[self.current].view,
+                resolve_target: None,
+                ops: Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            ..Default::default()
+        });
+        pass.set_pipeline(self.pipeline.pipeline());
+        pass.set_bind_group(0, &self.resolve_bind_groups[self.current], &[]);
+        pass.draw(0..3, 0..1);
+    }
+
+    /// This frame's resolved image
+    pub fn output_view(&self) -> &wgpu::TextureView {
+        &self.history[self.current].view
+    }
+
+    /// The pair of textures the frames are resolved into, in turn
+    pub fn history(&self) -> &[texture::Texture; 2] {
+        &self.history
+    }
+
+    /// Index into `history` of this frame's resolved image
+    pub fn output_index(&self) -> usize {
+        self.current
+    }
+
+    /// Bind group for tonemapping this frame's resolved image
+    pub fn output_bind_group(&self) -> &wgpu::BindGroup {
+        &self.output_bind_groups[self.current]
+    }
+}
@@ -1,5 +1,41 @@
 use crate::error::RendererResult;
 
+/// How the texel values of an 8-bit image should be interpreted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorSpace {
+    /// Colors meant to be seen, such as diffuse maps. Sampling converts
+    /// them to linear.
+    Srgb,
+    /// Data such as normals, masks and roughness, sampled as stored
+    Linear,
+}
+
+impl ColorSpace {
+    pub fn format(self) -> wgpu::TextureFormat {
+        match self {
+            ColorSpace::Srgb => wgpu::TextureFormat::Rgba8UnormSrgb,
+            ColorSpace::Linear => wgpu::TextureFormat::Rgba8Unorm,
+        }
+    }
+}
+
+/// Anisotropy clamps offered for image textures, 1 turns it off
+pub const ANISOTROPY_LEVELS: [u16; 4] = [1, 4, 8, 16];
+
+/// Largest anisotropy clamp `adapter` can sample with
+pub fn max_anisotropy(adapter: &wgpu::Adapter) -> u16 {
+    if adapter
+        .get_downlevel_capabilities()
+        .flags
+        .contains(wgpu::DownlevelFlags::ANISOTROPIC_FILTERING)
+    {
+        16
+    } else {
+        1
+    }
+}
+
+/// A 2D texture with a default view and a sampler to read it with
 pub struct Texture {
     pub texture: wgpu::Texture,
     pub view: wgpu::TextureView,
@@ -8,43 +44,47 @@ pub struct Texture {
 }
 
 impl Texture {
+    /// Format of the textures made by `create_depth_texture`
     pub const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
 
+    /// Decodes an image file held in `bytes`, in any format the `image`
+    /// crate recognizes, and uploads it. See `from_image`.
     pub fn from_bytes(
         device: &wgpu::Device,
         queue: &wgpu::Queue,
         bytes: &[u8],
         label: &str,
-        is_normal_map: bool,
+        color_space: ColorSpace,
     ) -> RendererResult<Self> {
         let image = image::load_from_memory(bytes)?;
-        Self::from_image(device, queue, &image, label, is_normal_map)
+        Self::from_image(device, queue, &image, label, color_space)
     }
 
+    /// Uploads `image` as an 8-bit RGBA texture with a full mip chain that
+    /// can be sampled and copied to. `color_space` picks whether sampling
+    /// converts from sRGB, and `label` names the texture in graphics
+    /// debuggers.
     pub fn from_image(
         device: &wgpu::Device,
         queue: &wgpu::Queue,
         image: &image::DynamicImage,
         label: &str,
-        is_normal_map: bool,
+        color_space: ColorSpace,
     ) -> RendererResult<Self> {
         let rgba = image.to_rgba8();
         let dims = rgba.dimensions();
-        let format = if is_normal_map {
-            wgpu::TextureFormat::Rgba8Unorm
-        } else {
-            wgpu::TextureFormat::Rgba8UnormSrgb
-        };
+        let format = color_space.format();
 
         let texture_size = wgpu::Extent3d {
             width: dims.0,
             height: dims.1,
             depth_or_array_layers: 1,
         };
+        let mip_level_count = dims.0.max(dims.1).ilog2() + 1;
         let texture = device.create_texture(&wgpu::TextureDescriptor {
             label: Some(label),
             size: texture_size,
-            mip_level_count: 1,
+            mip_level_count,
             sample_count: 1,
             dimension: wgpu::TextureDimension::D2,
             format,
@@ -52,33 +92,37 @@ impl Texture {
             view_formats: &[],
         });
 
-        queue.write_texture(
-            wgpu::ImageCopyTexture {
-                texture: &texture,
-                mip_level: 0,
-                origin: wgpu::Origin3d::ZERO,
-                aspect: wgpu::TextureAspect::All,
-            },
-            &rgba,
-            wgpu::ImageDataLayout {
-                offset: 0,
-                bytes_per_row: Some(4 * dims.0),
-                rows_per_image: Some(dims.1),
-            },
-            texture_size,
-        );
+        // Each level is filtered down from the one above it
+        let mut level = rgba;
+        for mip_level in 0..mip_level_count {
+            let level_size = texture_size.mip_level_size(mip_level, wgpu::TextureDimension::D2);
+            if mip_level > 0 {
+                level = image::imageops::resize(
+                    &level,
+                    level_size.width,
+                    level_size.height,
+                    image::imageops::FilterType::Triangle,
+                );
+            }
+            queue.write_texture(
+                wgpu::ImageCopyTexture {
+                    texture: &texture,
+                    mip_level,
+                    origin: wgpu::Origin3d::ZERO,
+                    aspect: wgpu::TextureAspect::All,
+                },
+                &level,
+                wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(4 * level_size.width),
+                    rows_per_image: Some(level_size.height),
+                },
+                level_size,
+            );
+        }
 
         let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
-        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
-            label: Some("Sampler Descriptor"),
-            address_mode_u: wgpu::AddressMode::ClampToEdge,
-            address_mode_v: wgpu::AddressMode::ClampToEdge,
-            address_mode_w: wgpu::AddressMode::ClampToEdge,
-            mag_filter: wgpu::FilterMode::Nearest,
-            min_filter: wgpu::FilterMode::Nearest,
-            mipmap_filter: wgpu::FilterMode::Nearest,
-            ..Default::default()
-        });
+        let sampler = Self::create_image_sampler(device, 1);
 
         Ok(Self {
             texture,
@@ -88,6 +132,46 @@ impl Texture {
         })
     }
 
+    fn create_image_sampler(device: &wgpu::Device, anisotropy_clamp: u16) -> wgpu::Sampler {
+        // wgpu only allows anisotropy with linear filtering everywhere
+        let filter = if anisotropy_clamp > 1 {
+            wgpu::FilterMode::Linear
+        } else {
+            wgpu::FilterMode::Nearest
+        };
+        device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Sampler Descriptor"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: filter,
+            min_filter: filter,
+            mipmap_filter: filter,
+            anisotropy_clamp,
+            ..Default::default()
+        })
+    }
+
+    pub fn has_mipmaps(&self) -> bool {
+        self.texture.mip_level_count() > 1
+    }
+
+    /// Recreates the sampler of a texture made by `from_image` with
+    /// `anisotropy_clamp`. Anisotropic filtering works by picking finer mip
+    /// levels along the direction of the slope, so it does nothing without
+    /// mipmaps and textures that have none keep the plain sampler.
+    pub fn set_anisotropy_clamp(&mut self, device: &wgpu::Device, anisotropy_clamp: u16) {
+        let anisotropy_clamp = if self.has_mipmaps() {
+            anisotropy_clamp
+        } else {
+            1
+        };
+        self.sampler = Self::create_image_sampler(device, anisotropy_clamp);
+    }
+
+    /// Creates an uninitialized `width` by `height` texture of `format` with
+    /// a single mip level. `usage` must include every way the texture will
+    /// be used, and `filter` is used for all of the sampler's filtering.
     pub fn create_2d_texture(
         device: &wgpu::Device,
         width: u32,
@@ -135,6 +219,9 @@ impl Texture {
         }
     }
 
+    /// Creates a `DEPTH_FORMAT` texture the size of `extent`, ignoring its
+    /// depth, that can be rendered to and sampled. The sampler compares
+    /// with `LessEqual`, as used for shadow maps.
     pub fn create_depth_texture(
         device: &wgpu::Device,
         extent: &wgpu::Extent3d,
@@ -180,17 +267,22 @@ impl Texture {
     }
 }
 
+/// Parameters of `CubeTexture::create_2d`
 pub struct CubeTextureCreate2dParams<'a> {
     pub device: &'a wgpu::Device,
+    /// Size of each face in texels
     pub width: u32,
     pub height: u32,
     pub format: wgpu::TextureFormat,
     pub mip_level_count: u32,
+    /// Every way the texture will be used
     pub usage: wgpu::TextureUsages,
+    /// Filter used when magnifying, minifying and mip filtering are nearest
     pub mag_filter: wgpu::FilterMode,
     pub label: Option<&'a str>,
 }
 
+/// A texture of six 2D faces, viewed as a cube
 pub struct CubeTexture {
     texture: wgpu::Texture,
     sampler: wgpu::Sampler,
@@ -198,6 +290,8 @@ pub struct CubeTexture {
 }
 
 impl CubeTexture {
+    /// Creates an uninitialized cube texture. The faces are array layers in
+    /// the order +X, -X, +Y, -Y, +Z, -Z.
     pub fn create_2d(params: CubeTextureCreate2dParams<'_>) -> Self {
         let CubeTextureCreate2dParams {
             device,
@@ -1,16 +1,23 @@
-use egui::{text::LayoutJob, Context};
+use egui::{
+    text::{LayoutJob, TextFormat},
+    Color32, Context, FontId,
+};
 use egui_wgpu::{Renderer, ScreenDescriptor};
 use egui_winit::State;
 use winit::{event::WindowEvent, window::Window};
 
-pub struct EguiDrawParams<'a, U: FnOnce(&Context)> {
+pub struct EguiDrawParams<'a> {
     pub device: &'a wgpu::Device,
     pub queue: &'a wgpu::Queue,
     pub encoder: &'a mut wgpu::CommandEncoder,
-    pub window: &'a Window,
     pub view: &'a wgpu::TextureView,
     pub screen_descriptor: ScreenDescriptor,
-    pub run_ui: U,
+}
+
+/// The output of one run of the UI, ready to be drawn
+pub struct EguiFrame {
+    tris: Vec<egui::ClippedPrimitive>,
+    textures_delta: egui::TexturesDelta,
 }
 
 pub struct EguiRenderer {
@@ -39,18 +46,11 @@ impl EguiRenderer {
         res.consumed
     }
 
-    pub fn draw<U: FnOnce(&Context)>(&mut self, draw_params: EguiDrawParams<'_, U>) {
-        let EguiDrawParams {
-            device,
-            queue,
-            encoder,
-            window,
-            view,
-            screen_descriptor,
-            run_ui,
-        } = draw_params;
+    /// Runs `run_ui` to lay out this frame's UI. It is drawn separately so
+    /// that `run_ui` is free to change anything the draw would borrow.
+    pub fn run(&mut self, window: &Window, run_ui: impl FnOnce(&Context)) -> EguiFrame {
         let raw_input = self.state.take_egui_input(window);
-        let full_output = self.state.egui_ctx().run(raw_input, |ui| run_ui(ui));
+        let full_output = self.state.egui_ctx().run(raw_input, run_ui);
 
         self.state
             .handle_platform_output(window, full_output.platform_output);
@@ -58,7 +58,25 @@ impl EguiRenderer {
             .state
             .egui_ctx()
             .tessellate(full_output.shapes, window.scale_factor() as f32);
-        for (id, image_delta) in &full_output.textures_delta.set {
+        EguiFrame {
+            tris,
+            textures_delta: full_output.textures_delta,
+        }
+    }
+
+    pub fn draw(&mut self, draw_params: EguiDrawParams<'_>, frame: EguiFrame) {
+        let EguiDrawParams {
+            device,
+            queue,
+            encoder,
+            view,
+            screen_descriptor,
+        } = draw_params;
+        let EguiFrame {
+            tris,
+            textures_delta,
+        } = frame;
+        for (id, image_delta) in &textures_delta.set {
             self.renderer
                 .update_texture(device, queue, *id, image_delta);
         }
@@ -80,12 +98,70 @@ impl EguiRenderer {
         });
         self.renderer.render(&mut rpass, &tris, &screen_descriptor);
         drop(rpass);
-        for x in &full_output.textures_delta.free {
+        for x in &textures_delta.free {
             self.renderer.free_texture(x);
         }
     }
 }
 
+/// Shows a column of line numbers that lines up with a code editor using
+/// the same monospace font. `error_line` is 1-based and drawn in red.
+pub fn line_number_gutter(
+    ui: &mut egui::Ui,
+    line_count: usize,
+    error_line: Option<usize>,
+) -> egui::Response {
+    let line_count = line_count.max(1);
+    let width = line_count.to_string().len();
+    let mut numbers = (1..=line_count)
+        .map(|n| format!("{:>width$}", n))
+        .collect::<Vec<_>>()
+        .join("\n");
+    let mut layouter = |ui: &egui::Ui, string: &str, _wrap_width: f32| {
+        let mut job = LayoutJob::default();
+        for (i, line) in string.split_inclusive('\n').enumerate() {
+            let color = if Some(i + 1) == error_line {
+                Color32::RED
+            } else {
+                Color32::GRAY
+            };
+            job.append(
+                line,
+                0.0,
+                TextFormat {
+                    font_id: FontId::monospace(12.0),
+                    color,
+                    ..Default::default()
+                },
+            );
+        }
+        ui.fonts(|f| f.layout_job(job))
+    };
+    ui.add(
+        egui::TextEdit::multiline(&mut numbers)
+            .interactive(false)
+            .desired_width(0.0)
+            .layouter(&mut layouter),
+    )
+}
+
+/// Sets a background color on every section of `job` in the 1-based `line`
+pub fn highlight_line(job: &mut LayoutJob, line: usize, color: Color32) {
+    let mut line_start = 0;
+    for (i, text) in job.text.split_inclusive('\n').enumerate() {
+        let line_end = line_start + text.len();
+        if i + 1 == line {
+            for section in &mut job.sections {
+                if section.byte_range.start >= line_start && section.byte_range.end <= line_end {
+                    section.format.background = color;
+                }
+            }
+            return;
+        }
+        line_start = line_end;
+    }
+}
+
 // Everything below here is taken from the egui_extras crate's syntax_highlighting module:
 // https://github.com/emilk/egui/blob/master/crates/egui_extras/src/syntax_highlighting.rs
 // and lightly edited to load the WGSL sublime syntax file from
@@ -0,0 +1,266 @@
+use crate::model::PerInstanceBindGroup;
+use crate::shader::{UniformMember, UniformMemberType, UniformStruct};
+
+/// Layout of the bind group holding a shader's own uniform struct, which it
+/// declares at binding 0 of the group after the renderer's groups. A
+/// `dynamic` struct is bound with an offset picking one of its copies.
+pub const fn get_user_uniform_layout_desc(
+    dynamic: bool,
+) -> wgpu::BindGroupLayoutDescriptor<'static> {
+    const fn entries(dynamic: bool) -> [wgpu::BindGroupLayoutEntry; 1] {
+        [wgpu::BindGroupLayoutEntry {
+            binding: 0,
+            visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: dynamic,
+                min_binding_size: None,
+            },
+            count: None,
+        }]
+    }
+    const STATIC_ENTRIES: [wgpu::BindGroupLayoutEntry; 1] = entries(false);
+    const DYNAMIC_ENTRIES: [wgpu::BindGroupLayoutEntry; 1] = entries(true);
+    wgpu::BindGroupLayoutDescriptor {
+        label: Some("User Uniforms Layout"),
+        entries: if dynamic {
+            &DYNAMIC_ENTRIES
+        } else {
+            &STATIC_ENTRIES
+        },
+    }
+}
+
+/// A mutable view of one member's value
+pub enum UniformValue<'a> {
+    F32(&'a mut f32),
+    I32(&'a mut i32),
+    U32(&'a mut u32),
+    Floats(&'a mut [f32]),
+}
+
+/// CPU copy and GPU buffer of a uniform struct declared by a user shader.
+///
+/// A dynamic struct has a copy per instance, `stride` bytes apart, and the
+/// model is drawn an instance at a time with the offset of its copy.
+pub struct UserUniforms {
+    reflection: UniformStruct,
+    /// Contents of the copies of the struct, every editable member is 4 byte
+    /// aligned
+    data: Vec<u32>,
+    /// `None` unless the struct is bound with a dynamic offset
+    copies: Option<u32>,
+    /// Bytes from one copy to the next
+    stride: u32,
+    /// Copy that `member_mut` edits
+    editing_copy: u32,
+    changed: bool,
+    layout: wgpu::BindGroupLayout,
+    buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+}
+
+impl UserUniforms {
+    /// Creates the buffer for `reflection`, with `copies` copies of it for a
+    /// dynamic offset. Values of members that `previous` has with the same
+    /// name and type are carried over.
+    pub fn new(
+        device: &wgpu::Device,
+        reflection: UniformStruct,
+        copies: Option<u32>,
+        previous: Option<&UserUniforms>,
+    ) -> Self {
+        // Uniform buffers are bound in 16 byte chunks
+        let size = (reflection.size.max(16) + 15) & !15;
+        let stride = match copies {
+            Some(_) => {
+                let alignment = device.limits().min_uniform_buffer_offset_alignment;
+                size.div_ceil(alignment) * alignment
+            }
+            None => size,
+        };
+        let copy_count = copies.unwrap_or(1);
+        let mut data = vec![0u32; (stride * copy_count) as usize / 4];
+        for copy in 0..copy_count {
+            let copy_start = (copy * stride) as usize / 4;
+            for member in &reflection.members {
+                let start = copy_start + member.offset as usize / 4;
+                let words = &mut data[start..start + member.ty.components()];
+                match previous.and_then(|previous| previous.member_words(member, copy)) {
+                    Some(previous_words) => words.copy_from_slice(previous_words),
+                    // Start colors out white rather than black
+                    None if member.is_color() => words.fill(1.0f32.to_bits()),
+                    None => (),
+                }
+            }
+        }
+
+        let layout =
+            device.create_bind_group_layout(&get_user_uniform_layout_desc(copies.is_some()));
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(&format!("User Uniforms ({})", reflection.name)),
+            size: (stride * copy_count) as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("User Uniforms Bind Group"),
+            layout: &layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                // One copy, which the dynamic offset moves between
+                resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                    buffer: &buffer,
+                    offset: 0,
+                    size: wgpu::BufferSize::new(size as u64),
+                }),
+            }],
+        });
+
+        Self {
+            reflection,
+            data,
+            copies,
+            stride,
+            editing_copy: 0,
+            changed: true,
+            layout,
+            buffer,
+            bind_group,
+        }
+    }
+
+    /// Name of the uniform variable in the shader
+    pub fn name(&self) -> &str {
+        &self.reflection.name
+    }
+
+    pub fn group(&self) -> u32 {
+        self.reflection.group
+    }
+
+    pub fn members(&self) -> &[UniformMember] {
+        &self.reflection.members
+    }
+
+    /// `None` unless the struct has a copy per instance
+    pub fn copies(&self) -> Option<u32> {
+        self.copies
+    }
+
+    pub fn editing_copy(&self) -> u32 {
+        self.editing_copy
+    }
+
+    /// Picks the copy `member_mut` edits, clamped to the copies there are
+    pub fn set_editing_copy(&mut self, copy: u32) {
+        self.editing_copy = copy.min(self.copies.unwrap_or(1) - 1);
+    }
+
+    /// Words of the previous struct's member matching `member`, from `copy`
+    /// or the first copy if it had fewer
+    fn member_words(&self, member: &UniformMember, copy: u32) -> Option<&[u32]> {
+        let old = self
+            .reflection
+            .members
+            .iter()
+            .find(|old| old.name == member.name && old.ty == member.ty)?;
+        let copy = if copy < self.copies.unwrap_or(1) {
+            copy
+        } else {
+            0
+        };
+        let start = (copy * self.stride + old.offset) as usize / 4;
+        self.data.get(start..start + old.ty.components())
+    }
+
+    /// The member at `index` and its value in the copy being edited. Call
+    /// `mark_changed` after editing it so that the buffer gets updated.
+    pub fn member_mut(&mut self, index: usize) -> (&UniformMember, UniformValue<'_>) {
+        let member = &self.reflection.members[index];
+        let start = (self.editing_copy * self.stride + member.offset) as usize / 4;
+        let words = &mut self.data[start..start + member.ty.components()];
+        let value = match member.ty {
+            UniformMemberType::F32 => UniformValue::F32(bytemuck::cast_mut(&mut words[0])),
+            UniformMemberType::I32 => UniformValue::I32(bytemuck::cast_mut(&mut words[0])),
+            UniformMemberType::U32 => UniformValue::U32(&mut words[0]),
+            UniformMemberType::Vec2 | UniformMemberType::Vec3 | UniformMemberType::Vec4 => {
+                UniformValue::Floats(bytemuck::cast_slice_mut(words))
+            }
+        };
+        (member, value)
+    }
+
+    /// Sets every resolution member to `width` by `height`. A `vec3` gets a
+    /// pixel aspect ratio of 1 in z, like Shadertoy's `iResolution`.
+    pub fn set_resolution(&mut self, width: u32, height: u32) {
+        let resolution = [width as f32, height as f32, 1.0];
+        for copy in 0..self.copies.unwrap_or(1) {
+            for member in &self.reflection.members {
+                if !member.is_resolution() {
+                    continue;
+                }
+                let start = (copy * self.stride + member.offset) as usize / 4;
+                let components = member.ty.components();
+                let words: &mut [f32] =
+                    bytemuck::cast_slice_mut(&mut self.data[start..start + components]);
+                if *words != resolution[..components] {
+                    words.copy_from_slice(&resolution[..components]);
+                    self.changed = true;
+                }
+            }
+        }
+    }
+
+    /// Sets every time member to `time` in seconds
+    pub fn set_time(&mut self, time: f32) {
+        for copy in 0..self.copies.unwrap_or(1) {
+            for member in &self.reflection.members {
+                let word = &mut self.data[(copy * self.stride + member.offset) as usize / 4];
+                if member.is_time() && *word != time.to_bits() {
+                    *word = time.to_bits();
+                    self.changed = true;
+                }
+            }
+        }
+    }
+
+    pub fn mark_changed(&mut self) {
+        self.changed = true;
+    }
+
+    /// Writes the values to the GPU if they were edited
+    pub fn upload(&mut self, queue: &wgpu::Queue) {
+        if std::mem::take(&mut self.changed) {
+            queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&self.data));
+        }
+    }
+
+    pub fn layout(&self) -> &wgpu::BindGroupLayout {
+        &self.layout
+    }
+
+    pub fn bind_group(&self) -> &wgpu::BindGroup {
+        &self.bind_group
+    }
+
+    /// Dynamic offsets to bind the bind group with when not drawing per
+    /// instance, selecting the first copy
+    pub fn default_offsets(&self) -> &'static [u32] {
+        match self.copies {
+            Some(_) => &[0],
+            None => &[],
+        }
+    }
+
+    /// The bind group for drawing an instance at a time, if the struct has a
+    /// copy per instance
+    pub fn per_instance(&self) -> Option<PerInstanceBindGroup<'_>> {
+        self.copies.map(|copies| PerInstanceBindGroup {
+            group: self.reflection.group,
+            bind_group: &self.bind_group,
+            stride: self.stride,
+            copies,
+        })
+    }
+}
@@ -0,0 +1,49 @@
+/// The part of a render target one camera draws to, in pixels
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Viewport {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+impl Viewport {
+    /// Covers the whole of a `width` by `height` target
+    pub fn full(width: u32, height: u32) -> Self {
+        Self {
+            x: 0.0,
+            y: 0.0,
+            width: width as f32,
+            height: height as f32,
+        }
+    }
+
+    /// Splits a `width` by `height` target into a left and a right half
+    pub fn split(width: u32, height: u32) -> [Self; 2] {
+        let left_width = width / 2;
+        let left = Self {
+            width: left_width as f32,
+            ..Self::full(width, height)
+        };
+        let right = Self {
+            x: left_width as f32,
+            width: (width - left_width) as f32,
+            ..left
+        };
+        [left, right]
+    }
+
+    pub fn contains(&self, x: f32, y: f32) -> bool {
+        (self.x..self.x + self.width).contains(&x) && (self.y..self.y + self.height).contains(&y)
+    }
+
+    /// Size to give a camera's projection, at least one pixel each way
+    pub fn size(&self) -> (u32, u32) {
+        ((self.width as u32).max(1), (self.height as u32).max(1))
+    }
+
+    /// Restricts the draws that follow in `render_pass` to the viewport
+    pub fn apply(&self, render_pass: &mut wgpu::RenderPass) {
+        render_pass.set_viewport(self.x, self.y, self.width, self.height, 0.0, 1.0);
+    }
+}